@@ -0,0 +1,38 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fortune_backend::fortunes::Fortunes;
+use fortune_backend::Fortune;
+use rand::seq::SliceRandom;
+
+fn populated(size: usize) -> Fortunes {
+    let mut fortunes = Fortunes::new();
+    for i in 0..size {
+        let id = i.to_string();
+        fortunes.insert(id.clone(), Fortune {
+            id,
+            message: format!("Fortune number {}", i),
+            ..Default::default()
+        });
+    }
+    fortunes
+}
+
+fn random(c: &mut Criterion) {
+    let mut group = c.benchmark_group("random");
+    for size in [1_000, 100_000, 300_000] {
+        let fortunes = populated(size);
+        let mut rng = rand::thread_rng();
+
+        group.bench_with_input(BenchmarkId::new("indexed", size), &fortunes, |b, fortunes| {
+            b.iter(|| fortunes.random(&mut rng).cloned())
+        });
+
+        // The previous approach: collect every fortune, then pick one
+        group.bench_with_input(BenchmarkId::new("collect", size), &fortunes, |b, fortunes| {
+            b.iter(|| fortunes.values().collect::<Vec<_>>().choose(&mut rng).map(|f| (*f).clone()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, random);
+criterion_main!(benches);
@@ -0,0 +1,178 @@
+// Throughput of the read paths behind GET /fortunes, /fortunes/random and
+// /fortunes/{id}, alone and with a writer storing fortunes alongside (as
+// replication or an import would). The Redis group only runs when BENCH_REDIS_URL is set, e.g.
+// `BENCH_REDIS_URL=redis://localhost:6379 cargo bench --bench store`; it
+// writes to the `bench:fortunes` hash.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fortune_backend::fortunes::Fortunes;
+use fortune_backend::redis_client::RedisStore;
+use fortune_backend::shared::Shared;
+use fortune_backend::storage::Storage;
+use fortune_backend::{store, Fortune, FortuneStore};
+use rand::Rng;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+const SIZES: [usize; 3] = [100, 10_000, 100_000];
+
+fn fortunes(size: usize) -> Vec<Fortune> {
+    (0..size)
+        .map(|i| Fortune {
+            id: i.to_string(),
+            message: format!("Fortune number {}", i),
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn populated(size: usize) -> FortuneStore {
+    let mut map = Fortunes::new();
+    for fortune in fortunes(size) {
+        map.insert(fortune.id.clone(), fortune);
+    }
+    Arc::new(Shared::new(map))
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+fn in_memory(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("in_memory");
+    for size in SIZES {
+        let fortunes = populated(size);
+
+        group.bench_with_input(BenchmarkId::new("list", size), &fortunes, |b, fortunes| {
+            b.iter(|| rt.block_on(store::list(fortunes)))
+        });
+        group.bench_with_input(BenchmarkId::new("random", size), &fortunes, |b, fortunes| {
+            b.iter(|| rt.block_on(store::random(fortunes, &[], None, false)))
+        });
+        group.bench_with_input(BenchmarkId::new("get", size), &fortunes, |b, fortunes| {
+            b.iter(|| {
+                let id = rand::thread_rng().gen_range(0..size).to_string();
+                rt.block_on(store::get(fortunes, &id))
+            })
+        });
+    }
+    group.finish();
+}
+
+// Tasks reading at once in the contended group
+const READERS: u64 = 4;
+// Fortunes the writer stores per write, as loading or syncing from Redis does
+const WRITE_BATCH: usize = 100;
+
+// Times `iters` calls of `read`, spread over READERS tasks, while another
+// task keeps replacing fortunes in batches
+fn contended<F, Fut>(rt: &Runtime, fortunes: &FortuneStore, size: usize, iters: u64, read: F) -> Duration
+where
+    F: Fn(FortuneStore, usize) -> Fut + Copy + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send,
+{
+    rt.block_on(async {
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = tokio::spawn({
+            let (fortunes, stop) = (fortunes.clone(), stop.clone());
+            async move {
+                let mut i = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let mut fortunes = fortunes.write().await;
+                    for _ in 0..WRITE_BATCH {
+                        let id = (i % size).to_string();
+                        let message = format!("Fortune number {}, revised {}", id, i);
+                        fortunes.insert(id.clone(), Fortune { id, message, ..Default::default() });
+                        i += 1;
+                    }
+                    drop(fortunes);
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+        let start = Instant::now();
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let fortunes = fortunes.clone();
+                tokio::spawn(async move {
+                    for _ in 0..iters.div_ceil(READERS) {
+                        read(fortunes.clone(), size).await;
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+        let elapsed = start.elapsed();
+        stop.store(true, Ordering::Relaxed);
+        writer.await.unwrap();
+        elapsed
+    })
+}
+
+fn with_writer(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread().worker_threads(READERS as usize + 1).enable_all().build().unwrap();
+    let mut group = c.benchmark_group("with_writer");
+    for size in SIZES {
+        let fortunes = populated(size);
+
+        group.bench_with_input(BenchmarkId::new("random", size), &fortunes, |b, fortunes| {
+            b.iter_custom(|iters| {
+                contended(&rt, fortunes, size, iters, |fortunes, _| async move {
+                    store::random(&fortunes, &[], None, false).await;
+                })
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("get", size), &fortunes, |b, fortunes| {
+            b.iter_custom(|iters| {
+                contended(&rt, fortunes, size, iters, |fortunes, size| async move {
+                    let id = rand::thread_rng().gen_range(0..size).to_string();
+                    store::get(&fortunes, &id).await;
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn redis_backed(c: &mut Criterion) {
+    let url = match std::env::var("BENCH_REDIS_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("BENCH_REDIS_URL not set, skipping the Redis benchmarks");
+            return;
+        }
+    };
+    let rt = runtime();
+    let redis = RedisStore::with_hash(redis::Client::open(url).expect("invalid BENCH_REDIS_URL"), "bench:fortunes");
+    let mut group = c.benchmark_group("redis");
+    // Every sample is a network round trip; keep the run short
+    group.sample_size(20);
+    for size in SIZES {
+        rt.block_on(async {
+            let mut conn = redis.connection().expect("failed to connect to BENCH_REDIS_URL");
+            redis::cmd("DEL").arg("bench:fortunes").query::<()>(&mut conn).expect("failed to clear bench:fortunes");
+            for chunk in fortunes(size).chunks(1000) {
+                redis.set_many(chunk).await.expect("failed to fill bench:fortunes");
+            }
+        });
+
+        group.bench_with_input(BenchmarkId::new("list", size), &redis, |b, redis| {
+            b.iter(|| rt.block_on(redis.load_all()).unwrap())
+        });
+        // Random selection happens in memory; what Redis adds is the read of the picked id
+        group.bench_with_input(BenchmarkId::new("get", size), &redis, |b, redis| {
+            b.iter(|| {
+                let id = rand::thread_rng().gen_range(0..size).to_string();
+                rt.block_on(redis.get(&id)).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, in_memory, with_writer, redis_backed);
+criterion_main!(benches);
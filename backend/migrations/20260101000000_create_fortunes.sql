@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS fortunes (
+    id TEXT PRIMARY KEY,
+    message TEXT NOT NULL
+);
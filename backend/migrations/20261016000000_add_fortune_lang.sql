@@ -0,0 +1,2 @@
+ALTER TABLE fortunes ADD COLUMN lang TEXT NOT NULL DEFAULT 'en';
+ALTER TABLE fortunes ADD COLUMN group_id TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE fortunes ADD COLUMN publish_at BIGINT;
+ALTER TABLE fortunes ADD COLUMN expires_at BIGINT;
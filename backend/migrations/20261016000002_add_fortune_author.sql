@@ -0,0 +1,2 @@
+ALTER TABLE fortunes ADD COLUMN author TEXT;
+CREATE INDEX IF NOT EXISTS fortunes_author ON fortunes (author);
@@ -0,0 +1 @@
+ALTER TABLE fortunes ADD COLUMN status TEXT NOT NULL DEFAULT 'approved';
@@ -0,0 +1,2 @@
+ALTER TABLE fortunes ADD COLUMN created_at BIGINT;
+ALTER TABLE fortunes ADD COLUMN updated_at BIGINT;
@@ -0,0 +1,2 @@
+-- Comma-separated, e.g. `programming,zen`; tags never contain commas
+ALTER TABLE fortunes ADD COLUMN tags TEXT;
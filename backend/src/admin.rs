@@ -0,0 +1,531 @@
+use crate::analytics::{self, Analytics};
+use crate::storage::Storage;
+use crate::duplicates::{self, DuplicateParams};
+use crate::jwt::{Jwt, Role};
+use crate::leader::{self, LeaseStatus};
+use crate::quotas::{self, Quotas};
+use crate::recorder::{self, Recorder};
+use crate::{audit, methods, redis_client, store, with_store, Fortune, FortuneStore, Status};
+use fortune_common::{error, info};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::time::Instant;
+use utoipa::{IntoParams, ToSchema};
+use warp::reject::Reject;
+use warp::{Filter, Rejection, Reply};
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+#[derive(Serialize, ToSchema)]
+pub struct Stats {
+    fortunes: usize,
+    moderation: ModerationCounts,
+    // "connected", "unreachable" or "disabled"
+    redis: &'static str,
+    uptime_secs: u64,
+    // Resident set size, when the platform reports it
+    memory_rss_bytes: Option<u64>,
+    // READ_ONLY is set and mutating requests are refused
+    read_only: bool,
+    // The leader lease for singleton jobs; null when LEADER_ELECTION is off
+    leader: Option<LeaseStatus>,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub struct ModerationCounts {
+    pending: usize,
+    approved: usize,
+    rejected: usize,
+    // Waiting for their submitter's verification
+    unverified: usize,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditParams {
+    /// Only entries at or after this Unix timestamp (seconds)
+    #[serde(default)]
+    since: u64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AnalyticsParams {
+    /// Days to report, counting back from today (UTC); 1 to 366
+    #[serde(default = "default_analytics_days")]
+    days: u64,
+    /// Number of most served fortunes; at most 100
+    #[serde(default = "default_analytics_top")]
+    top: usize,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct KeysParams {
+    /// Days of usage to report, counting back from today (UTC); 1 to 31
+    #[serde(default = "default_keys_days")]
+    days: u64,
+}
+
+fn default_keys_days() -> u64 {
+    1
+}
+
+fn default_analytics_days() -> u64 {
+    7
+}
+
+fn default_analytics_top() -> usize {
+    10
+}
+
+// Compares in constant time so the key cannot be guessed byte by byte
+pub(crate) fn key_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Who counts as admin: callers with ADMIN_API_KEY and, with JWT
+// authentication on, callers whose token has the admin role
+#[derive(Debug, Clone, Default)]
+pub struct AdminAccess {
+    pub(crate) key: Option<String>,
+    pub(crate) jwt: Option<Jwt>,
+}
+
+impl AdminAccess {
+    pub fn new(key: Option<String>, jwt: Option<Jwt>) -> Self {
+        AdminAccess { key, jwt }
+    }
+
+    async fn is_admin(&self, given: Option<&str>, authorization: Option<&str>) -> bool {
+        match &self.jwt {
+            Some(jwt) => matches!(jwt.resolve(self.key.as_deref(), given, authorization).await, Ok(Some(Role::Admin))),
+            None => matches!((&self.key, given), (Some(expected), Some(given)) if key_matches(expected, given)),
+        }
+    }
+}
+
+// Without a configured key or JWT authentication the admin routes behave
+// as if they did not exist
+pub(crate) fn authorized(access: AdminAccess) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |given: Option<String>, authorization: Option<String>| {
+            let access = access.clone();
+            async move {
+                if access.key.is_none() && access.jwt.is_none() {
+                    return Err(warp::reject::not_found());
+                }
+                match access.is_admin(given.as_deref(), authorization.as_deref()).await {
+                    true => Ok(()),
+                    false => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// True when a new fortune has to wait for moderation: MODERATION is on and
+// the request did not come from an admin
+pub fn needs_review(moderation: bool, access: AdminAccess) -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .and(warp::header::optional::<String>("authorization"))
+        .then(move |given: Option<String>, authorization: Option<String>| {
+            let access = access.clone();
+            async move { moderation && !access.is_admin(given.as_deref(), authorization.as_deref()).await }
+        })
+}
+
+fn memory_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key")),
+    responses(
+        (status = 200, description = "Process and store statistics", body = Stats),
+        (status = 401, description = "Missing or wrong API key", body = String),
+    )
+)]
+async fn stats_handler(started: Instant, read_only: bool, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    let redis = match redis_client::get_store().await {
+        Some(redis) if redis.ping().await => "connected",
+        Some(_) => "unreachable",
+        None => "disabled",
+    };
+    let (fortunes, moderation) = {
+        let store = store.read().await;
+        let mut counts = ModerationCounts::default();
+        for fortune in store.values() {
+            match fortune.status {
+                Status::Pending => counts.pending += 1,
+                Status::Approved => counts.approved += 1,
+                Status::Rejected => counts.rejected += 1,
+                Status::Unverified => counts.unverified += 1,
+            }
+        }
+        (store.len(), counts)
+    };
+    Ok(warp::reply::json(&Stats {
+        fortunes,
+        moderation,
+        redis,
+        uptime_secs: started.elapsed().as_secs(),
+        memory_rss_bytes: memory_rss_bytes(),
+        read_only,
+        leader: leader::status().await,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/resync",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key")),
+    responses(
+        (status = 200, description = "Number of fortunes loaded from Redis", body = usize),
+        (status = 401, description = "Missing or wrong API key", body = String),
+        (status = 503, description = "Redis is disabled or unreachable", body = String),
+    )
+)]
+async fn resync_handler(store: FortuneStore) -> Result<impl Reply, Infallible> {
+    let Some(redis) = redis_client::get_store().await else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"redis is not configured"),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    };
+    match redis.load_all().await {
+        Ok(fortunes) => {
+            let loaded = fortunes.len();
+            let mut store = store.write().await;
+            for fortune in fortunes {
+                store.insert(fortune.id.clone(), fortune);
+            }
+            crate::snapshot::mark_dirty();
+            info!("admin resync: loaded {} fortunes from redis", loaded);
+            Ok(warp::reply::with_status(warp::reply::json(&loaded), warp::http::StatusCode::OK))
+        }
+        Err(e) => {
+            error!("admin resync failed: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&format!("redis load failed: {}", e)),
+                warp::http::StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/flush-cache",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key")),
+    responses(
+        (status = 200, description = "Number of fortunes dropped from memory", body = usize),
+        (status = 401, description = "Missing or wrong API key", body = String),
+    )
+)]
+async fn flush_cache_handler(store: FortuneStore) -> Result<impl Reply, Infallible> {
+    // Only the in-memory copy is cleared; Redis and the database keep their data
+    let mut store = store.write().await;
+    let flushed = store.len();
+    store.clear();
+    info!("admin flush: dropped {} fortunes from memory", flushed);
+    Ok(warp::reply::json(&flushed))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key"), AuditParams),
+    responses(
+        (status = 200, description = "Audit entries, oldest first", body = [audit::AuditEntry]),
+        (status = 401, description = "Missing or wrong API key", body = String),
+        (status = 503, description = "AUDIT_LOG_FILE is not set or unreadable", body = String),
+    )
+)]
+async fn audit_handler(params: AuditParams) -> Result<impl Reply, Infallible> {
+    if !audit::is_enabled() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"audit log is not configured"),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    }
+    match audit::since(params.since).await {
+        Ok(entries) => Ok(warp::reply::with_status(warp::reply::json(&entries), warp::http::StatusCode::OK)),
+        Err(e) => {
+            error!("audit query failed: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&e),
+                warp::http::StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/analytics",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key"), AnalyticsParams),
+    responses(
+        (status = 200, description = "Requests per endpoint, submissions and unique clients per day, and the most served fortunes", body = Analytics),
+        (status = 400, description = "days is not between 1 and 366", body = String),
+        (status = 401, description = "Missing or wrong API key", body = String),
+        (status = 503, description = "Redis is disabled or unreachable, or ANALYTICS_FLUSH_SECS is 0", body = String),
+    )
+)]
+async fn analytics_handler(params: AnalyticsParams, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    let unavailable = |message: String| warp::reply::with_status(warp::reply::json(&message), warp::http::StatusCode::SERVICE_UNAVAILABLE);
+    if !(1..=366).contains(&params.days) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"days must be between 1 and 366"),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+    if !analytics::is_enabled() {
+        return Ok(unavailable("analytics are off".to_string()));
+    }
+    let Some(redis) = redis_client::get_store().await else {
+        return Ok(unavailable("redis is not configured".to_string()));
+    };
+    match analytics::report(&redis, &store, params.days, params.top.min(100)).await {
+        Ok(report) => Ok(warp::reply::with_status(warp::reply::json(&report), warp::http::StatusCode::OK)),
+        Err(e) => {
+            error!("analytics query failed: {}", e);
+            Ok(unavailable(format!("redis query failed: {}", e)))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/keys",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key"), KeysParams),
+    responses(
+        (status = 200, description = "The API_KEYS keys with their daily limits and usage", body = [quotas::KeyUsage]),
+        (status = 400, description = "days is not between 1 and 31", body = String),
+        (status = 401, description = "Missing or wrong API key", body = String),
+    )
+)]
+async fn keys_handler(params: KeysParams, quotas: Quotas) -> Result<impl Reply, Infallible> {
+    if !(1..=quotas::RETENTION_DAYS).contains(&params.days) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&format!("days must be between 1 and {}", quotas::RETENTION_DAYS)),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+    Ok(warp::reply::with_status(warp::reply::json(&quotas.report(params.days).await), warp::http::StatusCode::OK))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/recent-requests",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key")),
+    responses(
+        (status = 200, description = "The last requests with their responses, oldest first", body = [recorder::Recorded]),
+        (status = 401, description = "Missing or wrong API key", body = String),
+        (status = 503, description = "FLIGHT_RECORDER_SIZE is 0", body = String),
+    )
+)]
+async fn recent_requests_handler(recorder: Recorder) -> Result<impl Reply, Infallible> {
+    if !recorder.is_enabled() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"flight recorder is off"),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    }
+    Ok(warp::reply::with_status(warp::reply::json(&recorder.recent()), warp::http::StatusCode::OK))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/moderation",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key")),
+    responses(
+        (status = 200, description = "Fortunes awaiting moderation", body = [Fortune]),
+        (status = 401, description = "Missing or wrong API key", body = String),
+    )
+)]
+async fn pending_handler(store: FortuneStore) -> Result<impl Reply, Infallible> {
+    let mut pending: Vec<Fortune> = store
+        .read()
+        .await
+        .values()
+        .filter(|f| f.status == Status::Pending)
+        .cloned()
+        .collect();
+    pending.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(warp::reply::json(&pending))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/duplicates",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key"), DuplicateParams),
+    responses(
+        (status = 200, description = "Clusters of duplicate and near-duplicate fortunes, largest first", body = [duplicates::Cluster]),
+        (status = 400, description = "The threshold is not between 0 and 1", body = String),
+        (status = 401, description = "Missing or wrong API key", body = String),
+    )
+)]
+async fn duplicates_handler(params: DuplicateParams, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    if !(0.0..=1.0).contains(&params.threshold) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"threshold must be between 0 and 1"),
+            warp::http::StatusCode::BAD_REQUEST,
+        ));
+    }
+    let fortunes: Vec<Fortune> = store.read().await.values().cloned().collect();
+    let clusters = duplicates::clusters(&fortunes, params.threshold);
+    Ok(warp::reply::with_status(warp::reply::json(&clusters), warp::http::StatusCode::OK))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/fortunes/{id}/{decision}",
+    tag = "admin",
+    params(
+        ("X-API-Key" = String, Header, description = "Admin API key"),
+        ("id" = String, Path, description = "Fortune id"),
+        ("decision" = String, Path, description = "`approve` or `reject`"),
+    ),
+    responses(
+        (status = 200, description = "The fortune with its new status", body = Fortune),
+        (status = 401, description = "Missing or wrong API key", body = String),
+        (status = 403, description = "The backend is read-only", body = String),
+        (status = 404, description = "No fortune with this id", body = String),
+    )
+)]
+async fn moderate_handler(id: String, status: Status, actor: String, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    Ok(crate::fortune_reply(store::moderate(&store, &id, status, &actor).await))
+}
+
+// /admin routes, protected by ADMIN_API_KEY. Moderating is refused while
+// `read_only` is set; resync and flush-cache only touch the in-memory copy.
+pub fn routes(
+    store: FortuneStore,
+    access: AdminAccess,
+    read_only: bool,
+    actors: audit::Actors,
+    recorder: Recorder,
+    quotas: Quotas,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let started = Instant::now();
+    let admin = warp::path("admin");
+
+    let stats = admin
+        .and(warp::path("stats"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(authorized(access.clone()))
+        .and(warp::any().map(move || started))
+        .and(warp::any().map(move || read_only))
+        .and(with_store(store.clone()))
+        .and_then(stats_handler);
+
+    let resync = admin
+        .and(warp::path("resync"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(authorized(access.clone()))
+        .and(with_store(store.clone()))
+        .and_then(resync_handler);
+
+    let flush_cache = admin
+        .and(warp::path("flush-cache"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(authorized(access.clone()))
+        .and(with_store(store.clone()))
+        .and_then(flush_cache_handler);
+
+    let pending = admin
+        .and(warp::path("moderation"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(authorized(access.clone()))
+        .and(with_store(store.clone()))
+        .and_then(pending_handler);
+
+    let decision = warp::path("approve")
+        .map(|| Status::Approved)
+        .or(warp::path("reject").map(|| Status::Rejected))
+        .unify();
+    let moderate = admin
+        .and(warp::path("fortunes"))
+        .and(warp::path::param())
+        .and(decision)
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(authorized(access.clone()))
+        .and(crate::writable(read_only))
+        .and(audit::actor(actors))
+        .and(with_store(store.clone()))
+        .and_then(moderate_handler);
+
+    let audit = admin
+        .and(warp::path("audit"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(authorized(access.clone()))
+        .and(warp::query::<AuditParams>())
+        .and_then(audit_handler);
+
+    let analytics = admin
+        .and(warp::path("analytics"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(authorized(access.clone()))
+        .and(warp::query::<AnalyticsParams>())
+        .and(with_store(store.clone()))
+        .and_then(analytics_handler);
+
+    let recent_requests = admin
+        .and(warp::path("recent-requests"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(authorized(access.clone()))
+        .and(warp::any().map(move || recorder.clone()))
+        .and_then(recent_requests_handler);
+
+    let keys = admin
+        .and(warp::path("keys"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(authorized(access.clone()))
+        .and(warp::query::<KeysParams>())
+        .and(warp::any().map(move || quotas.clone()))
+        .and_then(keys_handler);
+
+    let duplicates = admin
+        .and(warp::path("duplicates"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(authorized(access))
+        .and(warp::query::<DuplicateParams>())
+        .and(with_store(store.clone()))
+        .and_then(duplicates_handler);
+
+    stats.or(resync).or(flush_cache).or(audit).or(analytics).or(pending).or(moderate).or(duplicates).or(recent_requests).or(keys)
+}
@@ -0,0 +1,267 @@
+use crate::backup::civil_from_days;
+use crate::client_ip::{self, TrustedProxies};
+use crate::config::Config;
+use crate::fortunes::now_secs;
+use crate::methods::{self, Enabled};
+use crate::redis_client::{self, RedisStore};
+use crate::FortuneStore;
+use fortune_common::error;
+use redis::RedisResult;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use utoipa::ToSchema;
+use warp::http::Method;
+use warp::path::FullPath;
+use warp::{Filter, Reply};
+
+// Usage counters for /admin/analytics, kept in Redis per UTC day so every
+// replica adds to the same totals:
+//   fortunes:analytics:requests:<date>  hash of endpoint name to request count
+//   fortunes:analytics:submissions:<date>  new fortunes stored
+//   fortunes:analytics:clients:<date>  HyperLogLog of client addresses
+// Requests are tallied in memory and added every ANALYTICS_FLUSH_SECS, so
+// serving a fortune never waits on Redis. Keys expire after
+// ANALYTICS_RETENTION_DAYS. The most served fortunes come from the view
+// counts Redis keeps anyway.
+
+const PREFIX: &str = "fortunes:analytics";
+pub(crate) const DAY_SECS: u64 = 24 * 60 * 60;
+// Client addresses held per day between flushes; more are dropped until the
+// next flush, so a flood of spoofed addresses cannot grow memory without bound
+const MAX_PENDING_CLIENTS: usize = 100_000;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static RETENTION_DAYS: AtomicU64 = AtomicU64::new(0);
+static FLUSH_FAILURES: AtomicU64 = AtomicU64::new(0);
+static PENDING: OnceLock<Mutex<Pending>> = OnceLock::new();
+
+// Counts not yet added to Redis, by day since the epoch
+#[derive(Default)]
+struct Pending {
+    requests: HashMap<(u64, &'static str), u64>,
+    submissions: HashMap<u64, u64>,
+    clients: HashMap<u64, HashSet<IpAddr>>,
+}
+
+impl Pending {
+    // Puts back counts whose flush failed
+    fn absorb(&mut self, other: Pending) {
+        for (key, count) in other.requests {
+            *self.requests.entry(key).or_default() += count;
+        }
+        for (day, count) in other.submissions {
+            *self.submissions.entry(day).or_default() += count;
+        }
+        for (day, clients) in other.clients {
+            let pending = self.clients.entry(day).or_default();
+            pending.extend(clients.into_iter().take(MAX_PENDING_CLIENTS.saturating_sub(pending.len())));
+        }
+    }
+}
+
+fn pending() -> &'static Mutex<Pending> {
+    PENDING.get_or_init(|| Mutex::new(Pending::default()))
+}
+
+pub(crate) fn today() -> u64 {
+    now_secs() / DAY_SECS
+}
+
+// `YYYY-MM-DD` of the day since the epoch
+pub(crate) fn date(day: u64) -> String {
+    let (year, month, day) = civil_from_days(day as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn key(kind: &str, day: u64) -> String {
+    format!("{}:{}:{}", PREFIX, kind, date(day))
+}
+
+// Starts collecting when Redis is configured and ANALYTICS_FLUSH_SECS is not 0
+pub fn init(config: &Config) {
+    let Some(interval) = config.analytics_flush() else {
+        return;
+    };
+    if config.redis_url().is_none() {
+        return;
+    }
+    RETENTION_DAYS.store(config.analytics_retention_days, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            // Counts wait in memory while Redis is away
+            if let Some(redis) = redis_client::get_store().await {
+                if let Err(e) = flush(&redis).await {
+                    error!("analytics flush failed: {}", e);
+                }
+            }
+        }
+    });
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn record_request(endpoint: &'static str, client: Option<IpAddr>) {
+    let day = today();
+    let mut pending = pending().lock().unwrap();
+    *pending.requests.entry((day, endpoint)).or_default() += 1;
+    if let Some(client) = client {
+        let clients = pending.clients.entry(day).or_default();
+        if clients.len() < MAX_PENDING_CLIENTS {
+            clients.insert(client);
+        }
+    }
+}
+
+// Counts a newly stored fortune
+pub fn record_submission() {
+    if !is_enabled() {
+        return;
+    }
+    *pending().lock().unwrap().submissions.entry(today()).or_default() += 1;
+}
+
+// Counts every request to a named endpoint, by the endpoint that answers
+// its method and path, along with the client's address
+pub fn wrap<F, R>(routes: F, enabled: Enabled, proxies: TrustedProxies) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::method()
+        .and(warp::path::full())
+        .and(client_ip::filter(proxies))
+        .and(routes)
+        .map(move |method: Method, path: FullPath, client: Option<IpAddr>, reply: R| {
+            if is_enabled() {
+                if let Some(endpoint) = methods::endpoint(&method, path.as_str(), enabled) {
+                    record_request(endpoint.name(), client);
+                }
+            }
+            reply.into_response()
+        })
+}
+
+// Adds the counts gathered since the last flush to Redis
+pub async fn flush(redis: &RedisStore) -> RedisResult<()> {
+    let taken = std::mem::take(&mut *pending().lock().unwrap());
+    if taken.requests.is_empty() && taken.submissions.is_empty() && taken.clients.is_empty() {
+        return Ok(());
+    }
+    let retention = (RETENTION_DAYS.load(Ordering::Relaxed) * DAY_SECS) as i64;
+    let mut pipe = redis::pipe();
+    for ((day, endpoint), count) in &taken.requests {
+        let key = key("requests", *day);
+        pipe.cmd("HINCRBY").arg(&key).arg(*endpoint).arg(*count).ignore();
+        pipe.cmd("EXPIRE").arg(&key).arg(retention).ignore();
+    }
+    for (day, count) in &taken.submissions {
+        let key = key("submissions", *day);
+        pipe.cmd("INCRBY").arg(&key).arg(*count).ignore();
+        pipe.cmd("EXPIRE").arg(&key).arg(retention).ignore();
+    }
+    for (day, clients) in &taken.clients {
+        if clients.is_empty() {
+            continue;
+        }
+        let key = key("clients", *day);
+        let addresses: Vec<String> = clients.iter().map(IpAddr::to_string).collect();
+        pipe.cmd("PFADD").arg(&key).arg(addresses).ignore();
+        pipe.cmd("EXPIRE").arg(&key).arg(retention).ignore();
+    }
+    let result = redis.connection().and_then(|mut conn| pipe.query::<()>(&mut conn));
+    if result.is_err() {
+        FLUSH_FAILURES.fetch_add(1, Ordering::Relaxed);
+        pending().lock().unwrap().absorb(taken);
+    }
+    result
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Analytics {
+    /// One entry per UTC day, oldest first
+    days: Vec<DayUsage>,
+    /// Estimated distinct clients over all the days
+    unique_clients: u64,
+    /// The most viewed fortunes of all time, most viewed first
+    most_served: Vec<Served>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DayUsage {
+    /// `YYYY-MM-DD`
+    date: String,
+    /// Requests by endpoint name, as DISABLE_ENDPOINTS names them
+    requests: BTreeMap<String, u64>,
+    /// New fortunes stored
+    submissions: u64,
+    /// Estimated distinct clients (HyperLogLog, about 1% off)
+    unique_clients: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Served {
+    id: String,
+    views: u64,
+    /// Null when this replica does not hold the fortune in memory
+    message: Option<String>,
+}
+
+// Usage over the last `days` days including today, after flushing this
+// replica's own counts; other replicas' reach Redis within a flush interval
+pub async fn report(redis: &RedisStore, store: &FortuneStore, days: u64, top: usize) -> RedisResult<Analytics> {
+    flush(redis).await?;
+    let last = today();
+    let range: Vec<u64> = (last + 1 - days..=last).collect();
+    let mut conn = redis.connection()?;
+
+    let mut pipe = redis::pipe();
+    for day in &range {
+        pipe.cmd("HGETALL").arg(key("requests", *day));
+        pipe.cmd("GET").arg(key("submissions", *day));
+        pipe.cmd("PFCOUNT").arg(key("clients", *day));
+    }
+    let answers: Vec<redis::Value> = pipe.query(&mut conn)?;
+    let mut answers = answers.into_iter();
+    let mut usage = Vec::with_capacity(range.len());
+    for day in &range {
+        let mut next = || answers.next().unwrap_or(redis::Value::Nil);
+        usage.push(DayUsage {
+            date: date(*day),
+            requests: redis::from_redis_value(&next())?,
+            submissions: redis::from_redis_value::<Option<u64>>(&next())?.unwrap_or_default(),
+            unique_clients: redis::from_redis_value(&next())?,
+        });
+    }
+    let client_keys: Vec<String> = range.iter().map(|day| key("clients", *day)).collect();
+    let unique_clients: u64 = redis::cmd("PFCOUNT").arg(client_keys).query(&mut conn)?;
+
+    let mut views: Vec<(String, u64)> = redis.views().await?.into_iter().collect();
+    views.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    views.truncate(top);
+    let fortunes = store.read().await;
+    let most_served = views
+        .into_iter()
+        .map(|(id, views)| Served {
+            message: fortunes.get(&id).map(|f| f.message.clone()),
+            id,
+            views,
+        })
+        .collect();
+
+    Ok(Analytics { days: usage, unique_clients, most_served })
+}
+
+pub fn metrics() -> String {
+    format!("backend_analytics_flush_failures_total {}\n", FLUSH_FAILURES.load(Ordering::Relaxed))
+}
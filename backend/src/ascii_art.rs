@@ -0,0 +1,120 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use utoipa::ToSchema;
+
+// Plain-text renderings of a fortune for terminals, in the spirit of the Unix
+// `fortune | cowsay`, so `curl -s host/fortunes/random?format=cowsay` can go
+// in a shell rc file
+
+// Text columns per line inside the figure, as cowsay wraps by default
+const WIDTH: usize = 40;
+
+const COW: &str = r"        \   ^__^
+         \  (oo)\_______
+            (__)\       )\/\
+                ||----w |
+                ||     ||
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[schema(rename_all = "lowercase")]
+pub enum Format {
+    Box,
+    Cowsay,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "box" => Some(Format::Box),
+            "cowsay" => Some(Format::Cowsay),
+            _ => None,
+        }
+    }
+}
+
+// Terminal columns, with wide characters such as CJK and emoji taking two
+fn columns(text: &str) -> usize {
+    text.width()
+}
+
+// Greedy word wrap that keeps the message's own line breaks; words longer
+// than a line are split between grapheme clusters
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word.to_string();
+            if columns(&word) > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let mut rest = String::new();
+                for grapheme in word.graphemes(true) {
+                    if !rest.is_empty() && columns(&rest) + columns(grapheme) > width {
+                        lines.push(std::mem::take(&mut rest));
+                    }
+                    rest.push_str(grapheme);
+                }
+                word = rest;
+            }
+            if !line.is_empty() && columns(&line) + 1 + columns(&word) > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+fn pad(line: &str, width: usize) -> String {
+    format!("{}{}", line, " ".repeat(width.saturating_sub(columns(line))))
+}
+
+// The message wrapped to WIDTH columns, with the author right-aligned below
+// it the way fortune files credit quotes
+pub fn render(format: Format, message: &str, author: Option<&str>) -> String {
+    let mut lines = wrap(message, WIDTH);
+    if let Some(author) = author {
+        let credit = wrap(&format!("-- {}", author), WIDTH);
+        let width = lines.iter().map(|line| columns(line)).max().unwrap_or(0);
+        lines.extend(credit.iter().map(|line| format!("{}{}", " ".repeat(width.saturating_sub(columns(line))), line)));
+    }
+    let width = lines.iter().map(|line| columns(line)).max().unwrap_or(0);
+
+    let mut out = String::new();
+    match format {
+        Format::Box => {
+            let border = format!("+{}+\n", "-".repeat(width + 2));
+            out.push_str(&border);
+            for line in &lines {
+                out.push_str(&format!("| {} |\n", pad(line, width)));
+            }
+            out.push_str(&border);
+        }
+        Format::Cowsay => {
+            out.push_str(&format!(" {}\n", "_".repeat(width + 2)));
+            let last = lines.len().saturating_sub(1);
+            for (i, line) in lines.iter().enumerate() {
+                let (open, close) = match i {
+                    _ if last == 0 => ('<', '>'),
+                    0 => ('/', '\\'),
+                    i if i == last => ('\\', '/'),
+                    _ => ('|', '|'),
+                };
+                out.push_str(&format!("{} {} {}\n", open, pad(line, width), close));
+            }
+            out.push_str(&format!(" {}\n", "-".repeat(width + 2)));
+            out.push_str(COW);
+        }
+    }
+    out
+}
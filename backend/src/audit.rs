@@ -0,0 +1,166 @@
+use crate::admin::key_matches;
+use crate::client_ip::{self, TrustedProxies};
+use crate::config::Config;
+use crate::quotas::Quotas;
+use fortune_common::error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+use warp::Filter;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    // Unix timestamp in seconds
+    pub timestamp: u64,
+    // `key:<name>` for API_KEYS holders, `key:<hash>` for ADMIN_API_KEY,
+    // `ip:<addr>` otherwise, `system` for
+    // submissions deleted because they were not verified in time
+    pub actor: String,
+    // create, update, delete, restore, approve, reject or verify
+    pub op: String,
+    pub id: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+struct AuditLog {
+    path: PathBuf,
+    // Serializes appends so concurrent entries never interleave
+    lock: Mutex<()>,
+}
+
+static AUDIT_LOG: OnceLock<Option<AuditLog>> = OnceLock::new();
+
+pub fn init(path: Option<PathBuf>) {
+    let log = path.map(|path| AuditLog {
+        path,
+        lock: Mutex::new(()),
+    });
+    AUDIT_LOG.set(log).ok();
+}
+
+fn get_log() -> Option<&'static AuditLog> {
+    AUDIT_LOG.get().and_then(|log| log.as_ref())
+}
+
+pub fn is_enabled() -> bool {
+    get_log().is_some()
+}
+
+// What is needed to tell who made a request
+#[derive(Debug, Clone, Default)]
+pub struct Actors {
+    proxies: TrustedProxies,
+    admin_key: Option<String>,
+    quotas: Quotas,
+}
+
+impl Actors {
+    pub fn from_config(config: &Config) -> Self {
+        Actors {
+            proxies: config.trusted_proxies(),
+            admin_key: config.admin_api_key.clone(),
+            quotas: Quotas::from_config(config),
+        }
+    }
+
+    // Identifies who made a request without ever logging the API key itself.
+    // Only keys this server knows count; anything else falls back to the
+    // client address so a caller cannot pick its own name.
+    fn identify(&self, api_key: Option<&str>, client: Option<IpAddr>) -> String {
+        if let Some(given) = api_key {
+            if let Some(name) = self.quotas.name_of(given) {
+                return format!("key:{}", name);
+            }
+            if self.admin_key.as_deref().is_some_and(|expected| key_matches(expected, given)) {
+                return format!("key:{}", &hex::encode(Sha256::digest(given.as_bytes()))[..8]);
+            }
+        }
+        match client {
+            Some(ip) => format!("ip:{}", ip),
+            None => "unknown".to_string(),
+        }
+    }
+}
+
+// Callers without a known API key are recorded by their client address as
+// resolved through TRUSTED_PROXIES
+pub fn actor(actors: Actors) -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::header::headers_cloned()
+        .and(client_ip::filter(actors.proxies.clone()))
+        .map(move |headers: warp::http::HeaderMap, client: Option<IpAddr>| {
+            let api_key = headers.get("x-api-key").and_then(|value| value.to_str().ok());
+            actors.identify(api_key, client)
+        })
+}
+
+pub fn grpc_actor(remote: Option<SocketAddr>) -> String {
+    match remote {
+        Some(addr) => format!("grpc:{}", addr.ip()),
+        None => "grpc:unknown".to_string(),
+    }
+}
+
+// Appends one JSON line per mutation; failures are logged but never block the write
+pub async fn record(actor: &str, op: &str, id: &str, before: Option<&str>, after: Option<&str>) {
+    let Some(log) = get_log() else {
+        return;
+    };
+    let entry = AuditEntry {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        actor: actor.to_string(),
+        op: op.to_string(),
+        id: id.to_string(),
+        before: before.map(str::to_string),
+        after: after.map(str::to_string),
+    };
+    let mut line = match serde_json::to_string(&entry) {
+        Ok(line) => line,
+        Err(e) => {
+            error!("failed to encode audit entry: {}", e);
+            return;
+        }
+    };
+    line.push('\n');
+
+    let _guard = log.lock.lock().await;
+    let result = async {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log.path)
+            .await?;
+        file.write_all(line.as_bytes()).await
+    }
+    .await;
+    if let Err(e) = result {
+        error!("failed to append audit entry to {}: {}", log.path.display(), e);
+    }
+}
+
+// Entries at or after `since` (Unix seconds), oldest first
+pub async fn since(since: u64) -> Result<Vec<AuditEntry>, String> {
+    let Some(log) = get_log() else {
+        return Ok(Vec::new());
+    };
+    let contents = match tokio::fs::read_to_string(&log.path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("failed to read {}: {}", log.path.display(), e)),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<AuditEntry>(line).ok())
+        .filter(|entry| entry.timestamp >= since)
+        .collect())
+}
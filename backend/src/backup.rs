@@ -0,0 +1,344 @@
+use crate::admin::AdminAccess;
+use crate::config::Config;
+use crate::fortunes::now_secs;
+use crate::{admin, audit, snapshot, store, with_store, writable, Fortune, FortuneStore};
+use fortune_common::{error, info};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+use warp::{Filter, Rejection, Reply};
+
+// Backups are written to and read from an S3-compatible bucket (AWS S3,
+// MinIO, R2, ...) with path-style URLs and Signature Version 4. Objects hold
+// the same JSON array as DATA_FILE, so one can also seed a fresh instance.
+pub struct Bucket {
+    http: reqwest::Client,
+    // e.g. `https://s3.eu-west-1.amazonaws.com` or `http://minio:9000`
+    endpoint: reqwest::Url,
+    name: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    // Prepended to the key of every new backup
+    prefix: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Backup {
+    key: String,
+    fortunes: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Restored {
+    // Fortunes loaded from the backup
+    fortunes: usize,
+    // Fortunes that were not in the backup and were deleted
+    removed: usize,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RestoreParams {
+    /// Object key of the backup, as returned by `POST /admin/backup`
+    key: String,
+}
+
+// Year, month and day of the days since 1970-01-01, after Howard Hinnant's
+// `civil_from_days`
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// `YYYYMMDD` and `YYYYMMDDTHHMMSSZ` for a Unix timestamp, as SigV4 wants them
+pub fn amz_date(secs: u64) -> (String, String) {
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let time = secs % 86_400;
+    let datetime = format!("{}T{:02}{:02}{:02}Z", date, time / 3600, time / 60 % 60, time % 60);
+    (date, datetime)
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// Percent-encodes everything but unreserved characters and `/`
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// The SigV4 credentials for signing requests to S3
+pub struct Signer<'a> {
+    pub access_key: &'a str,
+    pub secret_key: &'a str,
+    pub region: &'a str,
+}
+
+impl Signer<'_> {
+    // The Authorization header for a request that signs `host`,
+    // `x-amz-content-sha256` and `x-amz-date`. `path` is already encoded and
+    // `query` is the canonical query string, empty when there is none.
+    pub fn authorization(&self, method: &str, host: &str, path: &str, query: &str, payload_hash: &str, amz_date: &str) -> String {
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query, host, payload_hash, amz_date, signed_headers, payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+
+        let key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), date);
+        let key = hmac(&key, self.region);
+        let key = hmac(&key, "s3");
+        let key = hmac(&key, "aws4_request");
+        let signature = hex::encode(hmac(&key, &string_to_sign));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{},SignedHeaders={},Signature={}",
+            self.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+impl Bucket {
+    pub fn from_config(config: &Config) -> Option<Arc<Bucket>> {
+        let endpoint = reqwest::Url::parse(config.backup_s3_endpoint.as_deref()?).ok()?;
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .expect("failed to build HTTP client");
+        Some(Arc::new(Bucket {
+            http,
+            endpoint,
+            name: config.backup_s3_bucket.clone()?,
+            region: config.backup_s3_region.clone(),
+            access_key: config.backup_s3_access_key.clone()?,
+            secret_key: config.backup_s3_secret_key.clone()?,
+            prefix: config.backup_s3_prefix.clone(),
+        }))
+    }
+
+    // Sends a signed request for `key`; errors are the reason as text
+    async fn send(&self, method: reqwest::Method, key: &str, body: Vec<u8>) -> Result<reqwest::Response, String> {
+        let path = format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            encode_path(&self.name),
+            encode_path(key)
+        );
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{}", self.endpoint.host_str().unwrap_or_default(), port),
+            None => self.endpoint.host_str().unwrap_or_default().to_string(),
+        };
+        let payload_hash = sha256_hex(&body);
+        let (_, amz_date) = amz_date(now_secs());
+        let signer = Signer {
+            access_key: &self.access_key,
+            secret_key: &self.secret_key,
+            region: &self.region,
+        };
+        let authorization = signer.authorization(method.as_str(), &host, &path, "", &payload_hash, &amz_date);
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        self.http
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    // Stores `body` as `<prefix>fortunes-<timestamp>.json` and returns the key
+    pub async fn put(&self, body: Vec<u8>) -> Result<String, String> {
+        let (_, stamp) = amz_date(now_secs());
+        let key = format!("{}fortunes-{}.json", self.prefix, stamp);
+        let response = self.send(reqwest::Method::PUT, &key, body).await?;
+        if !response.status().is_success() {
+            return Err(format!("bucket answered {}", response.status()));
+        }
+        Ok(key)
+    }
+
+    // None when there is no object with this key
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let response = self.send(reqwest::Method::GET, key, Vec::new()).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("bucket answered {}", response.status()));
+        }
+        let body = response.bytes().await.map_err(|e| e.to_string())?;
+        Ok(Some(body.to_vec()))
+    }
+}
+
+fn not_configured() -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&"backup storage is not configured"),
+        warp::http::StatusCode::SERVICE_UNAVAILABLE,
+    )
+    .into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/backup",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key")),
+    responses(
+        (status = 201, description = "The key of the new backup and how many fortunes it holds", body = Backup),
+        (status = 401, description = "Missing or wrong API key", body = String),
+        (status = 502, description = "The bucket refused the upload or could not be reached", body = String),
+        (status = 503, description = "BACKUP_S3_ENDPOINT is not set", body = String),
+    )
+)]
+async fn backup_handler(bucket: Option<Arc<Bucket>>, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    let Some(bucket) = bucket else {
+        return Ok(not_configured());
+    };
+    let (fortunes, body) = match snapshot::to_json(&store).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            error!("failed to encode backup: {}", e);
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"failed to encode backup"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response());
+        }
+    };
+    match bucket.put(body).await {
+        Ok(key) => {
+            info!("admin backup: wrote {} fortunes to {}", fortunes, key);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&Backup { key, fortunes }),
+                warp::http::StatusCode::CREATED,
+            )
+            .into_response())
+        }
+        Err(e) => {
+            error!("admin backup failed: {}", e);
+            Ok(warp::reply::with_status(warp::reply::json(&e), warp::http::StatusCode::BAD_GATEWAY).into_response())
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/restore",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key"), RestoreParams),
+    responses(
+        (status = 200, description = "The store now holds exactly the fortunes of the backup", body = Restored),
+        (status = 401, description = "Missing or wrong API key", body = String),
+        (status = 403, description = "The backend is read-only", body = String),
+        (status = 404, description = "No backup with this key", body = String),
+        (status = 422, description = "The object is not a list of fortunes", body = String),
+        (status = 502, description = "The bucket could not be read", body = String),
+        (status = 503, description = "BACKUP_S3_ENDPOINT is not set", body = String),
+    )
+)]
+async fn restore_handler(
+    params: RestoreParams,
+    actor: String,
+    bucket: Option<Arc<Bucket>>,
+    store: FortuneStore,
+) -> Result<impl Reply, Infallible> {
+    let Some(bucket) = bucket else {
+        return Ok(not_configured());
+    };
+    let body = match bucket.get(&params.key).await {
+        Ok(Some(body)) => body,
+        Ok(None) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"backup not found"),
+                warp::http::StatusCode::NOT_FOUND,
+            )
+            .into_response());
+        }
+        Err(e) => {
+            error!("admin restore of {} failed: {}", params.key, e);
+            return Ok(warp::reply::with_status(warp::reply::json(&e), warp::http::StatusCode::BAD_GATEWAY).into_response());
+        }
+    };
+    let fortunes: Vec<Fortune> = match serde_json::from_slice(&body) {
+        Ok(fortunes) => fortunes,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&format!("not a fortune backup: {}", e)),
+                warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+            )
+            .into_response());
+        }
+    };
+    let loaded = fortunes.len();
+    let removed = store::replace_all(&store, fortunes, &actor).await;
+    info!("admin restore: loaded {} fortunes from {}, removed {}", loaded, params.key, removed);
+    Ok(warp::reply::json(&Restored { fortunes: loaded, removed }).into_response())
+}
+
+// POST /admin/backup and /admin/restore, for admins only.
+// Restoring is refused while `read_only` is set.
+pub fn routes(
+    store: FortuneStore,
+    access: AdminAccess,
+    read_only: bool,
+    actors: audit::Actors,
+    bucket: Option<Arc<Bucket>>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let with_bucket = warp::any().map(move || bucket.clone());
+
+    let backup = warp::path!("admin" / "backup")
+        .and(warp::post())
+        .and(admin::authorized(access.clone()))
+        .and(with_bucket.clone())
+        .and(with_store(store.clone()))
+        .and_then(backup_handler);
+
+    let restore = warp::path!("admin" / "restore")
+        .and(warp::post())
+        .and(admin::authorized(access))
+        .and(writable(read_only))
+        .and(warp::query::<RestoreParams>())
+        .and(audit::actor(actors))
+        .and(with_bucket)
+        .and(with_store(store))
+        .and_then(restore_handler);
+
+    backup.or(restore)
+}
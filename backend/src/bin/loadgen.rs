@@ -0,0 +1,154 @@
+// Hammers a running backend with concurrent GET requests and reports
+// throughput and latency percentiles:
+//
+//     cargo run --release --bin loadgen -- http://localhost:9000 \
+//         --concurrency 64 --duration 30 --path /fortunes/random --path /fortunes
+//
+// Each worker cycles through the paths in order until the duration is up.
+// With --http2 all requests share HTTP/2 connections (prior knowledge, no
+// TLS) instead of one HTTP/1.1 connection per worker.
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+struct Options {
+    url: String,
+    concurrency: usize,
+    duration: Duration,
+    paths: Vec<String>,
+    http2: bool,
+}
+
+const USAGE: &str = "usage: loadgen [URL] [--concurrency N] [--duration SECS] [--path PATH]... [--http2]";
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        url: "http://localhost:9000".to_string(),
+        concurrency: 16,
+        duration: Duration::from_secs(10),
+        paths: Vec::new(),
+        http2: false,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--concurrency" | "-c" => {
+                options.concurrency = value(&arg)?
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or("--concurrency must be a positive number")?;
+            }
+            "--duration" | "-d" => {
+                let secs: u64 = value(&arg)?.parse().map_err(|_| "--duration must be a number of seconds")?;
+                options.duration = Duration::from_secs(secs);
+            }
+            "--path" | "-p" => {
+                let path = value(&arg)?;
+                if !path.starts_with('/') {
+                    return Err(format!("path '{}' does not start with /", path));
+                }
+                options.paths.push(path);
+            }
+            "--http2" => options.http2 = true,
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            url if !url.starts_with('-') => options.url = url.trim_end_matches('/').to_string(),
+            other => return Err(format!("unknown option {}\n{}", other, USAGE)),
+        }
+    }
+    if options.paths.is_empty() {
+        options.paths.push("/fortunes/random".to_string());
+    }
+    Ok(options)
+}
+
+#[derive(Default)]
+struct Tally {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+async fn worker(http: reqwest::Client, urls: Vec<String>, deadline: Instant) -> Tally {
+    let mut tally = Tally::default();
+    for url in urls.iter().cycle() {
+        if Instant::now() >= deadline {
+            break;
+        }
+        let started = Instant::now();
+        match http.get(url).send().await {
+            Ok(res) if res.status().is_success() => {
+                // Read the body so the timing covers the whole response
+                match res.bytes().await {
+                    Ok(_) => tally.latencies.push(started.elapsed()),
+                    Err(_) => tally.errors += 1,
+                }
+            }
+            _ => tally.errors += 1,
+        }
+    }
+    tally
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[rank]
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut builder = reqwest::Client::builder()
+        .pool_max_idle_per_host(options.concurrency)
+        .timeout(Duration::from_secs(10));
+    if options.http2 {
+        builder = builder.http2_prior_knowledge();
+    }
+    let http = builder.build().expect("failed to build HTTP client");
+    let urls: Vec<String> = options.paths.iter().map(|path| format!("{}{}", options.url, path)).collect();
+
+    println!(
+        "loadgen: {} workers for {}s against {} over {} ({})",
+        options.concurrency,
+        options.duration.as_secs(),
+        options.url,
+        if options.http2 { "HTTP/2" } else { "HTTP/1.1" },
+        options.paths.join(", ")
+    );
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let workers: Vec<_> = (0..options.concurrency)
+        .map(|_| tokio::spawn(worker(http.clone(), urls.clone(), deadline)))
+        .collect();
+
+    let mut total = Tally::default();
+    for worker in workers {
+        let tally = worker.await.expect("worker panicked");
+        total.latencies.extend(tally.latencies);
+        total.errors += tally.errors;
+    }
+    let elapsed = started.elapsed();
+    total.latencies.sort();
+
+    let ok = total.latencies.len();
+    println!("requests: {} ok, {} failed", ok, total.errors);
+    println!("throughput: {:.1} req/s", ok as f64 / elapsed.as_secs_f64());
+    println!(
+        "latency: p50={:?} p90={:?} p99={:?} max={:?}",
+        percentile(&total.latencies, 0.50),
+        percentile(&total.latencies, 0.90),
+        percentile(&total.latencies, 0.99),
+        total.latencies.last().copied().unwrap_or_default(),
+    );
+    if ok == 0 {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,100 @@
+use crate::config::Config;
+use fortune_common::warn;
+use rand::Rng;
+use redis::RedisResult;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use warp::http::StatusCode;
+use warp::path::FullPath;
+use warp::{Filter, Reply};
+
+// Fault injection for resilience testing, off unless CHAOS_* settings are
+// given: requests are delayed or failed with 500 before reaching a handler,
+// and Redis connections fail as timeouts, each for a percentage of calls.
+// /healthz and /metrics are spared, so an orchestrator does not restart the
+// replica mid-test and the injected faults can be watched.
+
+static REDIS_TIMEOUT_PERCENT: AtomicU32 = AtomicU32::new(0);
+static DELAYED: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+static REDIS_TIMEOUTS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    // Delays are picked uniformly up to this
+    pub latency: Duration,
+    pub latency_percent: u32,
+    pub error_percent: u32,
+}
+
+pub fn init(config: &Config) {
+    REDIS_TIMEOUT_PERCENT.store(config.chaos_redis_timeout_percent, Ordering::Relaxed);
+    let faults = config.chaos();
+    if !faults.latency.is_zero() || faults.error_percent > 0 || config.chaos_redis_timeout_percent > 0 {
+        warn!(
+            "CHAOS: injecting faults: up to {}ms latency on {}% of requests, 500s on {}%, Redis timeouts on {}% of connections",
+            faults.latency.as_millis(),
+            faults.latency_percent,
+            faults.error_percent,
+            config.chaos_redis_timeout_percent,
+        );
+    }
+}
+
+fn roll(percent: u32) -> bool {
+    percent > 0 && rand::thread_rng().gen_range(0..100) < percent
+}
+
+// Called before every Redis connection; fails CHAOS_REDIS_TIMEOUT_PERCENT of
+// them the way an unreachable server would
+pub fn redis_fault() -> RedisResult<()> {
+    if !roll(REDIS_TIMEOUT_PERCENT.load(Ordering::Relaxed)) {
+        return Ok(());
+    }
+    REDIS_TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+    Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "chaos: simulated Redis timeout").into())
+}
+
+fn spared(path: &str) -> bool {
+    path == "/healthz" || path == "/metrics"
+}
+
+pub fn wrap<F, R>(routes: F, faults: Faults) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let faults = Arc::new(faults);
+    // Answers in place of the routes when a fault strikes, and steps aside otherwise
+    let injected = warp::path::full().and_then(move |path: FullPath| {
+        let faults = faults.clone();
+        async move {
+            if spared(path.as_str()) {
+                return Err(warp::reject::not_found());
+            }
+            if !faults.latency.is_zero() && roll(faults.latency_percent) {
+                DELAYED.fetch_add(1, Ordering::Relaxed);
+                let delay = rand::thread_rng().gen_range(0..=faults.latency.as_millis() as u64);
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            }
+            if roll(faults.error_percent) {
+                FAILED.fetch_add(1, Ordering::Relaxed);
+                let reply = warp::reply::json(&"chaos: injected failure");
+                return Ok(warp::reply::with_status(reply, StatusCode::INTERNAL_SERVER_ERROR).into_response());
+            }
+            Err(warp::reject::not_found())
+        }
+    });
+    injected.or(routes.map(Reply::into_response)).unify()
+}
+
+pub fn metrics() -> String {
+    format!(
+        "backend_chaos_faults_total{{fault=\"latency\"}} {}\nbackend_chaos_faults_total{{fault=\"error\"}} {}\nbackend_chaos_faults_total{{fault=\"redis_timeout\"}} {}\n",
+        DELAYED.load(Ordering::Relaxed),
+        FAILED.load(Ordering::Relaxed),
+        REDIS_TIMEOUTS.load(Ordering::Relaxed),
+    )
+}
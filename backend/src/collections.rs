@@ -0,0 +1,148 @@
+use crate::admin::{key_matches, Unauthorized};
+use crate::config::Config;
+use crate::fortunes::Fortunes;
+use crate::redis_client::{self, RedisStore};
+use crate::shared::Shared;
+use crate::{views, FortuneStore};
+use std::sync::Arc;
+use warp::reject::Reject;
+use warp::{Filter, Rejection};
+
+// Names /fortunes requests with the collection they address
+pub const HEADER: &str = "x-collection";
+
+// The collection requests without a prefix or header go to
+pub const DEFAULT: &str = "default";
+
+#[derive(Debug)]
+pub struct UnknownCollection;
+
+impl Reject for UnknownCollection {}
+
+// A set of fortunes with its own store and, when named, its own Redis hash
+// and an optional API key that writes have to carry
+#[derive(Clone)]
+pub struct Collection {
+    pub name: String,
+    pub api_key: Option<String>,
+    pub store: FortuneStore,
+}
+
+// The named collections from COLLECTIONS; the default collection is the
+// store `routes` is given
+#[derive(Clone, Default)]
+pub struct Collections(Arc<Vec<Collection>>);
+
+// Comma-separated `name` or `name:api-key` entries, e.g. `team-a:s3cret,team-b`
+pub fn parse(list: &str) -> Result<Vec<(String, Option<String>)>, String> {
+    let mut parsed: Vec<(String, Option<String>)> = Vec::new();
+    for entry in list.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (name, api_key) = match entry.split_once(':') {
+            Some((name, key)) if !key.is_empty() => (name, Some(key.to_string())),
+            Some((name, _)) => (name, None),
+            None => (entry, None),
+        };
+        let valid = !name.is_empty()
+            && name.len() <= 64
+            && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_');
+        if !valid {
+            return Err(format!("invalid collection name '{}', use up to 64 of a-z, 0-9, - and _", name));
+        }
+        if name == DEFAULT {
+            return Err(format!("'{}' is the collection used without a name", DEFAULT));
+        }
+        if parsed.iter().any(|(known, _)| known == name) {
+            return Err(format!("collection '{}' is listed twice", name));
+        }
+        parsed.push((name.to_string(), api_key));
+    }
+    Ok(parsed)
+}
+
+impl Collections {
+    // Each named collection starts empty; `load` fills them from Redis
+    pub fn from_config(config: &Config) -> Self {
+        let parsed = config
+            .collections
+            .as_deref()
+            .and_then(|list| parse(list).ok())
+            .unwrap_or_default();
+        let collections = parsed
+            .into_iter()
+            .map(|(name, api_key)| {
+                let mut fortunes = Fortunes::named(&name);
+                fortunes.set_capacity(config.cache_capacity());
+                Collection {
+                    store: Arc::new(Shared::new(fortunes)),
+                    name,
+                    api_key,
+                }
+            })
+            .collect();
+        Collections(Arc::new(collections))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Collection> {
+        self.0.iter().find(|collection| collection.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Collection> {
+        self.0.iter()
+    }
+
+    // Loads each collection from its own hash, and keeps it in step with
+    // other replicas when REDIS_SYNC_INTERVAL_SECS is set
+    pub async fn load(&self, redis: &RedisStore, config: &Config) {
+        for collection in self.iter() {
+            let redis = redis.for_collection(&collection.name);
+            collection.store.write().await.set_storage(Some(Arc::new(redis.clone())));
+            redis.load_into(collection.store.clone()).await;
+            redis.load_views_into(&collection.store).await;
+            if config.soft_delete {
+                redis.load_deleted_into(&collection.store).await;
+            }
+            if let Some(interval) = config.views_flush() {
+                views::spawn_flush(redis.clone(), collection.store.clone(), interval);
+            }
+            if let Some(interval) = config.redis_sync_interval() {
+                redis_client::spawn_sync(redis, collection.store.clone(), interval);
+            }
+        }
+    }
+}
+
+// The collection a fortunes request addresses, taking the path up to and
+// including `fortunes`: /collections/{name}/fortunes, or /fortunes with an
+// optional X-Collection header. Unknown names are rejected with
+// UnknownCollection.
+pub fn scope(default: FortuneStore, collections: Collections) -> impl Filter<Extract = (Collection,), Error = Rejection> + Clone {
+    let default = Collection {
+        name: DEFAULT.to_string(),
+        api_key: None,
+        store: default,
+    };
+    let find = move |name: Option<String>| {
+        let found = match name.as_deref() {
+            None | Some(DEFAULT) => Some(default.clone()),
+            Some(name) => collections.get(name).cloned(),
+        };
+        async move { found.ok_or_else(|| warp::reject::custom(UnknownCollection)) }
+    };
+
+    let prefixed = warp::path("collections")
+        .and(warp::path::param::<String>())
+        .and(warp::path("fortunes"))
+        .map(Some);
+    let plain = warp::path("fortunes").and(warp::header::optional::<String>(HEADER));
+    prefixed.or(plain).unify().and_then(find)
+}
+
+// Lets a write through to its collection's store when the collection has no
+// API key or the request carries it in X-API-Key
+pub async fn authorize(collection: Collection, given: Option<String>) -> Result<FortuneStore, Rejection> {
+    match (&collection.api_key, given) {
+        (None, _) => Ok(collection.store),
+        (Some(expected), Some(given)) if key_matches(expected, &given) => Ok(collection.store),
+        (Some(_), _) => Err(warp::reject::custom(Unauthorized)),
+    }
+}
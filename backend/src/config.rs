@@ -0,0 +1,920 @@
+use crate::access_log::{self, AccessLog};
+use crate::chaos;
+use crate::client_ip::TrustedProxies;
+use crate::collections;
+use crate::discord;
+use crate::endpoints;
+use crate::jwt;
+use crate::latency;
+use crate::quotas;
+use crate::recorder::Recorder;
+use crate::redis_client;
+use crate::response_headers;
+use crate::server;
+use crate::content_filter::FilterMode;
+use fortune_common::{env_file, log};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use warp::http::HeaderMap;
+
+// All settings are read from the environment once at startup. Field names map
+// to upper-cased env vars, e.g. `backend_port` is read from BACKEND_PORT.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default = "default_bind_addr")]
+    pub bind_addr: IpAddr,
+    #[serde(default = "default_backend_port")]
+    pub backend_port: u16,
+    #[serde(default = "default_backend_tls_port")]
+    pub backend_tls_port: u16,
+    #[serde(default = "default_grpc_port")]
+    pub grpc_port: u16,
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    #[serde(default)]
+    pub tls_only: bool,
+    pub redis_dns: Option<String>,
+    #[serde(default = "default_redis_port")]
+    pub redis_port: u16,
+    #[serde(default = "default_redis_connect_attempts")]
+    pub redis_connect_attempts: u32,
+    #[serde(default = "default_redis_retry_delay_secs")]
+    pub redis_retry_delay_secs: u64,
+    #[serde(default = "default_redis_retry_max_delay_secs")]
+    pub redis_retry_max_delay_secs: u64,
+    // Keep trying in the background when Redis is down at startup
+    #[serde(default = "default_redis_reconnect")]
+    pub redis_reconnect: bool,
+    #[serde(default = "default_redis_write_queue_size")]
+    pub redis_write_queue_size: usize,
+    #[serde(default = "default_redis_sync_interval_secs")]
+    pub redis_sync_interval_secs: u64,
+    // How often view counts are added to Redis in one batch; 0 sends every
+    // view on its own
+    #[serde(default = "default_views_flush_ms")]
+    pub views_flush_ms: u64,
+    // Hashes the fortunes are spread over; 1 keeps the single `fortunes` hash
+    #[serde(default = "default_redis_shards")]
+    pub redis_shards: u32,
+    // Most fortunes kept in memory per collection, least recently used evicted
+    // first; 0 keeps them all
+    #[serde(default)]
+    pub max_cached_fortunes: usize,
+    // How long an id found in neither memory nor Redis is answered 404 without
+    // asking Redis again; 0 turns this off
+    #[serde(default = "default_negative_cache_ttl_ms")]
+    pub negative_cache_ttl_ms: u64,
+    // How often request counts for /admin/analytics are added to Redis; 0 turns
+    // analytics off
+    #[serde(default = "default_analytics_flush_secs")]
+    pub analytics_flush_secs: u64,
+    // Days the analytics counters are kept in Redis
+    #[serde(default = "default_analytics_retention_days")]
+    pub analytics_retention_days: u64,
+    // Fault injection for resilience tests (see chaos.rs); all off by default
+    #[serde(default)]
+    pub chaos_latency_ms: u64,
+    #[serde(default = "default_chaos_latency_percent")]
+    pub chaos_latency_percent: u32,
+    #[serde(default)]
+    pub chaos_error_percent: u32,
+    #[serde(default)]
+    pub chaos_redis_timeout_percent: u32,
+    // Requests kept for GET /admin/recent-requests (see recorder.rs); 0 turns
+    // the flight recorder off
+    #[serde(default)]
+    pub flight_recorder_size: usize,
+    // Bytes of each request and response body kept by the flight recorder
+    #[serde(default = "default_flight_recorder_body_bytes")]
+    pub flight_recorder_body_bytes: usize,
+    // Fortunes submitted without the admin key stay hidden until their
+    // submitter follows a one-time link (see verification.rs)
+    #[serde(default)]
+    pub submission_verification: bool,
+    // How long the link works; unverified fortunes are deleted after it
+    #[serde(default = "default_verification_ttl_secs")]
+    pub verification_ttl_secs: u64,
+    // HTTP mail relay the link is posted to, as {"to", "subject", "text"};
+    // without it the token is returned to the submitter
+    pub verification_mail_url: Option<String>,
+    // Public URL of the API the mailed link starts with
+    pub verification_link_base: Option<String>,
+    // Run the daily Discord post and trash removal from Redis on one replica only
+    #[serde(default = "default_leader_election")]
+    pub leader_election: bool,
+    #[serde(default = "default_leader_lease_secs")]
+    pub leader_lease_secs: u64,
+    // Name of this replica in the leader lease; the host name when unset
+    pub instance_id: Option<String>,
+    // Approximate length the Redis event stream is trimmed to; 0 turns it off
+    #[serde(default = "default_event_log_max_len")]
+    pub event_log_max_len: usize,
+    pub database_url: Option<String>,
+    pub admin_api_key: Option<String>,
+    // Per-team keys with daily quotas, `name:key[:requests[:creates]]`
+    // separated by commas; see quotas.rs
+    pub api_keys: Option<String>,
+    // Bearer tokens signed with this secret (HS256) or with a key from this
+    // JWKS (RS256) are accepted, and their roles enforced; see jwt.rs
+    pub jwt_hs256_secret: Option<String>,
+    pub jwt_jwks_url: Option<String>,
+    // Required `iss` and `aud` of tokens, when set
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+    // Dotted path to the claim listing the caller's roles
+    #[serde(default = "default_jwt_roles_claim")]
+    pub jwt_roles_claim: String,
+    // `value:role` pairs mapping claim values to reader, contributor or
+    // admin; without it the values are the role names
+    pub jwt_role_map: Option<String>,
+    // Role of requests without a token: none, reader or contributor
+    #[serde(default = "default_jwt_anonymous_role")]
+    pub jwt_anonymous_role: String,
+    pub audit_log_file: Option<PathBuf>,
+    #[serde(default)]
+    pub moderation: bool,
+    pub content_filter_file: Option<PathBuf>,
+    #[serde(default)]
+    pub content_filter_mode: FilterMode,
+    #[serde(default)]
+    pub soft_delete: bool,
+    // Exit instead of running in memory when Redis is configured but unavailable
+    #[serde(default)]
+    pub strict_startup: bool,
+    // Rejects every request that would change the stored fortunes
+    #[serde(default)]
+    pub read_only: bool,
+    // Comma-separated routes that answer as if they did not exist, e.g. `create,delete`
+    pub disable_endpoints: Option<String>,
+    // Named fortune collections besides the default one, e.g. `team-a:s3cret,team-b`;
+    // the optional key after `:` is required for writes to that collection
+    pub collections: Option<String>,
+    #[serde(default = "default_trash_purge_after_secs")]
+    pub trash_purge_after_secs: u64,
+    #[serde(default = "default_schedule_refresh_secs")]
+    pub schedule_refresh_secs: u64,
+    pub discord_webhook_url: Option<String>,
+    #[serde(default = "default_discord_post_time")]
+    pub discord_post_time: String,
+    #[serde(default = "default_slow_request_ms")]
+    pub slow_request_ms: u64,
+    // Per-route overrides of SLOW_REQUEST_MS, e.g. `/fortunes/batch=2000,/admin=5000`
+    pub slow_request_routes: Option<String>,
+    #[serde(default = "default_session_ttl_secs")]
+    pub session_ttl_secs: u64,
+    #[serde(default = "default_idempotency_ttl_secs")]
+    pub idempotency_ttl_secs: u64,
+    pub data_file: Option<PathBuf>,
+    #[serde(default = "default_data_file_debounce_ms")]
+    pub data_file_debounce_ms: u64,
+    #[serde(default = "default_compression_enabled")]
+    pub compression_enabled: bool,
+    // Connection tuning for the plain HTTP listener (see server.rs)
+    #[serde(default = "default_http2")]
+    pub http2: bool,
+    #[serde(default = "default_http_keep_alive")]
+    pub http_keep_alive: bool,
+    #[serde(default)]
+    pub keep_alive_timeout_secs: u64,
+    #[serde(default)]
+    pub http2_max_concurrent_streams: u32,
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    // Serve plain HTTP on this Unix domain socket instead of the TCP port
+    pub unix_socket: Option<PathBuf>,
+    // Octal permissions for the socket, e.g. `660`
+    pub unix_socket_mode: Option<String>,
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: u64,
+    #[serde(default = "default_max_batch_bytes")]
+    pub max_batch_bytes: u64,
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    // Lines below it are not logged (see fortune_common::log)
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    // Serve the GraphiQL playground on GET /graphql
+    #[serde(default)]
+    pub graphiql: bool,
+    // Comma-separated URLs that receive fortune events, signed with WEBHOOK_SECRET
+    pub webhook_urls: Option<String>,
+    pub webhook_secret: Option<String>,
+    // Shared with the frontend; when set, writes must be signed with it (see
+    // signing.rs) or carry ADMIN_API_KEY
+    pub request_signing_secret: Option<String>,
+    #[serde(default = "default_request_signing_max_age_secs")]
+    pub request_signing_max_age_secs: u64,
+    #[serde(default = "default_webhook_max_attempts")]
+    pub webhook_max_attempts: u32,
+    #[serde(default = "default_webhook_retry_base_ms")]
+    pub webhook_retry_base_ms: u64,
+    // Events that exhausted their attempts are appended here
+    pub webhook_dead_letter_file: Option<PathBuf>,
+    // S3-compatible bucket for POST /admin/backup and /admin/restore
+    pub backup_s3_endpoint: Option<String>,
+    pub backup_s3_bucket: Option<String>,
+    #[serde(default = "default_backup_s3_region")]
+    pub backup_s3_region: String,
+    pub backup_s3_access_key: Option<String>,
+    pub backup_s3_secret_key: Option<String>,
+    #[serde(default = "default_backup_s3_prefix")]
+    pub backup_s3_prefix: String,
+    // Comma-separated CIDRs of reverse proxies whose X-Forwarded-For and
+    // Forwarded headers name the client, e.g. `10.0.0.0/8,192.168.1.7`
+    pub trusted_proxies: Option<String>,
+    // Extra headers for every response as a JSON object, e.g.
+    // `{"X-Frame-Options":"DENY"}`
+    pub response_headers: Option<String>,
+    // Opt-in access log: `common`, `combined` or `json`
+    pub access_log: Option<access_log::Format>,
+    // Written to stdout when unset; rotated once it reaches ACCESS_LOG_MAX_BYTES
+    pub access_log_file: Option<PathBuf>,
+    #[serde(default = "default_access_log_max_bytes")]
+    pub access_log_max_bytes: u64,
+    #[serde(default = "default_access_log_max_files")]
+    pub access_log_max_files: usize,
+}
+
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub addr: SocketAddr,
+    pub only: bool,
+}
+
+fn default_bind_addr() -> IpAddr {
+    IpAddr::from([0, 0, 0, 0])
+}
+
+fn default_backend_port() -> u16 {
+    9000
+}
+
+fn default_backend_tls_port() -> u16 {
+    9443
+}
+
+fn default_grpc_port() -> u16 {
+    50051
+}
+
+fn default_redis_port() -> u16 {
+    6379
+}
+
+fn default_redis_connect_attempts() -> u32 {
+    5
+}
+
+fn default_redis_retry_delay_secs() -> u64 {
+    2
+}
+
+fn default_redis_retry_max_delay_secs() -> u64 {
+    60
+}
+
+fn default_redis_reconnect() -> bool {
+    true
+}
+
+fn default_redis_write_queue_size() -> usize {
+    1000
+}
+
+fn default_redis_sync_interval_secs() -> u64 {
+    30
+}
+
+fn default_views_flush_ms() -> u64 {
+    1000
+}
+
+fn default_redis_shards() -> u32 {
+    1
+}
+
+fn default_negative_cache_ttl_ms() -> u64 {
+    5000
+}
+
+fn default_analytics_flush_secs() -> u64 {
+    10
+}
+
+fn default_analytics_retention_days() -> u64 {
+    90
+}
+
+fn default_chaos_latency_percent() -> u32 {
+    100
+}
+
+fn default_flight_recorder_body_bytes() -> usize {
+    1024
+}
+
+fn default_verification_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_jwt_roles_claim() -> String {
+    "roles".to_string()
+}
+
+fn default_jwt_anonymous_role() -> String {
+    "reader".to_string()
+}
+
+fn default_leader_election() -> bool {
+    true
+}
+
+fn default_leader_lease_secs() -> u64 {
+    15
+}
+
+fn default_event_log_max_len() -> usize {
+    100_000
+}
+
+fn default_data_file_debounce_ms() -> u64 {
+    500
+}
+
+fn default_compression_enabled() -> bool {
+    true
+}
+
+fn default_http2() -> bool {
+    true
+}
+
+fn default_http_keep_alive() -> bool {
+    true
+}
+
+fn default_tcp_nodelay() -> bool {
+    true
+}
+
+fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8).ok().filter(|mode| *mode <= 0o777)
+}
+
+fn default_trash_purge_after_secs() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+fn default_schedule_refresh_secs() -> u64 {
+    60
+}
+
+fn default_discord_post_time() -> String {
+    "09:00".to_string()
+}
+
+fn default_slow_request_ms() -> u64 {
+    500
+}
+
+fn default_session_ttl_secs() -> u64 {
+    30 * 60
+}
+
+fn default_request_signing_max_age_secs() -> u64 {
+    300
+}
+
+fn default_idempotency_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_max_body_bytes() -> u64 {
+    16 * 1024
+}
+
+fn default_max_batch_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    5
+}
+
+fn default_webhook_retry_base_ms() -> u64 {
+    1000
+}
+
+fn default_backup_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_backup_s3_prefix() -> String {
+    "backups/".to_string()
+}
+
+fn default_access_log_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_access_log_max_files() -> usize {
+    5
+}
+
+const REDACTED: &str = "<redacted>";
+
+impl Config {
+    pub fn from_env() -> Result<Config, String> {
+        let config: Config = envy::from_env().map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Settings from command-line `overrides`, then the environment, then
+    // the `KEY=VALUE` lines of `file`
+    pub fn load(file: Option<&Path>, overrides: &[(&str, String)]) -> Result<Config, String> {
+        let mut vars: HashMap<String, String> = HashMap::new();
+        if let Some(file) = file {
+            vars.extend(env_file::read(file)?);
+        }
+        vars.extend(std::env::vars());
+        vars.extend(overrides.iter().map(|(key, value)| (key.to_string(), value.clone())));
+        let config: Config = envy::from_iter(vars).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // A copy safe to print: keys, secrets and URLs that may carry
+    // credentials are replaced, and collection keys dropped from their names
+    pub fn redacted(&self) -> Config {
+        let hide = |value: &Option<String>| value.as_ref().map(|_| REDACTED.to_string());
+        Config {
+            admin_api_key: hide(&self.admin_api_key),
+            api_keys: hide(&self.api_keys),
+            jwt_hs256_secret: hide(&self.jwt_hs256_secret),
+            database_url: hide(&self.database_url),
+            discord_webhook_url: hide(&self.discord_webhook_url),
+            webhook_urls: hide(&self.webhook_urls),
+            webhook_secret: hide(&self.webhook_secret),
+            verification_mail_url: hide(&self.verification_mail_url),
+            request_signing_secret: hide(&self.request_signing_secret),
+            backup_s3_access_key: hide(&self.backup_s3_access_key),
+            backup_s3_secret_key: hide(&self.backup_s3_secret_key),
+            collections: self.collections.as_ref().map(|list| {
+                list.split(',')
+                    .map(|entry| match entry.split_once(':') {
+                        Some((name, _)) => format!("{}:{}", name, REDACTED),
+                        None => entry.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+            ..self.clone()
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (key, port) in [
+            ("BACKEND_PORT", self.backend_port),
+            ("BACKEND_TLS_PORT", self.backend_tls_port),
+            ("GRPC_PORT", self.grpc_port),
+            ("REDIS_PORT", self.redis_port),
+        ] {
+            if port == 0 {
+                return Err(format!("{} must be between 1 and 65535", key));
+            }
+        }
+
+        if self.redis_connect_attempts == 0 {
+            return Err("REDIS_CONNECT_ATTEMPTS must be at least 1".to_string());
+        }
+
+        if self.redis_retry_delay_secs > self.redis_retry_max_delay_secs {
+            return Err("REDIS_RETRY_DELAY_SECS must not exceed REDIS_RETRY_MAX_DELAY_SECS".to_string());
+        }
+
+        if !(1..=1024).contains(&self.redis_shards) {
+            return Err("REDIS_SHARDS must be between 1 and 1024".to_string());
+        }
+
+        if self.max_cached_fortunes > 0 && self.redis_dns.is_none() {
+            return Err("MAX_CACHED_FORTUNES requires REDIS_DNS, which holds the evicted fortunes".to_string());
+        }
+
+        if self.analytics_retention_days == 0 {
+            return Err("ANALYTICS_RETENTION_DAYS must be at least 1".to_string());
+        }
+
+        for (key, percent) in [
+            ("CHAOS_LATENCY_PERCENT", self.chaos_latency_percent),
+            ("CHAOS_ERROR_PERCENT", self.chaos_error_percent),
+            ("CHAOS_REDIS_TIMEOUT_PERCENT", self.chaos_redis_timeout_percent),
+        ] {
+            if percent > 100 {
+                return Err(format!("{} must be between 0 and 100", key));
+            }
+        }
+
+        if self.flight_recorder_size > 10_000 {
+            return Err("FLIGHT_RECORDER_SIZE must be at most 10000".to_string());
+        }
+
+        if self.flight_recorder_size > 0 && !self.has_admin() {
+            return Err("FLIGHT_RECORDER_SIZE requires ADMIN_API_KEY or JWT authentication for GET /admin/recent-requests".to_string());
+        }
+
+        if self.verification_ttl_secs < 60 {
+            return Err("VERIFICATION_TTL_SECS must be at least 60".to_string());
+        }
+
+        for (key, url) in [
+            ("VERIFICATION_MAIL_URL", &self.verification_mail_url),
+            ("VERIFICATION_LINK_BASE", &self.verification_link_base),
+        ] {
+            if let Some(url) = url {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(format!("{} must start with http:// or https://", key));
+                }
+            }
+        }
+
+        if self.verification_mail_url.is_some() && self.verification_link_base.is_none() {
+            return Err("VERIFICATION_MAIL_URL requires VERIFICATION_LINK_BASE for the link it mails".to_string());
+        }
+
+        if self.leader_lease_secs < 3 {
+            return Err("LEADER_LEASE_SECS must be at least 3".to_string());
+        }
+
+        if self.redis_write_queue_size == 0 {
+            return Err("REDIS_WRITE_QUEUE_SIZE must be at least 1".to_string());
+        }
+
+        if self.admin_api_key.as_deref().is_some_and(|key| key.trim().is_empty()) {
+            return Err("ADMIN_API_KEY must not be empty".to_string());
+        }
+
+        if self.jwt_hs256_secret.as_deref().is_some_and(|secret| secret.len() < 32) {
+            return Err("JWT_HS256_SECRET must be at least 32 characters".to_string());
+        }
+
+        if let Some(url) = &self.jwt_jwks_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!("JWT_JWKS_URL '{}' must start with http:// or https://", url));
+            }
+        }
+
+        if let Some(list) = &self.jwt_role_map {
+            jwt::parse_role_map(list)?;
+        }
+        jwt::parse_anonymous_role(&self.jwt_anonymous_role)?;
+        if self.jwt_roles_claim.split('.').any(str::is_empty) {
+            return Err(format!("JWT_ROLES_CLAIM '{}' is not a dotted claim path", self.jwt_roles_claim));
+        }
+
+        if let Some(list) = &self.api_keys {
+            let keys = quotas::parse(list).map_err(|e| format!("API_KEYS: {}", e))?;
+            if let Some(admin) = &self.admin_api_key {
+                if keys.iter().any(|key| key.is(admin)) {
+                    return Err("API_KEYS must not contain ADMIN_API_KEY".to_string());
+                }
+            }
+        }
+
+        if self.schedule_refresh_secs == 0 {
+            return Err("SCHEDULE_REFRESH_SECS must be at least 1".to_string());
+        }
+
+        if self.moderation && !self.has_admin() {
+            return Err("MODERATION requires ADMIN_API_KEY or JWT authentication to approve submissions".to_string());
+        }
+
+        if let Some(path) = &self.content_filter_file {
+            if !path.is_file() {
+                return Err(format!("CONTENT_FILTER_FILE '{}' does not exist", path.display()));
+            }
+            if self.content_filter_mode == FilterMode::Flag && !self.has_admin() {
+                return Err("CONTENT_FILTER_MODE=flag requires ADMIN_API_KEY or JWT authentication to review flagged fortunes".to_string());
+            }
+        }
+
+        if discord::parse_time(&self.discord_post_time).is_none() {
+            return Err(format!("DISCORD_POST_TIME '{}' is not a HH:MM time", self.discord_post_time));
+        }
+
+        if let Some(routes) = &self.slow_request_routes {
+            latency::Budget::parse_routes(routes).map_err(|e| format!("SLOW_REQUEST_ROUTES: {}", e))?;
+        }
+
+        if let Some(list) = &self.disable_endpoints {
+            endpoints::Disabled::parse(list).map_err(|e| format!("DISABLE_ENDPOINTS: {}", e))?;
+        }
+
+        if let Some(list) = &self.collections {
+            collections::parse(list).map_err(|e| format!("COLLECTIONS: {}", e))?;
+        }
+
+        if let Some(proxies) = &self.trusted_proxies {
+            TrustedProxies::parse(proxies).map_err(|e| format!("TRUSTED_PROXIES: {}", e))?;
+        }
+
+        if let Some(headers) = &self.response_headers {
+            response_headers::parse(headers)?;
+        }
+
+        if let Some(mode) = &self.unix_socket_mode {
+            if self.unix_socket.is_none() {
+                return Err("UNIX_SOCKET_MODE requires UNIX_SOCKET".to_string());
+            }
+            if parse_mode(mode).is_none() {
+                return Err(format!("UNIX_SOCKET_MODE '{}' must be octal permissions such as 660", mode));
+            }
+        }
+
+        if self.access_log_file.is_some() && self.access_log.is_none() {
+            return Err("ACCESS_LOG_FILE requires ACCESS_LOG to choose a format".to_string());
+        }
+
+        if self.session_ttl_secs == 0 {
+            return Err("SESSION_TTL_SECS must be at least 1".to_string());
+        }
+
+        if self.idempotency_ttl_secs == 0 {
+            return Err("IDEMPOTENCY_TTL_SECS must be at least 1".to_string());
+        }
+
+        if self.max_body_bytes == 0 {
+            return Err("MAX_BODY_BYTES must be at least 1".to_string());
+        }
+
+        if self.max_batch_bytes == 0 {
+            return Err("MAX_BATCH_BYTES must be at least 1".to_string());
+        }
+
+        if self.request_timeout_secs == 0 {
+            return Err("REQUEST_TIMEOUT_SECS must be at least 1".to_string());
+        }
+
+        let webhook_urls = self.webhook_urls();
+        if let Some(url) = webhook_urls.iter().find(|url| !url.starts_with("http://") && !url.starts_with("https://")) {
+            return Err(format!("WEBHOOK_URLS entry '{}' must start with http:// or https://", url));
+        }
+
+        if !webhook_urls.is_empty() && self.webhook_secret.as_deref().is_none_or(|secret| secret.len() < 16) {
+            return Err("WEBHOOK_URLS requires a WEBHOOK_SECRET of at least 16 characters".to_string());
+        }
+
+        if self.request_signing_secret.as_deref().is_some_and(|secret| secret.len() < 16) {
+            return Err("REQUEST_SIGNING_SECRET must be at least 16 characters".to_string());
+        }
+
+        if self.request_signing_max_age_secs == 0 {
+            return Err("REQUEST_SIGNING_MAX_AGE_SECS must be at least 1".to_string());
+        }
+
+        if let Some(endpoint) = &self.backup_s3_endpoint {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                return Err(format!("BACKUP_S3_ENDPOINT '{}' must start with http:// or https://", endpoint));
+            }
+            if self.backup_s3_bucket.is_none() || self.backup_s3_access_key.is_none() || self.backup_s3_secret_key.is_none() {
+                return Err("BACKUP_S3_ENDPOINT requires BACKUP_S3_BUCKET, BACKUP_S3_ACCESS_KEY and BACKUP_S3_SECRET_KEY".to_string());
+            }
+            if !self.has_admin() {
+                return Err("BACKUP_S3_ENDPOINT requires ADMIN_API_KEY or JWT authentication for the backup routes".to_string());
+            }
+        }
+
+        if self.webhook_max_attempts == 0 {
+            return Err("WEBHOOK_MAX_ATTEMPTS must be at least 1".to_string());
+        }
+
+        self.log_level.parse::<log::Level>()?;
+
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => {
+                for path in [cert, key] {
+                    if !Path::new(path).is_file() {
+                        return Err(format!("TLS file '{}' does not exist", path));
+                    }
+                }
+            }
+            (None, None) => {}
+            _ => return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string()),
+        }
+
+        Ok(())
+    }
+
+    pub fn listen(&self) -> server::Listen {
+        match &self.unix_socket {
+            Some(path) => server::Listen::Unix {
+                path: path.clone(),
+                mode: self.unix_socket_mode.as_deref().and_then(parse_mode),
+            },
+            None => server::Listen::Tcp(self.listen_addr()),
+        }
+    }
+
+    // 0 leaves idle connections unprobed and HTTP/2 streams unlimited
+    pub fn server_tuning(&self) -> server::Tuning {
+        server::Tuning {
+            http2: self.http2,
+            keep_alive: self.http_keep_alive,
+            keep_alive_timeout: match self.keep_alive_timeout_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            max_concurrent_streams: match self.http2_max_concurrent_streams {
+                0 => None,
+                streams => Some(streams),
+            },
+            tcp_nodelay: self.tcp_nodelay,
+        }
+    }
+
+    pub fn listen_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_addr, self.backend_port)
+    }
+
+    pub fn grpc_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_addr, self.grpc_port)
+    }
+
+    // HTTPS is served on its own port so it can run alongside plain HTTP unless TLS_ONLY is set.
+    pub fn tls(&self) -> Option<TlsConfig> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => Some(TlsConfig {
+                cert_path: cert.clone(),
+                key_path: key.clone(),
+                addr: SocketAddr::new(self.bind_addr, self.backend_tls_port),
+                only: self.tls_only,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn redis_backoff(&self) -> redis_client::Backoff {
+        redis_client::Backoff {
+            base: Duration::from_secs(self.redis_retry_delay_secs),
+            max: Duration::from_secs(self.redis_retry_max_delay_secs),
+        }
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs)
+    }
+
+    // None keeps trashed fortunes forever
+    pub fn trash_purge_after(&self) -> Option<Duration> {
+        match self.trash_purge_after_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    // Seconds after midnight UTC of the daily Discord post
+    pub fn discord_post_time(&self) -> u64 {
+        discord::parse_time(&self.discord_post_time).unwrap_or_default()
+    }
+
+    pub fn latency_budget(&self) -> latency::Budget {
+        let routes = self
+            .slow_request_routes
+            .as_deref()
+            .and_then(|routes| latency::Budget::parse_routes(routes).ok())
+            .unwrap_or_default();
+        latency::Budget::new(Duration::from_millis(self.slow_request_ms), routes)
+    }
+
+    pub fn session_ttl(&self) -> Duration {
+        Duration::from_secs(self.session_ttl_secs)
+    }
+
+    pub fn idempotency_ttl(&self) -> Duration {
+        Duration::from_secs(self.idempotency_ttl_secs)
+    }
+
+    pub fn schedule_refresh(&self) -> Duration {
+        Duration::from_secs(self.schedule_refresh_secs)
+    }
+
+    // None holds every fortune in memory
+    pub fn cache_capacity(&self) -> Option<usize> {
+        match self.max_cached_fortunes {
+            0 => None,
+            capacity => Some(capacity),
+        }
+    }
+
+    pub fn chaos(&self) -> chaos::Faults {
+        chaos::Faults {
+            latency: Duration::from_millis(self.chaos_latency_ms),
+            latency_percent: self.chaos_latency_percent,
+            error_percent: self.chaos_error_percent,
+        }
+    }
+
+    pub fn jwt_enabled(&self) -> bool {
+        self.jwt_hs256_secret.is_some() || self.jwt_jwks_url.is_some()
+    }
+
+    // Whether anyone can use the admin routes
+    pub fn has_admin(&self) -> bool {
+        self.admin_api_key.is_some() || self.jwt_enabled()
+    }
+
+    pub fn flight_recorder(&self) -> Recorder {
+        Recorder::new(self.flight_recorder_size, self.flight_recorder_body_bytes)
+    }
+
+    // None turns analytics off
+    pub fn analytics_flush(&self) -> Option<Duration> {
+        match self.analytics_flush_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn leader_lease(&self) -> Duration {
+        Duration::from_secs(self.leader_lease_secs)
+    }
+
+    // None disables the periodic Redis sync
+    pub fn redis_sync_interval(&self) -> Option<Duration> {
+        match self.redis_sync_interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    // None adds every view to Redis as it is served
+    pub fn views_flush(&self) -> Option<Duration> {
+        match self.views_flush_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    pub fn disabled_endpoints(&self) -> endpoints::Disabled {
+        self.disable_endpoints
+            .as_deref()
+            .and_then(|list| endpoints::Disabled::parse(list).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn trusted_proxies(&self) -> TrustedProxies {
+        self.trusted_proxies
+            .as_deref()
+            .and_then(|proxies| TrustedProxies::parse(proxies).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn access_log(&self) -> Option<AccessLog> {
+        self.access_log.map(|format| {
+            AccessLog::new(
+                format,
+                self.access_log_file.clone(),
+                self.access_log_max_bytes,
+                self.access_log_max_files,
+                self.trusted_proxies(),
+            )
+        })
+    }
+
+    // Checked by validate, so a bad value cannot reach here
+    pub fn response_headers(&self) -> HeaderMap {
+        self.response_headers
+            .as_deref()
+            .and_then(|headers| response_headers::parse(headers).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn webhook_urls(&self) -> Vec<String> {
+        self.webhook_urls
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn redis_url(&self) -> Option<String> {
+        self.redis_dns
+            .as_ref()
+            .map(|dns| format!("redis://{}:{}", dns, self.redis_port))
+    }
+}
@@ -0,0 +1,108 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterMode {
+    // Refuse the fortune with 422
+    #[default]
+    Reject,
+    // Store it as pending so a moderator decides
+    Flag,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    Rejected(String),
+    Flagged(String),
+}
+
+#[derive(Debug)]
+pub struct ContentFilter {
+    words: HashSet<String>,
+    mode: FilterMode,
+}
+
+static CONTENT_FILTER: OnceLock<Option<ContentFilter>> = OnceLock::new();
+
+pub fn init(filter: Option<ContentFilter>) {
+    CONTENT_FILTER.set(filter).ok();
+}
+
+// Undoes common letter substitutions so `h4x0r` is checked as `haxor`
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' | '|' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        '8' => 'b',
+        c => c,
+    }
+}
+
+// Lowercased, de-leeted words of `text`
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .chars()
+        .map(unleet)
+        .collect::<String>()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Collapses runs of the same letter, so `baaad` also matches `bad`
+fn squeeze(word: &str) -> String {
+    let mut squeezed = String::with_capacity(word.len());
+    for c in word.chars() {
+        if !squeezed.ends_with(c) {
+            squeezed.push(c);
+        }
+    }
+    squeezed
+}
+
+impl ContentFilter {
+    pub fn new<I: IntoIterator<Item = S>, S: AsRef<str>>(blocked: I, mode: FilterMode) -> Self {
+        let words = blocked.into_iter().flat_map(|word| words(word.as_ref())).collect();
+        ContentFilter { words, mode }
+    }
+
+    // One word per line; blank lines and lines starting with `#` are skipped
+    pub fn load(path: &Path, mode: FilterMode) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let lines = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        Ok(Self::new(lines, mode))
+    }
+
+    pub fn check(&self, message: &str) -> Verdict {
+        let matched = words(message).into_iter().find_map(|word| {
+            let squeezed = squeeze(&word);
+            [word, squeezed].into_iter().find(|w| self.words.contains(w))
+        });
+        match (matched, self.mode) {
+            (None, _) => Verdict::Clean,
+            (Some(word), FilterMode::Reject) => Verdict::Rejected(format!("message contains blocked word \"{}\"", word)),
+            (Some(word), FilterMode::Flag) => Verdict::Flagged(format!("message contains blocked word \"{}\"", word)),
+        }
+    }
+}
+
+// Checks a message against the configured filter; clean when none is configured
+pub fn check(message: &str) -> Verdict {
+    match CONTENT_FILTER.get().and_then(|filter| filter.as_ref()) {
+        Some(filter) => filter.check(message),
+        None => Verdict::Clean,
+    }
+}
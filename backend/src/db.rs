@@ -0,0 +1,110 @@
+use crate::config::Config;
+use crate::{Fortune, FortuneStore, Status};
+use fortune_common::{debug, error, info};
+use sqlx::any::{AnyPoolOptions, install_default_drivers};
+use sqlx::{AnyPool, Row};
+use std::sync::OnceLock;
+
+static DB_POOL: OnceLock<Option<AnyPool>> = OnceLock::new();
+
+pub async fn init(config: &Config) {
+    let database_url = match &config.database_url {
+        Some(url) => url,
+        None => {
+            info!("database config not set");
+            DB_POOL.set(None).unwrap();
+            return;
+        }
+    };
+
+    install_default_drivers();
+    let pool = match AnyPoolOptions::new().max_connections(5).connect(database_url).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!("database connection failed: {}", e);
+            DB_POOL.set(None).unwrap();
+            return;
+        }
+    };
+
+    if let Err(e) = sqlx::migrate!("./migrations").run(&pool).await {
+        error!("database migration failed: {}", e);
+        DB_POOL.set(None).unwrap();
+        return;
+    }
+
+    info!("Successfully connected to database");
+    DB_POOL.set(Some(pool)).unwrap();
+}
+
+pub async fn get_pool() -> Option<AnyPool> {
+    DB_POOL.get().and_then(|opt| opt.as_ref().cloned())
+}
+
+pub async fn load_fortunes(pool: &AnyPool, store: FortuneStore) {
+    let rows = match sqlx::query("SELECT id, message, lang, group_id, author, tags, status, publish_at, expires_at, created_at, updated_at FROM fortunes").fetch_all(pool).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("database select failed: {}", e);
+            return;
+        }
+    };
+
+    info!("*** loading database fortunes:");
+    let mut store_write = store.write().await;
+    for row in rows {
+        let fortune = Fortune {
+            id: row.get("id"),
+            message: row.get("message"),
+            lang: row.get("lang"),
+            group: row.get("group_id"),
+            author: row.get("author"),
+            tags: row
+                .get::<Option<String>, _>("tags")
+                .map(|tags| tags.split(',').filter(|tag| !tag.is_empty()).map(str::to_string).collect())
+                .unwrap_or_default(),
+            status: Status::parse(row.get("status")).unwrap_or_default(),
+            publish_at: row.get::<Option<i64>, _>("publish_at").map(|at| at as u64),
+            expires_at: row.get::<Option<i64>, _>("expires_at").map(|at| at as u64),
+            created_at: row.get::<Option<i64>, _>("created_at").map(|at| at as u64),
+            updated_at: row.get::<Option<i64>, _>("updated_at").map(|at| at as u64),
+            ..Default::default()
+        };
+        debug!("{} => {}", fortune.id, fortune.message);
+        store_write.insert(fortune.id.clone(), fortune);
+    }
+}
+
+pub async fn set_fortune(pool: &AnyPool, fortune: &Fortune) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO fortunes (id, message, lang, group_id, author, tags, status, publish_at, expires_at, created_at, updated_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) \
+         ON CONFLICT (id) DO UPDATE SET message = excluded.message, \
+         lang = excluded.lang, group_id = excluded.group_id, author = excluded.author, \
+         tags = excluded.tags, status = excluded.status, \
+         publish_at = excluded.publish_at, expires_at = excluded.expires_at, \
+         created_at = excluded.created_at, updated_at = excluded.updated_at",
+    )
+    .bind(&fortune.id)
+    .bind(&fortune.message)
+    .bind(&fortune.lang)
+    .bind(&fortune.group)
+    .bind(&fortune.author)
+    .bind(Some(fortune.tags.join(",")).filter(|tags| !tags.is_empty()))
+    .bind(fortune.status.as_str())
+    .bind(fortune.publish_at.map(|at| at as i64))
+    .bind(fortune.expires_at.map(|at| at as i64))
+    .bind(fortune.created_at.map(|at| at as i64))
+    .bind(fortune.updated_at.map(|at| at as i64))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_fortune(pool: &AnyPool, id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM fortunes WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
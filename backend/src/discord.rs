@@ -0,0 +1,139 @@
+use crate::admin::AdminAccess;
+use crate::config::Config;
+use crate::fortunes::now_secs;
+use crate::{admin, leader, store, with_store, Fortune, FortuneStore};
+use fortune_common::{error, info};
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use warp::{Filter, Rejection, Reply};
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+// Posts fortunes to a Discord channel through an incoming webhook
+pub struct Discord {
+    http: reqwest::Client,
+    webhook_url: String,
+}
+
+// Parses `HH:MM` into seconds after midnight
+pub fn parse_time(time: &str) -> Option<u64> {
+    let (hours, minutes) = time.split_once(':')?;
+    let hours: u64 = hours.parse().ok().filter(|h| *h < 24)?;
+    let minutes: u64 = minutes.parse().ok().filter(|m| *m < 60)?;
+    Some(hours * 3600 + minutes * 60)
+}
+
+// Seconds from `now` until the next `at` (seconds after midnight UTC); a full
+// day when `at` is right now, so a post is never sent twice
+fn secs_until(now: u64, at: u64) -> u64 {
+    match (at + DAY_SECS - now % DAY_SECS) % DAY_SECS {
+        0 => DAY_SECS,
+        secs => secs,
+    }
+}
+
+impl Discord {
+    pub fn from_config(config: &Config) -> Option<Arc<Discord>> {
+        let webhook_url = config.discord_webhook_url.clone()?;
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("failed to build HTTP client");
+        Some(Arc::new(Discord { http, webhook_url }))
+    }
+
+    pub async fn post(&self, fortune: &Fortune) -> Result<(), String> {
+        let mut embed = json!({
+            "title": "Fortune of the day",
+            "description": fortune.message,
+        });
+        if let Some(author) = &fortune.author {
+            embed["footer"] = json!({ "text": format!("— {}", author) });
+        }
+        let response = self
+            .http
+            .post(&self.webhook_url)
+            .json(&json!({ "embeds": [embed] }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("discord answered {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+// Posts the fortune of the day every day at `at` (seconds after midnight UTC),
+// from the leader only so replicas do not post it once each
+pub fn spawn_daily(discord: Arc<Discord>, store: FortuneStore, at: u64) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(secs_until(now_secs(), at))).await;
+            if !leader::is_leader().await {
+                continue;
+            }
+            let Some(fortune) = store::fortune_of_the_day(&store, now_secs() / DAY_SECS).await else {
+                info!("no published fortune to post to discord");
+                continue;
+            };
+            match discord.post(&fortune).await {
+                Ok(()) => info!("posted fortune {} to discord", fortune.id),
+                Err(e) => error!("discord post failed: {}", e),
+            }
+        }
+    });
+}
+
+#[utoipa::path(
+    post,
+    path = "/integrations/discord/test",
+    tag = "admin",
+    params(("X-API-Key" = String, Header, description = "Admin API key")),
+    responses(
+        (status = 200, description = "The fortune that was posted", body = Fortune),
+        (status = 401, description = "Missing or wrong API key", body = String),
+        (status = 502, description = "Discord refused the post or could not be reached", body = String),
+        (status = 503, description = "DISCORD_WEBHOOK_URL is not set, or there is nothing to post", body = String),
+    )
+)]
+async fn test_handler(discord: Option<Arc<Discord>>, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    let Some(discord) = discord else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"discord webhook is not configured"),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    };
+    let Some(fortune) = store::fortune_of_the_day(&store, now_secs() / DAY_SECS).await else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"there are no published fortunes"),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    };
+    match discord.post(&fortune).await {
+        Ok(()) => Ok(warp::reply::with_status(warp::reply::json(&fortune), warp::http::StatusCode::OK)),
+        Err(e) => {
+            error!("discord test post failed: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&e),
+                warp::http::StatusCode::BAD_GATEWAY,
+            ))
+        }
+    }
+}
+
+// POST /integrations/discord/test, for admins only
+pub fn routes(
+    store: FortuneStore,
+    access: AdminAccess,
+    discord: Option<Arc<Discord>>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("integrations" / "discord" / "test")
+        .and(warp::post())
+        .and(admin::authorized(access))
+        .and(warp::any().map(move || discord.clone()))
+        .and(with_store(store))
+        .and_then(test_handler)
+}
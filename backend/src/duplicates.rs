@@ -0,0 +1,154 @@
+use crate::fortunes::normalize;
+use crate::Fortune;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use utoipa::{IntoParams, ToSchema};
+
+// Words per shingle; messages shorter than this are one shingle
+const SHINGLE_WORDS: usize = 2;
+
+fn default_threshold() -> f64 {
+    0.6
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DuplicateParams {
+    /// Lowest Jaccard similarity of word shingles, between 0 and 1, at which
+    /// two messages count as near duplicates (default 0.6)
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    // Every message is the same once case and whitespace are ignored
+    Exact,
+    Near,
+}
+
+// Fortunes that are copies or close variants of each other
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Cluster {
+    kind: Kind,
+    // The weakest similarity that joined the cluster; 1 for exact clusters
+    similarity: f64,
+    // By id
+    fortunes: Vec<Fortune>,
+}
+
+// Overlapping runs of SHINGLE_WORDS words, ignoring case and punctuation
+fn shingles(message: &str) -> HashSet<String> {
+    let normalized = normalize(message);
+    let words: Vec<&str> = normalized
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.len() < SHINGLE_WORDS {
+        return HashSet::from([words.join(" ")]);
+    }
+    words.windows(SHINGLE_WORDS).map(|window| window.join(" ")).collect()
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let shared = a.intersection(b).count();
+    let total = a.len() + b.len() - shared;
+    if total == 0 {
+        return 1.0;
+    }
+    shared as f64 / total as f64
+}
+
+// Union-find over message indexes
+struct Groups(Vec<usize>);
+
+impl Groups {
+    fn find(&mut self, i: usize) -> usize {
+        let parent = self.0[i];
+        if parent == i {
+            return i;
+        }
+        let root = self.find(parent);
+        self.0[i] = root;
+        root
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.0[a.max(b)] = a.min(b);
+    }
+}
+
+// Groups fortunes whose messages normalize to the same text, then joins
+// groups whose shingles are at least `threshold` similar. Only pairs sharing
+// a shingle are compared. Largest clusters first.
+pub fn clusters(fortunes: &[Fortune], threshold: f64) -> Vec<Cluster> {
+    // Exact duplicates share a normalized message; compare each text once
+    let mut by_text: BTreeMap<String, Vec<&Fortune>> = BTreeMap::new();
+    for fortune in fortunes {
+        by_text.entry(normalize(&fortune.message)).or_default().push(fortune);
+    }
+    let texts: Vec<(&String, &Vec<&Fortune>)> = by_text.iter().collect();
+    let sets: Vec<HashSet<String>> = texts.iter().map(|(text, _)| shingles(text)).collect();
+
+    let mut index: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, set) in sets.iter().enumerate() {
+        for shingle in set {
+            index.entry(shingle.as_str()).or_default().push(i);
+        }
+    }
+
+    let mut groups = Groups((0..texts.len()).collect());
+    // The weakest similarity each text was joined by
+    let mut weakest: HashMap<usize, f64> = HashMap::new();
+    for i in 0..texts.len() {
+        let candidates: HashSet<usize> = sets[i]
+            .iter()
+            .flat_map(|shingle| index[shingle.as_str()].iter().copied())
+            .filter(|&j| j > i)
+            .collect();
+        for j in candidates {
+            let similarity = jaccard(&sets[i], &sets[j]);
+            if similarity >= threshold {
+                groups.join(i, j);
+                for k in [i, j] {
+                    let entry = weakest.entry(k).or_insert(similarity);
+                    *entry = entry.min(similarity);
+                }
+            }
+        }
+    }
+
+    let mut members: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..texts.len() {
+        let root = groups.find(i);
+        members.entry(root).or_default().push(i);
+    }
+
+    let mut clusters: Vec<Cluster> = members
+        .into_values()
+        .filter_map(|texts_in_cluster| {
+            let mut fortunes: Vec<Fortune> = texts_in_cluster
+                .iter()
+                .flat_map(|&i| texts[i].1.iter().map(|&f| f.clone()))
+                .collect();
+            if fortunes.len() < 2 {
+                return None;
+            }
+            fortunes.sort_by(|a, b| a.id.cmp(&b.id));
+            let (kind, similarity) = if texts_in_cluster.len() == 1 {
+                (Kind::Exact, 1.0)
+            } else {
+                let similarity = texts_in_cluster
+                    .iter()
+                    .filter_map(|i| weakest.get(i))
+                    .fold(1.0, |lowest: f64, &s| lowest.min(s));
+                (Kind::Near, similarity)
+            };
+            Some(Cluster { kind, similarity, fortunes })
+        })
+        .collect();
+    clusters.sort_by(|a, b| b.fortunes.len().cmp(&a.fortunes.len()).then_with(|| a.fortunes[0].id.cmp(&b.fortunes[0].id)));
+    clusters
+}
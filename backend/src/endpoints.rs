@@ -0,0 +1,86 @@
+// Routes that DISABLE_ENDPOINTS can switch off. A disabled route answers as
+// if it did not exist: 404, or 405 when the path has other methods left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    List,
+    Get,
+    Random,
+    Rotation,
+    Popular,
+    Authors,
+    Events,
+    Ws,
+    Trash,
+    Restore,
+    Create,
+    Batch,
+    Delete,
+    Graphql,
+    Admin,
+    Discord,
+    // /openapi.json and /docs
+    Docs,
+    Metrics,
+}
+
+const NAMES: &[(&str, Endpoint)] = &[
+    ("list", Endpoint::List),
+    ("get", Endpoint::Get),
+    ("random", Endpoint::Random),
+    ("rotation", Endpoint::Rotation),
+    ("popular", Endpoint::Popular),
+    ("authors", Endpoint::Authors),
+    ("events", Endpoint::Events),
+    ("ws", Endpoint::Ws),
+    ("trash", Endpoint::Trash),
+    ("restore", Endpoint::Restore),
+    ("create", Endpoint::Create),
+    ("batch", Endpoint::Batch),
+    ("delete", Endpoint::Delete),
+    ("graphql", Endpoint::Graphql),
+    ("admin", Endpoint::Admin),
+    ("discord", Endpoint::Discord),
+    ("docs", Endpoint::Docs),
+    ("metrics", Endpoint::Metrics),
+];
+
+impl Endpoint {
+    // The name DISABLE_ENDPOINTS knows it by
+    pub fn name(self) -> &'static str {
+        NAMES
+            .iter()
+            .find(|(_, endpoint)| *endpoint == self)
+            .map_or("unknown", |(name, _)| *name)
+    }
+}
+
+// The set of disabled endpoints, one bit per variant
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Disabled(u32);
+
+impl Disabled {
+    // Comma-separated names such as `create,delete`
+    pub fn parse(list: &str) -> Result<Disabled, String> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .try_fold(Disabled::default(), |disabled, name| {
+                let (_, endpoint) = NAMES
+                    .iter()
+                    .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| {
+                        let known: Vec<&str> = NAMES.iter().map(|(known, _)| *known).collect();
+                        format!("unknown endpoint '{}', expected one of {}", name, known.join(", "))
+                    })?;
+                Ok(Disabled(disabled.0 | 1 << *endpoint as u32))
+            })
+    }
+
+    pub fn contains(self, endpoint: Endpoint) -> bool {
+        self.0 & 1 << endpoint as u32 != 0
+    }
+
+    pub fn is_enabled(self, endpoint: Endpoint) -> bool {
+        !self.contains(endpoint)
+    }
+}
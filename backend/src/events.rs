@@ -0,0 +1,185 @@
+use crate::config::Config;
+use crate::fortunes::now_secs;
+use crate::webhooks::Kind;
+use crate::{limits, methods, redis_client, Fortune};
+use fortune_common::error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+// Event log: every fortune created, updated or deleted on any replica is
+// appended to the `fortunes:events` Redis Stream with its type, timestamp and
+// the fortune as JSON. GET /fortunes/events replays the stream from an entry
+// id, so consumers such as analytics jobs can follow changes at their own
+// pace without the backend knowing about them. The stream is trimmed to about
+// EVENT_LOG_MAX_LEN entries.
+
+// 0 until `init`, which keeps the log off
+static MAX_LEN: AtomicUsize = AtomicUsize::new(0);
+static APPENDED: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LoggedEvent {
+    /// Stream entry id, such as `1767225600000-0`; pass the last one seen as `since`
+    pub id: String,
+    /// `fortune.created`, `fortune.updated` or `fortune.deleted`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    /// The fortune as stored; only `id` for deletions
+    #[schema(value_type = Object)]
+    pub fortune: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventParams {
+    /// Entry id of the last event already seen; `0` (the default) starts at the oldest
+    #[serde(default = "default_since")]
+    since: String,
+    /// How many events to return, at most 1000
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_since() -> String {
+    "0".to_string()
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+pub fn init(config: &Config) {
+    MAX_LEN.store(config.event_log_max_len, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    MAX_LEN.load(Ordering::Relaxed) > 0
+}
+
+// Appends one entry per mutation. Failures are logged and counted but not
+// retried, so the log can miss events while Redis is unreachable.
+pub async fn append(mutations: &[(Kind, &str, Option<&Fortune>)]) {
+    let max_len = MAX_LEN.load(Ordering::Relaxed);
+    if max_len == 0 || mutations.is_empty() {
+        return;
+    }
+    let Some(redis) = redis_client::get_store().await else {
+        return;
+    };
+    let timestamp = now_secs().to_string();
+    let entries: Vec<Vec<(&str, String)>> = mutations
+        .iter()
+        .map(|(kind, id, fortune)| {
+            let fortune = match fortune {
+                Some(fortune) => json!(fortune),
+                None => json!({ "id": id }),
+            };
+            vec![
+                ("type", kind.as_str().to_string()),
+                ("timestamp", timestamp.clone()),
+                ("fortune", fortune.to_string()),
+            ]
+        })
+        .collect();
+    match redis.append_events(&entries, max_len).await {
+        Ok(()) => {
+            APPENDED.fetch_add(entries.len() as u64, Ordering::Relaxed);
+        }
+        Err(e) => {
+            FAILED.fetch_add(entries.len() as u64, Ordering::Relaxed);
+            error!("redis event log append failed: {}", e);
+        }
+    }
+}
+
+// Stream ids are `<ms>-<seq>`; the sequence may be left out
+fn valid_id(id: &str) -> bool {
+    let mut parts = id.splitn(2, '-');
+    let is_number = |part: &str| !part.is_empty() && part.len() <= 20 && part.bytes().all(|b| b.is_ascii_digit());
+    parts.next().is_some_and(is_number) && parts.next().is_none_or(is_number)
+}
+
+fn decode(id: String, mut fields: std::collections::HashMap<String, String>) -> LoggedEvent {
+    LoggedEvent {
+        id,
+        kind: fields.remove("type").unwrap_or_default(),
+        timestamp: fields.get("timestamp").and_then(|t| t.parse().ok()).unwrap_or_default(),
+        fortune: fields
+            .get("fortune")
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/fortunes/events",
+    tag = "fortunes",
+    params(EventParams),
+    responses(
+        (status = 200, description = "Events after `since`, oldest first", body = [LoggedEvent]),
+        (status = 400, description = "`since` is not a stream entry id", body = String),
+        (status = 503, description = "The event log is off, or Redis is not connected", body = String),
+    )
+)]
+async fn list_handler(params: EventParams) -> Result<impl Reply, Infallible> {
+    if !valid_id(&params.since) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"since must be a stream entry id such as 1767225600000-0"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    if !is_enabled() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"event log is disabled"),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    }
+    let Some(redis) = redis_client::get_store().await else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"event log requires Redis"),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    };
+    match redis.read_events(&params.since, params.limit.clamp(1, MAX_LIMIT)).await {
+        Ok(entries) => {
+            let events: Vec<LoggedEvent> = entries.into_iter().map(|(id, fields)| decode(id, fields)).collect();
+            Ok(warp::reply::with_status(warp::reply::json(&events), StatusCode::OK))
+        }
+        Err(e) => {
+            error!("redis event log read failed: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&"event log is unavailable"),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    }
+}
+
+// GET /fortunes/events
+pub fn routes(timeout: Duration) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    warp::path!("fortunes" / "events")
+        .and(methods::get_or_head())
+        .and(warp::query::<EventParams>())
+        .and_then(move |params| limits::timed(timeout, list_handler(params)))
+}
+
+pub fn metrics() -> String {
+    format!(
+        "backend_event_log_appended_total {}\n\
+         backend_event_log_failed_total {}\n",
+        APPENDED.load(Ordering::Relaxed),
+        FAILED.load(Ordering::Relaxed),
+    )
+}
@@ -0,0 +1,965 @@
+use crate::lru::{self, Recency};
+use crate::negative_cache;
+use crate::shared::Publish;
+use crate::storage::Storage;
+use crate::{Fortune, Sort};
+use dashmap::DashMap;
+use imbl::{OrdMap, OrdSet, Vector};
+use rand::seq::{index, SliceRandom};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Index;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+use utoipa::ToSchema;
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// A soft-deleted fortune waiting in the trash to be restored or purged
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TrashedFortune {
+    #[serde(flatten)]
+    pub fortune: Fortune,
+    // Unix timestamp in seconds
+    pub deleted_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AuthorCount {
+    pub author: String,
+    pub count: usize,
+}
+
+// Lowercases the message and collapses runs of whitespace so trivially
+// different submissions of the same fortune compare equal.
+pub fn normalize(message: &str) -> String {
+    message
+        .nfc()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// Length of a message as readers count it: in grapheme clusters, so an emoji
+// sequence or a letter with its accents is one
+pub fn message_len(message: &str) -> usize {
+    message.graphemes(true).count()
+}
+
+// A dense list of ids supporting constant-time insert, remove and random pick
+#[derive(Debug, Clone, Default)]
+struct IdPool {
+    ids: Vector<String>,
+    // Position of each id in `ids`
+    slots: imbl::HashMap<String, usize>,
+}
+
+impl IdPool {
+    fn insert(&mut self, id: String) {
+        if !self.slots.contains_key(&id) {
+            self.slots.insert(id.clone(), self.ids.len());
+            self.ids.push_back(id);
+        }
+    }
+
+    fn remove(&mut self, id: &str) {
+        // Fill the hole with the last id so `ids` stays dense
+        if let Some(slot) = self.slots.remove(id) {
+            let last = self.ids.pop_back().expect("a slotted id is in `ids`");
+            if slot < self.ids.len() {
+                self.slots.insert(last.clone(), slot);
+                self.ids.set(slot, last);
+            }
+        }
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.slots.contains_key(id)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    fn random<R: Rng>(&self, rng: &mut R) -> Option<&String> {
+        if self.ids.is_empty() {
+            return None;
+        }
+        Some(&self.ids[rng.gen_range(0..self.ids.len())])
+    }
+
+    // Up to `count` different ids
+    fn sample<R: Rng>(&self, count: usize, rng: &mut R) -> impl Iterator<Item = &String> {
+        index::sample(rng, self.ids.len(), count.min(self.ids.len())).into_iter().map(|i| &self.ids[i])
+    }
+}
+
+// Ids bucketed by message length, so asking for fortunes that fit in a
+// length only touches the buckets at or below it, and a draw skips whole
+// buckets instead of walking their ids
+#[derive(Debug, Clone, Default)]
+struct LengthIndex {
+    buckets: OrdMap<usize, IdPool>,
+    // Ids across all buckets
+    len: usize,
+}
+
+impl LengthIndex {
+    fn insert(&mut self, len: usize, id: String) {
+        let pool = self.buckets.entry(len).or_default();
+        if !pool.contains(&id) {
+            pool.insert(id);
+            self.len += 1;
+        }
+    }
+
+    fn remove(&mut self, len: usize, id: &str) {
+        if let Some(pool) = self.buckets.get_mut(&len) {
+            if pool.contains(id) {
+                pool.remove(id);
+                self.len -= 1;
+            }
+            if pool.is_empty() {
+                self.buckets.remove(&len);
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn within(&self, max_len: usize) -> impl Iterator<Item = &String> {
+        self.buckets.range(..=max_len).flat_map(|(_, pool)| pool.ids.iter())
+    }
+
+    fn count_within(&self, max_len: usize) -> usize {
+        match self.buckets.get_max() {
+            Some((longest, _)) if *longest > max_len => self.buckets.range(..=max_len).map(|(_, pool)| pool.ids.len()).sum(),
+            // Everything fits, as for any limit past the longest message
+            _ => self.len,
+        }
+    }
+
+    // The id at position `n` in `within(max_len)`
+    fn nth_within(&self, max_len: usize, mut n: usize) -> Option<&String> {
+        for (_, pool) in self.buckets.range(..=max_len) {
+            if n < pool.ids.len() {
+                return Some(&pool.ids[n]);
+            }
+            n -= pool.ids.len();
+        }
+        None
+    }
+
+    // Uniform over the ids in the buckets that fit
+    fn random<R: Rng>(&self, max_len: usize, rng: &mut R) -> Option<&String> {
+        let total = self.count_within(max_len);
+        if total == 0 {
+            return None;
+        }
+        self.nth_within(max_len, rng.gen_range(0..total))
+    }
+
+    // Up to `count` different ids among those that fit
+    fn sample<R: Rng>(&self, max_len: usize, count: usize, rng: &mut R) -> impl Iterator<Item = &String> {
+        let total = self.count_within(max_len);
+        index::sample(rng, total, count.min(total)).into_iter().filter_map(move |i| self.nth_within(max_len, i))
+    }
+}
+
+// An id ordered numerically when both are numbers, so 2 comes before 10,
+// and otherwise as text, after the numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SortedId(String);
+
+impl Ord for SortedId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_ids(&self.0, &other.0)
+    }
+}
+
+fn compare_ids(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        // "7" and "007" are different ids
+        (Ok(x), Ok(y)) => x.cmp(&y).then_with(|| a.cmp(b)),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+impl PartialOrd for SortedId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Ids grouped under a sort key. Either way round, ids sharing a key stay in
+// ascending id order, so ties come out the same on every call and replica.
+#[derive(Debug, Clone)]
+struct Sorted<K: Ord + Clone>(OrdMap<K, OrdSet<SortedId>>);
+
+impl<K: Ord + Clone> Default for Sorted<K> {
+    fn default() -> Self {
+        Sorted(OrdMap::new())
+    }
+}
+
+impl<K: Ord + Clone> Sorted<K> {
+    fn insert(&mut self, key: K, id: &str) {
+        self.0.entry(key).or_default().insert(SortedId(id.to_string()));
+    }
+
+    fn remove(&mut self, key: &K, id: &str) {
+        if let Some(ids) = self.0.get_mut(key) {
+            ids.remove(&SortedId(id.to_string()));
+            if ids.is_empty() {
+                self.0.remove(key);
+            }
+        }
+    }
+
+    fn iter(&self, descending: bool) -> Box<dyn Iterator<Item = &str> + '_> {
+        let groups: Box<dyn Iterator<Item = &OrdSet<SortedId>>> = if descending {
+            Box::new(self.0.iter().rev().map(|(_, ids)| ids))
+        } else {
+            Box::new(self.0.values())
+        };
+        Box::new(groups.flat_map(|ids| ids.iter().map(|id| id.0.as_str())))
+    }
+}
+
+// The hot set in each order `GET /fortunes` can list it in, kept up to date
+// as fortunes come and go so a listing never sorts
+#[derive(Debug, Clone, Default)]
+struct ListIndex {
+    by_id: OrdSet<SortedId>,
+    // Keyed by the normalized message, so case and spacing do not matter
+    by_message: Sorted<String>,
+    by_created: Sorted<u64>,
+    // Fortunes stored before timestamps were recorded; listed after the rest either way
+    undated: OrdSet<SortedId>,
+}
+
+impl ListIndex {
+    fn insert(&mut self, outline: &Outline) {
+        let fortune = &outline.fields;
+        self.by_id.insert(SortedId(fortune.id.clone()));
+        self.by_message.insert(outline.key.clone(), &fortune.id);
+        match fortune.created_at {
+            Some(created_at) => self.by_created.insert(created_at, &fortune.id),
+            None => {
+                self.undated.insert(SortedId(fortune.id.clone()));
+            }
+        }
+    }
+
+    fn remove(&mut self, outline: &Outline) {
+        let fortune = &outline.fields;
+        let id = SortedId(fortune.id.clone());
+        self.by_id.remove(&id);
+        self.by_message.remove(&outline.key, &fortune.id);
+        match fortune.created_at {
+            Some(created_at) => self.by_created.remove(&created_at, &fortune.id),
+            None => {
+                self.undated.remove(&id);
+            }
+        }
+    }
+
+    fn iter(&self, sort: Sort, descending: bool) -> Box<dyn Iterator<Item = &str> + '_> {
+        match sort {
+            Sort::Id if descending => Box::new(self.by_id.iter().rev().map(|id| id.0.as_str())),
+            Sort::Id => Box::new(self.by_id.iter().map(|id| id.0.as_str())),
+            Sort::Message => self.by_message.iter(descending),
+            Sort::CreatedAt | Sort::Newest => {
+                Box::new(self.by_created.iter(descending).chain(self.undated.iter().map(|id| id.0.as_str())))
+            }
+        }
+    }
+}
+
+// What the indexes need of a fortune: everything but its message, which is
+// only kept normalized and measured. An evicted fortune keeps its place in
+// them through this after its body is dropped.
+#[derive(Debug, Clone, PartialEq)]
+struct Outline {
+    // The fortune with an empty message
+    fields: Fortune,
+    key: String,
+    len: usize,
+}
+
+impl Outline {
+    fn of(fortune: &Fortune) -> Outline {
+        Outline {
+            fields: Fortune {
+                message: String::new(),
+                ..fortune.clone()
+            },
+            key: normalize(&fortune.message),
+            len: message_len(&fortune.message),
+        }
+    }
+}
+
+// A draft's change to the view counts or recency, which every copy shares
+#[derive(Debug, Clone)]
+pub enum Forget {
+    // Removed: its views and its place in the recency order go
+    Fortune(String),
+    // Evicted: only its place in the recency order goes
+    Body(String),
+}
+
+// The fortunes held in memory, keyed by id, with an index from normalized
+// message to ids used for duplicate detection and dense id pools, overall and
+// per language, for constant-time random selection, each also bucketed by
+// message length. The pools are the hot set: they only hold fortunes inside
+// their publish window, and are brought up to date by `refresh_schedule`.
+//
+// The store publishes each version as an immutable snapshot (see `Shared`),
+// so the maps and sets are persistent collections: a clone shares them and
+// costs the same however many fortunes there are. View counts and recency
+// change on reads and are shared by every clone instead.
+#[derive(Debug, Clone, Default)]
+pub struct Fortunes {
+    by_id: imbl::HashMap<String, Fortune>,
+    by_message: imbl::HashMap<String, OrdSet<String>>,
+    ids: IdPool,
+    by_lang: imbl::HashMap<String, IdPool>,
+    by_length: LengthIndex,
+    by_lang_length: imbl::HashMap<String, LengthIndex>,
+    // Ids in the hot set per tag, in id order so rotations step through them predictably
+    by_tag: imbl::HashMap<String, OrdSet<String>>,
+    sorted: ListIndex,
+    // Fortunes with a publish_at or expires_at that may still enter or leave the hot set
+    scheduled: imbl::HashSet<String>,
+    // Times each fortune was served; kept apart so views never bump `version`
+    views: Arc<DashMap<String, u64>>,
+    // Bumped whenever a view count changes, for the collection ETag
+    views_version: Arc<AtomicU64>,
+    // Bumped on every mutation; used to build the collection ETag
+    version: u64,
+    // Next id `allocate_id` hands out, past every numeric id ever inserted
+    next_id: u64,
+    trash: imbl::HashMap<String, TrashedFortune>,
+    // Set for the stores of named collections (COLLECTIONS); None is the default collection
+    collection: Option<String>,
+    // MAX_CACHED_FORTUNES: beyond it the least recently used fortunes are
+    // dropped from memory, to be read back from Redis when asked for
+    capacity: Option<usize>,
+    recency: Arc<Recency>,
+    // Fortunes whose body the cap dropped. They stay in every index, so they
+    // are still listed, drawn and matched as duplicates, and their bodies are
+    // read back from storage when needed.
+    evicted: imbl::HashMap<String, Outline>,
+    // Applied when this draft is published; empty in published copies
+    forget: Vec<Forget>,
+    // Where writes go through to and misses are read from; None keeps
+    // everything in memory only
+    storage: Option<Arc<dyn Storage>>,
+}
+
+impl Fortunes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn named(collection: &str) -> Self {
+        Fortunes {
+            collection: Some(collection.to_string()),
+            ..Self::default()
+        }
+    }
+
+    pub fn collection(&self) -> Option<&str> {
+        self.collection.as_deref()
+    }
+
+    pub fn storage(&self) -> Option<Arc<dyn Storage>> {
+        self.storage.clone()
+    }
+
+    pub fn set_storage(&mut self, storage: Option<Arc<dyn Storage>>) {
+        self.storage = storage;
+    }
+
+    // Caps the fortunes kept in memory, evicting the least recently used
+    // ones at once if there are more; None holds every fortune. Fortunes
+    // stored before the first cap count as used in no particular order.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        if capacity.is_none() {
+            self.recency = Arc::default();
+        } else if self.capacity.is_none() {
+            for id in self.by_id.keys() {
+                self.recency.touch(id);
+            }
+        }
+        self.capacity = capacity;
+        self.evict(None);
+    }
+
+    pub fn is_capped(&self) -> bool {
+        self.capacity.is_some()
+    }
+
+    // Stored, but only the outline is in memory
+    pub fn is_evicted(&self, id: &str) -> bool {
+        self.evicted.contains_key(id)
+    }
+
+    pub fn has_evicted(&self) -> bool {
+        !self.evicted.is_empty()
+    }
+
+    // Marks a fortune as just used, so it is evicted last
+    pub fn touch(&self, id: &str) {
+        if self.capacity.is_some() && self.by_id.contains_key(id) {
+            self.recency.touch(id);
+        }
+    }
+
+    // Drops the bodies of the least recently used fortunes until the cap is
+    // met, never `keep`'s. Their outlines stay, so nothing leaves the indexes.
+    fn evict(&mut self, keep: Option<&str>) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+        let excess = self.by_id.len().saturating_sub(capacity);
+        if excess == 0 {
+            return;
+        }
+        // Ids evicted earlier in this draft are still in the order until it is published
+        let oldest = self.recency.oldest(excess, |id| keep != Some(id) && self.by_id.contains_key(id));
+        for id in oldest {
+            if let Some(fortune) = self.by_id.remove(&id) {
+                self.evicted.insert(id.clone(), Outline::of(&fortune));
+                self.forget.push(Forget::Body(id));
+                lru::record_eviction();
+            }
+        }
+    }
+
+    // Returns the fortune replaced, or None when there was none or only its
+    // evicted outline
+    pub fn insert(&mut self, id: String, fortune: Fortune) -> Option<Fortune> {
+        let outline = Outline::of(&fortune);
+        let evicted = self.evicted.remove(&id);
+        // Re-inserting an unchanged fortune (e.g. Redis read-through) keeps the version
+        let unchanged = match self.by_id.get(&id) {
+            Some(previous) => *previous == fortune,
+            None => evicted.as_ref() == Some(&outline),
+        };
+        if !unchanged {
+            self.version += 1;
+        }
+        self.by_message.entry(outline.key.clone()).or_default().insert(id.clone());
+        negative_cache::forget(self.collection.as_deref(), &id);
+        if let Ok(n) = id.parse::<u64>() {
+            self.next_id = self.next_id.max(n.saturating_add(1));
+        }
+        let previous = self.by_id.insert(id.clone(), fortune);
+        if let Some(replaced) = previous.as_ref().map(Outline::of).or(evicted) {
+            if replaced.key != outline.key {
+                self.unindex(&id, &replaced.key);
+            }
+            self.unpool(&replaced);
+        }
+        self.scheduled.remove(&id);
+
+        if outline.fields.is_active(now_secs()) {
+            self.pool(&outline);
+        }
+        if outline.fields.is_scheduled() {
+            self.scheduled.insert(id.clone());
+        }
+        if self.capacity.is_some() {
+            self.recency.touch(&id);
+            self.evict(Some(&id));
+        }
+        previous
+    }
+
+    // A numeric id no fortune in this collection has had yet, for creates
+    // without Redis. Removed ids are not handed out again.
+    pub fn allocate_id(&mut self) -> String {
+        let id = self.next_id.max(1);
+        self.next_id = id + 1;
+        id.to_string()
+    }
+
+    // Returns the removed fortune, or None when it was unknown or evicted;
+    // either way nothing of it is left
+    pub fn remove(&mut self, id: &str) -> Option<Fortune> {
+        let removed = self.by_id.remove(id);
+        let outline = match &removed {
+            Some(fortune) => Outline::of(fortune),
+            None => self.evicted.remove(id)?,
+        };
+        self.version += 1;
+        self.unindex(id, &outline.key);
+        self.unpool(&outline);
+        self.scheduled.remove(id);
+        self.forget.push(Forget::Fortune(id.to_string()));
+        removed
+    }
+
+    // Drops the message index entry of a fortune that was replaced or removed
+    fn unindex(&mut self, id: &str, key: &str) {
+        if let Some(ids) = self.by_message.get_mut(key) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.by_message.remove(key);
+            }
+        }
+    }
+
+    // Adds a stored fortune to the hot set
+    fn pool(&mut self, outline: &Outline) {
+        let fortune = &outline.fields;
+        let (id, lang, len) = (&fortune.id, &fortune.lang, outline.len);
+        self.by_length.insert(len, id.clone());
+        self.by_lang_length.entry(lang.clone()).or_default().insert(len, id.clone());
+        for tag in &fortune.tags {
+            self.by_tag.entry(tag.clone()).or_default().insert(id.clone());
+        }
+        self.sorted.insert(outline);
+        self.by_lang.entry(lang.clone()).or_default().insert(id.clone());
+        self.ids.insert(id.clone());
+    }
+
+    // Takes a fortune out of the hot set; `outline` is of the version that was pooled
+    fn unpool(&mut self, outline: &Outline) {
+        let fortune = &outline.fields;
+        if !self.ids.contains(&fortune.id) {
+            return;
+        }
+        self.ids.remove(&fortune.id);
+        self.sorted.remove(outline);
+        if let Some(pool) = self.by_lang.get_mut(&fortune.lang) {
+            pool.remove(&fortune.id);
+            if pool.is_empty() {
+                self.by_lang.remove(&fortune.lang);
+            }
+        }
+        let len = outline.len;
+        self.by_length.remove(len, &fortune.id);
+        if let Some(lengths) = self.by_lang_length.get_mut(&fortune.lang) {
+            lengths.remove(len, &fortune.id);
+            if lengths.is_empty() {
+                self.by_lang_length.remove(&fortune.lang);
+            }
+        }
+        for tag in &fortune.tags {
+            if let Some(ids) = self.by_tag.get_mut(tag) {
+                ids.remove(&fortune.id);
+                if ids.is_empty() {
+                    self.by_tag.remove(tag);
+                }
+            }
+        }
+    }
+
+    // Moves scheduled fortunes in or out of the hot set as their publish window
+    // opens or closes. Returns how many moved.
+    pub fn refresh_schedule(&mut self, now: u64) -> usize {
+        let mut moved = 0;
+        let scheduled: Vec<String> = self.scheduled.iter().cloned().collect();
+        for id in scheduled {
+            let outline = match self.by_id.get(&id) {
+                Some(fortune) => Outline::of(fortune),
+                None => self.evicted[&id].clone(),
+            };
+            let active = outline.fields.is_active(now);
+            if active != self.ids.contains(&id) {
+                if active {
+                    self.pool(&outline);
+                } else {
+                    self.unpool(&outline);
+                }
+                moved += 1;
+            }
+            if outline.fields.expires_at.is_some_and(|expires_at| expires_at <= now) {
+                // Expired for good; nothing can bring it back
+                self.scheduled.remove(&id);
+            }
+        }
+        if moved > 0 {
+            self.version += 1;
+        }
+        moved
+    }
+
+    pub fn clear(&mut self) {
+        if !self.by_id.is_empty() || !self.evicted.is_empty() {
+            self.version += 1;
+        }
+        self.by_id.clear();
+        self.evicted.clear();
+        self.by_message.clear();
+        self.by_lang.clear();
+        self.by_length = LengthIndex::default();
+        self.by_lang_length.clear();
+        self.by_tag.clear();
+        self.sorted = ListIndex::default();
+        self.scheduled.clear();
+        // Published copies keep theirs
+        self.views = Arc::default();
+        self.recency = Arc::default();
+        self.forget.clear();
+        self.ids = IdPool::default();
+    }
+
+    // Moves a fortune into the trash
+    pub fn trash(&mut self, id: &str, deleted_at: u64) -> Option<TrashedFortune> {
+        let fortune = self.remove(id)?;
+        let trashed = TrashedFortune { fortune, deleted_at };
+        self.trash.insert(trashed.fortune.id.clone(), trashed.clone());
+        Some(trashed)
+    }
+
+    pub fn insert_trashed(&mut self, trashed: TrashedFortune) {
+        self.trash.insert(trashed.fortune.id.clone(), trashed);
+    }
+
+    pub fn take_trashed(&mut self, id: &str) -> Option<TrashedFortune> {
+        self.trash.remove(id)
+    }
+
+    pub fn trashed(&self) -> impl Iterator<Item = &TrashedFortune> {
+        self.trash.values()
+    }
+
+    // Drops trashed fortunes deleted before `cutoff` and returns their ids
+    pub fn purge_trash(&mut self, cutoff: u64) -> Vec<String> {
+        let expired: Vec<String> = self
+            .trash
+            .values()
+            .filter(|t| t.deleted_at < cutoff)
+            .map(|t| t.fortune.id.clone())
+            .collect();
+        for id in &expired {
+            self.trash.remove(id);
+        }
+        expired
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn views_version(&self) -> u64 {
+        self.views_version.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Fortune> {
+        self.by_id.get(id)
+    }
+
+    // Cached or evicted
+    pub fn contains_key(&self, id: &str) -> bool {
+        self.by_id.contains_key(id) || self.evicted.contains_key(id)
+    }
+
+    // Whether `fortune` is stored as it is, cached or evicted
+    pub fn holds(&self, fortune: &Fortune) -> bool {
+        match self.by_id.get(&fortune.id) {
+            Some(stored) => stored == fortune,
+            None => self.evicted.get(&fortune.id).is_some_and(|outline| *outline == Outline::of(fortune)),
+        }
+    }
+
+    // Every field of the fortune but, once it is evicted, its message
+    pub fn fields(&self, id: &str) -> Option<&Fortune> {
+        self.by_id.get(id).or_else(|| self.evicted.get(id).map(|outline| &outline.fields))
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Fortune> {
+        self.by_id.values()
+    }
+
+    // Cached fortunes in the hot set, i.e. inside their publish window
+    pub fn active(&self) -> impl Iterator<Item = &Fortune> {
+        self.ids.ids.iter().filter_map(|id| self.by_id.get(id))
+    }
+
+    // Ids in the hot set, evicted ones included
+    pub fn active_ids(&self) -> impl Iterator<Item = &String> {
+        self.ids.ids.iter()
+    }
+
+    // Ids in the hot set in list order; `descending` reverses it
+    pub fn sorted(&self, sort: Sort, descending: bool) -> impl Iterator<Item = &str> {
+        self.sorted.iter(sort, descending)
+    }
+
+    // `sorted` limited to messages at most `max_len` long. Only the length
+    // buckets that fit are read, and just their ids are put in list order.
+    pub fn sorted_within(&self, max_len: usize, sort: Sort, descending: bool) -> Vec<&str> {
+        let mut ids: Vec<&str> = self.by_length.within(max_len).map(String::as_str).collect();
+        let direction = |ordering: Ordering| if descending { ordering.reverse() } else { ordering };
+        match sort {
+            Sort::Id => ids.sort_by(|a, b| direction(compare_ids(a, b))),
+            Sort::Message => {
+                let mut keyed: Vec<(String, &str)> = ids.into_iter().map(|id| (self.message_key(id), id)).collect();
+                keyed.sort_by(|a, b| direction(a.0.cmp(&b.0)).then_with(|| compare_ids(a.1, b.1)));
+                ids = keyed.into_iter().map(|(_, id)| id).collect();
+            }
+            // Undated fortunes come last either way, as in `ListIndex`
+            Sort::CreatedAt | Sort::Newest => {
+                let mut keyed: Vec<(Option<u64>, &str)> = ids.into_iter().map(|id| (self.fields(id).and_then(|f| f.created_at), id)).collect();
+                keyed.sort_by(|a, b| {
+                    let by_created = match (a.0, b.0) {
+                        (Some(x), Some(y)) => direction(x.cmp(&y)),
+                        (x, y) => x.is_none().cmp(&y.is_none()),
+                    };
+                    by_created.then_with(|| compare_ids(a.1, b.1))
+                });
+                ids = keyed.into_iter().map(|(_, id)| id).collect();
+            }
+        }
+        ids
+    }
+
+    // The normalized message the list is sorted by, kept for evicted fortunes too
+    fn message_key(&self, id: &str) -> String {
+        match self.by_id.get(id) {
+            Some(fortune) => normalize(&fortune.message),
+            None => self.evicted.get(id).map(|outline| outline.key.clone()).unwrap_or_default(),
+        }
+    }
+
+    pub fn is_active(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    // Ids in the hot set whose message is at most `max_len` long (see `message_len`)
+    pub fn active_within(&self, max_len: usize) -> impl Iterator<Item = &String> {
+        self.by_length.within(max_len)
+    }
+
+    // How many fortunes in the hot set carry `tag`
+    pub fn tagged_count(&self, tag: &str) -> usize {
+        self.by_tag.get(tag).map_or(0, OrdSet::len)
+    }
+
+    // The id at position `n` (wrapping around) among those in the hot set
+    // carrying `tag`, in id order
+    pub fn nth_tagged(&self, tag: &str, n: u64) -> Option<&String> {
+        let ids = self.by_tag.get(tag)?;
+        ids.iter().nth((n % ids.len() as u64) as usize)
+    }
+
+    pub fn views(&self, id: &str) -> u64 {
+        self.views.get(id).map(|views| *views).unwrap_or_default()
+    }
+
+    // Counts a view of a stored fortune and returns the new total. Takes
+    // `&self`, like `touch`, so serving a fortune never waits for a writer.
+    pub fn add_view(&self, id: &str) -> u64 {
+        if !self.contains_key(id) {
+            return 0;
+        }
+        let mut views = self.views.entry(id.to_string()).or_default();
+        *views += 1;
+        self.views_version.fetch_add(1, AtomicOrdering::Relaxed);
+        *views
+    }
+
+    // Adopts a total kept elsewhere, e.g. the shared Redis counter
+    pub fn set_views(&self, id: &str, views: u64) {
+        if self.contains_key(id) && self.views.insert(id.to_string(), views) != Some(views) {
+            self.views_version.fetch_add(1, AtomicOrdering::Relaxed);
+        }
+    }
+
+    // A copy of `fortune` carrying its view count
+    pub fn with_views(&self, fortune: &Fortune) -> Fortune {
+        Fortune {
+            views: self.views(&fortune.id),
+            ..fortune.clone()
+        }
+    }
+
+    // Ids of the `limit` most viewed fortunes in the hot set, ties broken by id
+    pub fn popular(&self, limit: usize) -> Vec<String> {
+        let mut popular: Vec<(u64, &String)> = self.active_ids().map(|id| (self.views(id), id)).collect();
+        popular.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        popular.into_iter().take(limit).map(|(_, id)| id.clone()).collect()
+    }
+
+    // Authors of fortunes in the hot set, matched like messages (ignoring case
+    // and whitespace) and sorted by that normalized name
+    pub fn authors(&self) -> Vec<AuthorCount> {
+        let mut authors: BTreeMap<String, AuthorCount> = BTreeMap::new();
+        for author in self.active_ids().filter_map(|id| self.fields(id)?.author.as_deref()) {
+            authors
+                .entry(normalize(author))
+                .and_modify(|a| a.count += 1)
+                .or_insert_with(|| AuthorCount {
+                    author: author.to_string(),
+                    count: 1,
+                });
+        }
+        authors.into_values().collect()
+    }
+
+    pub fn random<R: Rng>(&self, rng: &mut R) -> Option<&Fortune> {
+        self.by_id.get(self.ids.random(rng)?)
+    }
+
+    // Picks from the first language in `langs` that has any fortunes, falling
+    // back to any fortune at all. With `max_len` only fortunes that short
+    // count, for the language choice too.
+    pub fn random_in<R: Rng>(&self, langs: &[String], max_len: Option<usize>, rng: &mut R) -> Option<&String> {
+        match max_len {
+            None => self.pool_for(langs).random(rng),
+            Some(max_len) => self.lengths_for(langs, max_len).random(max_len, rng),
+        }
+    }
+
+    fn lengths_for(&self, langs: &[String], max_len: usize) -> &LengthIndex {
+        langs
+            .iter()
+            .find_map(|lang| self.by_lang_length.get(lang).filter(|lengths| lengths.count_within(max_len) > 0))
+            .unwrap_or(&self.by_length)
+    }
+
+    fn pool_for(&self, langs: &[String]) -> &IdPool {
+        langs
+            .iter()
+            .find_map(|lang| self.by_lang.get(lang))
+            .unwrap_or(&self.ids)
+    }
+
+    // Like `random_in`, but skips ids in `seen`. Once every fortune in the pool
+    // has been seen any fortune is returned, flagged with `true` so the caller
+    // can start over.
+    pub fn random_unseen<R: Rng>(
+        &self,
+        langs: &[String],
+        max_len: Option<usize>,
+        seen: &HashSet<String>,
+        rng: &mut R,
+    ) -> Option<(&String, bool)> {
+        if let Some(max_len) = max_len {
+            let lengths = self.lengths_for(langs, max_len);
+            for _ in 0..8 {
+                let id = lengths.random(max_len, rng)?;
+                if !seen.contains(id) {
+                    return Some((id, false));
+                }
+            }
+            let unseen: Vec<&String> = lengths.within(max_len).filter(|id| !seen.contains(*id)).collect();
+            return match unseen.choose(rng) {
+                Some(id) => Some((*id, false)),
+                None => Some((lengths.random(max_len, rng)?, true)),
+            };
+        }
+        let pool = self.pool_for(langs);
+        // A few cheap tries first; only scan the pool when most of it was seen
+        for _ in 0..8 {
+            let id = pool.random(rng)?;
+            if !seen.contains(id) {
+                return Some((id, false));
+            }
+        }
+        let unseen: Vec<&String> = pool.ids.iter().filter(|id| !seen.contains(*id)).collect();
+        match unseen.choose(rng) {
+            Some(id) => Some((*id, false)),
+            None => Some((pool.random(rng)?, true)),
+        }
+    }
+
+    // Up to `count` different ids, drawn like `random_unseen`: ids in
+    // `seen` are only drawn once the unseen ones run out, which is flagged
+    // with `true` so the caller can start over
+    pub fn random_distinct<R: Rng>(
+        &self,
+        langs: &[String],
+        max_len: Option<usize>,
+        count: usize,
+        seen: &HashSet<String>,
+        rng: &mut R,
+    ) -> (Vec<&String>, bool) {
+        let ids: Vec<&String> = match max_len {
+            // Sampling straight from the pool needs no pass over it
+            None if seen.is_empty() => {
+                let picked = self.pool_for(langs).sample(count, rng);
+                return (picked.collect(), false);
+            }
+            None => self.pool_for(langs).ids.iter().collect(),
+            Some(max_len) if seen.is_empty() => {
+                let picked = self.lengths_for(langs, max_len).sample(max_len, count, rng);
+                return (picked.collect(), false);
+            }
+            Some(max_len) => self.lengths_for(langs, max_len).within(max_len).collect(),
+        };
+        let (unseen, repeats): (Vec<&String>, Vec<&String>) = ids.into_iter().partition(|id| !seen.contains(*id));
+        let mut picked: Vec<&String> = unseen.choose_multiple(rng, count).copied().collect();
+        let exhausted = picked.len() < count && !repeats.is_empty();
+        if exhausted {
+            picked.extend(repeats.choose_multiple(rng, count - picked.len()).copied());
+        }
+        (picked, exhausted)
+    }
+
+    // Returns the id of a fortune other than `id` whose message normalizes to the same text
+    pub fn find_duplicate(&self, id: &str, message: &str) -> Option<&String> {
+        self.by_message.get(&normalize(message))?.iter().find(|other| other.as_str() != id)
+    }
+}
+
+impl Publish for Fortunes {
+    type Deferred = Vec<Forget>;
+
+    fn take_deferred(&mut self) -> Vec<Forget> {
+        std::mem::take(&mut self.forget)
+    }
+
+    // A fortune cached again later in the same draft keeps its place in the order
+    fn apply(published: &Fortunes, forget: Vec<Forget>) {
+        for forget in forget {
+            let id = match forget {
+                Forget::Fortune(id) => {
+                    published.views.remove(&id);
+                    id
+                }
+                Forget::Body(id) => id,
+            };
+            if !published.by_id.contains_key(&id) {
+                published.recency.forget(&id);
+            }
+        }
+    }
+}
+
+impl Index<&str> for Fortunes {
+    type Output = Fortune;
+
+    fn index(&self, id: &str) -> &Fortune {
+        &self.by_id[id]
+    }
+}
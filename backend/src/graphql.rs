@@ -0,0 +1,289 @@
+use crate::admin::{self, AdminAccess};
+use crate::config::Config;
+use crate::endpoints::{Disabled, Endpoint};
+use crate::jwt::{self, Role};
+use crate::quotas::{self, Creator, Quotas};
+use crate::{audit, language, limits, methods, store, Fortune, FortuneStore, Status};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{Context, EmptySubscription, ErrorExtensions, InputObject, Object, Schema, SimpleObject};
+use std::convert::Infallible;
+use warp::{Filter, Rejection, Reply};
+
+// POST /graphql, over the same store operations as the REST routes and
+// gRPC. Mutations follow the REST rules: READ_ONLY refuses them, new fortunes
+// are moderated unless the caller sends ADMIN_API_KEY, and deletes go to the
+// trash when SOFT_DELETE is on, and DISABLE_ENDPOINTS `create` and `delete`
+// switch off the mutations like the REST routes. Errors carry an `extensions.code` matching
+// the REST status: BAD_REQUEST, FORBIDDEN, CONFLICT, UNPROCESSABLE or
+// UNAVAILABLE, and TOO_MANY_REQUESTS when an API_KEYS key has used up its
+// creates.
+
+pub type FortuneSchema = Schema<Query, Mutation, EmptySubscription>;
+
+// Deeper queries are refused; the schema has no nesting deeper than this
+const MAX_DEPTH: usize = 8;
+const MAX_SEARCH_RESULTS: usize = 100;
+
+#[derive(SimpleObject)]
+#[graphql(name = "Fortune")]
+pub struct FortuneObject {
+    id: String,
+    message: String,
+    lang: String,
+    group: Option<String>,
+    author: Option<String>,
+    tags: Vec<String>,
+    // `approved`, `pending` or `rejected`
+    status: String,
+    views: u64,
+    publish_at: Option<u64>,
+    expires_at: Option<u64>,
+    created_at: Option<u64>,
+    updated_at: Option<u64>,
+}
+
+impl From<Fortune> for FortuneObject {
+    fn from(fortune: Fortune) -> Self {
+        FortuneObject {
+            id: fortune.id,
+            message: fortune.message,
+            lang: fortune.lang,
+            group: fortune.group,
+            author: fortune.author,
+            tags: fortune.tags,
+            status: fortune.status.as_str().to_string(),
+            views: fortune.views,
+            publish_at: fortune.publish_at,
+            expires_at: fortune.expires_at,
+            created_at: fortune.created_at,
+            updated_at: fortune.updated_at,
+        }
+    }
+}
+
+#[derive(InputObject)]
+pub struct NewFortune {
+    // Allocated by the server when omitted
+    id: Option<String>,
+    message: String,
+    lang: Option<String>,
+    group: Option<String>,
+    author: Option<String>,
+    tags: Option<Vec<String>>,
+    publish_at: Option<u64>,
+    expires_at: Option<u64>,
+}
+
+// Settings the resolvers need, shared by every request
+struct Settings {
+    read_only: bool,
+    soft_delete: bool,
+    disabled: Disabled,
+}
+
+// Who sent the request, for the audit log, whether what it creates waits
+// for moderation, and which mutations it may use
+struct Caller {
+    actor: String,
+    needs_review: bool,
+    // SUBMISSION_VERIFICATION is on and no admin key was sent
+    needs_verification: bool,
+    // The caller's JWT role allows the mutations; always with JWT
+    // authentication off
+    may_create: bool,
+    may_delete: bool,
+    // The API_KEYS key the request carries, whose creates count against it
+    creator: Option<Creator>,
+}
+
+fn error(message: impl Into<String>, code: &'static str) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, e| e.set("code", code))
+}
+
+// Refuses a mutation while the store is read-only or DISABLE_ENDPOINTS has
+// switched off the REST route doing the same
+fn check_writable(ctx: &Context<'_>, endpoint: Endpoint) -> async_graphql::Result<()> {
+    let settings = ctx.data_unchecked::<Settings>();
+    if settings.read_only {
+        return Err(error("the fortune store is read-only", "FORBIDDEN"));
+    }
+    if settings.disabled.contains(endpoint) {
+        return Err(error(format!("the {} endpoint is disabled", endpoint.name()), "FORBIDDEN"));
+    }
+    Ok(())
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Published fortunes
+    async fn fortunes(&self, ctx: &Context<'_>) -> Vec<FortuneObject> {
+        let store = ctx.data_unchecked::<FortuneStore>();
+        store::list(store).await.into_iter().map(Into::into).collect()
+    }
+
+    /// A fortune by id; reading it counts as a view
+    async fn fortune(&self, ctx: &Context<'_>, id: String) -> Option<FortuneObject> {
+        store::get(ctx.data_unchecked::<FortuneStore>(), &id).await.map(Into::into)
+    }
+
+    /// A random published fortune, preferring `lang` with English as the fallback
+    async fn random(&self, ctx: &Context<'_>, lang: Option<String>) -> Option<FortuneObject> {
+        let langs = language::preferences(lang.as_deref(), None);
+        store::random(ctx.data_unchecked::<FortuneStore>(), &langs, None, false).await.map(Into::into)
+    }
+
+    /// Published fortunes whose message or author contains `query`, ignoring case
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        #[graphql(default = 20)] limit: usize,
+    ) -> Vec<FortuneObject> {
+        let store = ctx.data_unchecked::<FortuneStore>();
+        store::search(store, &query, limit.min(MAX_SEARCH_RESULTS))
+            .await
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Stores a fortune; `force` allows a message another fortune already has
+    async fn create_fortune(
+        &self,
+        ctx: &Context<'_>,
+        input: NewFortune,
+        #[graphql(default)] force: bool,
+    ) -> async_graphql::Result<FortuneObject> {
+        check_writable(ctx, Endpoint::Create)?;
+        let caller = ctx.data_unchecked::<Caller>();
+        if !caller.may_create {
+            return Err(error("creating fortunes needs the contributor role", "FORBIDDEN"));
+        }
+        if caller.needs_verification {
+            return Err(error("submissions have to be verified through POST /fortunes", "FORBIDDEN"));
+        }
+        let reserved = match &caller.creator {
+            Some(creator) => Some(creator.reserve().await.map_err(|e| error(e, "TOO_MANY_REQUESTS"))?),
+            None => None,
+        };
+        let fortune = Fortune {
+            id: input.id.unwrap_or_default(),
+            message: input.message,
+            lang: input.lang.unwrap_or_else(language::default_lang),
+            group: input.group,
+            author: input.author,
+            tags: input.tags.unwrap_or_default(),
+            status: if caller.needs_review { Status::Pending } else { Status::Approved },
+            publish_at: input.publish_at,
+            expires_at: input.expires_at,
+            ..Default::default()
+        };
+        let created = store::create(ctx.data_unchecked::<FortuneStore>(), fortune, force, &caller.actor).await;
+        if let (Err(_), Some(creator), Some(day)) = (&created, &caller.creator, reserved) {
+            creator.release(day).await;
+        }
+        match created {
+            Ok(fortune) => Ok(fortune.into()),
+            Err(e @ (store::CreateError::ReservedId
+                | store::CreateError::InvalidLang
+                | store::CreateError::TooLong
+                | store::CreateError::InvalidSchedule
+                | store::CreateError::InvalidTags)) => {
+                Err(error(e.to_string(), "BAD_REQUEST"))
+            }
+            Err(e @ store::CreateError::Duplicate(_)) => Err(error(e.to_string(), "CONFLICT")),
+            Err(e @ store::CreateError::Blocked(_)) => Err(error(e.to_string(), "UNPROCESSABLE")),
+            Err(e @ store::CreateError::IdUnavailable) => Err(error(e.to_string(), "UNAVAILABLE")),
+        }
+    }
+
+    /// Deletes a fortune, or moves it to the trash when SOFT_DELETE is on.
+    /// Returns the deleted fortune, or null if there was none with this id.
+    async fn delete_fortune(&self, ctx: &Context<'_>, id: String) -> async_graphql::Result<Option<FortuneObject>> {
+        check_writable(ctx, Endpoint::Delete)?;
+        let store = ctx.data_unchecked::<FortuneStore>();
+        let caller = ctx.data_unchecked::<Caller>();
+        if !caller.may_delete {
+            return Err(error("deleting fortunes needs the admin role", "FORBIDDEN"));
+        }
+        let actor = &caller.actor;
+        let deleted = if ctx.data_unchecked::<Settings>().soft_delete {
+            store::soft_delete(store, &id, actor).await.map(|trashed| trashed.fortune)
+        } else {
+            store::delete(store, &id, actor).await
+        };
+        Ok(deleted.map(Into::into))
+    }
+}
+
+pub fn schema(store: FortuneStore, config: &Config) -> FortuneSchema {
+    Schema::build(Query, Mutation, EmptySubscription)
+        .data(store)
+        .data(Settings {
+            read_only: config.read_only,
+            soft_delete: config.soft_delete,
+            disabled: config.disabled_endpoints(),
+        })
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+async fn graphql_handler(request: async_graphql::Request, caller: Caller, schema: FortuneSchema) -> Result<impl Reply, Infallible> {
+    let response = schema.execute(request.data(caller)).await;
+    Ok(warp::reply::json(&response))
+}
+
+async fn graphiql_handler() -> Result<warp::reply::Response, Infallible> {
+    Ok(warp::reply::html(GraphiQLSource::build().endpoint("/graphql").finish()).into_response())
+}
+
+// POST /graphql, and the GraphiQL playground on GET /graphql when `graphiql` is set
+pub fn routes(store: FortuneStore, config: &Config, access: AdminAccess, quotas: Quotas) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    let schema = schema(store, config);
+    let timeout = config.request_timeout();
+    let graphql = warp::path("graphql").and(warp::path::end());
+
+    let execute = graphql
+        .and(warp::post())
+        .and(limits::json_body(config.max_body_bytes, timeout))
+        .and(audit::actor(audit::Actors::from_config(config)))
+        .and(admin::needs_review(config.moderation, access.clone()))
+        .and(admin::needs_review(config.submission_verification, access.clone()))
+        .and(jwt::allows(access.clone(), Role::Contributor))
+        .and(jwt::allows(access, Role::Admin))
+        .and(quotas::creator(quotas))
+        .and(warp::any().map(move || schema.clone()))
+        .and_then(move |request, actor, needs_review, needs_verification, may_create, may_delete, creator, schema| {
+            let caller = Caller {
+                actor,
+                needs_review,
+                needs_verification,
+                may_create,
+                may_delete,
+                creator,
+            };
+            limits::timed(timeout, graphql_handler(request, caller, schema))
+        });
+
+    let graphiql_enabled = config.graphiql;
+    let graphiql = graphql
+        .and(methods::get_or_head())
+        .and(warp::any().and_then(move || async move {
+            if graphiql_enabled {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        }))
+        .untuple_one()
+        .and_then(graphiql_handler);
+
+    execute.or(graphiql).unify()
+}
@@ -0,0 +1,133 @@
+use crate::{audit, language, store, Fortune, FortuneStore};
+use fortune_common::{error, info};
+use std::net::SocketAddr;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("fortune");
+}
+
+use proto::fortune_service_server::{FortuneService, FortuneServiceServer};
+
+impl From<Fortune> for proto::Fortune {
+    fn from(fortune: Fortune) -> Self {
+        proto::Fortune {
+            id: fortune.id,
+            message: fortune.message,
+            lang: fortune.lang,
+            group: fortune.group.unwrap_or_default(),
+            author: fortune.author.unwrap_or_default(),
+            tags: fortune.tags,
+            status: fortune.status.as_str().to_string(),
+            views: fortune.views,
+            publish_at: fortune.publish_at,
+            expires_at: fortune.expires_at,
+            created_at: fortune.created_at,
+            updated_at: fortune.updated_at,
+        }
+    }
+}
+
+struct GrpcService {
+    store: FortuneStore,
+    // New fortunes wait for approval
+    moderation: bool,
+    // SUBMISSION_VERIFICATION: gRPC callers cannot be verified, so creating
+    // fortunes is refused
+    verification: bool,
+    // READ_ONLY: creating fortunes is refused
+    read_only: bool,
+}
+
+#[tonic::async_trait]
+impl FortuneService for GrpcService {
+    async fn list_fortunes(
+        &self,
+        _request: Request<proto::ListFortunesRequest>,
+    ) -> Result<Response<proto::ListFortunesResponse>, Status> {
+        let fortunes = store::list(&self.store).await.into_iter().map(Into::into).collect();
+        Ok(Response::new(proto::ListFortunesResponse { fortunes }))
+    }
+
+    async fn get_fortune(
+        &self,
+        request: Request<proto::GetFortuneRequest>,
+    ) -> Result<Response<proto::Fortune>, Status> {
+        match store::get(&self.store, &request.into_inner().id).await {
+            Some(fortune) => Ok(Response::new(fortune.into())),
+            None => Err(Status::not_found("fortune not found")),
+        }
+    }
+
+    async fn random_fortune(
+        &self,
+        request: Request<proto::RandomFortuneRequest>,
+    ) -> Result<Response<proto::Fortune>, Status> {
+        let langs = language::preferences(Some(&request.into_inner().lang), None);
+        match store::random(&self.store, &langs, None, false).await {
+            Some(fortune) => Ok(Response::new(fortune.into())),
+            None => Err(Status::not_found("fortune not found")),
+        }
+    }
+
+    async fn create_fortune(
+        &self,
+        request: Request<proto::CreateFortuneRequest>,
+    ) -> Result<Response<proto::Fortune>, Status> {
+        if self.read_only {
+            return Err(Status::permission_denied("the fortune store is read-only"));
+        }
+        if self.verification {
+            return Err(Status::permission_denied("submissions have to be verified through POST /fortunes"));
+        }
+        let actor = audit::grpc_actor(request.remote_addr());
+        let request = request.into_inner();
+        let fortune = Fortune {
+            id: request.id,
+            message: request.message,
+            lang: if request.lang.is_empty() { language::default_lang() } else { request.lang },
+            group: Some(request.group).filter(|g| !g.is_empty()),
+            author: Some(request.author).filter(|a| !a.is_empty()),
+            tags: request.tags,
+            // gRPC callers are not authenticated, so they go through moderation like anonymous HTTP
+            status: if self.moderation { crate::Status::Pending } else { crate::Status::Approved },
+            views: 0,
+            publish_at: request.publish_at,
+            expires_at: request.expires_at,
+            ..Default::default()
+        };
+        match store::create(&self.store, fortune, request.force, &actor).await {
+            Ok(fortune) => Ok(Response::new(fortune.into())),
+            Err(store::CreateError::ReservedId) => Err(Status::invalid_argument("fortune id is reserved")),
+            Err(store::CreateError::InvalidLang) => Err(Status::invalid_argument("invalid language tag")),
+            Err(e @ (store::CreateError::TooLong | store::CreateError::InvalidTags)) => Err(Status::invalid_argument(e.to_string())),
+            Err(store::CreateError::InvalidSchedule) => {
+                Err(Status::invalid_argument("expires_at must be after publish_at"))
+            }
+            Err(store::CreateError::Blocked(reason)) => Err(Status::invalid_argument(reason)),
+            Err(store::CreateError::IdUnavailable) => Err(Status::unavailable("could not allocate a fortune id")),
+            Err(store::CreateError::Duplicate(existing)) => Err(Status::already_exists(format!(
+                "duplicate of fortune {}",
+                existing.id
+            ))),
+        }
+    }
+}
+
+pub fn spawn_server(addr: SocketAddr, store: FortuneStore, moderation: bool, verification: bool, read_only: bool) {
+    tokio::spawn(async move {
+        info!("Starting gRPC server on {}...", addr);
+        let result = tonic::transport::Server::builder()
+            .add_service(FortuneServiceServer::new(GrpcService {
+                store,
+                moderation,
+                verification,
+                read_only,
+            }))
+            .serve(addr)
+            .await;
+        if let Err(e) = result {
+            error!("gRPC server failed: {}", e);
+        }
+    });
+}
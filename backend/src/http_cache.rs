@@ -0,0 +1,53 @@
+use crate::Fortune;
+use fortune_common::compression;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::OnceLock;
+use warp::http::{header, StatusCode};
+use warp::Reply;
+
+// Clients revalidate on every use but can skip the download on a 304
+const CACHE_CONTROL: &str = "no-cache";
+
+static EPOCH: OnceLock<String> = OnceLock::new();
+
+// Random per process so collection versions from before a restart never match
+fn epoch() -> &'static str {
+    EPOCH.get_or_init(|| format!("{:08x}", rand::random::<u32>()))
+}
+
+// The listed fortunes carry their views, so view changes count too
+pub fn collection_tag(version: u64, views_version: u64) -> String {
+    format!("\"{}-{}.{}\"", epoch(), version, views_version)
+}
+
+pub fn fortune_tag(fortune: &Fortune) -> String {
+    let mut hasher = DefaultHasher::new();
+    // Views are part of the body, so they are part of the tag too
+    fortune.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+// Weak comparison as required for If-None-Match; accepts lists and `*`
+pub fn matches(if_none_match: Option<&str>, tag: &str) -> bool {
+    let Some(value) = if_none_match else {
+        return false;
+    };
+    // Compressed copies carry the encoding in their tag
+    value.split(',').map(|candidate| compression::strip_encoding(candidate.trim())).any(|candidate| {
+        candidate == "*" || candidate.trim_start_matches("W/") == tag.trim_start_matches("W/")
+    })
+}
+
+pub fn not_modified(tag: &str) -> warp::reply::Response {
+    tagged(warp::reply::with_status(warp::reply(), StatusCode::NOT_MODIFIED), tag)
+}
+
+pub fn tagged(reply: impl Reply, tag: &str) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    if let (Ok(etag), Ok(cache_control)) = (tag.parse(), CACHE_CONTROL.parse()) {
+        response.headers_mut().insert(header::ETAG, etag);
+        response.headers_mut().insert(header::CACHE_CONTROL, cache_control);
+    }
+    response
+}
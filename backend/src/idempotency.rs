@@ -0,0 +1,185 @@
+use crate::redis_client;
+use fortune_common::{error, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use warp::http::{HeaderValue, StatusCode};
+use warp::hyper::body::to_bytes;
+use warp::reply::Response;
+
+// Request header naming a create that may be retried
+pub const HEADER: &str = "idempotency-key";
+
+// Set on responses that replay an earlier request's
+pub const REPLAYED: &str = "idempotent-replayed";
+
+// How long a claimed key waits for its response; a request that timed out or
+// died never records one, and retries should not be refused for the whole TTL
+const PENDING: Duration = Duration::from_secs(60);
+
+// What a key stands for: nothing yet while its request runs, then that
+// request's response, along with a hash of the request that claimed it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    fingerprint: String,
+    response: Option<Stored>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Stored {
+    status: u16,
+    body: String,
+}
+
+// Fallback for when Redis is not configured
+static LOCAL: OnceLock<Mutex<HashMap<String, (Entry, Instant)>>> = OnceLock::new();
+
+fn local() -> &'static Mutex<HashMap<String, (Entry, Instant)>> {
+    LOCAL.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Accepts up to 255 visible ASCII characters, enough for a UUID or a hash
+pub fn valid(key: &str) -> bool {
+    (1..=255).contains(&key.len()) && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+// Keys are per collection, so two collections never replay each other's creates
+fn key(collection: &str, key: &str) -> String {
+    format!("fortunes:idempotency:{}:{}", collection, key)
+}
+
+// Identifies a request body, so a key reused for a different fortune is caught
+pub fn fingerprint(request: &impl Serialize) -> String {
+    let body = serde_json::to_vec(request).expect("requests serialize");
+    hex::encode(Sha256::digest(body))
+}
+
+enum Claim {
+    // The key is new and now belongs to this request
+    Claimed,
+    Existing(Entry),
+}
+
+// Claims `key` for a request, unless a request already did and is still
+// running or answered within the TTL
+async fn claim(key: &str, fingerprint: &str) -> Claim {
+    let pending = Entry {
+        fingerprint: fingerprint.to_string(),
+        response: None,
+    };
+    if let Some(redis) = redis_client::get_store().await {
+        let pending = serde_json::to_string(&pending).expect("idempotency entries serialize");
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("SET").arg(key).arg(pending).arg("NX").arg("EX").arg(PENDING.as_secs());
+        pipe.cmd("GET").arg(key);
+        let result: redis::RedisResult<(Option<String>, Option<String>)> = redis
+            .connection()
+            .and_then(|mut conn| pipe.query(&mut conn));
+        match result {
+            Ok((Some(_), _)) | Ok((None, None)) => return Claim::Claimed,
+            Ok((None, Some(existing))) => match serde_json::from_str(&existing) {
+                Ok(entry) => return Claim::Existing(entry),
+                Err(e) => warn!("unreadable idempotency entry {}: {}", key, e),
+            },
+            Err(e) => error!("redis idempotency claim failed: {}", e),
+        }
+    }
+
+    let now = Instant::now();
+    let mut local = local().lock().unwrap();
+    local.retain(|_, (_, expires_at)| *expires_at > now);
+    match local.get(key) {
+        Some((entry, _)) => Claim::Existing(entry.clone()),
+        None => {
+            local.insert(key.to_string(), (pending, now + PENDING));
+            Claim::Claimed
+        }
+    }
+}
+
+// Stores the response to a claimed key for `ttl`, or frees the key when the
+// response is a server error worth retrying
+async fn settle(key: &str, fingerprint: &str, response: Option<Stored>, ttl: Duration) {
+    let entry = response.map(|response| Entry {
+        fingerprint: fingerprint.to_string(),
+        response: Some(response),
+    });
+    if let Some(redis) = redis_client::get_store().await {
+        let result = redis.connection().and_then(|mut conn| match &entry {
+            Some(entry) => {
+                let entry = serde_json::to_string(entry).expect("idempotency entries serialize");
+                redis::cmd("SETEX").arg(key).arg(ttl.as_secs()).arg(entry).query::<()>(&mut conn)
+            }
+            None => redis::cmd("DEL").arg(key).query::<()>(&mut conn),
+        });
+        match result {
+            Ok(()) => return,
+            Err(e) => error!("redis idempotency write failed: {}", e),
+        }
+    }
+
+    let mut local = local().lock().unwrap();
+    match entry {
+        Some(entry) => {
+            local.insert(key.to_string(), (entry, Instant::now() + ttl));
+        }
+        None => {
+            local.remove(key);
+        }
+    }
+}
+
+fn json(body: String, status: StatusCode) -> Response {
+    let mut res = Response::new(body.into());
+    *res.status_mut() = status;
+    res.headers_mut().insert("content-type", HeaderValue::from_static("application/json"));
+    res
+}
+
+fn error(message: &str, status: StatusCode) -> Response {
+    json(serde_json::to_string(message).expect("strings serialize"), status)
+}
+
+// Runs a create once per Idempotency-Key. A retry with the same key and body
+// within `ttl` gets the first response again, marked with Idempotent-Replayed;
+// one that arrives while the first is still running gets 409, and one with a
+// different body 422. Server errors are not remembered, so they can be retried.
+pub async fn once<F>(collection: &str, given: Option<String>, fingerprint: String, ttl: Duration, create: F) -> Response
+where
+    F: std::future::Future<Output = Response>,
+{
+    let Some(given) = given else {
+        return create.await;
+    };
+    if !valid(&given) {
+        return error("invalid idempotency key", StatusCode::BAD_REQUEST);
+    }
+    let key = key(collection, &given);
+    match claim(&key, &fingerprint).await {
+        Claim::Existing(entry) if entry.fingerprint != fingerprint => {
+            error("idempotency key was used for a different request", StatusCode::UNPROCESSABLE_ENTITY)
+        }
+        Claim::Existing(Entry { response: None, .. }) => {
+            error("a request with this idempotency key is in progress", StatusCode::CONFLICT)
+        }
+        Claim::Existing(Entry { response: Some(stored), .. }) => {
+            let mut res = json(stored.body, StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK));
+            res.headers_mut().insert(REPLAYED, HeaderValue::from_static("true"));
+            res
+        }
+        Claim::Claimed => {
+            let res = create.await;
+            let (parts, body) = res.into_parts();
+            let body = to_bytes(body).await.unwrap_or_default();
+            let stored = (!parts.status.is_server_error()).then(|| Stored {
+                status: parts.status.as_u16(),
+                body: String::from_utf8_lossy(&body).into_owned(),
+            });
+            settle(&key, &fingerprint, stored, ttl).await;
+            Response::from_parts(parts, body.into())
+        }
+    }
+}
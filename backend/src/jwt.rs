@@ -0,0 +1,396 @@
+use crate::admin::{self, AdminAccess};
+use crate::config::Config;
+use crate::endpoints::Endpoint;
+use crate::fortunes::now_secs;
+use crate::methods::{self, Enabled};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use fortune_common::error;
+use hmac::{Hmac, Mac};
+use ring::signature::{RsaPublicKeyComponents, RSA_PKCS1_2048_8192_SHA256};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use warp::http::Method;
+use warp::path::FullPath;
+use warp::reject::Reject;
+use warp::{Filter, Rejection};
+
+// JWT authentication: with JWT_HS256_SECRET or JWT_JWKS_URL set, callers
+// send `Authorization: Bearer <token>` and the roles in the token decide
+// which routes they may use. Readers read, contributors also create
+// fortunes, admins do everything, including the /admin routes. Requests
+// with ADMIN_API_KEY count as admin, and requests without a token get
+// JWT_ANONYMOUS_ROLE.
+
+// Clock skew allowed on `exp` and `nbf`
+const LEEWAY_SECS: u64 = 60;
+// Keys are fetched again after this, or sooner for an unknown `kid`
+const JWKS_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+// An unknown `kid` fetches the keys at most this often
+const JWKS_MIN_REFETCH: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Reader,
+    Contributor,
+    Admin,
+}
+
+impl Role {
+    pub fn parse(name: &str) -> Option<Role> {
+        match name {
+            "reader" => Some(Role::Reader),
+            "contributor" => Some(Role::Contributor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    // The role a route needs; None for routes anyone may use, such as
+    // /healthz and /verify/{token}
+    fn required(endpoint: Option<Endpoint>) -> Option<Role> {
+        match endpoint? {
+            Endpoint::Create | Endpoint::Batch => Some(Role::Contributor),
+            Endpoint::Delete | Endpoint::Trash | Endpoint::Restore | Endpoint::Admin | Endpoint::Discord => Some(Role::Admin),
+            _ => Some(Role::Reader),
+        }
+    }
+}
+
+// JWT_ROLE_MAP: comma-separated `value:role` pairs translating the values of
+// the roles claim, e.g. `fortune-admins:admin,staff:contributor`
+pub fn parse_role_map(list: &str) -> Result<Vec<(String, Role)>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (value, role) = entry
+                .rsplit_once(':')
+                .ok_or_else(|| format!("JWT_ROLE_MAP entry '{}' is not value:role", entry))?;
+            let role = Role::parse(role.trim())
+                .ok_or_else(|| format!("JWT_ROLE_MAP role '{}' is not reader, contributor or admin", role.trim()))?;
+            Ok((value.trim().to_string(), role))
+        })
+        .collect()
+}
+
+// JWT_ANONYMOUS_ROLE: `none` or a role below admin
+pub fn parse_anonymous_role(name: &str) -> Result<Option<Role>, String> {
+    match name {
+        "none" => Ok(None),
+        "reader" | "contributor" => Ok(Role::parse(name)),
+        _ => Err(format!("JWT_ANONYMOUS_ROLE '{}' is not none, reader or contributor", name)),
+    }
+}
+
+// A missing, malformed, expired or wrongly signed token
+#[derive(Debug)]
+pub struct InvalidToken;
+
+impl Reject for InvalidToken {}
+
+// A valid token, or none, whose role is below what the route needs
+#[derive(Debug)]
+pub struct Forbidden;
+
+impl Reject for Forbidden {}
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+// Modulus and exponent of an RSA public key
+#[derive(Debug, Clone)]
+struct RsaKey {
+    n: Vec<u8>,
+    e: Vec<u8>,
+}
+
+// RSA keys from JWT_JWKS_URL by `kid`, with when they were fetched
+#[derive(Debug, Default)]
+struct Cached {
+    keys: HashMap<String, RsaKey>,
+    fetched: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Jwks {
+    http: reqwest::Client,
+    url: String,
+    cached: RwLock<Cached>,
+    fetching: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: String,
+    alg: Option<String>,
+    #[serde(rename = "use")]
+    usage: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+impl Jwks {
+    fn cached(&self, kid: Option<&str>) -> (Vec<RsaKey>, Option<Instant>) {
+        let cached = self.cached.read().unwrap();
+        let matching = match kid {
+            Some(kid) => cached.keys.get(kid).cloned().into_iter().collect(),
+            None => cached.keys.values().cloned().collect(),
+        };
+        (matching, cached.fetched)
+    }
+
+    // The keys a token may be signed with; fetched when stale, or when the
+    // `kid` is unknown, since the provider may have rotated its keys
+    async fn keys(&self, kid: Option<&str>) -> Vec<RsaKey> {
+        let (keys, fetched) = self.cached(kid);
+        let stale = fetched.is_none_or(|at| at.elapsed() > JWKS_MAX_AGE);
+        let missing = keys.is_empty() && fetched.is_none_or(|at| at.elapsed() > JWKS_MIN_REFETCH);
+        if !stale && !missing {
+            return keys;
+        }
+        let _fetching = self.fetching.lock().await;
+        // Another request may have fetched them while this one waited
+        if self.cached(kid).1 != fetched {
+            return self.cached(kid).0;
+        }
+        match self.fetch().await {
+            Ok(keys) => {
+                *self.cached.write().unwrap() = Cached {
+                    keys,
+                    fetched: Some(Instant::now()),
+                }
+            }
+            Err(e) => {
+                error!("fetching JWT keys from JWT_JWKS_URL failed: {}", e);
+                // Keep the old keys, and wait before trying again
+                self.cached.write().unwrap().fetched = Some(Instant::now());
+            }
+        }
+        self.cached(kid).0
+    }
+
+    async fn fetch(&self) -> Result<HashMap<String, RsaKey>, String> {
+        let response = self.http.get(&self.url).send().await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("answered {}", response.status()));
+        }
+        let set: JwkSet = response.json().await.map_err(|e| e.to_string())?;
+        let keys = set
+            .keys
+            .into_iter()
+            .filter(|jwk| jwk.kty == "RSA" && jwk.alg.as_deref().is_none_or(|alg| alg == "RS256") && jwk.usage.as_deref().is_none_or(|usage| usage == "sig"))
+            .filter_map(|jwk| {
+                let n = URL_SAFE_NO_PAD.decode(jwk.n?).ok()?;
+                let e = URL_SAFE_NO_PAD.decode(jwk.e?).ok()?;
+                Some((jwk.kid, RsaKey { n, e }))
+            })
+            .collect();
+        Ok(keys)
+    }
+}
+
+#[derive(Debug)]
+struct Settings {
+    hs256_secret: Option<Vec<u8>>,
+    jwks: Option<Jwks>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    // Dotted path to the claim holding the roles, e.g. `realm_access.roles`
+    roles_claim: String,
+    role_map: Vec<(String, Role)>,
+    anonymous: Option<Role>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Jwt(Arc<Settings>);
+
+fn audience_matches(claim: Option<&Value>, expected: &str) -> bool {
+    match claim {
+        Some(Value::String(aud)) => aud == expected,
+        Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(expected)),
+        _ => false,
+    }
+}
+
+impl Jwt {
+    // None while neither JWT_HS256_SECRET nor JWT_JWKS_URL is set
+    pub fn from_config(config: &Config) -> Option<Jwt> {
+        if !config.jwt_enabled() {
+            return None;
+        }
+        let jwks = config.jwt_jwks_url.clone().map(|url| Jwks {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("failed to build HTTP client"),
+            url,
+            cached: RwLock::default(),
+            fetching: tokio::sync::Mutex::new(()),
+        });
+        Some(Jwt(Arc::new(Settings {
+            hs256_secret: config.jwt_hs256_secret.clone().map(String::into_bytes),
+            jwks,
+            issuer: config.jwt_issuer.clone(),
+            audience: config.jwt_audience.clone(),
+            roles_claim: config.jwt_roles_claim.clone(),
+            role_map: config.jwt_role_map.as_deref().and_then(|list| parse_role_map(list).ok()).unwrap_or_default(),
+            anonymous: parse_anonymous_role(&config.jwt_anonymous_role).ok().flatten(),
+        })))
+    }
+
+    async fn signature_valid(&self, header: &Header, signed: &str, signature: &[u8]) -> bool {
+        match header.alg.as_str() {
+            "HS256" => {
+                let Some(secret) = &self.0.hs256_secret else {
+                    return false;
+                };
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+                mac.update(signed.as_bytes());
+                mac.verify_slice(signature).is_ok()
+            }
+            "RS256" => {
+                let Some(jwks) = &self.0.jwks else {
+                    return false;
+                };
+                jwks.keys(header.kid.as_deref()).await.iter().any(|key| {
+                    RsaPublicKeyComponents { n: &key.n, e: &key.e }
+                        .verify(&RSA_PKCS1_2048_8192_SHA256, signed.as_bytes(), signature)
+                        .is_ok()
+                })
+            }
+            // Never `none`, and no algorithm the token picks for itself
+            _ => false,
+        }
+    }
+
+    // The claims of a token that is signed with a configured key, unexpired
+    // and, when set, from JWT_ISSUER for JWT_AUDIENCE
+    async fn verify(&self, token: &str) -> Option<serde_json::Map<String, Value>> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return None;
+        };
+        let parsed: Header = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        if !self.signature_valid(&parsed, &token[..header.len() + 1 + payload.len()], &signature).await {
+            return None;
+        }
+        let claims: serde_json::Map<String, Value> = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        let now = now_secs();
+        // Tokens without an expiry would be good forever
+        let exp = claims.get("exp")?.as_u64()?;
+        if exp + LEEWAY_SECS <= now {
+            return None;
+        }
+        if claims.get("nbf").and_then(Value::as_u64).is_some_and(|nbf| nbf > now + LEEWAY_SECS) {
+            return None;
+        }
+        if let Some(issuer) = &self.0.issuer {
+            if claims.get("iss").and_then(Value::as_str) != Some(issuer) {
+                return None;
+            }
+        }
+        if let Some(audience) = &self.0.audience {
+            if !audience_matches(claims.get("aud"), audience) {
+                return None;
+            }
+        }
+        Some(claims)
+    }
+
+    // The highest role among the values of the roles claim, which may be a
+    // string of space-separated values or a list
+    fn role(&self, claims: &serde_json::Map<String, Value>) -> Option<Role> {
+        let mut path = self.0.roles_claim.split('.');
+        let first = claims.get(path.next()?)?;
+        let claim = path.try_fold(first, |value, key| value.get(key))?;
+        let values: Vec<&str> = match claim {
+            Value::String(values) => values.split_whitespace().collect(),
+            Value::Array(values) => values.iter().filter_map(Value::as_str).collect(),
+            _ => return None,
+        };
+        values
+            .into_iter()
+            .filter_map(|value| match self.0.role_map.is_empty() {
+                true => Role::parse(value),
+                false => self.0.role_map.iter().find(|(mapped, _)| mapped == value).map(|(_, role)| *role),
+            })
+            .max()
+    }
+
+    // The role of a request: admin with the admin API key, what its bearer
+    // token grants, at least the anonymous role. Err for an invalid token.
+    pub(crate) async fn resolve(&self, admin_key: Option<&str>, given_key: Option<&str>, authorization: Option<&str>) -> Result<Option<Role>, Rejection> {
+        if let (Some(expected), Some(given)) = (admin_key, given_key) {
+            if admin::key_matches(expected, given) {
+                return Ok(Some(Role::Admin));
+            }
+        }
+        let Some(authorization) = authorization else {
+            return Ok(self.0.anonymous);
+        };
+        let token = authorization
+            .strip_prefix("Bearer ")
+            .or_else(|| authorization.strip_prefix("bearer "))
+            .ok_or_else(|| warp::reject::custom(InvalidToken))?;
+        let claims = self.verify(token.trim()).await.ok_or_else(|| warp::reject::custom(InvalidToken))?;
+        Ok(self.role(&claims).max(self.0.anonymous))
+    }
+}
+
+// Whether the caller has at least `needed`; always true while JWT
+// authentication is off, and false for an invalid token
+pub fn allows(access: AdminAccess, needed: Role) -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .and(warp::header::optional::<String>("authorization"))
+        .then(move |given: Option<String>, authorization: Option<String>| {
+            let access = access.clone();
+            async move {
+                let Some(jwt) = &access.jwt else {
+                    return true;
+                };
+                let role = jwt.resolve(access.key.as_deref(), given.as_deref(), authorization.as_deref()).await;
+                role.ok().flatten().is_some_and(|role| role >= needed)
+            }
+        })
+}
+
+// Holds every route to the role it needs: InvalidToken for an invalid
+// token or none, Forbidden when the token's role is too low
+pub fn required(access: AdminAccess, enabled: Enabled) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |method: Method, path: FullPath, given: Option<String>, authorization: Option<String>| {
+            let access = access.clone();
+            let needed = Role::required(methods::endpoint(&method, path.as_str(), enabled));
+            async move {
+                let (Some(jwt), Some(needed)) = (&access.jwt, needed) else {
+                    return Ok(());
+                };
+                match jwt.resolve(access.key.as_deref(), given.as_deref(), authorization.as_deref()).await? {
+                    Some(role) if role >= needed => Ok(()),
+                    // Without a token, one might help
+                    _ if authorization.is_none() => Err(warp::reject::custom(InvalidToken)),
+                    _ => Err(warp::reject::custom(Forbidden)),
+                }
+            }
+        })
+        .untuple_one()
+}
@@ -0,0 +1,52 @@
+// Language tags are stored lowercased with `-` separators, e.g. `pt-br`
+pub const DEFAULT_LANG: &str = "en";
+
+pub fn default_lang() -> String {
+    DEFAULT_LANG.to_string()
+}
+
+// Canonical form of a language tag, or None if it is not a plausible tag
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.trim().replace('_', "-").to_lowercase();
+    let valid = !tag.is_empty()
+        && tag.len() <= 35
+        && tag.split('-').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+    valid.then_some(tag)
+}
+
+// Tags from an Accept-Language header, most preferred first; `*` and `q=0` entries are dropped
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut weighted: Vec<(f32, String)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let tag = normalize(parts.next()?)?;
+            let q = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            (q > 0.0).then_some((q, tag))
+        })
+        .collect();
+    // Stable, so equally weighted tags keep their header order
+    weighted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    weighted.into_iter().map(|(_, tag)| tag).collect()
+}
+
+// Languages to try in order: the `?lang=` parameter, then Accept-Language,
+// each followed by its primary subtag (`de-ch` then `de`), then English.
+pub fn preferences(query: Option<&str>, header: Option<&str>) -> Vec<String> {
+    let mut tags: Vec<String> = query.and_then(normalize).into_iter().collect();
+    tags.extend(header.map(parse_accept_language).unwrap_or_default());
+    tags.push(default_lang());
+
+    let mut preferred = Vec::with_capacity(tags.len() * 2);
+    for tag in tags {
+        let primary = tag.split('-').next().unwrap_or_default().to_string();
+        for candidate in [tag, primary] {
+            if !preferred.contains(&candidate) {
+                preferred.push(candidate);
+            }
+        }
+    }
+    preferred
+}
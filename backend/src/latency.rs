@@ -0,0 +1,111 @@
+use crate::request_id;
+use fortune_common::warn;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use warp::http::Method;
+use warp::path::FullPath;
+use warp::{Filter, Reply};
+
+tokio::task_local! {
+    static REDIS_USED: Arc<AtomicBool>;
+}
+
+// Response extension set on replies whose handler talked to Redis
+#[derive(Debug, Clone, Copy)]
+struct UsedRedis;
+
+// Notes that the running handler talked to Redis; does nothing outside one
+pub fn mark_redis() {
+    let _ = REDIS_USED.try_with(|used| used.store(true, Ordering::Relaxed));
+}
+
+// Runs a handler and reports whether it talked to Redis. The flag survives
+// the future being dropped, e.g. by a timeout.
+pub async fn tracked<T>(handler: impl Future<Output = T>, used: Arc<AtomicBool>) -> T {
+    REDIS_USED.scope(used, handler).await
+}
+
+pub fn tag(res: &mut warp::reply::Response, used_redis: bool) {
+    if used_redis {
+        res.extensions_mut().insert(UsedRedis);
+    }
+}
+
+// How long a request may take before it is logged as slow
+#[derive(Debug, Clone)]
+pub struct Budget {
+    default: Duration,
+    // Path prefixes with their own budget; the longest match wins
+    routes: Vec<(String, Duration)>,
+}
+
+impl Budget {
+    pub fn new(default: Duration, routes: Vec<(String, Duration)>) -> Self {
+        Budget { default, routes }
+    }
+
+    // Parses `/fortunes/batch=2000,/admin=5000` (milliseconds)
+    pub fn parse_routes(spec: &str) -> Result<Vec<(String, Duration)>, String> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (prefix, ms) = entry.split_once('=').ok_or_else(|| format!("'{}' is not <path>=<ms>", entry))?;
+                let ms: u64 = ms.trim().parse().map_err(|_| format!("'{}' is not a number of milliseconds", ms))?;
+                let prefix = prefix.trim();
+                if !prefix.starts_with('/') {
+                    return Err(format!("'{}' does not start with /", prefix));
+                }
+                Ok((prefix.trim_end_matches('/').to_string(), Duration::from_millis(ms)))
+            })
+            .collect()
+    }
+
+    fn for_path(&self, path: &str) -> Duration {
+        self.routes
+            .iter()
+            .filter(|(prefix, _)| {
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, budget)| *budget)
+    }
+}
+
+// Logs a warning for every request that takes longer than its budget, with
+// the route, status and whether Redis was involved
+pub fn wrap<F, R>(routes: F, budget: Budget) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let budget = Arc::new(budget);
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(request_id::incoming())
+        .and(routes)
+        .map(move |started: Instant, method: Method, path: FullPath, request_id: Option<String>, reply: R| {
+            let res = reply.into_response();
+            let elapsed = started.elapsed();
+            let limit = budget.for_path(path.as_str());
+            if elapsed > limit {
+                warn!(
+                    "slow request: method={} route={} status={} elapsed_ms={} budget_ms={} redis={} request_id={}",
+                    method,
+                    path.as_str(),
+                    res.status().as_u16(),
+                    elapsed.as_millis(),
+                    limit.as_millis(),
+                    res.extensions().get::<UsedRedis>().is_some(),
+                    request_id.as_deref().unwrap_or("-"),
+                );
+            }
+            res
+        })
+}
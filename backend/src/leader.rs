@@ -0,0 +1,170 @@
+use crate::config::Config;
+use crate::redis_client::{self, RedisStore};
+use fortune_common::{error, info};
+use redis::RedisResult;
+use serde::Serialize;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+// Leader election for jobs that should run once across all replicas rather
+// than on each of them: the daily Discord post and removing purged trash from
+// Redis. The replica holding the `fortunes:leader` key in Redis is the leader.
+// It is taken with SET NX PX, renewed every third of LEADER_LEASE_SECS while
+// the key still names this instance, and lapses on its own when the leader
+// dies. A leader that cannot renew steps down once its lease would have run
+// out, so two replicas never both believe they lead. Without Redis each
+// replica is on its own and leads itself.
+
+const KEY: &str = "fortunes:leader";
+
+// Extends the lease only while it is still ours
+const RENEW: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+  return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+struct Lease {
+    instance: String,
+    ttl: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // Until when this instance may act as leader
+    leading_until: Option<Instant>,
+    // Who held the lease at the last attempt, when Redis answered
+    holder: Option<String>,
+}
+
+static LEASE: OnceLock<Lease> = OnceLock::new();
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LeaseStatus {
+    /// This replica's name in the lease
+    pub instance: String,
+    /// This replica runs the singleton jobs
+    pub leader: bool,
+    /// The replica holding the lease at the last attempt; null without Redis
+    pub holder: Option<String>,
+    /// Seconds left on this replica's lease, when it leads through Redis
+    pub expires_in_secs: Option<u64>,
+}
+
+// INSTANCE_ID, else the host name (the pod name on Kubernetes), with a random
+// suffix so a restarted pod does not mistake its predecessor's lease for its own
+fn instance_name(config: &Config) -> String {
+    let base = config
+        .instance_id
+        .clone()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "backend".to_string());
+    format!("{}-{:08x}", base, rand::random::<u32>())
+}
+
+// Starts campaigning for the lease; without LEADER_ELECTION every replica leads
+pub fn init(config: &Config) {
+    if !config.leader_election {
+        return;
+    }
+    let lease = LEASE.get_or_init(|| Lease {
+        instance: instance_name(config),
+        ttl: config.leader_lease(),
+        state: Mutex::new(State::default()),
+    });
+    info!("leader election on as {}", lease.instance);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(lease.ttl / 3);
+        loop {
+            ticker.tick().await;
+            campaign(lease).await;
+        }
+    });
+}
+
+// Renews `instance`'s lease or takes a free one, and returns who holds it
+// afterwards: `instance` when it leads
+pub async fn claim(redis: &RedisStore, instance: &str, ttl: Duration) -> RedisResult<Option<String>> {
+    let ttl_ms = ttl.as_millis() as u64;
+    let mut conn = redis.connection()?;
+    let renewed: i64 = redis::Script::new(RENEW)
+        .key(KEY)
+        .arg(instance)
+        .arg(ttl_ms)
+        .invoke(&mut conn)?;
+    if renewed == 1 {
+        return Ok(Some(instance.to_string()));
+    }
+    let acquired: Option<String> = redis::cmd("SET")
+        .arg(KEY)
+        .arg(instance)
+        .arg("NX")
+        .arg("PX")
+        .arg(ttl_ms)
+        .query(&mut conn)?;
+    if acquired.is_some() {
+        return Ok(Some(instance.to_string()));
+    }
+    redis::cmd("GET").arg(KEY).query(&mut conn)
+}
+
+async fn campaign(lease: &Lease) {
+    let Some(redis) = redis_client::get_store().await else {
+        return;
+    };
+    let started = Instant::now();
+    let result = claim(&redis, &lease.instance, lease.ttl).await;
+
+    let mut state = lease.state.lock().unwrap();
+    let was_leading = state.leading_until.is_some_and(|until| until > Instant::now());
+    match result {
+        Ok(holder) if holder.as_deref() == Some(lease.instance.as_str()) => {
+            // Counted from before the round trip, so it never outlives the key
+            state.leading_until = Some(started + lease.ttl);
+            state.holder = holder;
+            if !was_leading {
+                info!("{} is now the leader", lease.instance);
+            }
+        }
+        Ok(holder) => {
+            state.leading_until = None;
+            state.holder = holder;
+            if was_leading {
+                info!("{} lost the leader lease", lease.instance);
+            }
+        }
+        Err(e) => error!("leader lease renewal failed: {}", e),
+    }
+}
+
+// Whether this replica should run the singleton jobs right now
+pub async fn is_leader() -> bool {
+    let Some(lease) = LEASE.get() else {
+        return true;
+    };
+    if redis_client::get_store().await.is_none() {
+        return true;
+    }
+    let state = lease.state.lock().unwrap();
+    state.leading_until.is_some_and(|until| until > Instant::now())
+}
+
+// None when LEADER_ELECTION is off
+pub async fn status() -> Option<LeaseStatus> {
+    let lease = LEASE.get()?;
+    let leader = is_leader().await;
+    let state = lease.state.lock().unwrap();
+    let now = Instant::now();
+    Some(LeaseStatus {
+        instance: lease.instance.clone(),
+        leader,
+        holder: state.holder.clone(),
+        expires_in_secs: state
+            .leading_until
+            .filter(|until| *until > now)
+            .map(|until| (until - now).as_secs()),
+    })
+}
@@ -0,0 +1,1325 @@
+pub mod admin;
+pub mod analytics;
+pub mod ascii_art;
+pub mod audit;
+pub mod backup;
+pub mod chaos;
+pub mod collections;
+pub mod compression;
+pub mod config;
+pub mod content_filter;
+pub mod db;
+pub mod discord;
+pub mod duplicates;
+pub mod endpoints;
+pub mod events;
+pub mod fortunes;
+pub mod graphql;
+pub mod grpc;
+pub mod http_cache;
+pub mod jwt;
+pub mod idempotency;
+pub mod language;
+pub mod latency;
+pub mod leader;
+pub mod limits;
+pub mod live;
+pub mod lru;
+pub mod methods;
+pub mod negative_cache;
+pub mod openapi;
+pub mod payload;
+pub mod pubsub;
+pub mod quotas;
+pub mod recorder;
+pub mod redis_client;
+pub mod request_id;
+pub mod response_headers;
+pub mod server;
+pub mod rotation;
+pub mod sessions;
+pub mod shared;
+pub mod signing;
+pub mod snapshot;
+pub mod storage;
+pub mod store;
+pub mod streaming;
+pub mod verification;
+pub mod views;
+pub mod webhooks;
+pub mod write_queue;
+
+pub use fortune_common::{access_log, client_ip};
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use warp::{Filter, Reply, Rejection};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use fortunes::{AuthorCount, Fortunes, TrashedFortune};
+use collections::{Collection, Collections};
+use shared::Shared;
+use config::Config;
+use streaming::ListFormat;
+use payload::FortunePayload;
+use endpoints::Endpoint;
+use admin::AdminAccess;
+use jwt::Jwt;
+use quotas::Quotas;
+use verification::{Review, Verification};
+
+// Reported by /healthz; GIT_COMMIT is passed in by the Docker build
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const COMMIT: &str = match option_env!("GIT_COMMIT") {
+    Some(commit) => commit,
+    None => "unknown",
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+pub struct Fortune {
+    /// Allocated by the server when empty or omitted
+    #[serde(default, deserialize_with = "payload::id")]
+    pub id: String,
+    pub message: String,
+    /// Language tag of the message, e.g. `en` or `pt-br`
+    #[serde(default = "language::default_lang")]
+    pub lang: String,
+    /// Shared by translations of the same fortune
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Lowercase themes such as `zen` or `programming`, used by /fortunes/rotation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Set by the server; only approved fortunes are listed or served at random
+    #[serde(default)]
+    pub status: Status,
+    /// How often the fortune was served by id or at random; filled in on reads
+    #[serde(default)]
+    pub views: u64,
+    /// Unix timestamp (seconds) before which the fortune is not listed or served at random
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publish_at: Option<u64>,
+    /// Unix timestamp (seconds) from which the fortune is no longer listed or served at random
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Unix timestamp (seconds) of when the fortune was first stored; set by the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// Unix timestamp (seconds) of the last change to the fortune; set by the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+}
+
+impl Fortune {
+    // Approved and inside the publish window
+    pub fn is_active(&self, now: u64) -> bool {
+        self.status == Status::Approved
+            && self.publish_at.is_none_or(|at| at <= now)
+            && self.expires_at.is_none_or(|at| now < at)
+    }
+
+    pub fn is_scheduled(&self) -> bool {
+        self.publish_at.is_some() || self.expires_at.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    #[default]
+    Approved,
+    Pending,
+    Rejected,
+    // Waits for its submitter to follow the verification link (see verification.rs)
+    Unverified,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Approved => "approved",
+            Status::Pending => "pending",
+            Status::Rejected => "rejected",
+            Status::Unverified => "unverified",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Status> {
+        [Status::Approved, Status::Pending, Status::Rejected, Status::Unverified]
+            .into_iter()
+            .find(|status| status.as_str() == name)
+    }
+}
+
+impl Default for Fortune {
+    fn default() -> Self {
+        Fortune {
+            id: String::new(),
+            message: String::new(),
+            lang: language::default_lang(),
+            group: None,
+            author: None,
+            tags: Vec::new(),
+            status: Status::default(),
+            views: 0,
+            publish_at: None,
+            expires_at: None,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+}
+
+pub type FortuneStore = Arc<Shared<Fortunes>>;
+
+pub fn create_default_store() -> FortuneStore {
+    let mut map = Fortunes::new();
+    map.insert("1".to_string(), Fortune {
+        id: "1".to_string(),
+        message: "A new voyage will fill your life with untold memories.".to_string(),
+        ..Default::default()
+    });
+    map.insert("2".to_string(), Fortune {
+        id: "2".to_string(),
+        message: "The measure of time to your next goal is the measure of your discipline.".to_string(),
+        ..Default::default()
+    });
+    map.insert("3".to_string(), Fortune {
+        id: "3".to_string(),
+        message: "The only way to do well is to do better each day.".to_string(),
+        ..Default::default()
+    });
+    map.insert("4".to_string(), Fortune {
+        id: "4".to_string(),
+        message: "It ain't over till it's EOF.".to_string(),
+        ..Default::default()
+    });
+
+    Arc::new(Shared::new(map))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CreateParams {
+    /// Store the fortune even if another one has the same message
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListParams {
+    /// Only fortunes by this author (case-insensitive)
+    author: Option<String>,
+    /// What to order the list by, `id` when not given
+    sort: Option<Sort>,
+    /// `asc` or `desc`; ascending by default, except for `newest`
+    order: Option<Order>,
+    /// Only fortunes created at or after this Unix timestamp (seconds)
+    since: Option<u64>,
+    /// `ndjson` streams one fortune per line instead of a JSON array
+    #[serde(default)]
+    format: ListFormat,
+    /// Only fortunes at most this many characters (grapheme clusters) long
+    max_len: Option<usize>,
+    /// Comma-separated ids, at most 100: just these fortunes, in this order.
+    /// Unknown and unpublished ids are left out. Not combined with the other filters
+    ids: Option<String>,
+}
+
+// Most fortunes one request may ask for with `ids` or `count`
+const MAX_FORTUNES_PER_REQUEST: usize = 100;
+
+// How `GET /fortunes` orders its list. Ids compare as numbers when both are
+// numeric; fortunes without `created_at` come last when sorting by it. Ties
+// go to the lower id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Sort {
+    Id,
+    // By message, ignoring case and whitespace
+    Message,
+    CreatedAt,
+    // `created_at`, descending unless `order` says otherwise
+    Newest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PopularParams {
+    /// How many fortunes to return, at most 100
+    #[serde(default = "default_popular_limit")]
+    limit: usize,
+}
+
+fn default_popular_limit() -> usize {
+    10
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RandomParams {
+    /// Preferred language; takes precedence over Accept-Language
+    lang: Option<String>,
+    /// Session token; fortunes already served to it are skipped until all have been seen
+    session: Option<String>,
+    /// Answer with the fortune drawn in a `box` or by a `cowsay` cow, as plain text.
+    /// Parsed by the handler: a query that fails to parse would fall through to /fortunes/{id}
+    #[param(value_type = Option<ascii_art::Format>)]
+    format: Option<String>,
+    /// Only draw fortunes at most this many characters (grapheme clusters) long.
+    /// Parsed by the handler, like `format`
+    #[param(value_type = Option<usize>)]
+    max_len: Option<String>,
+    /// Draw this many different fortunes, 1 to 100, answered as an array; fewer
+    /// when not enough fit. Parsed by the handler, like `format`
+    #[param(value_type = Option<usize>)]
+    count: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RotationParams {
+    /// Comma-separated `tag:weight` entries, e.g. `zen:3,programming:1`; a tag
+    /// without a weight counts once. Parsed by the handler, like `format` on /fortunes/random
+    tags: Option<String>,
+    /// Session token that keeps the client's place in the rotation
+    session: Option<String>,
+}
+
+// Outcome of one entry of a batch create
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "result", rename_all = "lowercase")]
+pub enum BatchResult {
+    Created { fortune: Fortune },
+    Failed { id: String, reason: String },
+}
+
+fn with_store(store: FortuneStore) -> impl Filter<Extract = (FortuneStore,), Error = Infallible> + Clone {
+    warp::any().map(move || store.clone())
+}
+
+fn bad_request(message: &str) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&message), warp::http::StatusCode::BAD_REQUEST).into_response()
+}
+
+pub(crate) fn fortune_reply(fortune: Option<Fortune>) -> warp::reply::Response {
+    match fortune {
+        Some(fortune) => warp::reply::with_status(
+            warp::reply::json(&fortune),
+            warp::http::StatusCode::OK
+        ).into_response(),
+        None => warp::reply::with_status(
+            warp::reply::json(&"fortune not found"),
+            warp::http::StatusCode::NOT_FOUND,
+        ).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/fortunes",
+    tag = "fortunes",
+    params(
+        ListParams,
+        ("If-None-Match" = Option<String>, Header, description = "ETag from a previous response"),
+    ),
+    responses(
+        (status = 200, description = "All fortunes in the order asked for, streamed as a JSON array or, with `format=ndjson`, one per line", body = [Fortune]),
+        (status = 304, description = "The collection has not changed"),
+        (status = 400, description = "An unknown `sort` or `order`, more than 100 `ids`, or `ids` with another filter", body = String),
+    )
+)]
+async fn list_fortunes(params: ListParams, if_none_match: Option<String>, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    // Read the version before the list: a mutation in between yields an ETag
+    // older than the data, which costs a re-download but never serves stale data
+    let (version, views_version) = store::version(&store).await;
+    let tag = http_cache::collection_tag(version, views_version);
+    if http_cache::matches(if_none_match.as_deref(), &tag) {
+        return Ok(http_cache::not_modified(&tag));
+    }
+    let ids = match params.ids.as_deref() {
+        Some(_)
+            if params.author.is_some()
+                || params.since.is_some()
+                || params.max_len.is_some()
+                || params.sort.is_some()
+                || params.order.is_some() =>
+        {
+            return Ok(bad_request("ids cannot be combined with author, since, max_len, sort or order"));
+        }
+        Some(ids) => {
+            let ids: Vec<String> = ids.split(',').map(str::trim).filter(|id| !id.is_empty()).map(String::from).collect();
+            if ids.len() > MAX_FORTUNES_PER_REQUEST {
+                return Ok(bad_request("at most 100 ids may be asked for at once"));
+            }
+            store::active_among(&store, &ids).await
+        }
+        None => {
+            let sort = params.sort.unwrap_or(Sort::Id);
+            let descending = match params.order {
+                Some(order) => order == Order::Desc,
+                None => sort == Sort::Newest,
+            };
+            store::active_ids(&store, params.author.as_deref(), params.since, params.max_len, sort, descending).await
+        }
+    };
+    Ok(http_cache::tagged(streaming::fortunes(store, ids, params.format), &tag))
+}
+
+#[utoipa::path(
+    get,
+    path = "/fortunes/popular",
+    tag = "fortunes",
+    params(PopularParams),
+    responses(
+        (status = 200, description = "Published fortunes, most viewed first", body = [Fortune]),
+    )
+)]
+async fn popular_fortunes(params: PopularParams, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    Ok(warp::reply::json(&store::popular(&store, params.limit.min(100)).await))
+}
+
+#[utoipa::path(
+    get,
+    path = "/fortunes/authors",
+    tag = "fortunes",
+    responses(
+        (status = 200, description = "Authors of published fortunes with their fortune count, by name", body = [AuthorCount]),
+    )
+)]
+async fn list_authors(store: FortuneStore) -> Result<impl Reply, Infallible> {
+    Ok(warp::reply::json(&store::authors(&store).await))
+}
+
+#[utoipa::path(
+    get,
+    path = "/fortunes/{id}",
+    tag = "fortunes",
+    params(
+        ("id" = String, Path, description = "Fortune id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag from a previous response"),
+    ),
+    responses(
+        (status = 200, description = "The fortune", body = Fortune),
+        (status = 304, description = "The fortune has not changed"),
+        (status = 404, description = "No fortune with this id", body = String),
+    )
+)]
+async fn get_fortune(id: String, if_none_match: Option<String>, peek: bool, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    let Some(mut fortune) = store::peek(&store, &id).await else {
+        return Ok(fortune_reply(None));
+    };
+    // The tag covers the view count, so a copy stays fresh until someone
+    // else reads the fortune; a 304 is not counted as a view
+    let tag = http_cache::fortune_tag(&fortune);
+    if http_cache::matches(if_none_match.as_deref(), &tag) {
+        return Ok(http_cache::not_modified(&tag));
+    }
+    if !peek {
+        store::view(&store, &mut fortune).await;
+    }
+    let tag = http_cache::fortune_tag(&fortune);
+    Ok(http_cache::tagged(fortune_reply(Some(fortune)), &tag))
+}
+
+#[utoipa::path(
+    get,
+    path = "/fortunes/random",
+    tag = "fortunes",
+    params(
+        RandomParams,
+        ("Accept-Language" = Option<String>, Header, description = "Preferred languages; English is the fallback"),
+        ("fortune_session" = Option<String>, Cookie, description = "Session token, used when `session` is not given"),
+    ),
+    responses(
+        (status = 200, description = "A randomly chosen fortune; plain text with `format`, an array of different fortunes with `count`", body = Fortune),
+        (status = 400, description = "Unknown `format`, a `max_len` that is not a number, a `count` outside 1 to 100, or `count` with `format`", body = String),
+        (status = 404, description = "The store is empty, or no fortune is short enough", body = String),
+    )
+)]
+async fn random_fortune(
+    params: RandomParams,
+    accept_language: Option<String>,
+    session_cookie: Option<String>,
+    session_ttl: Duration,
+    peek: bool,
+    store: FortuneStore,
+) -> Result<impl Reply, Infallible> {
+    let format = match params.format.as_deref().map(ascii_art::Format::parse) {
+        Some(None) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"format must be box or cowsay"),
+                warp::http::StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+        Some(format) => format,
+        None => None,
+    };
+    let max_len = match params.max_len.as_deref().map(str::parse::<usize>) {
+        Some(Err(_)) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"max_len must be a non-negative number"),
+                warp::http::StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+        Some(Ok(max_len)) => Some(max_len),
+        None => None,
+    };
+    let langs = language::preferences(params.lang.as_deref(), accept_language.as_deref());
+    if let Some(count) = params.count.as_deref() {
+        let count = match count.parse::<usize>() {
+            Ok(count) if (1..=MAX_FORTUNES_PER_REQUEST).contains(&count) => count,
+            _ => return Ok(bad_request("count must be a number from 1 to 100")),
+        };
+        if format.is_some() {
+            return Ok(bad_request("count cannot be combined with format"));
+        }
+        let token = sessions::token(params.session, session_cookie);
+        let session = token.as_deref().map(|token| (token, session_ttl));
+        return Ok(warp::reply::json(&store::random_many(&store, &langs, max_len, count, session, peek).await).into_response());
+    }
+    let fortune = match sessions::token(params.session, session_cookie) {
+        Some(token) => store::random_for_session(&store, &langs, max_len, &token, session_ttl, peek).await,
+        None => store::random(&store, &langs, max_len, peek).await,
+    };
+    let Some(format) = format else {
+        return Ok(fortune_reply(fortune));
+    };
+    let (text, status) = match fortune {
+        Some(fortune) => (
+            ascii_art::render(format, &fortune.message, fortune.author.as_deref()),
+            warp::http::StatusCode::OK,
+        ),
+        None => ("fortune not found\n".to_string(), warp::http::StatusCode::NOT_FOUND),
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::with_header(text, "content-type", "text/plain; charset=utf-8"),
+        status,
+    ).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/fortunes/rotation",
+    tag = "fortunes",
+    params(
+        RotationParams,
+        ("fortune_session" = Option<String>, Cookie, description = "Session token, used when `session` is not given"),
+    ),
+    responses(
+        (status = 200, description = "The next fortune in the client's rotation", body = Fortune),
+        (status = 400, description = "`tags` is missing or invalid, or there is no session token", body = String),
+        (status = 404, description = "No published fortune carries any of the tags", body = String),
+    )
+)]
+async fn rotation_fortune(
+    params: RotationParams,
+    session_cookie: Option<String>,
+    session_ttl: Duration,
+    peek: bool,
+    store: FortuneStore,
+) -> Result<impl Reply, Infallible> {
+    let bad_request = |message: String| {
+        warp::reply::with_status(warp::reply::json(&message), warp::http::StatusCode::BAD_REQUEST).into_response()
+    };
+    let parsed = params
+        .tags
+        .as_deref()
+        .ok_or_else(|| "tags is required, e.g. zen:3,programming:1".to_string())
+        .and_then(rotation::Rotation::parse);
+    let rotation = match parsed {
+        Ok(rotation) => rotation,
+        Err(message) => return Ok(bad_request(message)),
+    };
+    let Some(token) = sessions::token(params.session, session_cookie) else {
+        return Ok(bad_request(format!("a session token is required, in `session` or the {} cookie", sessions::COOKIE)));
+    };
+    let collection = store.read().await.collection().unwrap_or(collections::DEFAULT).to_string();
+    let step = match peek {
+        true => rotation::peek(&collection, &token, &rotation).await,
+        false => rotation::advance(&collection, &token, &rotation, session_ttl).await,
+    };
+    Ok(fortune_reply(store::rotation_step(&store, &rotation, step, peek).await))
+}
+
+#[utoipa::path(
+    post,
+    path = "/fortunes",
+    tag = "fortunes",
+    params(
+        CreateParams,
+        ("Idempotency-Key" = Option<String>, Header, description = "Makes the create safe to retry: a repeat with the same key and body within IDEMPOTENCY_TTL_SECS gets the first response again, with `Idempotent-Replayed: true`"),
+        ("X-Submitter-Email" = Option<String>, Header, description = "Where the verification link is mailed; required when SUBMISSION_VERIFICATION is on and VERIFICATION_MAIL_URL is set"),
+    ),
+    request_body(content = Fortune, description = "A fortune; bodies with `api_version` 2 use the FortuneV2 layout"),
+    responses(
+        (status = 200, description = "The stored fortune", body = Fortune),
+        (status = 202, description = "The fortune awaits moderation (MODERATION is on and no admin key was sent), or verification (SUBMISSION_VERIFICATION is on); then the body also has the `verification_token` unless the link was mailed", body = Fortune),
+        (status = 400, description = "The id is reserved, the message is longer than 500 characters, the language tag is invalid, expires_at is not after publish_at, the idempotency key is invalid or X-Submitter-Email is missing or invalid", body = String),
+        (status = 403, description = "The backend is read-only", body = String),
+        (status = 409, description = "A fortune with the same message exists; the body is that fortune. Also sent while an earlier request with the same idempotency key is still running", body = Fortune),
+        (status = 422, description = "The content filter rejected the message, or the idempotency key was used with a different body; the body is the reason", body = String),
+        (status = 503, description = "The verification token could not be stored or mailed; the fortune was not kept", body = String),
+    )
+)]
+async fn create_fortune(
+    params: CreateParams,
+    idempotency_key: Option<String>,
+    payload: FortunePayload,
+    actor: String,
+    review: Review,
+    store: FortuneStore,
+    idempotency_ttl: Duration,
+) -> Result<impl Reply, Infallible> {
+    let mut fortune = payload.0;
+    fortune.status = review.status();
+    let submitter = match review {
+        Review::Verification(submitter) => Some(submitter),
+        _ => None,
+    };
+    if let Some(Err(reason)) = submitter.as_ref().map(verification::check) {
+        return Ok(warp::reply::with_status(warp::reply::json(&reason), warp::http::StatusCode::BAD_REQUEST).into_response());
+    }
+    let fingerprint = idempotency::fingerprint(&(&fortune, params.force));
+    let collection = store.read().await.collection().unwrap_or(collections::DEFAULT).to_string();
+    let create = store_fortune(store, fortune, params.force, actor, submitter);
+    Ok(idempotency::once(&collection, idempotency_key, fingerprint, idempotency_ttl, create).await)
+}
+
+async fn store_fortune(
+    store: FortuneStore,
+    fortune: Fortune,
+    force: bool,
+    actor: String,
+    submitter: Option<verification::Submitter>,
+) -> warp::reply::Response {
+    let mut res = match store::create(&store, fortune, force, &actor).await {
+        Ok(fortune) if fortune.status == Status::Unverified => match submitter {
+            Some(submitter) => verification::started(&store, fortune, submitter, &actor).await,
+            None => warp::reply::with_status(warp::reply::json(&fortune), warp::http::StatusCode::ACCEPTED).into_response(),
+        },
+        Ok(fortune) if fortune.status == Status::Pending => warp::reply::with_status(
+            warp::reply::json(&fortune),
+            warp::http::StatusCode::ACCEPTED,
+        ).into_response(),
+        Ok(fortune) => warp::reply::json(&fortune).into_response(),
+        Err(store::CreateError::ReservedId) => warp::reply::with_status(
+            warp::reply::json(&"fortune id is reserved"),
+            warp::http::StatusCode::BAD_REQUEST,
+        ).into_response(),
+        Err(store::CreateError::InvalidLang) => warp::reply::with_status(
+            warp::reply::json(&"invalid language tag"),
+            warp::http::StatusCode::BAD_REQUEST,
+        ).into_response(),
+        Err(store::CreateError::InvalidSchedule) => warp::reply::with_status(
+            warp::reply::json(&"expires_at must be after publish_at"),
+            warp::http::StatusCode::BAD_REQUEST,
+        ).into_response(),
+        Err(e @ (store::CreateError::TooLong | store::CreateError::InvalidTags)) => warp::reply::with_status(
+            warp::reply::json(&e.to_string()),
+            warp::http::StatusCode::BAD_REQUEST,
+        ).into_response(),
+        Err(store::CreateError::Duplicate(existing)) => warp::reply::with_status(
+            warp::reply::json(&existing),
+            warp::http::StatusCode::CONFLICT,
+        ).into_response(),
+        Err(store::CreateError::Blocked(reason)) => warp::reply::with_status(
+            warp::reply::json(&reason),
+            warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+        ).into_response(),
+        Err(store::CreateError::IdUnavailable) => warp::reply::with_status(
+            warp::reply::json(&"could not allocate a fortune id"),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ).into_response(),
+    };
+    // Counted against the API key's daily creates
+    if res.status().is_success() {
+        res.extensions_mut().insert(quotas::Created(1));
+    }
+    res
+}
+
+#[utoipa::path(
+    post,
+    path = "/fortunes/batch",
+    tag = "fortunes",
+    params(CreateParams),
+    request_body(content = Vec<Fortune>, description = "Fortunes, each in the layout named by its own `api_version`"),
+    responses(
+        (status = 200, description = "One result per submitted fortune, in order; created fortunes may be pending moderation", body = Vec<BatchResult>),
+        (status = 403, description = "The backend is read-only, or SUBMISSION_VERIFICATION is on and no admin key was sent", body = String),
+        (status = 413, description = "The body is larger than MAX_BATCH_BYTES", body = String),
+    )
+)]
+async fn create_batch(
+    params: CreateParams,
+    fortunes: Vec<FortunePayload>,
+    actor: String,
+    needs_review: bool,
+    needs_verification: bool,
+    store: FortuneStore,
+) -> Result<impl Reply, Infallible> {
+    if needs_verification {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"submissions have to be verified one at a time through POST /fortunes"),
+            warp::http::StatusCode::FORBIDDEN,
+        )
+        .into_response());
+    }
+    let fortunes: Vec<Fortune> = fortunes.into_iter().map(|payload| payload.0).collect();
+    let status = if needs_review { Status::Pending } else { Status::Approved };
+    let ids: Vec<String> = fortunes.iter().map(|f| f.id.clone()).collect();
+    let fortunes = fortunes.into_iter().map(|fortune| Fortune { status, ..fortune }).collect();
+    let results: Vec<BatchResult> = store::create_batch(&store, fortunes, params.force, &actor)
+        .await
+        .into_iter()
+        .zip(ids)
+        .map(|(result, id)| match result {
+            Ok(fortune) => BatchResult::Created { fortune },
+            Err(e) => BatchResult::Failed { id, reason: e.to_string() },
+        })
+        .collect();
+    let created = results.iter().filter(|result| matches!(result, BatchResult::Created { .. })).count();
+    let mut res = warp::reply::json(&results).into_response();
+    res.extensions_mut().insert(quotas::Created(created as u64));
+    Ok(res)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/fortunes/{id}",
+    tag = "fortunes",
+    params(("id" = String, Path, description = "Fortune id")),
+    responses(
+        (status = 200, description = "The deleted fortune; with SOFT_DELETE on, the trashed fortune including `deleted_at`", body = Fortune),
+        (status = 403, description = "The backend is read-only", body = String),
+        (status = 404, description = "No fortune with this id", body = String),
+    )
+)]
+async fn delete_fortune(id: String, soft: bool, actor: String, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    if !soft {
+        return Ok(fortune_reply(store::delete(&store, &id, &actor).await));
+    }
+    Ok(match store::soft_delete(&store, &id, &actor).await {
+        Some(trashed) => warp::reply::json(&trashed).into_response(),
+        None => fortune_reply(None),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/fortunes/trash",
+    tag = "fortunes",
+    responses((status = 200, description = "Soft-deleted fortunes, newest first", body = [TrashedFortune]))
+)]
+async fn list_trash(store: FortuneStore) -> Result<impl Reply, Infallible> {
+    Ok(warp::reply::json(&store::trash(&store).await))
+}
+
+#[utoipa::path(
+    post,
+    path = "/fortunes/{id}/restore",
+    tag = "fortunes",
+    params(("id" = String, Path, description = "Fortune id")),
+    responses(
+        (status = 200, description = "The restored fortune", body = Fortune),
+        (status = 403, description = "The backend is read-only", body = String),
+        (status = 404, description = "No fortune with this id in the trash", body = String),
+        (status = 409, description = "Another fortune now uses this id", body = String),
+    )
+)]
+async fn restore_fortune(id: String, actor: String, store: FortuneStore) -> Result<impl Reply, Infallible> {
+    Ok(match store::restore(&store, &id, &actor).await {
+        Ok(fortune) => fortune_reply(Some(fortune)),
+        Err(store::RestoreError::NotInTrash) => warp::reply::with_status(
+            warp::reply::json(&"fortune not in trash"),
+            warp::http::StatusCode::NOT_FOUND,
+        ).into_response(),
+        Err(store::RestoreError::IdInUse) => warp::reply::with_status(
+            warp::reply::json(&"fortune id is in use"),
+            warp::http::StatusCode::CONFLICT,
+        ).into_response(),
+    })
+}
+
+// Rejection for mutating requests while READ_ONLY is set
+#[derive(Debug)]
+pub struct ReadOnly;
+
+impl warp::reject::Reject for ReadOnly {}
+
+// Passes unless the backend runs with READ_ONLY
+pub(crate) fn writable(read_only: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || async move {
+            if read_only {
+                Err(warp::reject::custom(ReadOnly))
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one()
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HealthParams {
+    /// Check each component the backend depends on
+    #[serde(default)]
+    verbose: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Health {
+    /// `ok`, or `degraded` when a configured component is down
+    status: &'static str,
+    read_only: bool,
+    version: &'static str,
+    commit: &'static str,
+    /// Only with `verbose`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    components: Option<Components>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Components {
+    redis: Component,
+    database: Component,
+    store: StoreHealth,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Component {
+    Up,
+    Down,
+    // Not configured
+    Disabled,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StoreHealth {
+    /// Published fortunes
+    count: usize,
+}
+
+// Which optional components /healthz?verbose=true should check
+#[derive(Clone, Copy)]
+struct Configured {
+    redis: bool,
+    database: bool,
+}
+
+async fn redis_health() -> Component {
+    match redis_client::get_store().await {
+        Some(redis) if redis.ping().await => Component::Up,
+        _ => Component::Down,
+    }
+}
+
+async fn database_health() -> Component {
+    match db::get_pool().await {
+        Some(pool) if sqlx::query("SELECT 1").execute(&pool).await.is_ok() => Component::Up,
+        _ => Component::Down,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "fortunes",
+    params(HealthParams),
+    responses(
+        (status = 200, description = "The backend is up; with `verbose`, how each component is doing", body = Health),
+    )
+)]
+async fn healthz_handler(
+    params: HealthParams,
+    read_only: bool,
+    configured: Configured,
+    store: FortuneStore,
+) -> Result<impl Reply, Infallible> {
+    let components = if params.verbose {
+        Some(Components {
+            redis: if configured.redis { redis_health().await } else { Component::Disabled },
+            database: if configured.database { database_health().await } else { Component::Disabled },
+            store: StoreHealth {
+                count: store.read().await.active_ids().count(),
+            },
+        })
+    } else {
+        None
+    };
+    let degraded = components
+        .as_ref()
+        .is_some_and(|c| c.redis == Component::Down || c.database == Component::Down);
+    Ok(warp::reply::json(&Health {
+        status: if degraded { "degraded" } else { "ok" },
+        read_only,
+        version: VERSION,
+        commit: COMMIT,
+        components,
+    }))
+}
+
+// Passes only when `enabled` is set; otherwise the route does not exist
+fn enabled(enabled: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || async move {
+            if enabled {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "Prometheus text metrics", body = String, content_type = "text/plain"))
+)]
+async fn metrics_handler() -> Result<impl Reply, Infallible> {
+    Ok(warp::reply::with_header(
+        format!(
+            "{}{}{}{}{}{}{}{}",
+            write_queue::metrics(),
+            redis_client::metrics(),
+            webhooks::metrics(),
+            events::metrics(),
+            lru::metrics(),
+            negative_cache::metrics(),
+            analytics::metrics(),
+            chaos::metrics(),
+        ),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
+async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    if err.is_not_found() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"not found"),
+            warp::http::StatusCode::NOT_FOUND,
+        ))
+    } else if err.find::<collections::UnknownCollection>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"unknown collection"),
+            warp::http::StatusCode::NOT_FOUND,
+        ))
+    } else if err.find::<admin::Unauthorized>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"invalid API key"),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ))
+    } else if err.find::<jwt::InvalidToken>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"missing or invalid bearer token"),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ))
+    } else if err.find::<jwt::Forbidden>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"the token's role may not use this route"),
+            warp::http::StatusCode::FORBIDDEN,
+        ))
+    } else if err.find::<signing::BadSignature>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"missing or invalid request signature"),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ))
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"request body too large"),
+            warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+        ))
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"content-length required"),
+            warp::http::StatusCode::LENGTH_REQUIRED,
+        ))
+    } else if err.find::<ReadOnly>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"the fortune store is read-only"),
+            warp::http::StatusCode::FORBIDDEN,
+        ))
+    } else if err.find::<limits::Timeout>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"request timed out"),
+            warp::http::StatusCode::REQUEST_TIMEOUT,
+        ))
+    } else if let Some(limits::InvalidBody(e)) = err.find() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&format!("invalid request body: {}", e)),
+            warp::http::StatusCode::BAD_REQUEST,
+        ))
+    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"invalid query"),
+            warp::http::StatusCode::BAD_REQUEST,
+        ))
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"method not allowed"),
+            warp::http::StatusCode::METHOD_NOT_ALLOWED,
+        ))
+    } else {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&"internal server error"),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ))
+    }
+}
+
+pub fn routes(store: FortuneStore, config: &Config) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    routes_with_collections(store, Collections::from_config(config), config)
+}
+
+// `routes` serving named collections that were already loaded, as the server
+// does; `store` is the default collection
+pub fn routes_with_collections(
+    store: FortuneStore,
+    collections: Collections,
+    config: &Config,
+) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    // /fortunes, or /collections/{name}/fortunes for a named collection
+    let fortunes = collections::scope(store.clone(), collections.clone());
+    // Writes to a collection with an API key have to carry it
+    let write_key = || warp::header::optional::<String>("x-api-key");
+    // Every handler that touches the store is bounded by the request timeout
+    let timeout = config.request_timeout();
+    let proxies = config.trusted_proxies();
+    let actors = audit::Actors::from_config(config);
+    let disabled = config.disabled_endpoints();
+    let recorder = config.flight_recorder();
+    let quotas = Quotas::from_config(config);
+    let access = AdminAccess::new(config.admin_api_key.clone(), Jwt::from_config(config));
+    let verification = Verification::from_config(config);
+
+    // GET /fortunes - list all fortunes
+    let list = fortunes
+        .clone()
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(enabled(disabled.is_enabled(Endpoint::List)))
+        .and(warp::query::<ListParams>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and_then(move |collection: Collection, params, if_none_match| {
+            limits::timed(timeout, list_fortunes(params, if_none_match, collection.store))
+        });
+
+    // GET /fortunes/popular - most viewed fortunes
+    let popular = fortunes
+        .clone()
+        .and(warp::path("popular"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(enabled(disabled.is_enabled(Endpoint::Popular)))
+        .and(warp::query::<PopularParams>())
+        .and_then(move |collection: Collection, params| limits::timed(timeout, popular_fortunes(params, collection.store)));
+
+    // GET /fortunes/events - the mutation event log from Redis
+    let events = enabled(disabled.is_enabled(Endpoint::Events)).and(events::routes(timeout));
+
+    // GET /fortunes/authors - authors with their fortune counts
+    let authors = fortunes
+        .clone()
+        .and(warp::path("authors"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(enabled(disabled.is_enabled(Endpoint::Authors)))
+        .and_then(move |collection: Collection| limits::timed(timeout, list_authors(collection.store)));
+
+    // GET /fortunes/ws - WebSocket stream of created and updated fortunes
+    let ws = warp::path("fortunes")
+        .and(warp::path("ws"))
+        .and(warp::path::end())
+        .and(enabled(disabled.is_enabled(Endpoint::Ws)))
+        .and(warp::ws())
+        .map(live::ws_handler);
+
+    // GET /fortunes/random - get random fortune; must be tried before the
+    // {id} route, which would otherwise capture "random" as an id
+    let session_ttl = config.session_ttl();
+    let random = fortunes
+        .clone()
+        .and(warp::path("random"))
+        .and(warp::path::end())
+        .and(methods::get_or_peek())
+        .and(enabled(disabled.is_enabled(Endpoint::Random)))
+        .and(warp::query::<RandomParams>())
+        .and(warp::header::optional::<String>("accept-language"))
+        .and(warp::cookie::optional::<String>(sessions::COOKIE))
+        .and_then(move |collection: Collection, peek, params, accept_language, session_cookie| {
+            limits::timed(timeout, random_fortune(params, accept_language, session_cookie, session_ttl, peek, collection.store))
+        });
+
+    // GET /fortunes/rotation - the next fortune of a weighted tag rotation,
+    // kept per session token; before {id} like random
+    let rotation = fortunes
+        .clone()
+        .and(warp::path("rotation"))
+        .and(warp::path::end())
+        .and(methods::get_or_peek())
+        .and(enabled(disabled.is_enabled(Endpoint::Rotation)))
+        .and(warp::query::<RotationParams>())
+        .and(warp::cookie::optional::<String>(sessions::COOKIE))
+        .and_then(move |collection: Collection, peek, params, session_cookie| {
+            limits::timed(timeout, rotation_fortune(params, session_cookie, session_ttl, peek, collection.store))
+        });
+
+    // GET /fortunes/trash - soft-deleted fortunes, when SOFT_DELETE is on
+    let trash = fortunes
+        .clone()
+        .and(warp::path("trash"))
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(enabled(config.soft_delete && disabled.is_enabled(Endpoint::Trash)))
+        .and_then(move |collection: Collection| limits::timed(timeout, list_trash(collection.store)));
+
+    // POST /fortunes/{id}/restore - bring a fortune back from the trash
+    let restore = fortunes
+        .clone()
+        .and(warp::post())
+        .and(enabled(config.soft_delete && disabled.is_enabled(Endpoint::Restore)))
+        .and(writable(config.read_only))
+        .and(write_key())
+        .and_then(collections::authorize)
+        .and(warp::path::param())
+        .and(warp::path("restore"))
+        .and(warp::path::end())
+        .and(audit::actor(actors.clone()))
+        .and_then(move |store, id, actor| limits::timed(timeout, restore_fortune(id, actor, store)));
+
+    // GET /fortunes/{id} - get specific fortune
+    let get = fortunes
+        .clone()
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(methods::get_or_peek())
+        .and(enabled(disabled.is_enabled(Endpoint::Get)))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and_then(move |collection: Collection, id, peek, if_none_match| {
+            limits::timed(timeout, get_fortune(id, if_none_match, peek, collection.store))
+        });
+
+    // POST /fortunes - create new fortune
+    // Boxed, as the idempotency wrapper makes its future large enough to
+    // overflow the stack once nested in the full route tree
+    let idempotency_ttl = config.idempotency_ttl();
+    let create = fortunes
+        .clone()
+        .and(warp::post())
+        .and(enabled(disabled.is_enabled(Endpoint::Create)))
+        .and(writable(config.read_only))
+        .and(write_key())
+        .and_then(collections::authorize)
+        .and(warp::path::end())
+        .and(warp::query::<CreateParams>())
+        .and(warp::header::optional::<String>(idempotency::HEADER))
+        .and(limits::json_body(config.max_body_bytes, timeout))
+        .and(audit::actor(actors.clone()))
+        .and(admin::needs_review(config.moderation, access.clone()))
+        .and(verification::submitter(verification.clone(), access.clone()))
+        .and_then(move |store, params, idempotency_key, fortune, actor, needs_review, submitter| {
+            let review = Review::new(needs_review, submitter);
+            limits::timed(timeout, create_fortune(params, idempotency_key, fortune, actor, review, store, idempotency_ttl))
+        })
+        .boxed();
+
+    // POST /fortunes/batch - create many fortunes at once
+    let batch = fortunes
+        .clone()
+        .and(warp::post())
+        .and(enabled(disabled.is_enabled(Endpoint::Batch)))
+        .and(writable(config.read_only))
+        .and(write_key())
+        .and_then(collections::authorize)
+        .and(warp::path("batch"))
+        .and(warp::path::end())
+        .and(warp::query::<CreateParams>())
+        .and(limits::json_body(config.max_batch_bytes, timeout))
+        .and(audit::actor(actors.clone()))
+        .and(admin::needs_review(config.moderation, access.clone()))
+        .and(verification::submitter(verification.clone(), access.clone()))
+        .and_then(move |store, params, fortunes, actor, needs_review, submitter: Option<verification::Submitter>| {
+            limits::timed(timeout, create_batch(params, fortunes, actor, needs_review, submitter.is_some(), store))
+        });
+
+    // GET /verify/{token} - the page confirming a submission
+    let confirm = warp::path("verify")
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(enabled(verification.is_some()))
+        .and(writable(config.read_only))
+        .and(with_store(store.clone()))
+        .and_then({
+            let collections = collections.clone();
+            move |token, store| {
+                let collections = collections.clone();
+                limits::timed(timeout, verification::confirm_handler(token, store, collections))
+            }
+        });
+
+    // POST /verify/{token} - publish a submission with its one-time token
+    let verify_moderation = verification.as_ref().is_some_and(Verification::moderation);
+    let verify = warp::path("verify")
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(enabled(verification.is_some()))
+        .and(writable(config.read_only))
+        .and(audit::actor(actors.clone()))
+        .and(with_store(store.clone()))
+        .and_then(move |token, actor, store| {
+            let collections = collections.clone();
+            limits::timed(timeout, verification::verify_handler(token, verify_moderation, actor, store, collections))
+        });
+
+    // DELETE /fortunes/{id} - delete a fortune, or move it to the trash
+    let soft_delete = config.soft_delete;
+    let delete = fortunes
+        .and(warp::delete())
+        .and(enabled(disabled.is_enabled(Endpoint::Delete)))
+        .and(writable(config.read_only))
+        .and(write_key())
+        .and_then(collections::authorize)
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(audit::actor(actors.clone()))
+        .and_then(move |store, id, actor| limits::timed(timeout, delete_fortune(id, soft_delete, actor, store)));
+
+    // GET /healthz - liveness, whether writes are accepted and, with
+    // ?verbose=true, the state of each component
+    let read_only = config.read_only;
+    let configured = Configured {
+        redis: config.redis_dns.is_some(),
+        database: config.database_url.is_some(),
+    };
+    let healthz = warp::path("healthz")
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(warp::query::<HealthParams>())
+        .and(with_store(store.clone()))
+        .and_then(move |params, store| healthz_handler(params, read_only, configured, store));
+
+    // GET /metrics - write-behind queue metrics
+    let metrics = warp::path("metrics")
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(enabled(disabled.is_enabled(Endpoint::Metrics)))
+        .and_then(metrics_handler);
+
+    // GET /openapi.json and /docs - API specification and Swagger UI
+    let spec = warp::path("openapi.json")
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(enabled(disabled.is_enabled(Endpoint::Docs)))
+        .and_then(openapi::spec_handler);
+
+    let docs = warp::path("docs")
+        .and(warp::path::end())
+        .and(methods::get_or_head())
+        .and(enabled(disabled.is_enabled(Endpoint::Docs)))
+        .and_then(openapi::docs_handler);
+
+    // /admin/* - operational endpoints guarded by ADMIN_API_KEY or an admin JWT
+    let admin = enabled(disabled.is_enabled(Endpoint::Admin)).and(admin::routes(store.clone(), access.clone(), config.read_only, actors.clone(), recorder.clone(), quotas.clone()));
+
+    // POST /admin/backup and /admin/restore - snapshots in an S3-compatible bucket
+    let backup = enabled(disabled.is_enabled(Endpoint::Admin)).and(backup::routes(
+        store.clone(),
+        access.clone(),
+        config.read_only,
+        actors.clone(),
+        backup::Bucket::from_config(config),
+    ));
+
+    // POST /integrations/discord/test - post the fortune of the day now
+    let discord = enabled(disabled.is_enabled(Endpoint::Discord)).and(discord::routes(
+        store.clone(),
+        access.clone(),
+        discord::Discord::from_config(config),
+    ));
+
+    // POST /graphql - GraphQL queries and mutations over the same store
+    let graphql = enabled(disabled.is_enabled(Endpoint::Graphql)).and(graphql::routes(store.clone(), config, access.clone(), quotas.clone()));
+
+    // OPTIONS on any route - the methods it allows
+    let enabled = methods::Enabled {
+        soft_delete: config.soft_delete,
+        admin: config.has_admin(),
+        graphiql: config.graphiql,
+        verification: config.submission_verification,
+        disabled,
+    };
+    let options = methods::options(enabled);
+
+    // With REQUEST_SIGNING_SECRET, writes must be signed or carry the admin key
+    let signed = signing::required(
+        config.request_signing_secret.clone(),
+        config.request_signing_max_age_secs,
+        config.admin_api_key.clone(),
+    );
+
+    let api = options
+        .or(list)
+        .or(ws)
+        .or(random)
+        .or(rotation)
+        .or(authors)
+        .or(popular)
+        .or(events)
+        .or(trash)
+        .or(get)
+        .or(create)
+        .or(batch)
+        .or(delete)
+        .or(restore)
+        .or(confirm)
+        .or(verify)
+        .or(healthz)
+        .or(metrics)
+        .or(spec)
+        .or(docs)
+        .or(admin)
+        .or(backup)
+        .or(discord)
+        .or(graphql);
+    // Boxed so the full filter type stays within the compiler's recursion limit
+    let api = signed
+        .and(jwt::required(access, enabled))
+        .and(quotas::admit(quotas, enabled))
+        .and(quotas::outcome(api))
+        .and_then(quotas::counted)
+        .boxed()
+        .recover(quotas::refused)
+        .unify()
+        .recover(handle_rejection);
+    let api = methods::finish(api, enabled);
+    let api = analytics::wrap(api, enabled, proxies.clone());
+    let api = chaos::wrap(api, config.chaos());
+    let api = latency::wrap(api, config.latency_budget());
+
+    let api = request_id::incoming()
+        .and(api)
+        .map(request_id::echo);
+    let api = recorder::wrap(api, recorder);
+    let api = request_id::log(api, proxies);
+    let api = response_headers::wrap(api, config.response_headers());
+    access_log::wrap(api, config.access_log())
+}
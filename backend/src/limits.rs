@@ -0,0 +1,89 @@
+use serde::de::DeserializeOwned;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::{latency, recorder, signing};
+use futures_util::{Stream, TryStreamExt};
+use warp::hyper::body::{Buf, Bytes};
+use warp::reject::Reject;
+use warp::{Filter, Rejection, Reply};
+
+#[derive(Debug)]
+pub struct Timeout;
+
+impl Reject for Timeout {}
+
+#[derive(Debug)]
+pub struct InvalidBody(pub String);
+
+impl Reject for InvalidBody {}
+
+fn timeout_reply() -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&"request timed out"),
+        warp::http::StatusCode::REQUEST_TIMEOUT,
+    )
+    .into_response()
+}
+
+async fn read_body<S, B>(body: S) -> Result<Vec<u8>, warp::Error>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    body.try_fold(Vec::new(), |mut bytes, mut chunk| async move {
+        while chunk.has_remaining() {
+            let part = chunk.chunk();
+            let len = part.len();
+            bytes.extend_from_slice(part);
+            chunk.advance(len);
+        }
+        Ok(bytes)
+    })
+    .await
+}
+
+// JSON request body capped at `max_bytes` that must arrive within `timeout`,
+// so slow or oversized uploads cannot tie up the server. A body sent with an
+// X-Content-SHA256 header has to match it (see signing.rs). The raw body is
+// left for the flight recorder when the request carries its slot.
+pub fn json_body<T>(max_bytes: u64, timeout: Duration) -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Send,
+{
+    warp::body::content_length_limit(max_bytes)
+        .and(warp::header::optional::<String>(signing::CONTENT_HEADER))
+        .and(warp::ext::optional::<recorder::RequestBody>())
+        .and(warp::body::stream())
+        .and_then(move |content_hash: Option<String>, recorded: Option<recorder::RequestBody>, body| async move {
+            let bytes = tokio::time::timeout(timeout, read_body(body))
+                .await
+                .map_err(|_| warp::reject::custom(Timeout))?
+                .map_err(|e| warp::reject::custom(InvalidBody(e.to_string())))?;
+            let bytes = Bytes::from(bytes);
+            if let Some(recorded) = recorded {
+                recorded.keep(bytes.clone());
+            }
+            if content_hash.is_some_and(|hash| !hash.eq_ignore_ascii_case(&signing::content_hash(&bytes))) {
+                return Err(warp::reject::custom(signing::BadSignature));
+            }
+            serde_json::from_slice::<T>(&bytes).map_err(|e| warp::reject::custom(InvalidBody(e.to_string())))
+        })
+}
+
+// Answers 408 if the handler does not finish within `timeout`. The reply is
+// tagged for the slow-request log if the handler talked to Redis.
+pub async fn timed<R: Reply>(
+    timeout: Duration,
+    handler: impl Future<Output = Result<R, Infallible>>,
+) -> Result<warp::reply::Response, Infallible> {
+    let used_redis = Arc::new(AtomicBool::new(false));
+    let mut res = match latency::tracked(tokio::time::timeout(timeout, handler), used_redis.clone()).await {
+        Ok(reply) => reply?.into_response(),
+        Err(_) => timeout_reply(),
+    };
+    latency::tag(&mut res, used_redis.load(Ordering::Relaxed));
+    Ok(res)
+}
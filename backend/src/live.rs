@@ -0,0 +1,56 @@
+use crate::pubsub::FortuneEvent;
+use fortune_common::{error, warn};
+use futures_util::{SinkExt, StreamExt};
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::Reply;
+
+static EVENTS: OnceLock<broadcast::Sender<FortuneEvent>> = OnceLock::new();
+
+fn sender() -> &'static broadcast::Sender<FortuneEvent> {
+    EVENTS.get_or_init(|| broadcast::channel(256).0)
+}
+
+// Fan out an event to every connected WebSocket client. Events are dropped
+// when nobody is listening.
+pub fn notify(event: FortuneEvent) {
+    let _ = sender().send(event);
+}
+
+pub fn ws_handler(ws: Ws) -> impl Reply {
+    ws.on_upgrade(client_connected)
+}
+
+async fn client_connected(socket: WebSocket) {
+    let (mut tx, mut rx) = socket.split();
+    let mut events = sender().subscribe();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let json = match serde_json::to_string(&event) {
+                        Ok(json) => json,
+                        Err(e) => {
+                            error!("failed to encode websocket event: {}", e);
+                            continue;
+                        }
+                    };
+                    if tx.send(Message::text(json)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("websocket client lagged, skipped {} events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            // Incoming messages are ignored; a close or error ends the session
+            msg = rx.next() => match msg {
+                Some(Ok(msg)) if !msg.is_close() => {}
+                _ => break,
+            },
+        }
+    }
+}
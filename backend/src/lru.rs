@@ -0,0 +1,71 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// Least-recently-used order of the fortunes held in memory, for stores capped
+// with MAX_CACHED_FORTUNES. Reads touch entries under the store's read lock,
+// so the order sits behind its own mutex.
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static EVICTIONS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default)]
+struct Order {
+    tick: u64,
+    // Last use → id, oldest first
+    by_tick: BTreeMap<u64, String>,
+    by_id: HashMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Recency(Mutex<Order>);
+
+impl Recency {
+    // Marks `id` as just used
+    pub fn touch(&self, id: &str) {
+        let mut order = self.0.lock().unwrap();
+        order.tick += 1;
+        let tick = order.tick;
+        if let Some(previous) = order.by_id.insert(id.to_string(), tick) {
+            order.by_tick.remove(&previous);
+        }
+        order.by_tick.insert(tick, id.to_string());
+    }
+
+    pub fn forget(&self, id: &str) {
+        let mut order = self.0.lock().unwrap();
+        if let Some(tick) = order.by_id.remove(id) {
+            order.by_tick.remove(&tick);
+        }
+    }
+
+    // Up to `n` ids for which `pick` holds, least recently used first
+    pub fn oldest(&self, n: usize, pick: impl Fn(&str) -> bool) -> Vec<String> {
+        let order = self.0.lock().unwrap();
+        order.by_tick.values().filter(|id| pick(id)).take(n).cloned().collect()
+    }
+}
+
+pub fn record_hit() {
+    HITS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_miss() {
+    MISSES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_eviction() {
+    EVICTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn metrics() -> String {
+    format!(
+        "backend_cache_hits_total {}\n\
+         backend_cache_misses_total {}\n\
+         backend_cache_evictions_total {}\n",
+        HITS.load(Ordering::Relaxed),
+        MISSES.load(Ordering::Relaxed),
+        EVICTIONS.load(Ordering::Relaxed),
+    )
+}
@@ -1,180 +1,175 @@
-mod redis_client;
-mod utils;
-
-use std::collections::HashMap;
-use std::convert::Infallible;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use warp::{Filter, Reply, Rejection};
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Fortune {
-    id: String,
-    message: String,
+// The full warp filter tree is deeper than the default limit allows
+#![recursion_limit = "256"]
+
+use clap::{Parser, Subcommand};
+use fortune_backend::{analytics, audit, chaos, collections, compression, content_filter, config, create_default_store, db, discord, events, grpc, leader, negative_cache, redis_client, routes_with_collections, server, snapshot, store, verification, webhooks, COMMIT, VERSION};
+use fortune_backend::verification::Verification;
+use fortune_common::{error, info, log};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Settings come from the environment (see README-RUST.md); the flags only
+// cover what is handy when starting the backend by hand
+#[derive(Parser)]
+#[command(name = "fortune-backend", version, about = "Serve fortunes over HTTP and gRPC")]
+struct Args {
+    /// File of KEY=VALUE settings, used for anything the environment does not set
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// HTTP port, instead of BACKEND_PORT
+    #[arg(long)]
+    port: Option<u16>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-type FortuneStore = Arc<RwLock<HashMap<String, Fortune>>>;
-
-fn create_default_store() -> FortuneStore {
-    let mut map = HashMap::new();
-    map.insert("1".to_string(), Fortune {
-        id: "1".to_string(),
-        message: "A new voyage will fill your life with untold memories.".to_string(),
-    });
-    map.insert("2".to_string(), Fortune {
-        id: "2".to_string(),
-        message: "The measure of time to your next goal is the measure of your discipline.".to_string(),
-    });
-    map.insert("3".to_string(), Fortune {
-        id: "3".to_string(),
-        message: "The only way to do well is to do better each day.".to_string(),
-    });
-    map.insert("4".to_string(), Fortune {
-        id: "4".to_string(),
-        message: "It ain't over till it's EOF.".to_string(),
-    });
-
-    Arc::new(RwLock::new(map))
+#[derive(Subcommand)]
+enum Command {
+    /// Lay the Redis fortunes out for REDIS_SHARDS, then exit
+    Reshard,
 }
 
-fn with_store(store: FortuneStore) -> impl Filter<Extract = (FortuneStore,), Error = Infallible> + Clone {
-    warp::any().map(move || store.clone())
-}
-
-async fn list_fortunes(store: FortuneStore) -> Result<impl Reply, Infallible> {
-    let fortunes = store.read().await;
-    let fortunes_vec: Vec<Fortune> = fortunes.values().cloned().collect();
-    Ok(warp::reply::json(&fortunes_vec))
-}
-
-async fn get_fortune(id: String, store: FortuneStore) -> Result<impl Reply, Infallible> {
-    // Try to get from Redis first if available
-    if let Some(redis_client) = redis_client::get_client().await {
-        if let Ok(message) = redis_client::get_fortune(&redis_client, &id).await {
-            let fortune = Fortune { id: id.clone(), message };
-            // Update local store
-            store.write().await.insert(id.clone(), fortune.clone());
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&fortune),
-                warp::http::StatusCode::OK
-            ).into_response());
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let overrides: Vec<(&str, String)> = args.port.iter().map(|port| ("BACKEND_PORT", port.to_string())).collect();
+    let config = match config::Config::load(args.config.as_deref(), &overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    log::set_level(config.log_level.parse().expect("LOG_LEVEL is checked when the configuration loads"));
+    info!("fortune-backend {} (commit {})", VERSION, COMMIT);
+    info!("Resolved configuration: {:#?}", config.redacted());
+
+    if let Some(Command::Reshard) = args.command {
+        match redis_client::reshard(&config).await {
+            Ok(moved) => {
+                info!("resharded redis into {} hashes, moved {} fortunes", config.redis_shards, moved);
+                return;
+            }
+            Err(e) => {
+                error!("reshard: {}", e);
+                std::process::exit(1);
+            }
         }
     }
 
-    let fortunes = store.read().await;
-    match fortunes.get(&id) {
-        Some(fortune) => Ok(warp::reply::with_status(
-            warp::reply::json(fortune),
-            warp::http::StatusCode::OK
-        ).into_response()),
-        None => Ok(warp::reply::with_status(
-            warp::reply::json(&"fortune not found"),
-            warp::http::StatusCode::NOT_FOUND,
-        ).into_response()),
+    audit::init(config.audit_log_file.clone());
+    webhooks::init(&config);
+    events::init(&config);
+    negative_cache::init(&config);
+    if let Some(path) = &config.content_filter_file {
+        match content_filter::ContentFilter::load(path, config.content_filter_mode) {
+            Ok(filter) => content_filter::init(Some(filter)),
+            Err(e) => {
+                error!("Invalid configuration: {}", e);
+                std::process::exit(1);
+            }
+        }
     }
-}
 
-async fn random_fortune(store: FortuneStore) -> Result<impl Reply, Infallible> {
-    let fortunes = store.read().await;
-    let fortunes_vec: Vec<Fortune> = fortunes.values().cloned().collect();
+    // Initialize Redis connection
+    redis_client::init(&config).await;
 
-    if fortunes_vec.is_empty() {
-        drop(fortunes);
-        return get_fortune("zero".to_string(), store).await;
+    // Serving the built-in defaults would look healthy while the real data is missing
+    if config.strict_startup && config.redis_url().is_some() && redis_client::get_store().await.is_none() {
+        error!("STRICT_STARTUP: redis is configured but unavailable, exiting");
+        std::process::exit(1);
     }
 
-    // Generate random index before the await to avoid Send issues
-    let random_index = {
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
-        rng.gen_range(0..fortunes_vec.len())
-    };
+    // Initialize database connection
+    db::init(&config).await;
 
-    let id = fortunes_vec[random_index].id.clone();
-    drop(fortunes);
+    // Create store and load from the snapshot file, database and Redis if available
+    let store = create_default_store();
+    store.write().await.set_capacity(config.cache_capacity());
+    if let Some(path) = &config.data_file {
+        snapshot::load(path, store.clone()).await;
+        snapshot::init(
+            path.clone(),
+            store.clone(),
+            Duration::from_millis(config.data_file_debounce_ms),
+        );
+    }
+    if let Some(pool) = db::get_pool().await {
+        db::load_fortunes(&pool, store.clone()).await;
+    }
+    // Named collections live in memory and their own Redis hashes only
+    let collections = collections::Collections::from_config(&config);
+    if let Some(redis) = redis_client::get_store().await {
+        collections.load(&redis, &config).await;
+    }
+    match redis_client::get_store().await {
+        Some(redis) => redis_client::attach(redis, &store, &config).await,
+        None if config.redis_reconnect => redis_client::spawn_reconnect(config.clone(), store.clone()),
+        None => {}
+    }
 
-    get_fortune(id, store).await
-}
+    leader::init(&config);
+    analytics::init(&config);
+    chaos::init(&config);
 
-async fn create_fortune(fortune: Fortune, store: FortuneStore) -> Result<impl Reply, Infallible> {
-    // Save to Redis if available
-    if let Some(redis_client) = redis_client::get_client().await {
-        if let Err(e) = redis_client::set_fortune(&redis_client, &fortune.id, &fortune.message).await {
-            eprintln!("Redis hset failed: {}", e);
-        }
+    store::spawn_schedule_refresh(store.clone(), config.schedule_refresh());
+    for collection in collections.iter() {
+        store::spawn_schedule_refresh(collection.store.clone(), config.schedule_refresh());
     }
 
-    store.write().await.insert(fortune.id.clone(), fortune.clone());
-    Ok(warp::reply::json(&fortune))
-}
-
-async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
-    if err.is_not_found() {
-        Ok(warp::reply::with_status(
-            warp::reply::json(&"not found"),
-            warp::http::StatusCode::NOT_FOUND,
-        ))
-    } else {
-        Ok(warp::reply::with_status(
-            warp::reply::json(&"internal server error"),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+    if let Some(discord) = discord::Discord::from_config(&config) {
+        discord::spawn_daily(discord, store.clone(), config.discord_post_time());
+        info!("Posting the fortune of the day to Discord at {} UTC", config.discord_post_time);
     }
-}
 
-#[tokio::main]
-async fn main() {
-    // Initialize Redis connection
-    redis_client::init().await;
+    if config.soft_delete {
+        if let Some(max_age) = config.trash_purge_after() {
+            store::spawn_trash_purge(store.clone(), max_age);
+            for collection in collections.iter() {
+                store::spawn_trash_purge(collection.store.clone(), max_age);
+            }
+        }
+    }
 
-    // Create store and load from Redis if available
-    let store = create_default_store();
-    if let Some(redis_client) = redis_client::get_client().await {
-        redis_client::load_fortunes(&redis_client, store.clone()).await;
+    if let Some(verification) = Verification::from_config(&config) {
+        verification::spawn_purge(store.clone(), verification.ttl());
+        for collection in collections.iter() {
+            verification::spawn_purge(collection.store.clone(), verification.ttl());
+        }
     }
 
-    let fortunes = warp::path("fortunes");
-
-    // GET /fortunes - list all fortunes
-    let list = fortunes
-        .and(warp::path::end())
-        .and(warp::get())
-        .and(with_store(store.clone()))
-        .and_then(list_fortunes);
-
-    // GET /fortunes/{id} - get specific fortune
-    let get = fortunes
-        .and(warp::path::param())
-        .and(warp::path::end())
-        .and(warp::get())
-        .and(with_store(store.clone()))
-        .and_then(get_fortune);
-
-    // GET /fortunes/random - get random fortune
-    let random = fortunes
-        .and(warp::path("random"))
-        .and(warp::path::end())
-        .and(warp::get())
-        .and(with_store(store.clone()))
-        .and_then(random_fortune);
-
-    // POST /fortunes - create new fortune
-    let create = fortunes
-        .and(warp::path::end())
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(with_store(store.clone()))
-        .and_then(create_fortune);
-
-    let routes = list
-        .or(get)
-        .or(random)
-        .or(create)
-        .recover(handle_rejection);
-
-    println!("Starting server on port 9000...");
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], 9000))
-        .await;
+    grpc::spawn_server(
+        config.grpc_addr(),
+        store.clone(),
+        config.moderation,
+        config.submission_verification,
+        config.read_only,
+    );
+
+    let routes = compression::wrap(routes_with_collections(store, collections, &config), config.compression_enabled);
+
+    let listen = config.listen();
+    let tuning = config.server_tuning();
+    match config.tls() {
+        Some(tls) => {
+            let https = warp::serve(routes.clone())
+                .tls()
+                .cert_path(&tls.cert_path)
+                .key_path(&tls.key_path)
+                .run(tls.addr);
+            info!("Starting server on https://{}...", tls.addr);
+            if tls.only {
+                https.await;
+            } else {
+                info!("Starting server on {}...", listen);
+                tokio::join!(server::run(routes, listen, tuning), https);
+            }
+        }
+        None => {
+            info!("Starting server on {}...", listen);
+            server::run(routes, listen, tuning).await;
+        }
+    }
 }
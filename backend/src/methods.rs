@@ -0,0 +1,141 @@
+use crate::endpoints::{Disabled, Endpoint};
+use std::convert::Infallible;
+use warp::http::{header, HeaderValue, Method, StatusCode};
+use warp::hyper::body::HttpBody;
+use warp::path::FullPath;
+use warp::{Filter, Rejection, Reply};
+
+// Optional routes that change which methods a path answers
+#[derive(Debug, Clone, Copy)]
+pub struct Enabled {
+    pub soft_delete: bool,
+    pub admin: bool,
+    pub graphiql: bool,
+    // SUBMISSION_VERIFICATION: GET and POST /verify/{token}
+    pub verification: bool,
+    pub disabled: Disabled,
+}
+
+// The routes on `path` with their methods; None cannot be disabled
+fn routes_on(path: &str, enabled: Enabled) -> Option<&'static [(Option<Endpoint>, &'static str)]> {
+    let mut segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    // A named collection answers like /fortunes, without the shared feeds
+    if let ["collections", _, "fortunes", rest @ ..] = segments.as_slice() {
+        if matches!(rest, ["ws" | "events"]) {
+            return None;
+        }
+        segments.drain(..2);
+    }
+    let routes: &[(Option<Endpoint>, &str)] = match segments.as_slice() {
+        ["fortunes"] => &[(Some(Endpoint::List), "GET, HEAD"), (Some(Endpoint::Create), "POST")],
+        ["fortunes", "batch"] => &[(Some(Endpoint::Batch), "POST")],
+        // The WebSocket upgrade has no HEAD equivalent
+        ["fortunes", "ws"] => &[(Some(Endpoint::Ws), "GET")],
+        ["fortunes", "random"] => &[(Some(Endpoint::Random), "GET, HEAD")],
+        ["fortunes", "rotation"] => &[(Some(Endpoint::Rotation), "GET, HEAD")],
+        ["fortunes", "authors"] => &[(Some(Endpoint::Authors), "GET, HEAD")],
+        ["fortunes", "popular"] => &[(Some(Endpoint::Popular), "GET, HEAD")],
+        ["fortunes", "events"] => &[(Some(Endpoint::Events), "GET, HEAD")],
+        ["fortunes", "trash"] if enabled.soft_delete => &[(Some(Endpoint::Trash), "GET, HEAD")],
+        ["fortunes", _, "restore"] if enabled.soft_delete => &[(Some(Endpoint::Restore), "POST")],
+        ["fortunes", _] => &[(Some(Endpoint::Get), "GET, HEAD"), (Some(Endpoint::Delete), "DELETE")],
+        ["verify", _] if enabled.verification => &[(None, "GET, POST")],
+        ["healthz"] => &[(None, "GET, HEAD")],
+        ["metrics"] => &[(Some(Endpoint::Metrics), "GET, HEAD")],
+        ["openapi.json" | "docs"] => &[(Some(Endpoint::Docs), "GET, HEAD")],
+        ["graphql"] if enabled.graphiql => &[(Some(Endpoint::Graphql), "GET, HEAD, POST")],
+        ["graphql"] => &[(Some(Endpoint::Graphql), "POST")],
+        ["admin", "stats" | "moderation" | "audit" | "duplicates" | "analytics" | "recent-requests" | "keys"] if enabled.admin => &[(Some(Endpoint::Admin), "GET, HEAD")],
+        ["admin", "resync" | "flush-cache" | "backup" | "restore"] if enabled.admin => &[(Some(Endpoint::Admin), "POST")],
+        ["admin", "fortunes", _, "approve" | "reject"] if enabled.admin => &[(Some(Endpoint::Admin), "POST")],
+        ["integrations", "discord", "test"] if enabled.admin => &[(Some(Endpoint::Discord), "POST")],
+        _ => return None,
+    };
+    Some(routes)
+}
+
+// The Allow header value for `path`, or None if no enabled route matches it
+pub fn allowed(path: &str, enabled: Enabled) -> Option<String> {
+    let routes = routes_on(path, enabled)?;
+    let mut methods: Vec<&str> = routes
+        .iter()
+        .filter(|(endpoint, _)| endpoint.is_none_or(|endpoint| enabled.disabled.is_enabled(endpoint)))
+        .map(|(_, methods)| *methods)
+        .collect();
+    if methods.is_empty() {
+        return None;
+    }
+    methods.push("OPTIONS");
+    Some(methods.join(", "))
+}
+
+// The endpoint answering `method` on `path`, whether or not it is disabled;
+// None for unknown paths and for routes such as /healthz that have no name
+pub fn endpoint(method: &Method, path: &str, enabled: Enabled) -> Option<Endpoint> {
+    routes_on(path, enabled)?
+        .iter()
+        .find(|(_, methods)| methods.split(", ").any(|m| m == method.as_str()))
+        .and_then(|(endpoint, _)| *endpoint)
+}
+
+// Matches GET and HEAD; `finish` drops the body of HEAD responses
+pub fn get_or_head() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    warp::get().or(warp::head()).unify()
+}
+
+// Like `get_or_head`, extracting true for HEAD: a peek that must not count
+// views or move a session along
+pub fn get_or_peek() -> impl Filter<Extract = (bool,), Error = Rejection> + Copy {
+    warp::get().map(|| false).or(warp::head().map(|| true)).unify()
+}
+
+// OPTIONS on any known path answers 204 with the Allow header. Other methods
+// are rejected as not found so they don't turn into 405s here.
+pub fn options(enabled: Enabled) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and_then(move |method: Method, path: FullPath| async move {
+            match allowed(path.as_str(), enabled) {
+                Some(allow) if method == Method::OPTIONS => {
+                    Ok(warp::reply::with_header(StatusCode::NO_CONTENT, header::ALLOW, allow).into_response())
+                }
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+// Adds Allow to 405 responses (or makes them 404s on unknown paths) and empties the body of HEAD responses,
+// keeping the Content-Length the GET would have had
+pub fn finish<F, R>(routes: F, enabled: Enabled) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::method()
+        .and(warp::path::full())
+        .and(routes)
+        .map(move |method: Method, path: FullPath, reply: R| {
+            let mut res = reply.into_response();
+            if res.status() == StatusCode::METHOD_NOT_ALLOWED {
+                match allowed(path.as_str(), enabled) {
+                    Some(allow) => {
+                        if let Ok(allow) = HeaderValue::from_str(&allow) {
+                            res.headers_mut().insert(header::ALLOW, allow);
+                        }
+                    }
+                    // e.g. the admin routes while they are disabled
+                    None => {
+                        res = warp::reply::with_status(warp::reply::json(&"not found"), StatusCode::NOT_FOUND).into_response();
+                    }
+                }
+            }
+            if method == Method::HEAD {
+                let (mut parts, body) = res.into_parts();
+                if let Some(len) = body.size_hint().exact() {
+                    parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+                }
+                res = warp::reply::Response::from_parts(parts, warp::hyper::Body::empty());
+            }
+            res
+        })
+}
@@ -0,0 +1,75 @@
+use crate::config::Config;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// Ids that were looked up and found neither in memory nor in Redis, so that
+// asking for them again within NEGATIVE_CACHE_TTL_MS does not reach Redis.
+// Storing a fortune under an id forgets it here at once; a fortune created on
+// another replica is found once its event arrives or the entry expires.
+
+// Entries kept at most, so scans over random ids cannot grow the map forever
+const MAX_ENTRIES: usize = 100_000;
+
+static TTL_MS: AtomicU64 = AtomicU64::new(0);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSING: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+
+fn missing() -> &'static Mutex<HashMap<String, Instant>> {
+    MISSING.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn key(collection: Option<&str>, id: &str) -> String {
+    format!("{}:{}", collection.unwrap_or_default(), id)
+}
+
+pub fn init(config: &Config) {
+    TTL_MS.store(config.negative_cache_ttl_ms, Ordering::Relaxed);
+}
+
+fn ttl() -> Option<Duration> {
+    match TTL_MS.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(Duration::from_millis(ms)),
+    }
+}
+
+// Whether `id` was recently found missing; counts a hit when it was
+pub fn is_missing(collection: Option<&str>, id: &str) -> bool {
+    if ttl().is_none() {
+        return false;
+    }
+    let missing = missing().lock().unwrap();
+    let hit = missing.get(&key(collection, id)).is_some_and(|expires_at| *expires_at > Instant::now());
+    if hit {
+        HITS.fetch_add(1, Ordering::Relaxed);
+    }
+    hit
+}
+
+pub fn remember(collection: Option<&str>, id: &str) {
+    let Some(ttl) = ttl() else {
+        return;
+    };
+    let now = Instant::now();
+    let mut missing = missing().lock().unwrap();
+    if missing.len() >= MAX_ENTRIES {
+        missing.retain(|_, expires_at| *expires_at > now);
+        if missing.len() >= MAX_ENTRIES {
+            missing.clear();
+        }
+    }
+    missing.insert(key(collection, id), now + ttl);
+}
+
+pub fn forget(collection: Option<&str>, id: &str) {
+    if ttl().is_none() {
+        return;
+    }
+    missing().lock().unwrap().remove(&key(collection, id));
+}
+
+pub fn metrics() -> String {
+    format!("backend_negative_cache_hits_total {}\n", HITS.load(Ordering::Relaxed))
+}
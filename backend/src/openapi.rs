@@ -0,0 +1,66 @@
+use std::convert::Infallible;
+use utoipa::OpenApi;
+use warp::Reply;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Fortune Cookie Backend", description = "Fortune storage and retrieval API"),
+    paths(
+        crate::list_fortunes,
+        crate::get_fortune,
+        crate::random_fortune,
+        crate::rotation_fortune,
+        crate::create_fortune,
+        crate::create_batch,
+        crate::delete_fortune,
+        crate::list_authors,
+        crate::popular_fortunes,
+        crate::events::list_handler,
+        crate::list_trash,
+        crate::restore_fortune,
+        crate::healthz_handler,
+        crate::metrics_handler,
+        crate::admin::stats_handler,
+        crate::admin::resync_handler,
+        crate::admin::flush_cache_handler,
+        crate::admin::audit_handler,
+        crate::admin::analytics_handler,
+        crate::admin::pending_handler,
+        crate::admin::moderate_handler,
+        crate::admin::duplicates_handler,
+        crate::admin::recent_requests_handler,
+        crate::admin::keys_handler,
+        crate::verification::confirm_handler,
+        crate::verification::verify_handler,
+        crate::backup::backup_handler,
+        crate::backup::restore_handler,
+        crate::discord::test_handler,
+    ),
+    components(schemas(crate::Fortune, crate::payload::FortuneV2, crate::payload::Translation, crate::payload::Schedule, crate::BatchResult, crate::fortunes::TrashedFortune, crate::fortunes::AuthorCount, crate::admin::Stats, crate::admin::ModerationCounts, crate::analytics::Analytics, crate::analytics::DayUsage, crate::analytics::Served, crate::leader::LeaseStatus, crate::duplicates::Cluster, crate::duplicates::Kind, crate::backup::Backup, crate::backup::Restored, crate::Status, crate::Sort, crate::Order, crate::streaming::ListFormat, crate::ascii_art::Format, crate::Health, crate::Components, crate::Component, crate::StoreHealth, crate::audit::AuditEntry, crate::events::LoggedEvent, crate::recorder::Recorded, crate::recorder::Captured, crate::quotas::KeyUsage, crate::quotas::DailyUsage))
+)]
+struct ApiDoc;
+
+// Swagger UI assets are loaded from a CDN so the binary stays self-contained
+const DOCS_HTML: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+    <title>Fortune Cookie API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>
+        window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+    </script>
+</body>
+</html>
+"##;
+
+pub async fn spec_handler() -> Result<impl Reply, Infallible> {
+    Ok(warp::reply::json(&ApiDoc::openapi()))
+}
+
+pub async fn docs_handler() -> Result<impl Reply, Infallible> {
+    Ok(warp::reply::html(DOCS_HTML))
+}
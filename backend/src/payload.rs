@@ -0,0 +1,113 @@
+use crate::{language, Fortune};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+// Layouts a submitted fortune may use, named by its `api_version` field
+pub const VERSIONS: &[u64] = &[1, 2];
+
+// Ids used to be free-form JSON in some clients; numbers are taken as their digits
+pub(crate) fn id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        Text(String),
+        Number(u64),
+    }
+    Ok(match Id::deserialize(deserializer)? {
+        Id::Text(id) => id,
+        Id::Number(id) => id.to_string(),
+    })
+}
+
+/// Version 2 of a submitted fortune: the translation and schedule fields
+/// grouped into objects. Send it with `"api_version": 2`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FortuneV2 {
+    /// Allocated by the server when empty or omitted
+    #[serde(default, deserialize_with = "id")]
+    pub id: String,
+    pub message: String,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub translation: Translation,
+    #[serde(default)]
+    pub schedule: Schedule,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Translation {
+    /// Language tag of the message, e.g. `en` or `pt-br`
+    #[serde(default = "language::default_lang")]
+    pub lang: String,
+    /// Shared by translations of the same fortune
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+impl Default for Translation {
+    fn default() -> Self {
+        Translation {
+            lang: language::default_lang(),
+            group: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Schedule {
+    #[serde(default)]
+    pub publish_at: Option<u64>,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+impl From<FortuneV2> for Fortune {
+    fn from(v2: FortuneV2) -> Self {
+        Fortune {
+            id: v2.id,
+            message: v2.message,
+            lang: v2.translation.lang,
+            group: v2.translation.group,
+            author: v2.author,
+            tags: v2.tags,
+            publish_at: v2.schedule.publish_at,
+            expires_at: v2.schedule.expires_at,
+            ..Default::default()
+        }
+    }
+}
+
+// A fortune in a POST /fortunes or /fortunes/batch body. Without
+// `api_version`, or with 1, the body has the flat fields of `Fortune`, of
+// which the original `{"id", "message"}` bodies are a subset; 2 is `FortuneV2`.
+#[derive(Debug)]
+pub struct FortunePayload(pub Fortune);
+
+impl<'de> Deserialize<'de> for FortunePayload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let body = serde_json::Value::deserialize(deserializer)?;
+        let version = match body.get("api_version") {
+            None => 1,
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| de::Error::custom("api_version must be a number"))?,
+        };
+        let fortune = match version {
+            1 => Fortune::deserialize(body).map_err(de::Error::custom)?,
+            2 => FortuneV2::deserialize(body).map_err(de::Error::custom)?.into(),
+            other => {
+                let known: Vec<String> = VERSIONS.iter().map(u64::to_string).collect();
+                return Err(de::Error::custom(format!(
+                    "unsupported api_version {}, expected one of {}",
+                    other,
+                    known.join(", ")
+                )));
+            }
+        };
+        Ok(FortunePayload(fortune))
+    }
+}
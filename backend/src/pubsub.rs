@@ -0,0 +1,113 @@
+use crate::{Fortune, FortuneStore};
+use fortune_common::{error, info, warn};
+use futures_util::StreamExt;
+use redis::Client;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+const CHANNEL: &str = "fortunes:events";
+
+static REPLICA_ID: OnceLock<String> = OnceLock::new();
+
+// Identifies this process so replicas can skip their own events
+fn replica_id() -> &'static str {
+    REPLICA_ID.get_or_init(|| format!("{:016x}", rand::random::<u64>()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum FortuneEvent {
+    Create { fortune: Fortune },
+    Update { fortune: Fortune },
+    Delete { id: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EventMessage {
+    origin: String,
+    #[serde(flatten)]
+    event: FortuneEvent,
+}
+
+pub async fn publish(client: &Client, event: FortuneEvent) {
+    let message = EventMessage {
+        origin: replica_id().to_string(),
+        event,
+    };
+    let payload = match serde_json::to_string(&message) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("failed to encode fortune event: {}", e);
+            return;
+        }
+    };
+
+    crate::latency::mark_redis();
+    let result = client.get_connection().and_then(|mut conn| {
+        redis::cmd("PUBLISH")
+            .arg(CHANNEL)
+            .arg(payload)
+            .query::<()>(&mut conn)
+    });
+    if let Err(e) = result {
+        error!("redis publish failed: {}", e);
+    }
+}
+
+// WebSocket clients connected to this replica hear about the change as they
+// would on the one that made it: only approved fortunes are announced
+async fn apply(event: FortuneEvent, store: &FortuneStore) {
+    let mut store = store.write().await;
+    match event {
+        FortuneEvent::Create { fortune } | FortuneEvent::Update { fortune } => {
+            let previous = store.insert(fortune.id.clone(), fortune.clone());
+            if let Some(kind) = crate::store::announcement(&fortune, previous.as_ref()) {
+                crate::live::notify(crate::store::live_event(kind, &fortune));
+            }
+        }
+        FortuneEvent::Delete { id } => {
+            if store.remove(&id).is_some_and(|removed| removed.status == crate::Status::Approved) {
+                crate::live::notify(FortuneEvent::Delete { id });
+            }
+        }
+    }
+    crate::snapshot::mark_dirty();
+}
+
+async fn subscribe(client: &Client, store: &FortuneStore) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(CHANNEL).await?;
+    info!("subscribed to redis channel {}", CHANNEL);
+
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        let payload: String = match msg.get_payload() {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("invalid fortune event payload: {}", e);
+                continue;
+            }
+        };
+        match serde_json::from_str::<EventMessage>(&payload) {
+            Ok(message) if message.origin == replica_id() => {}
+            Ok(message) => apply(message.event, store).await,
+            Err(e) => error!("failed to decode fortune event: {}", e),
+        }
+    }
+    Ok(())
+}
+
+// Keeps the local store in sync with events published by other replicas,
+// resubscribing if the connection drops.
+pub fn spawn_subscriber(client: Client, store: FortuneStore) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = subscribe(&client, &store).await {
+                error!("redis subscribe failed: {}", e);
+            } else {
+                warn!("redis subscription closed");
+            }
+            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        }
+    });
+}
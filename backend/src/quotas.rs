@@ -0,0 +1,446 @@
+use crate::admin;
+use crate::analytics::{date, today, DAY_SECS};
+use crate::config::Config;
+use crate::fortunes::now_secs;
+use crate::methods::{self, Enabled};
+use crate::redis_client;
+use crate::endpoints::Endpoint;
+use fortune_common::error;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+use warp::http::{HeaderValue, Method, StatusCode};
+use warp::path::FullPath;
+use warp::reject::Reject;
+use warp::{Filter, Rejection, Reply};
+
+// Per-team API keys from API_KEYS, each with a daily request and create
+// limit. Usage is counted per UTC day in Redis, so every replica adds to the
+// same totals (in memory without Redis, or while it is unreachable):
+//   fortunes:quota:<name>:<date>  hash with `requests` and `creates`
+// A request over its key's limit gets 429. Keys count towards the limits
+// only when a request carries them; without one it is not tracked. A create
+// takes its place in the count before it runs and gives it back if it fails,
+// so concurrent creates cannot all pass the check and go over together.
+
+const PREFIX: &str = "fortunes:quota";
+// Days of usage /admin/keys can report
+pub const RETENTION_DAYS: u64 = 31;
+
+pub const LIMIT: &str = "x-ratelimit-limit";
+pub const REMAINING: &str = "x-ratelimit-remaining";
+// Seconds until the counts start over at midnight UTC
+pub const RESET: &str = "x-ratelimit-reset";
+pub const CREATES_LIMIT: &str = "x-ratelimit-creates-limit";
+pub const CREATES_REMAINING: &str = "x-ratelimit-creates-remaining";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub name: String,
+    key: String,
+    // Requests per day; None is unlimited
+    pub requests: Option<u64>,
+    // Fortunes created per day through POST /fortunes, /fortunes/batch and
+    // the GraphQL createFortune mutation
+    pub creates: Option<u64>,
+}
+
+// Comma-separated `name:key[:requests[:creates]]` entries, e.g.
+// `team-a:k3y-a:10000:100,team-b:k3y-b::50`; a missing or 0 limit is none
+pub fn parse(list: &str) -> Result<Vec<ApiKey>, String> {
+    let mut parsed: Vec<ApiKey> = Vec::new();
+    for entry in list.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let mut fields = entry.split(':');
+        let name = fields.next().unwrap_or_default();
+        let key = fields.next().unwrap_or_default();
+        let mut limit = |what: &str| -> Result<Option<u64>, String> {
+            match fields.next().map(str::trim) {
+                None | Some("") | Some("0") => Ok(None),
+                Some(value) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("the {} limit of API key '{}' is not a number", what, name)),
+            }
+        };
+        let requests = limit("request")?;
+        let creates = limit("create")?;
+        if fields.next().is_some() {
+            return Err(format!("API key '{}' has more than name:key:requests:creates", name));
+        }
+        let valid = !name.is_empty()
+            && name.len() <= 64
+            && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_');
+        if !valid {
+            return Err(format!("invalid API key name '{}', use up to 64 of a-z, 0-9, - and _", name));
+        }
+        if key.is_empty() {
+            return Err(format!("API key '{}' has no key", name));
+        }
+        if parsed.iter().any(|known| known.name == name) {
+            return Err(format!("API key '{}' is listed twice", name));
+        }
+        if parsed.iter().any(|known| known.key == key) {
+            return Err(format!("API key '{}' has the same key as another", name));
+        }
+        parsed.push(ApiKey {
+            name: name.to_string(),
+            key: key.to_string(),
+            requests,
+            creates,
+        });
+    }
+    Ok(parsed)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub requests: u64,
+    pub creates: u64,
+}
+
+// Response extension with how many fortunes a create stored
+#[derive(Debug, Clone, Copy)]
+pub struct Created(pub u64);
+
+#[derive(Debug, Clone, Default)]
+pub struct Quotas {
+    keys: Arc<Vec<ApiKey>>,
+    // Counts by key name and day, for when Redis is not there
+    local: Arc<Mutex<HashMap<(String, u64), Usage>>>,
+}
+
+fn key(name: &str, day: u64) -> String {
+    format!("{}:{}:{}", PREFIX, name, date(day))
+}
+
+impl ApiKey {
+    pub fn is(&self, given: &str) -> bool {
+        admin::key_matches(&self.key, given)
+    }
+}
+
+impl Quotas {
+    pub fn from_config(config: &Config) -> Self {
+        let keys = config.api_keys.as_deref().and_then(|list| parse(list).ok()).unwrap_or_default();
+        Quotas {
+            keys: Arc::new(keys),
+            local: Arc::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    fn find(&self, given: &str) -> Option<&ApiKey> {
+        self.keys.iter().find(|key| key.is(given))
+    }
+
+    // The name of the API_KEYS entry whose key was given
+    pub fn name_of(&self, given: &str) -> Option<&str> {
+        self.find(given).map(|key| key.name.as_str())
+    }
+
+    // Adds to a count and returns the day's usage after it; Redis errors
+    // fall back to this replica's counts rather than refusing requests
+    async fn add(&self, name: &str, day: u64, field: &str, by: i64) -> Usage {
+        if let Some(redis) = redis_client::get_store().await {
+            let key = key(name, day);
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            pipe.cmd("HINCRBY").arg(&key).arg(field).arg(by).ignore();
+            pipe.cmd("EXPIRE").arg(&key).arg(RETENTION_DAYS * DAY_SECS).ignore();
+            pipe.cmd("HMGET").arg(&key).arg("requests").arg("creates");
+            let result: redis::RedisResult<((Option<u64>, Option<u64>),)> =
+                redis.connection().and_then(|mut conn| pipe.query(&mut conn));
+            match result {
+                Ok(((requests, creates),)) => {
+                    return Usage {
+                        requests: requests.unwrap_or_default(),
+                        creates: creates.unwrap_or_default(),
+                    }
+                }
+                Err(e) => error!("Redis quota count for {} failed: {}", name, e),
+            }
+        }
+        let mut local = self.local.lock().unwrap();
+        local.retain(|(_, kept), _| kept + RETENTION_DAYS > day);
+        let usage = local.entry((name.to_string(), day)).or_default();
+        let count = match field {
+            "creates" => &mut usage.creates,
+            _ => &mut usage.requests,
+        };
+        *count = count.saturating_add_signed(by);
+        *usage
+    }
+
+    // Takes `n` of the key's creates for `day` ahead of storing them. Err with
+    // the usage when that would go over the limit, in which case nothing is taken.
+    async fn reserve(&self, key: &ApiKey, day: u64, n: u64) -> Result<Usage, Usage> {
+        let usage = self.add(&key.name, day, "creates", n as i64).await;
+        match key.creates {
+            Some(limit) if usage.creates > limit => Err(self.add(&key.name, day, "creates", -(n as i64)).await),
+            _ => Ok(usage),
+        }
+    }
+
+    async fn usage(&self, name: &str, days: &[u64]) -> Vec<Usage> {
+        if let Some(redis) = redis_client::get_store().await {
+            let mut pipe = redis::pipe();
+            for day in days {
+                pipe.cmd("HMGET").arg(key(name, *day)).arg("requests").arg("creates");
+            }
+            let result: redis::RedisResult<Vec<(Option<u64>, Option<u64>)>> =
+                redis.connection().and_then(|mut conn| pipe.query(&mut conn));
+            match result {
+                Ok(counts) => {
+                    return counts
+                        .into_iter()
+                        .map(|(requests, creates)| Usage {
+                            requests: requests.unwrap_or_default(),
+                            creates: creates.unwrap_or_default(),
+                        })
+                        .collect()
+                }
+                Err(e) => error!("Redis quota lookup for {} failed: {}", name, e),
+            }
+        }
+        let local = self.local.lock().unwrap();
+        days.iter()
+            .map(|day| local.get(&(name.to_string(), *day)).copied().unwrap_or_default())
+            .collect()
+    }
+
+    // Every key with its limits and usage over the last `days` days
+    pub async fn report(&self, days: u64) -> Vec<KeyUsage> {
+        let last = today();
+        let range: Vec<u64> = (last + 1 - days..=last).collect();
+        let mut report = Vec::with_capacity(self.keys.len());
+        for key in self.keys.iter() {
+            let usage = self.usage(&key.name, &range).await;
+            report.push(KeyUsage {
+                name: key.name.clone(),
+                requests_per_day: key.requests,
+                creates_per_day: key.creates,
+                days: range
+                    .iter()
+                    .zip(usage)
+                    .map(|(day, usage)| DailyUsage {
+                        date: date(*day),
+                        requests: usage.requests,
+                        creates: usage.creates,
+                    })
+                    .collect(),
+            });
+        }
+        report
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct KeyUsage {
+    name: String,
+    /// Null when unlimited
+    requests_per_day: Option<u64>,
+    /// Null when unlimited
+    creates_per_day: Option<u64>,
+    /// Oldest first, today last
+    days: Vec<DailyUsage>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DailyUsage {
+    /// `YYYY-MM-DD`
+    date: String,
+    requests: u64,
+    creates: u64,
+}
+
+// A request over its key's daily limit
+#[derive(Debug)]
+pub struct OverQuota {
+    limits: Limits,
+}
+
+impl Reject for OverQuota {}
+
+// What the response tells the caller about its key's quota
+#[derive(Debug, Clone)]
+struct Limits {
+    name: String,
+    day: u64,
+    requests: Option<(u64, u64)>,
+    // Only on the routes that create fortunes
+    creates: Option<(u64, u64)>,
+}
+
+// A request let through on a team key
+#[derive(Debug, Clone)]
+pub struct Admitted {
+    quotas: Quotas,
+    limits: Limits,
+    // Creates taken ahead of the route running
+    reserved: u64,
+}
+
+fn remaining((limit, used): (u64, u64)) -> u64 {
+    limit.saturating_sub(used)
+}
+
+fn with_headers(mut res: warp::reply::Response, limits: &Limits) -> warp::reply::Response {
+    let headers = res.headers_mut();
+    if let Some(requests) = limits.requests {
+        headers.insert(LIMIT, HeaderValue::from(requests.0));
+        headers.insert(REMAINING, HeaderValue::from(remaining(requests)));
+    }
+    if let Some(creates) = limits.creates {
+        headers.insert(CREATES_LIMIT, HeaderValue::from(creates.0));
+        headers.insert(CREATES_REMAINING, HeaderValue::from(remaining(creates)));
+    }
+    if limits.requests.is_some() || limits.creates.is_some() {
+        let reset = (limits.day + 1) * DAY_SECS - now_secs().min((limits.day + 1) * DAY_SECS);
+        headers.insert(RESET, HeaderValue::from(reset));
+    }
+    res
+}
+
+// Counts the request against its key, or refuses it when the key has used
+// up its requests, or its creates on a route that creates fortunes. Such a
+// route takes one create up front; `counted` settles the difference.
+async fn count(quotas: Quotas, creating: bool, given: Option<String>) -> Result<Option<Admitted>, Rejection> {
+    let Some(key) = given.as_deref().and_then(|given| quotas.find(given)).cloned() else {
+        return Ok(None);
+    };
+    let day = today();
+    let usage = quotas.add(&key.name, day, "requests", 1).await;
+    let mut limits = Limits {
+        name: key.name.clone(),
+        day,
+        requests: key.requests.map(|limit| (limit, usage.requests)),
+        creates: key.creates.filter(|_| creating).map(|limit| (limit, usage.creates)),
+    };
+    let mut over = key.requests.is_some_and(|limit| usage.requests > limit);
+    let mut reserved = 0;
+    if creating && !over {
+        let reservation = quotas.reserve(&key, day, 1).await;
+        reserved = u64::from(reservation.is_ok());
+        over = reservation.is_err();
+        let (Ok(usage) | Err(usage)) = reservation;
+        limits.creates = key.creates.map(|limit| (limit, usage.creates));
+    }
+    if over {
+        // Refused requests do not use up the quota
+        let usage = quotas.add(&key.name, day, "requests", -1).await;
+        limits.requests = key.requests.map(|limit| (limit, usage.requests));
+        return Err(warp::reject::custom(OverQuota { limits }));
+    }
+    Ok(Some(Admitted { quotas, limits, reserved }))
+}
+
+// Applies the API_KEYS quotas ahead of the routes; requests over them are
+// rejected with OverQuota. A batch is let through while any creates remain.
+pub fn admit(quotas: Quotas, enabled: Enabled) -> impl Filter<Extract = (Option<Admitted>,), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and_then(move |method: Method, path: FullPath, given: Option<String>| {
+            let quotas = quotas.clone();
+            let creating = matches!(methods::endpoint(&method, path.as_str(), enabled), Some(Endpoint::Create | Endpoint::Batch));
+            async move {
+                match quotas.is_enabled() {
+                    true => count(quotas, creating, given).await,
+                    false => Ok(None),
+                }
+            }
+        })
+}
+
+// The routes' response, or the rejection they ended in, so `counted` can
+// settle a create taken up front either way
+pub fn outcome<F, R>(api: F) -> impl Filter<Extract = (Result<warp::reply::Response, Rejection>,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone,
+    R: Reply,
+{
+    api.map(|reply: R| Ok::<_, Rejection>(reply.into_response()))
+        .or_else(|rejection| async move { Ok::<_, Rejection>((Err(rejection),)) })
+}
+
+// Settles the key's create count with the fortunes the route stored, giving
+// back the one taken up front when it stored none, and tells the caller where
+// it stands in the X-RateLimit-* headers. A rejected request, such as one with
+// a malformed body, gives its create back and stays rejected. A batch counts
+// in full, so it can take a key past its limit once.
+pub async fn counted(
+    admitted: Option<Admitted>,
+    outcome: Result<warp::reply::Response, Rejection>,
+) -> Result<warp::reply::Response, Rejection> {
+    let Some(Admitted { quotas, mut limits, reserved }) = admitted else {
+        return outcome;
+    };
+    let res = match outcome {
+        Ok(res) => res,
+        Err(rejection) => {
+            if reserved > 0 {
+                quotas.add(&limits.name, limits.day, "creates", -(reserved as i64)).await;
+            }
+            return Err(rejection);
+        }
+    };
+    let created = res.extensions().get::<Created>().map_or(0, |created| created.0);
+    if created != reserved {
+        let usage = quotas.add(&limits.name, limits.day, "creates", created as i64 - reserved as i64).await;
+        limits.creates = limits.creates.map(|(limit, _)| (limit, usage.creates));
+    }
+    Ok(with_headers(res, &limits))
+}
+
+// A team key's hold on its creates where the route alone does not tell
+// whether a request creates anything, as for GraphQL
+#[derive(Debug, Clone)]
+pub struct Creator {
+    quotas: Quotas,
+    key: ApiKey,
+}
+
+impl Creator {
+    // Takes one create for today, or says why not; hand the day back to
+    // `release` if the create then fails
+    pub async fn reserve(&self) -> Result<u64, String> {
+        let day = today();
+        match self.quotas.reserve(&self.key, day, 1).await {
+            Ok(_) => Ok(day),
+            Err(_) => Err(format!("API key '{}' has used up its creates for today", self.key.name)),
+        }
+    }
+
+    pub async fn release(&self, day: u64) {
+        self.quotas.add(&self.key.name, day, "creates", -1).await;
+    }
+}
+
+// The team key the request carries, if any
+pub fn creator(quotas: Quotas) -> impl Filter<Extract = (Option<Creator>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key").map(move |given: Option<String>| {
+        let key = given.as_deref().and_then(|given| quotas.find(given)).cloned()?;
+        Some(Creator {
+            quotas: quotas.clone(),
+            key,
+        })
+    })
+}
+
+// 429 with the quota headers for OverQuota; other rejections pass on
+pub async fn refused(rejection: Rejection) -> Result<warp::reply::Response, Rejection> {
+    let Some(OverQuota { limits }) = rejection.find::<OverQuota>() else {
+        return Err(rejection);
+    };
+    let what = match limits.requests.is_some_and(|requests| remaining(requests) == 0) {
+        true => "requests",
+        false => "creates",
+    };
+    let message = format!("API key '{}' has used up its {} for today", limits.name, what);
+    let reply = warp::reply::with_status(warp::reply::json(&message), StatusCode::TOO_MANY_REQUESTS);
+    Ok(with_headers(reply.into_response(), limits))
+}
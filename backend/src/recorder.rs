@@ -0,0 +1,160 @@
+use crate::fortunes::now_secs;
+use crate::request_id;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use utoipa::ToSchema;
+use warp::http::Method;
+use warp::hyper::body::{Bytes, HttpBody};
+use warp::hyper::Body;
+use warp::path::FullPath;
+use warp::{Filter, Reply};
+
+// Flight recorder: with FLIGHT_RECORDER_SIZE set, the last requests and their
+// responses are kept in memory, bodies cut to FLIGHT_RECORDER_BODY_BYTES, for
+// GET /admin/recent-requests to show what led up to an incident. Streamed
+// responses (SSE, NDJSON, WebSocket) are kept without their body. Request
+// bodies are only kept for the listeners in server.rs, not over HTTPS.
+
+pub const ROUTE: &str = "/admin/recent-requests";
+
+// Request extension the JSON body readers leave the raw body in, for the
+// recorder to pick up once the response is ready
+#[derive(Debug, Clone, Default)]
+pub struct RequestBody(Arc<Mutex<Option<Bytes>>>);
+
+impl RequestBody {
+    pub fn keep(&self, bytes: Bytes) {
+        *self.0.lock().unwrap() = Some(bytes);
+    }
+
+    fn take(&self) -> Option<Bytes> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Captured {
+    // Lossy UTF-8, at most FLIGHT_RECORDER_BODY_BYTES
+    pub text: String,
+    // Size of the whole body
+    pub bytes: usize,
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Recorded {
+    // Unix timestamp in seconds
+    pub timestamp: u64,
+    pub method: String,
+    // With the query string
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: u64,
+    pub request_id: Option<String>,
+    pub request_body: Option<Captured>,
+    pub response_body: Option<Captured>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    size: usize,
+    body_bytes: usize,
+    entries: Arc<Mutex<VecDeque<Recorded>>>,
+}
+
+impl Recorder {
+    // A `size` of 0 records nothing
+    pub fn new(size: usize, body_bytes: usize) -> Self {
+        Recorder {
+            size,
+            body_bytes,
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(size))),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.size > 0
+    }
+
+    fn capture(&self, bytes: &[u8]) -> Captured {
+        let kept = &bytes[..bytes.len().min(self.body_bytes)];
+        Captured {
+            text: String::from_utf8_lossy(kept).into_owned(),
+            bytes: bytes.len(),
+            truncated: kept.len() < bytes.len(),
+        }
+    }
+
+    fn push(&self, entry: Recorded) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.size {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    // Oldest first
+    pub fn recent(&self) -> Vec<Recorded> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// Records every request except the ones reading the recorder
+pub fn wrap<F, R>(routes: F, recorder: Recorder) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(query)
+        .and(request_id::incoming())
+        .and(warp::ext::optional::<RequestBody>())
+        .and(routes)
+        .then(
+            move |started: Instant, method: Method, path: FullPath, query: String, request_id: Option<String>, request_body: Option<RequestBody>, reply: R| {
+                let recorder = recorder.clone();
+                async move {
+                    let res = reply.into_response();
+                    if !recorder.is_enabled() || path.as_str() == ROUTE {
+                        return res;
+                    }
+                    let latency_ms = started.elapsed().as_millis() as u64;
+                    let (parts, body) = res.into_parts();
+                    // Only bodies already in memory are read; a streamed one
+                    // would hold the response back until it ends
+                    let (body, response_body) = match body.size_hint().exact() {
+                        Some(_) => match warp::hyper::body::to_bytes(body).await {
+                            Ok(bytes) => {
+                                let captured = recorder.capture(&bytes);
+                                (Body::from(bytes), Some(captured))
+                            }
+                            Err(_) => (Body::empty(), None),
+                        },
+                        None => (body, None),
+                    };
+                    let path = match query.as_str() {
+                        "" => path.as_str().to_string(),
+                        query => format!("{}?{}", path.as_str(), query),
+                    };
+                    recorder.push(Recorded {
+                        timestamp: now_secs(),
+                        method: method.to_string(),
+                        path,
+                        status: parts.status.as_u16(),
+                        latency_ms,
+                        request_id,
+                        request_body: request_body.and_then(|body| body.take()).map(|bytes| recorder.capture(&bytes)),
+                        response_body,
+                    });
+                    warp::reply::Response::from_parts(parts, body)
+                }
+            },
+        )
+}
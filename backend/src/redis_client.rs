@@ -1,104 +1,898 @@
+use fortune_common::{debug, error, info, warn};
 use redis::{Client, RedisResult};
-use crate::{Fortune, FortuneStore};
-use std::sync::OnceLock;
+use crate::chaos;
+use crate::config::Config;
+use crate::latency;
+use crate::storage::{Storage, StorageResult};
+use crate::fortunes::{normalize, TrashedFortune};
+use crate::{pubsub, views, write_queue, Fortune, FortuneStore, Status};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-static REDIS_CLIENT: OnceLock<Option<Client>> = OnceLock::new();
+// Empty until Redis is connected, which may happen after startup
+static REDIS_STORE: RwLock<Option<RedisStore>> = RwLock::new(None);
 
-pub async fn init() {
-    let redis_dns = std::env::var("REDIS_DNS");
-    if redis_dns.is_err() {
-        println!("redis config not set");
-        REDIS_CLIENT.set(None).unwrap();
-        return;
+// Takes ids from the counter until one is free in the hash and claims it by
+// writing the fortune there. Skipping taken ids keeps client-chosen numeric
+// ids from being overwritten.
+const ALLOCATE_ID: &str = r#"
+local id
+repeat
+  id = tostring(redis.call('INCR', KEYS[2]))
+until redis.call('HSETNX', KEYS[1], id, ARGV[1]) == 1
+return id
+"#;
+
+// Fortunes read per HSCAN round trip while loading at startup
+const LOAD_BATCH: usize = 1000;
+// A progress line is printed every this many loaded fortunes
+const LOAD_PROGRESS_EVERY: usize = 10_000;
+
+// Fortunes loaded by the last `load_into` and how long it took, for /metrics
+static LAST_LOAD: Mutex<Option<(usize, Duration)>> = Mutex::new(None);
+
+// A stream entry id with its fields
+pub type StreamEntry = (String, HashMap<String, String>);
+
+// Delay between connection attempts: `base` doubled after every failure, up to `max`
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    // Delay after the `attempt`-th failure, counting from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base
+            .saturating_mul(1u32 << attempt.saturating_sub(1).min(16))
+            .min(self.max)
+    }
+}
+
+// Layout of the fortune hash, recorded in `<hash>:schema`:
+// 1. id -> message, with the other fields as JSON in `<hash>:meta` (no version key)
+// 2. id -> JSON of every field, in one place
+const SCHEMA_VERSION: u32 = 2;
+
+// A fortune as stored in the hash; the id is the hash field and views are
+// counted in `<hash>:views`. Also reads the schema 1 `meta` values, which
+// have no message.
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    #[serde(default)]
+    message: String,
+    #[serde(default = "crate::language::default_lang")]
+    lang: String,
+    #[serde(default)]
+    group: Option<String>,
+    #[serde(default)]
+    author: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default)]
+    status: Status,
+    #[serde(default)]
+    publish_at: Option<u64>,
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    created_at: Option<u64>,
+    #[serde(default)]
+    updated_at: Option<u64>,
+}
+
+impl Stored {
+    fn new(fortune: &Fortune) -> Self {
+        Stored {
+            message: fortune.message.clone(),
+            lang: fortune.lang.clone(),
+            group: fortune.group.clone(),
+            author: fortune.author.clone(),
+            tags: fortune.tags.clone(),
+            status: fortune.status,
+            publish_at: fortune.publish_at,
+            expires_at: fortune.expires_at,
+            created_at: fortune.created_at,
+            updated_at: fortune.updated_at,
+        }
+    }
+
+    fn into_fortune(self, id: String) -> Fortune {
+        Fortune {
+            id,
+            message: self.message,
+            lang: self.lang,
+            group: self.group,
+            author: self.author,
+            tags: self.tags,
+            status: self.status,
+            publish_at: self.publish_at,
+            expires_at: self.expires_at,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            ..Default::default()
+        }
+    }
+
+    // A schema 2 value; anything else, such as a schema 1 plain message, is None
+    fn parse(json: &str) -> Option<Stored> {
+        serde_json::from_str::<Stored>(json).ok().filter(|stored| !stored.message.is_empty())
+    }
+}
+
+fn decode(id: String, json: &str) -> Option<Fortune> {
+    match Stored::parse(json) {
+        Some(stored) => Some(stored.into_fortune(id)),
+        None => {
+            warn!("invalid stored fortune {}", id);
+            None
+        }
+    }
+}
+
+// 64-bit FNV-1a, stable across builds and platforms unlike DefaultHasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
+
+// The shard of `id` among `shards`, by jump consistent hashing (Lamping and
+// Veach): going from n to n + 1 shards only moves about 1/(n + 1) of the ids
+pub fn shard_of(id: &str, shards: u32) -> u32 {
+    let mut key = fnv1a(id.as_bytes());
+    let (mut b, mut j) = (-1i64, 0i64);
+    while j < i64::from(shards) {
+        b = j;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b.max(0) as u32
+}
+
+#[derive(Debug, Clone)]
+pub struct RedisStore {
+    client: Client,
+    hash: String,
+    // Fortunes are spread over this many `<hash>:shard:<n>` hashes; 1 keeps them in `hash`
+    shards: u32,
+}
+
+impl RedisStore {
+    pub fn new(client: Client) -> Self {
+        Self::with_hash(client, "fortunes")
+    }
+
+    // Fortunes are kept in a single Redis hash; tests use their own hash name
+    pub fn with_hash(client: Client, hash: &str) -> Self {
+        RedisStore {
+            client,
+            hash: hash.to_string(),
+            shards: 1,
+        }
+    }
+
+    pub fn with_shards(self, shards: u32) -> Self {
+        RedisStore {
+            shards: shards.max(1),
+            ..self
+        }
     }
 
-    let redis_url = format!("redis://{}:6379", crate::utils::get_env("REDIS_DNS", "localhost"));
+    // A named collection's fortunes, e.g. `fortunes:collection:team-a`, with
+    // the same sibling keys as the default hash. Collections are not sharded.
+    pub fn for_collection(&self, name: &str) -> Self {
+        Self::with_hash(self.client.clone(), &format!("{}:collection:{}", self.hash, name))
+    }
+
+    fn shard_hash(&self, shard: u32) -> String {
+        format!("{}:shard:{}", self.hash, shard)
+    }
+
+    // The hash holding the fortune `id`
+    fn hash_for(&self, id: &str) -> String {
+        match self.shards {
+            1 => self.hash.clone(),
+            shards => self.shard_hash(shard_of(id, shards)),
+        }
+    }
+
+    // Every hash holding fortunes, which reads of the whole set scatter over
+    fn hashes(&self) -> Vec<String> {
+        match self.shards {
+            1 => vec![self.hash.clone()],
+            shards => (0..shards).map(|shard| self.shard_hash(shard)).collect(),
+        }
+    }
+
+    // The shard count the data was last laid out with, e.g. `fortunes:shards`
+    fn shards_key(&self) -> String {
+        format!("{}:shards", self.hash)
+    }
+
+    fn try_connect(redis_url: &str) -> Result<RedisStore, String> {
+        let client = Client::open(redis_url).map_err(|e| format!("redis client creation failed: {}", e))?;
+        client.get_connection().map_err(|e| format!("redis connection failed: {}", e))?;
+        Ok(RedisStore::new(client))
+    }
 
-    for attempt in 1..=5 {
-        match Client::open(redis_url.as_str()) {
-            Ok(client) => {
-                match client.get_connection() {
-                    Ok(_) => {
-                        REDIS_CLIENT.set(Some(client)).unwrap();
-                        println!("Successfully connected to Redis");
+    pub async fn connect(redis_url: &str, attempts: u32, backoff: Backoff) -> Option<RedisStore> {
+        for attempt in 1..=attempts {
+            match Self::try_connect(redis_url) {
+                Ok(redis) => {
+                    info!("Successfully connected to Redis");
+                    return Some(redis);
+                }
+                Err(e) => warn!("Attempt {}: {}", attempt, e),
+            }
+            if attempt < attempts {
+                tokio::time::sleep(backoff.delay(attempt)).await;
+            }
+        }
+
+        error!("Failed to connect to redis after {} attempts", attempts);
+        None
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    // A connection for a request or background job; counts towards the
+    // request's Redis use in the slow-request log
+    pub fn connection(&self) -> RedisResult<redis::Connection> {
+        latency::mark_redis();
+        chaos::redis_fault()?;
+        self.client.get_connection()
+    }
+
+    pub async fn ping(&self) -> bool {
+        self
+            .connection()
+            .and_then(|mut conn| redis::cmd("PING").query::<String>(&mut conn))
+            .is_ok()
+    }
+
+    // Streams the hash into the store with HSCAN, a batch at a time, so a
+    // large hash neither blocks Redis with one huge reply nor holds the store
+    // lock for the whole load
+    pub async fn load_into(&self, store: FortuneStore) {
+        let started = Instant::now();
+        let mut conn = match self.connection() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("redis load failed: {}", e);
+                return;
+            }
+        };
+        let mut loaded = 0;
+        for hash in self.hashes() {
+            let mut cursor: u64 = 0;
+            loop {
+                let page: RedisResult<(u64, Vec<(String, String)>)> = redis::cmd("HSCAN")
+                    .arg(&hash)
+                    .arg(cursor)
+                    .arg("COUNT")
+                    .arg(LOAD_BATCH)
+                    .query(&mut conn);
+                let (next, entries) = match page {
+                    Ok(page) => page,
+                    Err(e) => {
+                        error!("redis load failed after {} fortunes: {}", loaded, e);
                         return;
                     }
-                    Err(e) => {
-                        eprintln!("Attempt {}: redis connection failed: {}", attempt, e);
+                };
+                let batch: Vec<Fortune> = entries.into_iter().filter_map(|(id, json)| decode(id, &json)).collect();
+                let before = loaded;
+                {
+                    // Past MAX_CACHED_FORTUNES only the outlines stay in memory
+                    let mut store_write = store.write().await;
+                    for fortune in batch {
+                        store_write.insert(fortune.id.clone(), fortune);
+                        loaded += 1;
                     }
                 }
+                if before / LOAD_PROGRESS_EVERY != loaded / LOAD_PROGRESS_EVERY {
+                    debug!("loading redis fortunes: {} so far", loaded);
+                }
+                cursor = next;
+                if cursor == 0 {
+                    break;
+                }
             }
+        }
+        let elapsed = started.elapsed();
+        info!("loaded {} fortunes from redis in {}ms", loaded, elapsed.as_millis());
+        *LAST_LOAD.lock().unwrap() = Some((loaded, elapsed));
+    }
+
+    // Schema 1 only; removed by `migrate`
+    fn meta_hash(&self) -> String {
+        format!("{}:meta", self.hash)
+    }
+
+    fn schema_key(&self) -> String {
+        format!("{}:schema", self.hash)
+    }
+
+    fn id_counter(&self) -> String {
+        format!("{}:next_id", self.hash)
+    }
+
+    // Brings the hash up to SCHEMA_VERSION. Runs in a WATCH transaction, so a
+    // replica that writes or migrates concurrently makes it start over rather
+    // than lose data. Returns the number of converted fortunes.
+    pub async fn migrate(&self) -> StorageResult<usize> {
+        let mut conn = self.connection()?;
+        let schema_key = self.schema_key();
+        let meta_hash = self.meta_hash();
+        let converted = redis::transaction(&mut conn, &[&schema_key, &self.hash, &meta_hash], |conn, pipe| {
+            let version: Option<u32> = redis::cmd("GET").arg(&schema_key).query(conn)?;
+            match version {
+                Some(version) if version > SCHEMA_VERSION => {
+                    warn!("redis schema {} is newer than this build ({})", version, SCHEMA_VERSION);
+                    return Ok(Some(0));
+                }
+                Some(SCHEMA_VERSION) => return Ok(Some(0)),
+                _ => {}
+            }
+
+            let messages: HashMap<String, String> = redis::cmd("HGETALL").arg(&self.hash).query(conn)?;
+            let metas: HashMap<String, String> = redis::cmd("HGETALL").arg(&meta_hash).query(conn)?;
+            let mut converted = 0;
+            for (id, message) in messages {
+                // Written by an upgraded replica before the version was recorded
+                if Stored::parse(&message).is_some() {
+                    continue;
+                }
+                let mut stored = metas
+                    .get(&id)
+                    .and_then(|json| serde_json::from_str::<Stored>(json).ok())
+                    .unwrap_or_else(|| Stored::new(&Fortune::default()));
+                stored.message = message;
+                let json = serde_json::to_string(&stored).expect("stored fortunes serialize");
+                pipe.cmd("HSET").arg(&self.hash).arg(&id).arg(json).ignore();
+                converted += 1;
+            }
+            pipe.cmd("DEL").arg(&meta_hash).ignore()
+                .cmd("SET").arg(&schema_key).arg(SCHEMA_VERSION).ignore();
+            Ok(pipe.query::<Option<()>>(conn)?.map(|()| converted))
+        })?;
+        Ok(converted)
+    }
+
+    // View counters, e.g. `fortunes:views`
+    fn views_hash(&self) -> String {
+        format!("{}:views", self.hash)
+    }
+
+    // Adds several ids' views in one round trip; the new totals come back in order
+    pub async fn add_views(&self, counts: &[(String, u64)]) -> StorageResult<Vec<u64>> {
+        let mut conn = self.connection()?;
+        let mut pipe = redis::pipe();
+        for (id, count) in counts {
+            pipe.cmd("HINCRBY").arg(self.views_hash()).arg(id).arg(*count);
+        }
+        Ok(pipe.query(&mut conn)?)
+    }
+
+    // Every fortune's view count across all replicas
+    pub async fn views(&self) -> RedisResult<HashMap<String, u64>> {
+        let mut conn = self.connection()?;
+        redis::cmd("HGETALL").arg(self.views_hash()).query(&mut conn)
+    }
+
+    pub async fn load_views_into(&self, store: &FortuneStore) {
+        match self.views().await {
+            Ok(views) => {
+                let fortunes = store.read().await;
+                for (id, count) in views {
+                    fortunes.set_views(&id, count);
+                }
+            }
+            Err(e) => error!("redis view count load failed: {}", e),
+        }
+    }
+
+    // Ids of fortunes by an author are kept in a set per normalized name,
+    // e.g. `fortunes:author:confucius`
+    fn author_set(&self, author: &str) -> String {
+        format!("{}:author:{}", self.hash, normalize(author))
+    }
+
+    fn stored_author(&self, conn: &mut redis::Connection, id: &str) -> StorageResult<Option<String>> {
+        let stored: Option<String> = redis::cmd("HGET").arg(self.hash_for(id)).arg(id).query(conn)?;
+        Ok(stored.and_then(|json| Stored::parse(&json)).and_then(|stored| stored.author))
+    }
+
+    pub async fn ids_by_author(&self, author: &str) -> StorageResult<Vec<String>> {
+        let mut conn = self.connection()?;
+        Ok(redis::cmd("SMEMBERS").arg(self.author_set(author)).query(&mut conn)?)
+    }
+
+    // Soft-deleted fortunes live in a sibling hash, e.g. `fortunes:deleted`
+    fn deleted_hash(&self) -> String {
+        format!("{}:deleted", self.hash)
+    }
+
+    // The mutation event log, a stream such as `fortunes:events`
+    fn events_stream(&self) -> String {
+        format!("{}:events", self.hash)
+    }
+
+    // Adds one stream entry per list of field/value pairs in a single round
+    // trip, trimming the stream to roughly `max_len` entries
+    pub async fn append_events(&self, events: &[Vec<(&str, String)>], max_len: usize) -> StorageResult<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.connection()?;
+        let mut pipe = redis::pipe();
+        for fields in events {
+            pipe.cmd("XADD")
+                .arg(self.events_stream())
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(max_len)
+                .arg("*")
+                .arg(fields)
+                .ignore();
+        }
+        pipe.query::<()>(&mut conn)?;
+        Ok(())
+    }
+
+    // Up to `count` entries added after the entry id `after`, oldest first
+    pub async fn read_events(&self, after: &str, count: usize) -> StorageResult<Vec<StreamEntry>> {
+        let mut conn = self.connection()?;
+        // One (stream, entries) pair per stream read, or nil when there are none
+        let reply: Option<Vec<(String, Vec<StreamEntry>)>> = redis::cmd("XREAD")
+            .arg("COUNT")
+            .arg(count)
+            .arg("STREAMS")
+            .arg(self.events_stream())
+            .arg(after)
+            .query(&mut conn)?;
+        Ok(reply.into_iter().flatten().flat_map(|(_, entries)| entries).collect())
+    }
+
+    pub async fn load_deleted_into(&self, store: &FortuneStore) {
+        let entries: RedisResult<HashMap<String, String>> = self
+            .connection()
+            .and_then(|mut conn| redis::cmd("HGETALL").arg(self.deleted_hash()).query(&mut conn));
+        let entries = match entries {
+            Ok(entries) => entries,
             Err(e) => {
-                eprintln!("Attempt {}: redis client creation failed: {}", attempt, e);
+                error!("redis trash load failed: {}", e);
+                return;
+            }
+        };
+        let mut store_write = store.write().await;
+        for (id, json) in entries {
+            match serde_json::from_str::<TrashedFortune>(&json) {
+                Ok(trashed) => store_write.insert_trashed(trashed),
+                Err(e) => warn!("invalid trashed fortune {}: {}", id, e),
             }
         }
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
     }
 
-    eprintln!("Failed to connect to redis after 5 attempts");
-    REDIS_CLIENT.set(None).unwrap();
+    // Queues the writes for one fortune, moving it out of the set of the
+    // author it was stored under before
+    fn queue_set(&self, pipe: &mut redis::Pipeline, fortune: &Fortune, previous_author: Option<String>) -> StorageResult<()> {
+        pipe.cmd("HSET")
+            .arg(self.hash_for(&fortune.id))
+            .arg(&fortune.id)
+            .arg(serde_json::to_string(&Stored::new(fortune))?)
+            .ignore();
+        if let Some(author) = previous_author {
+            pipe.cmd("SREM").arg(self.author_set(&author)).arg(&fortune.id).ignore();
+        }
+        if let Some(author) = &fortune.author {
+            pipe.cmd("SADD").arg(self.author_set(author)).arg(&fortune.id).ignore();
+        }
+        Ok(())
+    }
+
+    async fn get_all(&self) -> RedisResult<HashMap<String, Fortune>> {
+        let mut conn = self.connection()?;
+        let mut all = HashMap::new();
+        for hash in self.hashes() {
+            let stored: HashMap<String, String> = redis::cmd("HGETALL").arg(hash).query(&mut conn)?;
+            all.extend(
+                stored
+                    .into_iter()
+                    .filter_map(|(id, json)| decode(id.clone(), &json).map(|fortune| (id, fortune))),
+            );
+        }
+        Ok(all)
+    }
+
+    // The shard count the data is laid out with when it differs from
+    // `self.shards`. An empty Redis takes the configured layout.
+    pub async fn layout_mismatch(&self) -> StorageResult<Option<u32>> {
+        let mut conn = self.connection()?;
+        let recorded: Option<u32> = redis::cmd("GET").arg(self.shards_key()).query(&mut conn)?;
+        let recorded = match recorded {
+            Some(recorded) => recorded,
+            None if self.shards == 1 => return Ok(None),
+            None => {
+                let unsharded: usize = redis::cmd("HLEN").arg(&self.hash).query(&mut conn)?;
+                if unsharded > 0 {
+                    return Ok(Some(1));
+                }
+                redis::cmd("SET").arg(self.shards_key()).arg(self.shards).query::<()>(&mut conn)?;
+                self.shards
+            }
+        };
+        Ok((recorded != self.shards).then_some(recorded))
+    }
+
+    // Moves every fortune into the hash it belongs in with `self.shards`,
+    // from the unsharded hash and from the shards of any earlier layout, then
+    // records the layout. Each batch moves atomically, but writes from
+    // running replicas may land in the old layout, so stop them first.
+    // Returns the number of fortunes moved.
+    pub async fn reshard(&self) -> StorageResult<usize> {
+        let mut conn = self.connection()?;
+        let mut sources = vec![self.hash.clone()];
+        let pattern = format!("{}:shard:*", self.hash);
+        let mut cursor: u64 = 0;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(LOAD_BATCH)
+                .query(&mut conn)?;
+            sources.extend(keys);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+        sources.sort();
+        sources.dedup();
+
+        let mut moved = 0;
+        for source in sources {
+            let mut cursor: u64 = 0;
+            loop {
+                let (next, entries): (u64, Vec<(String, String)>) = redis::cmd("HSCAN")
+                    .arg(&source)
+                    .arg(cursor)
+                    .arg("COUNT")
+                    .arg(LOAD_BATCH)
+                    .query(&mut conn)?;
+                let mut pipe = redis::pipe();
+                pipe.atomic();
+                let before = moved;
+                for (id, json) in entries {
+                    let target = self.hash_for(&id);
+                    if target != source {
+                        pipe.cmd("HSET").arg(&target).arg(&id).arg(json).ignore()
+                            .cmd("HDEL").arg(&source).arg(&id).ignore();
+                        moved += 1;
+                    }
+                }
+                if moved > before {
+                    pipe.query::<()>(&mut conn)?;
+                }
+                cursor = next;
+                if cursor == 0 {
+                    break;
+                }
+            }
+        }
+        redis::cmd("SET").arg(self.shards_key()).arg(self.shards).query::<()>(&mut conn)?;
+        Ok(moved)
+    }
 }
 
-pub async fn get_client() -> Option<Client> {
-    REDIS_CLIENT.get().and_then(|opt| opt.as_ref().cloned())
+#[async_trait]
+impl Storage for RedisStore {
+    async fn load_all(&self) -> StorageResult<Vec<Fortune>> {
+        Ok(self.get_all().await?.into_values().collect())
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Fortune>> {
+        let mut conn = self.connection()?;
+        let stored: Option<String> = redis::cmd("HGET").arg(self.hash_for(id)).arg(id).query(&mut conn)?;
+        Ok(stored.and_then(|json| decode(id.to_string(), &json)))
+    }
+
+    async fn set(&self, fortune: &Fortune) -> StorageResult<()> {
+        let mut conn = self.connection()?;
+        let previous_author = self.stored_author(&mut conn, &fortune.id)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.queue_set(&mut pipe, fortune, previous_author)?;
+        pipe.query::<()>(&mut conn)?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<()> {
+        let mut conn = self.connection()?;
+        let author = self.stored_author(&mut conn, id)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("HDEL")
+            .arg(self.hash_for(id))
+            .arg(id)
+            .ignore()
+            .cmd("HDEL")
+            .arg(self.views_hash())
+            .arg(id)
+            .ignore();
+        if let Some(author) = author {
+            pipe.cmd("SREM").arg(self.author_set(&author)).arg(id).ignore();
+        }
+        pipe.query::<()>(&mut conn)?;
+        Ok(())
+    }
+
+    // Writes all fortunes in one atomic pipeline
+    async fn set_many(&self, fortunes: &[Fortune]) -> StorageResult<()> {
+        if fortunes.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.connection()?;
+        let mut lookup = redis::pipe();
+        for fortune in fortunes {
+            lookup.cmd("HGET").arg(self.hash_for(&fortune.id)).arg(&fortune.id);
+        }
+        let stored: Vec<Option<String>> = lookup.query(&mut conn)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (fortune, stored) in fortunes.iter().zip(stored) {
+            let previous_author = stored
+                .and_then(|json| Stored::parse(&json))
+                .and_then(|stored| stored.author);
+            self.queue_set(&mut pipe, fortune, previous_author)?;
+        }
+        pipe.query::<()>(&mut conn)?;
+        Ok(())
+    }
+
+    // Allocates the next free numeric id and stores the fortune under it in
+    // one script, so concurrent creates on different replicas can neither get
+    // the same id nor overwrite each other's fortunes
+    async fn allocate_id(&self, fortune: &Fortune) -> StorageResult<String> {
+        let mut conn = self.connection()?;
+        let json = serde_json::to_string(&Stored::new(fortune))?;
+        if self.shards == 1 {
+            let id = redis::Script::new(ALLOCATE_ID)
+                .key(&self.hash)
+                .key(self.id_counter())
+                .arg(json)
+                .invoke(&mut conn)?;
+            return Ok(id);
+        }
+        // The script cannot know the shard of an id it has yet to draw; INCR
+        // still hands each id out once, and HSETNX keeps taken ones
+        loop {
+            let id: u64 = redis::cmd("INCR").arg(self.id_counter()).query(&mut conn)?;
+            let id = id.to_string();
+            let claimed: bool = redis::cmd("HSETNX").arg(self.hash_for(&id)).arg(&id).arg(&json).query(&mut conn)?;
+            if claimed {
+                return Ok(id);
+            }
+        }
+    }
+
+    // Gives back ids `allocate_id` claimed for fortunes the store then
+    // refused, so what it wrote under them is not loaded as a fortune
+    async fn release_ids(&self, ids: &[String]) -> StorageResult<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.connection()?;
+        let mut pipe = redis::pipe();
+        for id in ids {
+            pipe.cmd("HDEL").arg(self.hash_for(id)).arg(id).ignore();
+        }
+        pipe.query::<()>(&mut conn)?;
+        Ok(())
+    }
+
+    async fn add_view(&self, id: &str) -> StorageResult<u64> {
+        let mut conn = self.connection()?;
+        Ok(redis::cmd("HINCRBY").arg(self.views_hash()).arg(id).arg(1).query(&mut conn)?)
+    }
+
+    async fn set_deleted(&self, trashed: &TrashedFortune) -> StorageResult<()> {
+        let mut conn = self.connection()?;
+        redis::cmd("HSET")
+            .arg(self.deleted_hash())
+            .arg(&trashed.fortune.id)
+            .arg(serde_json::to_string(trashed)?)
+            .query::<()>(&mut conn)?;
+        Ok(())
+    }
+
+    async fn remove_deleted(&self, ids: &[String]) -> StorageResult<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.connection()?;
+        redis::cmd("HDEL")
+            .arg(self.deleted_hash())
+            .arg(ids)
+            .query::<()>(&mut conn)?;
+        Ok(())
+    }
+
+    async fn publish(&self, event: pubsub::FortuneEvent) {
+        pubsub::publish(self.client(), event).await;
+    }
 }
 
-pub async fn load_fortunes(client: &Client, store: FortuneStore) {
-    let mut conn = match client.get_connection() {
-        Ok(conn) => conn,
+// Reading a hash in a format this build does not understand would look
+// like an empty store, so Redis is left out if the migration fails
+async fn migrated(redis: RedisStore) -> Option<RedisStore> {
+    // Likewise for fortunes laid out over a different number of shards
+    match redis.layout_mismatch().await {
+        Ok(None) => {}
+        Ok(Some(recorded)) => {
+            warn!(
+                "redis holds fortunes in {} shards but REDIS_SHARDS is {}; run `fortune-backend reshard` to move them, not using redis",
+                recorded, redis.shards
+            );
+            return None;
+        }
         Err(e) => {
-            eprintln!("Failed to get Redis connection: {}", e);
-            return;
+            error!("redis shard layout check failed, not using redis: {}", e);
+            return None;
+        }
+    }
+    match redis.migrate().await {
+        Ok(0) => Some(redis),
+        Ok(converted) => {
+            info!("migrated {} redis fortunes to schema {}", converted, SCHEMA_VERSION);
+            Some(redis)
+        }
+        Err(e) => {
+            error!("redis schema migration failed, not using redis: {}", e);
+            None
+        }
+    }
+}
+
+pub async fn init(config: &Config) {
+    let store = match config.redis_url() {
+        Some(url) => match RedisStore::connect(&url, config.redis_connect_attempts, config.redis_backoff()).await {
+            Some(redis) => migrated(redis.with_shards(config.redis_shards)).await,
+            None => None,
+        },
+        None => {
+            info!("redis config not set");
+            None
         }
     };
+    *REDIS_STORE.write().unwrap() = store;
+}
 
-    let keys: RedisResult<Vec<String>> = redis::cmd("HKEYS").arg("fortunes").query(&mut conn);
+pub fn metrics() -> String {
+    let (loaded, elapsed) = LAST_LOAD.lock().unwrap().unwrap_or_default();
+    format!(
+        "backend_redis_load_fortunes {}\n\
+         backend_redis_load_seconds {:.3}\n",
+        loaded,
+        elapsed.as_secs_f64(),
+    )
+}
 
-    match keys {
-        Ok(fortune_keys) => {
-            println!("*** loading redis fortunes:");
-            let mut store_write = store.write().await;
+pub async fn get_store() -> Option<RedisStore> {
+    REDIS_STORE.read().unwrap().clone()
+}
 
-            for key in fortune_keys {
-                let message: RedisResult<String> = redis::cmd("HGET")
-                    .arg("fortunes")
-                    .arg(&key)
-                    .query(&mut conn);
+// Loads what Redis holds into the store and starts the jobs that keep the
+// two in step
+pub async fn attach(redis: RedisStore, store: &FortuneStore, config: &Config) {
+    store.write().await.set_storage(Some(Arc::new(redis.clone())));
+    redis.load_into(store.clone()).await;
+    redis.load_views_into(store).await;
+    write_queue::init(
+        redis.clone(),
+        config.redis_write_queue_size,
+        Duration::from_secs(config.redis_retry_delay_secs),
+    );
+    if let Some(interval) = config.redis_sync_interval() {
+        spawn_sync(redis.clone(), store.clone(), interval);
+    }
+    if let Some(interval) = config.views_flush() {
+        views::spawn_flush(redis.clone(), store.clone(), interval);
+    }
+    pubsub::spawn_subscriber(redis.client().clone(), store.clone());
+    if config.soft_delete {
+        redis.load_deleted_into(store).await;
+    }
+}
 
-                match message {
-                    Ok(msg) => {
-                        let fortune = Fortune {
-                            id: key.clone(),
-                            message: msg.clone(),
-                        };
-                        store_write.insert(key.clone(), fortune);
-                        println!("{} => {}", key, msg);
-                    }
-                    Err(e) => {
-                        eprintln!("redis hget failed: {}", e);
-                    }
+// `fortune-backend reshard`: lays the fortunes out over REDIS_SHARDS hashes,
+// wherever they are now. Returns the number of fortunes moved.
+pub async fn reshard(config: &Config) -> Result<usize, String> {
+    let url = config.redis_url().ok_or("REDIS_DNS is not set")?;
+    let redis = RedisStore::connect(&url, config.redis_connect_attempts, config.redis_backoff())
+        .await
+        .ok_or("redis is unreachable")?
+        .with_shards(config.redis_shards);
+    redis.migrate().await.map_err(|e| format!("schema migration failed: {}", e))?;
+    redis.reshard().await.map_err(|e| format!("resharding failed: {}", e))
+}
+
+// Keeps trying to reach a Redis that was down at startup, continuing the
+// backoff where `init` left off, and attaches it once it answers. Fortunes
+// created in the meantime stay in memory only.
+pub fn spawn_reconnect(config: Config, store: FortuneStore) {
+    let Some(url) = config.redis_url() else {
+        return;
+    };
+    let backoff = config.redis_backoff();
+    tokio::spawn(async move {
+        let mut attempt = config.redis_connect_attempts;
+        loop {
+            tokio::time::sleep(backoff.delay(attempt)).await;
+            attempt = attempt.saturating_add(1);
+            let redis = match RedisStore::try_connect(&url) {
+                Ok(redis) => redis.with_shards(config.redis_shards),
+                Err(e) => {
+                    warn!("redis reconnect attempt {}: {}", attempt, e);
+                    continue;
                 }
-            }
+            };
+            let Some(redis) = migrated(redis).await else {
+                continue;
+            };
+            info!("Connected to Redis after {} attempts", attempt);
+            *REDIS_STORE.write().unwrap() = Some(redis.clone());
+            attach(redis, &store, &config).await;
+            return;
         }
-        Err(e) => {
-            eprintln!("redis hkeys failed: {}", e);
+    });
+}
+
+// Reconciles the store with the Redis hash. Ids that were present in Redis on
+// the previous pass but are gone now are removed; ids never seen in Redis
+// (such as the built-in defaults) are left alone.
+async fn sync_fortunes(redis: &RedisStore, store: &FortuneStore, known: &mut HashSet<String>) -> RedisResult<()> {
+    let remote = redis.get_all().await?;
+
+    let mut store_write = store.write().await;
+    let mut added = 0;
+    let mut removed = 0;
+    for id in known.iter() {
+        if !remote.contains_key(id) && store_write.contains_key(id) {
+            store_write.remove(id);
+            removed += 1;
         }
     }
-}
+    for (id, fortune) in &remote {
+        if !store_write.holds(fortune) {
+            store_write.insert(id.clone(), fortune.clone());
+            added += 1;
+        }
+    }
+    *known = remote.into_keys().collect();
 
-pub async fn get_fortune(client: &Client, key: &str) -> RedisResult<String> {
-    let mut conn = client.get_connection()?;
-    redis::cmd("HGET")
-        .arg("fortunes")
-        .arg(key)
-        .query(&mut conn)
+    if added > 0 || removed > 0 {
+        crate::snapshot::mark_dirty();
+        debug!("redis sync: {} added or updated, {} removed", added, removed);
+    }
+    Ok(())
 }
 
-pub async fn set_fortune(client: &Client, key: &str, message: &str) -> RedisResult<()> {
-    let mut conn = client.get_connection()?;
-    redis::cmd("HSET")
-        .arg("fortunes")
-        .arg(key)
-        .arg(message)
-        .query(&mut conn)
+pub fn spawn_sync(redis: RedisStore, store: FortuneStore, interval: Duration) {
+    tokio::spawn(async move {
+        let mut known = HashSet::new();
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = sync_fortunes(&redis, &store, &mut known).await {
+                error!("redis sync failed: {}", e);
+            }
+        }
+    });
 }
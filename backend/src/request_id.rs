@@ -0,0 +1,73 @@
+use crate::client_ip::{self, TrustedProxies};
+use fortune_common::warn;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Instant;
+use warp::http::{HeaderMap, Method};
+use warp::path::FullPath;
+use warp::{Filter, Reply};
+
+pub const HEADER: &str = fortune_common::REQUEST_ID_HEADER;
+
+const MAX_LEN: usize = 128;
+
+fn is_valid(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_LEN
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn from_headers(headers: &warp::http::HeaderMap) -> Option<String> {
+    headers
+        .get(HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| is_valid(value))
+        .map(str::to_string)
+}
+
+// X-Request-Id forwarded by the frontend (or any other client), if present and valid
+pub fn incoming() -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: warp::http::HeaderMap| from_headers(&headers))
+}
+
+// Returns the caller's request id on every response, including errors
+pub fn echo(request_id: Option<String>, reply: impl Reply) -> warp::reply::Response {
+    match request_id {
+        Some(id) => warp::reply::with_header(reply, HEADER, id).into_response(),
+        None => reply.into_response(),
+    }
+}
+
+// Logs failed requests with their request id, so they can be matched to
+// frontend logs, and the client address
+pub fn log<F, R>(routes: F, proxies: TrustedProxies) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(client_ip::remote())
+        .and(routes)
+        .map(
+            move |started: Instant, method: Method, path: FullPath, headers: HeaderMap, peer: Option<SocketAddr>, reply: R| {
+                let res = reply.into_response();
+                let status = res.status();
+                if status.is_client_error() || status.is_server_error() {
+                    warn!(
+                        "[{}] {} {} {} -> {} ({}ms)",
+                        from_headers(&headers).as_deref().unwrap_or("-"),
+                        proxies.resolve(peer, &headers).map_or_else(|| "-".to_string(), |ip| ip.to_string()),
+                        method,
+                        path.as_str(),
+                        status.as_u16(),
+                        started.elapsed().as_millis(),
+                    );
+                }
+                res
+            },
+        )
+}
@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use warp::http::header::{HeaderName, HeaderValue};
+use warp::http::HeaderMap;
+use warp::{Filter, Reply};
+
+// Extra headers from RESPONSE_HEADERS added to every response, e.g.
+// `{"X-Frame-Options":"DENY","Cache-Control":"no-store"}`. A header the
+// handler already set is left alone.
+
+pub fn parse(json: &str) -> Result<HeaderMap, String> {
+    let headers: BTreeMap<String, String> =
+        serde_json::from_str(json).map_err(|e| format!("RESPONSE_HEADERS is not a JSON object of strings: {}", e))?;
+    let mut parsed = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("RESPONSE_HEADERS: '{}' is not a valid header name", name))?;
+        let value = HeaderValue::from_str(&value).map_err(|_| format!("RESPONSE_HEADERS: the value of {} is not a valid header value", name))?;
+        parsed.insert(name, value);
+    }
+    Ok(parsed)
+}
+
+pub fn wrap<F, R>(routes: F, headers: HeaderMap) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let headers = Arc::new(headers);
+    routes.map(move |reply: R| {
+        let mut res = reply.into_response();
+        for (name, value) in headers.iter() {
+            if !res.headers().contains_key(name) {
+                res.headers_mut().insert(name, value.clone());
+            }
+        }
+        res
+    })
+}
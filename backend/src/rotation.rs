@@ -0,0 +1,174 @@
+use crate::fortunes::Fortunes;
+use crate::redis_client;
+use crate::store::{normalize_tags, MAX_TAGS};
+use fortune_common::error;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// Highest weight a single tag can be given
+pub const MAX_WEIGHT: u64 = 100;
+
+// Fallback for when Redis is not configured
+struct Step {
+    next: u64,
+    expires_at: Instant,
+}
+
+static LOCAL: OnceLock<Mutex<HashMap<String, Step>>> = OnceLock::new();
+
+fn local() -> &'static Mutex<HashMap<String, Step>> {
+    LOCAL.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Tags with how many of every `total()` fortunes should carry them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rotation(Vec<(String, u64)>);
+
+impl Rotation {
+    // Comma-separated `tag` or `tag:weight` entries such as `zen:3,programming:1`;
+    // a tag without a weight counts once. Tags are matched like stored tags.
+    pub fn parse(spec: &str) -> Result<Rotation, String> {
+        let mut weights: Vec<(String, u64)> = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (tag, weight) = match entry.split_once(':') {
+                Some((tag, weight)) => {
+                    let weight = weight
+                        .trim()
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|weight| (1..=MAX_WEIGHT).contains(weight))
+                        .ok_or_else(|| format!("weight of '{}' must be a number from 1 to {}", tag.trim(), MAX_WEIGHT))?;
+                    (tag, weight)
+                }
+                None => (entry, 1),
+            };
+            let tag = match normalize_tags(&[tag.to_string()]).as_deref() {
+                Some([tag]) => tag.clone(),
+                _ => return Err(format!("invalid tag '{}', use a-z, 0-9, - and _", tag.trim())),
+            };
+            if weights.iter().any(|(known, _)| *known == tag) {
+                return Err(format!("tag '{}' is listed twice", tag));
+            }
+            weights.push((tag, weight));
+        }
+        if weights.is_empty() {
+            return Err("tags must name at least one tag".to_string());
+        }
+        if weights.len() > MAX_TAGS {
+            return Err(format!("tags can name at most {} tags", MAX_TAGS));
+        }
+        Ok(Rotation(weights))
+    }
+
+    fn total(&self) -> u64 {
+        self.0.iter().map(|(_, weight)| weight).sum()
+    }
+
+    // The spec in a stable form, so `zen:3,programming` and
+    // `programming:1, zen:3` share their position
+    fn canonical(&self) -> String {
+        let mut entries: Vec<String> = self.0.iter().map(|(tag, weight)| format!("{}:{}", tag, weight)).collect();
+        entries.sort();
+        entries.join(",")
+    }
+
+    // One cycle of tag indexes, each tag appearing `weight` times, spread out
+    // by smooth weighted round-robin: zen:3,programming:1 gives
+    // zen, zen, programming, zen rather than three zens in a row
+    fn cycle(&self) -> Vec<usize> {
+        let total = self.total() as i64;
+        let mut current = vec![0i64; self.0.len()];
+        (0..total)
+            .map(|_| {
+                for (i, (_, weight)) in self.0.iter().enumerate() {
+                    current[i] += *weight as i64;
+                }
+                let chosen = (0..current.len()).max_by_key(|&i| (current[i], -(i as i64))).unwrap_or_default();
+                current[chosen] -= total;
+                chosen
+            })
+            .collect()
+    }
+
+    // The id shown at `step`, or None when no tag has any fortunes.
+    // Each tag walks its fortunes in id order, so the sequence only depends on
+    // the step and the store. Tags with no fortunes are left out of the cycle.
+    pub fn pick<'a>(&self, fortunes: &'a Fortunes, step: u64) -> Option<&'a String> {
+        let stocked = Rotation(
+            self.0
+                .iter()
+                .filter(|(tag, _)| fortunes.tagged_count(tag) > 0)
+                .cloned()
+                .collect(),
+        );
+        let cycle = stocked.cycle();
+        if cycle.is_empty() {
+            return None;
+        }
+        let len = cycle.len() as u64;
+        let (round, position) = (step / len, (step % len) as usize);
+        let tag = cycle[position];
+        let (name, weight) = &stocked.0[tag];
+        let earlier = cycle[..position].iter().filter(|&&t| t == tag).count() as u64;
+        fortunes.nth_tagged(name, round * weight + earlier)
+    }
+}
+
+fn key(collection: &str, token: &str, rotation: &Rotation) -> String {
+    format!("fortunes:rotation:{}:{}:{}", collection, token, rotation.canonical())
+}
+
+// The client's next step through `rotation` in `collection`, counting from 0.
+// The position expires `ttl` after its last use, like a session.
+pub async fn advance(collection: &str, token: &str, rotation: &Rotation, ttl: Duration) -> u64 {
+    let key = key(collection, token, rotation);
+    if let Some(redis) = redis_client::get_store().await {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("INCR").arg(&key);
+        pipe.cmd("EXPIRE").arg(&key).arg(ttl.as_secs()).ignore();
+        let result = redis
+            .connection()
+            .and_then(|mut conn| pipe.query::<(u64,)>(&mut conn));
+        match result {
+            Ok((next,)) => return next - 1,
+            Err(e) => error!("redis rotation update failed: {}", e),
+        }
+    }
+
+    let now = Instant::now();
+    let mut local = local().lock().unwrap();
+    if !local.contains_key(&key) {
+        local.retain(|_, step| step.expires_at > now);
+    }
+    let step = local.entry(key).or_insert(Step { next: 0, expires_at: now });
+    if step.expires_at <= now {
+        step.next = 0;
+    }
+    let current = step.next;
+    step.next += 1;
+    step.expires_at = now + ttl;
+    current
+}
+
+// The step `advance` would return next, without taking it
+pub async fn peek(collection: &str, token: &str, rotation: &Rotation) -> u64 {
+    let key = key(collection, token, rotation);
+    if let Some(redis) = redis_client::get_store().await {
+        let result = redis
+            .connection()
+            .and_then(|mut conn| redis::cmd("GET").arg(&key).query::<Option<u64>>(&mut conn));
+        match result {
+            Ok(next) => return next.unwrap_or_default(),
+            Err(e) => error!("redis rotation lookup failed: {}", e),
+        }
+    }
+
+    let now = Instant::now();
+    let local = local().lock().unwrap();
+    match local.get(&key) {
+        Some(step) if step.expires_at > now => step.next,
+        _ => 0,
+    }
+}
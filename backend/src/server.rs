@@ -0,0 +1,13 @@
+use crate::recorder::RequestBody;
+use warp::filters::BoxedFilter;
+
+pub use fortune_common::server::{Listen, Tuning};
+
+// The shared listener, with the slot the flight recorder finds each request
+// body in
+pub async fn run(routes: BoxedFilter<(warp::reply::Response,)>, listen: Listen, tuning: Tuning) {
+    fortune_common::server::run_with(routes, listen, tuning, |req| {
+        req.extensions_mut().insert(RequestBody::default());
+    })
+    .await
+}
@@ -0,0 +1,90 @@
+use crate::redis_client;
+use fortune_common::error;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// Cookie a client can use instead of `?session=`
+pub const COOKIE: &str = "fortune_session";
+
+// Fallback for when Redis is not configured
+struct Seen {
+    ids: HashSet<String>,
+    expires_at: Instant,
+}
+
+static LOCAL: OnceLock<Mutex<HashMap<String, Seen>>> = OnceLock::new();
+
+fn local() -> &'static Mutex<HashMap<String, Seen>> {
+    LOCAL.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Accepts short tokens of letters, digits, `-` and `_`; anything else is ignored
+fn valid(token: &str) -> bool {
+    (1..=64).contains(&token.len())
+        && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// The session token from the `session` query value, else the cookie
+pub fn token(query: Option<String>, cookie: Option<String>) -> Option<String> {
+    query.or(cookie).filter(|token| valid(token))
+}
+
+fn key(token: &str) -> String {
+    format!("fortunes:session:{}", token)
+}
+
+// Ids already served to the session
+pub async fn seen(token: &str) -> HashSet<String> {
+    if let Some(redis) = redis_client::get_store().await {
+        let result: redis::RedisResult<HashSet<String>> = redis
+            .connection()
+            .and_then(|mut conn| redis::cmd("SMEMBERS").arg(key(token)).query(&mut conn));
+        match result {
+            Ok(ids) => return ids,
+            Err(e) => error!("redis session read failed: {}", e),
+        }
+    }
+    let local = local().lock().unwrap();
+    local
+        .get(token)
+        .filter(|seen| seen.expires_at > Instant::now())
+        .map(|seen| seen.ids.clone())
+        .unwrap_or_default()
+}
+
+// Records that `id` was served; the session expires `ttl` after its last use.
+// With `reset` the ids seen so far are forgotten first.
+pub async fn remember(token: &str, id: &str, reset: bool, ttl: Duration) {
+    if let Some(redis) = redis_client::get_store().await {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if reset {
+            pipe.cmd("DEL").arg(key(token)).ignore();
+        }
+        pipe.cmd("SADD").arg(key(token)).arg(id).ignore();
+        pipe.cmd("EXPIRE").arg(key(token)).arg(ttl.as_secs()).ignore();
+        let result = redis
+            .connection()
+            .and_then(|mut conn| pipe.query::<()>(&mut conn));
+        match result {
+            Ok(()) => return,
+            Err(e) => error!("redis session write failed: {}", e),
+        }
+    }
+
+    let now = Instant::now();
+    let mut local = local().lock().unwrap();
+    if !local.contains_key(token) {
+        local.retain(|_, seen| seen.expires_at > now);
+    }
+    let seen = local.entry(token.to_string()).or_insert_with(|| Seen {
+        ids: HashSet::new(),
+        expires_at: now,
+    });
+    if reset || seen.expires_at <= now {
+        seen.ids.clear();
+    }
+    seen.ids.insert(id.to_string());
+    seen.expires_at = now + ttl;
+}
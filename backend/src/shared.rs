@@ -0,0 +1,84 @@
+use arc_swap::ArcSwap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+
+// A value readers take snapshots of without ever waiting, behind FortuneStore.
+// Writers take turns on a copy, which replaces the snapshot when they are done,
+// so everything they change in one `write()` shows up at once and a reader
+// never sees half of it. For that to be cheap the value should share its
+// data between copies (Fortunes keeps its indexes in persistent collections).
+
+// State a value shares with its published copies, such as Fortunes' view
+// counts, cannot go through the draft. The draft holds back its changes to it,
+// and they are applied once the draft is published, so readers of the current
+// value never see them early.
+pub trait Publish {
+    type Deferred;
+
+    fn take_deferred(&mut self) -> Self::Deferred;
+    fn apply(published: &Self, deferred: Self::Deferred);
+}
+
+#[derive(Debug)]
+pub struct Shared<T> {
+    current: ArcSwap<T>,
+    writer: Mutex<()>,
+}
+
+impl<T: Clone + Publish> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Shared {
+            current: ArcSwap::from_pointee(value),
+            writer: Mutex::new(()),
+        }
+    }
+
+    // The latest published value. Async like the write side, but it never
+    // waits, not even for a writer.
+    pub async fn read(&self) -> Arc<T> {
+        self.current.load_full()
+    }
+
+    // Waits for earlier writers only
+    pub async fn write(&self) -> WriteGuard<'_, T> {
+        let turn = self.writer.lock().await;
+        WriteGuard {
+            draft: Some(T::clone(&self.current.load())),
+            shared: self,
+            _turn: turn,
+        }
+    }
+}
+
+pub struct WriteGuard<'a, T: Publish> {
+    draft: Option<T>,
+    shared: &'a Shared<T>,
+    _turn: MutexGuard<'a, ()>,
+}
+
+impl<T: Publish> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.draft.as_ref().expect("the draft is only taken on drop")
+    }
+}
+
+impl<T: Publish> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.draft.as_mut().expect("the draft is only taken on drop")
+    }
+}
+
+impl<T: Publish> Drop for WriteGuard<'_, T> {
+    // Publishes before the next writer's turn, which `_turn` still holds
+    fn drop(&mut self) {
+        if let Some(mut draft) = self.draft.take() {
+            let deferred = draft.take_deferred();
+            let published = Arc::new(draft);
+            self.shared.current.store(published.clone());
+            T::apply(&published, deferred);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::admin::key_matches;
+use crate::fortunes::now_secs;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use warp::http::{HeaderMap, Method};
+use warp::path::FullPath;
+use warp::reject::Reject;
+use warp::{Filter, Rejection};
+
+// Signed requests from the frontend. With REQUEST_SIGNING_SECRET set, a
+// request that can change data (any method but GET, HEAD and OPTIONS) needs
+// either the ADMIN_API_KEY in X-API-Key, for admin tooling, or these headers:
+//
+//   X-Signature-Timestamp: Unix seconds, within REQUEST_SIGNING_MAX_AGE_SECS of now
+//   X-Content-SHA256: hex SHA-256 of the body, of nothing for an empty one
+//   X-Signature: hex HMAC-SHA256 of the canonical request under the secret
+//
+// The canonical request is `<method>\n<path>?<query>\n<timestamp>\n<body hash>`,
+// without the `?` when there is no query. Bodies are read by
+// `limits::json_body`, which refuses one that does not match its hash.
+
+pub const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+pub const CONTENT_HEADER: &str = "x-content-sha256";
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+#[derive(Debug)]
+pub struct BadSignature;
+
+impl Reject for BadSignature {}
+
+pub fn content_hash(body: &[u8]) -> String {
+    hex::encode(Sha256::digest(body))
+}
+
+pub fn canonical(method: &str, path_and_query: &str, timestamp: u64, content_hash: &str) -> String {
+    format!("{}\n{}\n{}\n{}", method, path_and_query, timestamp, content_hash)
+}
+
+pub fn sign(secret: &[u8], canonical: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(canonical.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn verify(secret: &[u8], max_age: u64, method: &Method, path_and_query: &str, headers: &HeaderMap) -> bool {
+    let Some(timestamp) = header(headers, TIMESTAMP_HEADER).and_then(|t| t.parse::<u64>().ok()) else {
+        return false;
+    };
+    if now_secs().abs_diff(timestamp) > max_age {
+        return false;
+    }
+    let (Some(content_hash), Some(given)) = (header(headers, CONTENT_HEADER), header(headers, SIGNATURE_HEADER)) else {
+        return false;
+    };
+    let expected = sign(secret, &canonical(method.as_str(), path_and_query, timestamp, content_hash));
+    key_matches(&expected, &given.to_ascii_lowercase())
+}
+
+// Lets safe requests through, and the rest when they are signed or carry the
+// admin key; passes everything when `secret` is None
+pub fn required(
+    secret: Option<String>,
+    max_age: u64,
+    admin_key: Option<String>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::headers_cloned())
+        .and_then(move |method: Method, path: FullPath, query: String, headers: HeaderMap| {
+            let allowed = match &secret {
+                None => true,
+                Some(_) if is_safe(&method) => true,
+                Some(_) if admin_key
+                    .as_deref()
+                    .zip(header(&headers, "x-api-key"))
+                    .is_some_and(|(expected, given)| key_matches(expected, given)) => true,
+                Some(secret) => {
+                    let path_and_query = if query.is_empty() {
+                        path.as_str().to_string()
+                    } else {
+                        format!("{}?{}", path.as_str(), query)
+                    };
+                    verify(secret.as_bytes(), max_age, &method, &path_and_query, &headers)
+                }
+            };
+            async move {
+                if allowed {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(BadSignature))
+                }
+            }
+        })
+        .untuple_one()
+}
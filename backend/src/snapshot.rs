@@ -0,0 +1,74 @@
+use crate::{Fortune, FortuneStore};
+use fortune_common::{error, info};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+static DIRTY: OnceLock<Notify> = OnceLock::new();
+
+pub async fn load(path: &PathBuf, store: FortuneStore) {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("no snapshot at {}, starting fresh", path.display());
+            return;
+        }
+        Err(e) => {
+            error!("failed to read snapshot {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    match serde_json::from_str::<Vec<Fortune>>(&contents) {
+        Ok(fortunes) => {
+            info!("*** loading {} fortunes from {}", fortunes.len(), path.display());
+            let mut store_write = store.write().await;
+            for fortune in fortunes {
+                store_write.insert(fortune.id.clone(), fortune);
+            }
+        }
+        Err(e) => error!("failed to parse snapshot {}: {}", path.display(), e),
+    }
+}
+
+// Every fortune as a pretty JSON array sorted by id, with how many there are
+pub async fn to_json(store: &FortuneStore) -> serde_json::Result<(usize, Vec<u8>)> {
+    let mut fortunes: Vec<Fortune> = store.read().await.values().cloned().collect();
+    fortunes.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok((fortunes.len(), serde_json::to_vec_pretty(&fortunes)?))
+}
+
+async fn write(path: &PathBuf, store: &FortuneStore) -> std::io::Result<()> {
+    let (_, json) = to_json(store).await?;
+
+    // Write to a temp file and rename so a crash never leaves a truncated snapshot
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+// Starts the writer task. Mutations within the debounce window are coalesced
+// into a single write.
+pub fn init(path: PathBuf, store: FortuneStore, debounce: Duration) {
+    if DIRTY.set(Notify::new()).is_err() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let dirty = DIRTY.get().unwrap();
+        loop {
+            dirty.notified().await;
+            tokio::time::sleep(debounce).await;
+            if let Err(e) = write(&path, &store).await {
+                error!("failed to write snapshot {}: {}", path.display(), e);
+            }
+        }
+    });
+}
+
+pub fn mark_dirty() {
+    if let Some(dirty) = DIRTY.get() {
+        dirty.notify_one();
+    }
+}
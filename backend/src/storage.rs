@@ -0,0 +1,28 @@
+use crate::fortunes::TrashedFortune;
+use crate::pubsub::FortuneEvent;
+use crate::Fortune;
+use async_trait::async_trait;
+use std::fmt::Debug;
+
+pub type StorageResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Persistent backing store for fortunes. The in-memory map stays the source of
+// truth for reads; implementations only need to durably hold what it contains.
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+    async fn load_all(&self) -> StorageResult<Vec<Fortune>>;
+    async fn get(&self, id: &str) -> StorageResult<Option<Fortune>>;
+    async fn set(&self, fortune: &Fortune) -> StorageResult<()>;
+    async fn delete(&self, id: &str) -> StorageResult<()>;
+    async fn set_many(&self, fortunes: &[Fortune]) -> StorageResult<()>;
+    // Claims a free numeric id shared by every replica and stores `fortune` under it
+    async fn allocate_id(&self, fortune: &Fortune) -> StorageResult<String>;
+    async fn release_ids(&self, ids: &[String]) -> StorageResult<()>;
+    // The fortune's view count after this one
+    async fn add_view(&self, id: &str) -> StorageResult<u64>;
+    async fn set_deleted(&self, trashed: &TrashedFortune) -> StorageResult<()>;
+    async fn remove_deleted(&self, ids: &[String]) -> StorageResult<()>;
+
+    // Tells other replicas about a change; a store nobody else reads has no one to tell
+    async fn publish(&self, _event: FortuneEvent) {}
+}
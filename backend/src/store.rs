@@ -0,0 +1,977 @@
+use crate::fortunes::{message_len, normalize, now_secs, AuthorCount, Fortunes, TrashedFortune};
+use crate::rotation::Rotation;
+use crate::storage::Storage;
+use fortune_common::{debug, error, info};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::fmt;
+use std::time::Duration;
+use unicode_normalization::UnicodeNormalization;
+use crate::content_filter::{self, Verdict};
+use crate::{analytics, audit, db, events, language, leader, live, lru, negative_cache, pubsub, sessions, snapshot, views, webhooks, write_queue, Fortune, FortuneStore, Sort, Status};
+
+// Store operations shared by the HTTP and gRPC APIs. The in-memory map is the
+// source of truth for reads; Redis, the database and the snapshot file are kept
+// up to date on writes.
+
+// Only fortunes inside their publish window
+pub async fn list(store: &FortuneStore) -> Vec<Fortune> {
+    active_fortunes(store).await
+}
+
+// The fortunes in the hot set with their views. A capped store only holds
+// some of their bodies, so the others are read from storage in one pass,
+// without caching them.
+async fn active_fortunes(store: &FortuneStore) -> Vec<Fortune> {
+    let storage = {
+        let fortunes = store.read().await;
+        if !fortunes.has_evicted() {
+            return fortunes.active().map(|f| fortunes.with_views(f)).collect();
+        }
+        fortunes.storage()
+    };
+    let stored = match storage {
+        Some(storage) => storage.load_all().await.unwrap_or_else(|e| {
+            error!("Redis read of evicted fortunes failed: {}", e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+    let fortunes = store.read().await;
+    let mut evicted: HashMap<String, Fortune> = stored
+        .into_iter()
+        .filter(|f| fortunes.is_evicted(&f.id))
+        .map(|f| (f.id.clone(), f))
+        .collect();
+    fortunes
+        .active_ids()
+        .filter_map(|id| fortunes.get(id).cloned().or_else(|| evicted.remove(id)))
+        .map(|f| fortunes.with_views(&f))
+        .collect()
+}
+
+pub async fn popular(store: &FortuneStore, limit: usize) -> Vec<Fortune> {
+    let ids = store.read().await.popular(limit);
+    get_many(store, &ids).await
+}
+
+// Ids of the published fortunes, by `author` (as in `list_by_author`), created
+// at or after `since` and at most `max_len` characters long when given, in
+// `sort` order. Only ids are copied, so a large list costs little until it is read.
+pub async fn active_ids(
+    store: &FortuneStore,
+    author: Option<&str>,
+    since: Option<u64>,
+    max_len: Option<usize>,
+    sort: Sort,
+    descending: bool,
+) -> Vec<String> {
+    let author = author.map(normalize);
+    let fortunes = store.read().await;
+    let ids: Box<dyn Iterator<Item = &str>> = match max_len {
+        Some(max_len) => Box::new(fortunes.sorted_within(max_len, sort, descending).into_iter()),
+        None => Box::new(fortunes.sorted(sort, descending)),
+    };
+    ids
+        .filter_map(|id| fortunes.fields(id))
+        .filter(|f| author.as_ref().is_none_or(|author| f.author.as_deref().is_some_and(|a| normalize(a) == *author)))
+        .filter(|f| since.is_none_or(|since| f.created_at.is_some_and(|at| at >= since)))
+        .map(|f| f.id.clone())
+        .collect()
+}
+
+// The ids among `ids` in the hot set, in the order given and each once
+pub async fn active_among(store: &FortuneStore, ids: &[String]) -> Vec<String> {
+    let fortunes = store.read().await;
+    let mut listed = HashSet::new();
+    ids.iter()
+        .filter(|id| fortunes.is_active(id) && listed.insert(id.as_str()))
+        .cloned()
+        .collect()
+}
+
+// The fortunes with `ids` that are still stored, in order, without counting a
+// view. Evicted ones are read from storage without caching them.
+pub async fn get_many(store: &FortuneStore, ids: &[String]) -> Vec<Fortune> {
+    let fortunes = store.read().await;
+    let mut found = Vec::with_capacity(ids.len());
+    for id in ids {
+        let fortune = match fortunes.get(id) {
+            Some(fortune) => Some(fortune.clone()),
+            None if fortunes.is_evicted(id) => read_evicted(&fortunes, id).await,
+            None => None,
+        };
+        if let Some(fortune) = fortune {
+            found.push(fortunes.with_views(&fortune));
+        }
+    }
+    found
+}
+
+async fn read_evicted(fortunes: &Fortunes, id: &str) -> Option<Fortune> {
+    match fortunes.storage()?.get(id).await {
+        Ok(fortune) => fortune,
+        Err(e) => {
+            error!("Redis hget failed: {}", e);
+            None
+        }
+    }
+}
+
+// Published fortunes whose author matches, ignoring case and whitespace
+pub async fn list_by_author(store: &FortuneStore, author: &str) -> Vec<Fortune> {
+    let author = normalize(author);
+    let ids: Vec<String> = {
+        let fortunes = store.read().await;
+        fortunes
+            .active_ids()
+            .filter(|id| fortunes.fields(id).and_then(|f| f.author.as_deref()).is_some_and(|a| normalize(a) == author))
+            .cloned()
+            .collect()
+    };
+    get_many(store, &ids).await
+}
+
+// Published fortunes whose message or author contains `query`, ignoring case
+// and whitespace, by id
+pub async fn search(store: &FortuneStore, query: &str, limit: usize) -> Vec<Fortune> {
+    let query = normalize(query);
+    let mut found: Vec<Fortune> = active_fortunes(store)
+        .await
+        .into_iter()
+        .filter(|f| normalize(&f.message).contains(&query) || f.author.as_deref().is_some_and(|a| normalize(a).contains(&query)))
+        .collect();
+    found.sort_by(|a, b| a.id.cmp(&b.id));
+    found.truncate(limit);
+    found
+}
+
+pub async fn authors(store: &FortuneStore) -> Vec<AuthorCount> {
+    store.read().await.authors()
+}
+
+// Changes whenever the in-memory collection is mutated, and the second
+// value whenever a view count does
+pub async fn version(store: &FortuneStore) -> (u64, u64) {
+    let fortunes = store.read().await;
+    (fortunes.version(), fortunes.views_version())
+}
+
+// Serving a fortune counts as a view
+pub async fn get(store: &FortuneStore, id: &str) -> Option<Fortune> {
+    serve(store, id, false).await
+}
+
+// A fortune as `get` would serve it, with its views so far but without
+// counting one; HEAD requests and revalidations change nothing
+pub async fn peek(store: &FortuneStore, id: &str) -> Option<Fortune> {
+    serve(store, id, true).await
+}
+
+// Counts a view of `fortune`, fetched with `peek`
+pub async fn view(store: &FortuneStore, fortune: &mut Fortune) {
+    fortune.views = record_view(store, &fortune.id).await;
+}
+
+async fn serve(store: &FortuneStore, id: &str, peek: bool) -> Option<Fortune> {
+    let mut fortune = lookup(store, id).await?;
+    match peek {
+        true => fortune.views = store.read().await.views(id),
+        false => fortune.views = record_view(store, id).await,
+    }
+    Some(fortune)
+}
+
+// The storage behind `store`: Redis when connected, with the shared hash for
+// the default collection and its own for a named one
+async fn storage_for(store: &FortuneStore) -> Option<Arc<dyn Storage>> {
+    store.read().await.storage()
+}
+
+// Named collections stay out of the database, the snapshot file, replication
+// and the change feeds, which all carry the default collection only
+async fn is_default(store: &FortuneStore) -> bool {
+    store.read().await.collection().is_none()
+}
+
+async fn lookup(store: &FortuneStore, id: &str) -> Option<Fortune> {
+    // A capped store is a cache in front of Redis: replication keeps what it
+    // holds current, so only misses go to Redis
+    {
+        let fortunes = store.read().await;
+        if fortunes.is_capped() {
+            if let Some(fortune) = fortunes.get(id) {
+                fortunes.touch(id);
+                lru::record_hit();
+                return Some(fortune.clone());
+            }
+            lru::record_miss();
+        }
+    }
+
+    // Ids recently found nowhere are not looked up in Redis again
+    let collection = store.read().await.collection().map(str::to_string);
+    if negative_cache::is_missing(collection.as_deref(), id) {
+        return None;
+    }
+
+    // Try the backing storage first if there is one
+    if let Some(storage) = storage_for(store).await {
+        match storage.get(id).await {
+            Ok(Some(fortune)) => {
+                // Update local store
+                store.write().await.insert(fortune.id.clone(), fortune.clone());
+                return Some(fortune);
+            }
+            Ok(None) => {
+                let found = store.read().await.get(id).cloned();
+                if found.is_none() {
+                    negative_cache::remember(collection.as_deref(), id);
+                }
+                return found;
+            }
+            Err(_) => {}
+        }
+    }
+
+    store.read().await.get(id).cloned()
+}
+
+// Reads an evicted fortune back into memory, for writes that need its body
+async fn recache(store: &FortuneStore, id: &str) {
+    if store.read().await.is_evicted(id) {
+        lookup(store, id).await;
+    }
+}
+
+// The fortune a new one duplicates, for the 409; an evicted one is read back
+async fn existing(store: &FortuneStore, id: &str) -> Fortune {
+    if let Some(fortune) = store.read().await.get(id) {
+        return fortune.clone();
+    }
+    lookup(store, id).await.unwrap_or_else(|| Fortune {
+        id: id.to_string(),
+        ..Default::default()
+    })
+}
+
+// Counts in memory and queues the view for the shared Redis counter, so
+// replicas agree on totals; with VIEWS_FLUSH_MS at 0 every view goes to
+// Redis right away
+async fn record_view(store: &FortuneStore, id: &str) -> u64 {
+    let fortunes = store.read().await;
+    if views::is_batched(fortunes.collection()) {
+        let views = fortunes.add_view(id);
+        if views > 0 {
+            views::queue(fortunes.collection(), id);
+        }
+        return views;
+    }
+    drop(fortunes);
+    if let Some(storage) = storage_for(store).await {
+        match storage.add_view(id).await {
+            Ok(views) => {
+                store.read().await.set_views(id, views);
+                return views;
+            }
+            Err(e) => error!("Redis hincrby failed: {}", e),
+        }
+    }
+    store.read().await.add_view(id)
+}
+
+// Prefers the languages in `langs`, in order; see `language::preferences`.
+// With `max_len` only fortunes at most that many characters long are drawn,
+// and with `peek` the draw counts no view.
+pub async fn random(store: &FortuneStore, langs: &[String], max_len: Option<usize>, peek: bool) -> Option<Fortune> {
+    // Pick the id before the await; ThreadRng is not Send
+    let id = {
+        let fortunes = store.read().await;
+        fortunes.random_in(langs, max_len, &mut rand::thread_rng()).cloned()
+    };
+
+    match id {
+        Some(id) => serve(store, &id, peek).await,
+        None => serve(store, "zero", peek).await,
+    }
+}
+
+// Up to `count` different fortunes, preferring `langs` like `random`. With
+// a session token they are drawn like `random_for_session`, all remembered
+// as served unless this is a `peek`.
+pub async fn random_many(
+    store: &FortuneStore,
+    langs: &[String],
+    max_len: Option<usize>,
+    count: usize,
+    session: Option<(&str, Duration)>,
+    peek: bool,
+) -> Vec<Fortune> {
+    let seen = match session {
+        Some((token, _)) => sessions::seen(token).await,
+        None => HashSet::new(),
+    };
+    let (ids, exhausted) = {
+        let fortunes = store.read().await;
+        let (picked, exhausted) = fortunes.random_distinct(langs, max_len, count, &seen, &mut rand::thread_rng());
+        (picked.into_iter().cloned().collect::<Vec<_>>(), exhausted)
+    };
+    if let Some((token, ttl)) = session.filter(|_| !peek) {
+        // Starting over forgets what came before, so only this batch counts as seen
+        for (i, id) in ids.iter().enumerate() {
+            sessions::remember(token, id, exhausted && i == 0, ttl).await;
+        }
+    }
+    let mut fortunes = Vec::with_capacity(ids.len());
+    for id in ids {
+        if let Some(fortune) = serve(store, &id, peek).await {
+            fortunes.push(fortune);
+        }
+    }
+    fortunes
+}
+
+// Approves or rejects a fortune; only approved fortunes are listed
+pub async fn moderate(store: &FortuneStore, id: &str, status: Status, actor: &str) -> Option<Fortune> {
+    recache(store, id).await;
+    let mut fortune = store.read().await.get(id).cloned()?;
+    fortune.status = status;
+    fortune.updated_at = Some(now_secs());
+    persist(store, &fortune).await;
+    let op = match status {
+        Status::Rejected => "reject",
+        _ => "approve",
+    };
+    audit::record(actor, op, id, Some(&fortune.message), Some(&fortune.message)).await;
+    Some(fortune)
+}
+
+// Publishes an unverified fortune once its submitter followed the link, or
+// hands it to the moderators when `moderation` is on or the content filter
+// flags it
+pub async fn verify(store: &FortuneStore, id: &str, moderation: bool, actor: &str) -> Option<Fortune> {
+    recache(store, id).await;
+    let mut fortune = store.read().await.get(id).cloned().filter(|f| f.status == Status::Unverified)?;
+    let flagged = matches!(content_filter::check(&fortune.message), Verdict::Flagged(_));
+    fortune.status = if moderation || flagged { Status::Pending } else { Status::Approved };
+    fortune.updated_at = Some(now_secs());
+    persist(store, &fortune).await;
+    audit::record(actor, "verify", id, Some(&fortune.message), Some(&fortune.message)).await;
+    Some(fortune)
+}
+
+// A random fortune the session has not been served yet, starting over once
+// it has seen them all; a `peek` leaves the session as it was
+pub async fn random_for_session(
+    store: &FortuneStore,
+    langs: &[String],
+    max_len: Option<usize>,
+    token: &str,
+    ttl: Duration,
+    peek: bool,
+) -> Option<Fortune> {
+    let seen = sessions::seen(token).await;
+    let pick = {
+        let fortunes = store.read().await;
+        fortunes
+            .random_unseen(langs, max_len, &seen, &mut rand::thread_rng())
+            .map(|(id, exhausted)| (id.clone(), exhausted))
+    };
+    let (id, exhausted) = pick?;
+    if !peek {
+        sessions::remember(token, &id, exhausted, ttl).await;
+    }
+    serve(store, &id, peek).await
+}
+
+// The fortune served at `step` of a weighted tag rotation
+pub async fn rotation_step(store: &FortuneStore, rotation: &Rotation, step: u64, peek: bool) -> Option<Fortune> {
+    let id = {
+        let fortunes = store.read().await;
+        rotation.pick(&fortunes, step).cloned()?
+    };
+    serve(store, &id, peek).await
+}
+
+// The same published fortune all day: `day` (days since the epoch) picks
+// one from the fortunes ordered by id
+pub async fn fortune_of_the_day(store: &FortuneStore, day: u64) -> Option<Fortune> {
+    let id = {
+        let fortunes = store.read().await;
+        let mut active: Vec<&String> = fortunes.active_ids().collect();
+        if active.is_empty() {
+            return None;
+        }
+        active.sort();
+        let mut hasher = DefaultHasher::new();
+        day.hash(&mut hasher);
+        active[(hasher.finish() % active.len() as u64) as usize].clone()
+    };
+    get_many(store, &[id]).await.pop()
+}
+
+// Path segments under /fortunes that can never be used as fortune ids
+pub const RESERVED_IDS: &[&str] = &["authors", "batch", "events", "popular", "random", "rotation", "trash", "ws"];
+
+// Longest accepted message, counted in grapheme clusters (what a reader sees
+// as one character) so emoji and combining marks count once
+pub const MAX_MESSAGE_GRAPHEMES: usize = 500;
+
+// Tags per fortune, and characters per tag
+pub const MAX_TAGS: usize = 10;
+pub const MAX_TAG_LEN: usize = 32;
+
+#[derive(Debug)]
+pub enum CreateError {
+    // The id collides with a fixed route under /fortunes
+    ReservedId,
+    // `lang` is not a language tag
+    InvalidLang,
+    // The message is longer than MAX_MESSAGE_GRAPHEMES
+    TooLong,
+    // `expires_at` is not after `publish_at`
+    InvalidSchedule,
+    // A tag is not made of a-z, 0-9, - and _, or there are too many
+    InvalidTags,
+    // Another fortune already has the same normalized message
+    Duplicate(Fortune),
+    // The content filter refused the message, with the reason
+    Blocked(String),
+    // No id was given and Redis could not hand one out
+    IdUnavailable,
+}
+
+impl fmt::Display for CreateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CreateError::ReservedId => write!(f, "fortune id is reserved"),
+            CreateError::InvalidLang => write!(f, "invalid language tag"),
+            CreateError::TooLong => write!(f, "message is longer than {} characters", MAX_MESSAGE_GRAPHEMES),
+            CreateError::InvalidSchedule => write!(f, "expires_at must be after publish_at"),
+            CreateError::InvalidTags => write!(
+                f,
+                "use up to {} tags of up to {} characters from a-z, 0-9, - and _",
+                MAX_TAGS, MAX_TAG_LEN
+            ),
+            CreateError::Duplicate(existing) => write!(f, "message duplicates fortune {}", existing.id),
+            CreateError::Blocked(reason) => write!(f, "{}", reason),
+            CreateError::IdUnavailable => write!(f, "could not allocate a fortune id"),
+        }
+    }
+}
+
+// Rejects reserved ids, and fortunes whose message duplicates an existing one
+// unless `force` is set. `actor` is recorded in the audit log.
+pub async fn create(store: &FortuneStore, fortune: Fortune, force: bool, actor: &str) -> Result<Fortune, CreateError> {
+    let fortune = validate(store, fortune, force).await?;
+    let allocated = fortune.id.is_empty();
+    let fortune = allocate_id(store, fortune).await?;
+    let previous = match persist_unique(store, &fortune, force).await {
+        Ok(previous) => previous,
+        Err(e) => {
+            if allocated {
+                release_ids(store, &[fortune.id]).await;
+            }
+            return Err(e);
+        }
+    };
+    record_create(actor, &fortune, previous.as_ref()).await;
+    Ok(fortune)
+}
+
+// Creates every fortune that passes validation, writing them to Redis in a
+// single pipeline. Results are in the order of `fortunes`; a message repeated
+// within the batch counts as a duplicate of its first occurrence.
+pub async fn create_batch(store: &FortuneStore, fortunes: Vec<Fortune>, force: bool, actor: &str) -> Vec<Result<Fortune, CreateError>> {
+    let mut results = Vec::with_capacity(fortunes.len());
+    let mut seen: HashMap<String, Fortune> = HashMap::new();
+    let mut allocated = HashSet::new();
+    for fortune in fortunes {
+        let result = match validate(store, fortune, force).await {
+            Ok(fortune) if !force => match seen.get(&normalize(&fortune.message)) {
+                Some(first) => Err(CreateError::Duplicate(first.clone())),
+                None => {
+                    seen.insert(normalize(&fortune.message), fortune.clone());
+                    Ok(fortune)
+                }
+            },
+            result => result,
+        };
+        let result = match result {
+            Ok(fortune) if fortune.id.is_empty() => {
+                let result = allocate_id(store, fortune).await;
+                if let Ok(fortune) = &result {
+                    allocated.insert(fortune.id.clone());
+                }
+                result
+            }
+            result => result,
+        };
+        results.push(result);
+    }
+
+    let created: Vec<Fortune> = results.iter().filter_map(|r| r.as_ref().ok()).cloned().collect();
+    let mut stored = persist_many(store, &created, force).await.into_iter();
+    let mut refused = Vec::new();
+    for result in results.iter_mut().filter(|r| r.is_ok()) {
+        let outcome = stored.next().expect("one outcome per created fortune");
+        let Ok(fortune) = result else {
+            continue;
+        };
+        match outcome {
+            Ok(previous) => record_create(actor, fortune, previous.as_ref()).await,
+            Err(e) => {
+                if allocated.contains(&fortune.id) {
+                    refused.push(fortune.id.clone());
+                }
+                *result = Err(e);
+            }
+        }
+    }
+    release_ids(store, &refused).await;
+    results
+}
+
+async fn record_create(actor: &str, fortune: &Fortune, previous: Option<&Fortune>) {
+    match previous {
+        Some(previous) => audit::record(actor, "update", &fortune.id, Some(&previous.message), Some(&fortune.message)).await,
+        None => {
+            analytics::record_submission();
+            audit::record(actor, "create", &fortune.id, None, Some(&fortune.message)).await
+        }
+    }
+}
+
+// Lowercased, sorted and without repeats; None if a tag has characters other
+// than a-z, 0-9, - and _, or there are more than MAX_TAGS
+pub fn normalize_tags(tags: &[String]) -> Option<Vec<String>> {
+    let mut normalized: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    let valid = normalized.len() <= MAX_TAGS
+        && normalized.iter().all(|tag| {
+            tag.len() <= MAX_TAG_LEN
+                && tag.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+        });
+    valid.then_some(normalized)
+}
+
+// Normalizes a new fortune and checks it can be stored, without storing it
+async fn validate(store: &FortuneStore, mut fortune: Fortune, force: bool) -> Result<Fortune, CreateError> {
+    if RESERVED_IDS.contains(&fortune.id.as_str()) {
+        return Err(CreateError::ReservedId);
+    }
+    fortune.lang = language::normalize(&fortune.lang).ok_or(CreateError::InvalidLang)?;
+    // NFC, so the same text typed with precomposed or combining characters is
+    // stored, compared and counted alike
+    fortune.message = fortune.message.nfc().collect();
+    if message_len(&fortune.message) > MAX_MESSAGE_GRAPHEMES {
+        return Err(CreateError::TooLong);
+    }
+    if let (Some(publish_at), Some(expires_at)) = (fortune.publish_at, fortune.expires_at) {
+        if expires_at <= publish_at {
+            return Err(CreateError::InvalidSchedule);
+        }
+    }
+    fortune.group = fortune.group.map(|g| g.trim().to_string()).filter(|g| !g.is_empty());
+    fortune.author = fortune.author.map(|a| a.trim().nfc().collect::<String>()).filter(|a| !a.is_empty());
+    fortune.tags = normalize_tags(&fortune.tags).ok_or(CreateError::InvalidTags)?;
+    fortune.views = 0;
+    // Timestamps are the server's; replacing a fortune keeps its creation time
+    let now = now_secs();
+    let capped = store.read().await.is_capped();
+    let previous = match capped {
+        // The fortune being replaced may have been evicted
+        true => lookup(store, &fortune.id).await,
+        false => store.read().await.get(&fortune.id).cloned(),
+    };
+    fortune.created_at = previous.and_then(|f| f.created_at).or(Some(now));
+    fortune.updated_at = Some(now);
+    match content_filter::check(&fortune.message) {
+        Verdict::Clean => {}
+        Verdict::Rejected(reason) => return Err(CreateError::Blocked(reason)),
+        Verdict::Flagged(reason) => {
+            info!("fortune {} held for moderation: {}", fortune.id, reason);
+            // An unverified fortune is checked again once verified
+            if fortune.status != Status::Unverified {
+                fortune.status = Status::Pending;
+            }
+        }
+    }
+    // Checked again when the fortune is stored; this early check only saves
+    // handing an id to a fortune that would be refused
+    if !force {
+        let duplicate = store.read().await.find_duplicate(&fortune.id, &fortune.message).cloned();
+        if let Some(duplicate) = duplicate {
+            return Err(CreateError::Duplicate(existing(store, &duplicate).await));
+        }
+    }
+    Ok(fortune)
+}
+
+// Gives a fortune submitted without an id the next numeric one. With Redis the
+// id comes from a shared counter and is claimed atomically, so replicas never
+// hand out the same id; without it each collection keeps its own counter.
+async fn allocate_id(store: &FortuneStore, mut fortune: Fortune) -> Result<Fortune, CreateError> {
+    if !fortune.id.is_empty() {
+        return Ok(fortune);
+    }
+    if let Some(storage) = storage_for(store).await {
+        fortune.id = storage.allocate_id(&fortune).await.map_err(|e| {
+            error!("Redis id allocation failed: {}", e);
+            CreateError::IdUnavailable
+        })?;
+        return Ok(fortune);
+    }
+    fortune.id = store.write().await.allocate_id();
+    Ok(fortune)
+}
+
+// Hands back ids `allocate_id` took from Redis for fortunes that were then
+// refused. Without Redis there is nothing to give back.
+async fn release_ids(store: &FortuneStore, ids: &[String]) {
+    if ids.is_empty() {
+        return;
+    }
+    if let Some(storage) = storage_for(store).await {
+        if let Err(e) = storage.release_ids(ids).await {
+            error!("Redis id release failed: {}", e);
+        }
+    }
+}
+
+// Writes a new or restored fortune to every configured backend and announces
+// it. Returns the fortune it replaced, if any.
+async fn persist(store: &FortuneStore, fortune: &Fortune) -> Option<Fortune> {
+    let previous = store.write().await.insert(fortune.id.clone(), fortune.clone());
+    write_through(store, fortune, previous.as_ref()).await;
+    previous
+}
+
+// `persist` for a new fortune, refused unless `force` when another fortune
+// has the same message. The check and the insert share one write guard, so
+// two creates of the same message cannot both get in.
+async fn persist_unique(store: &FortuneStore, fortune: &Fortune, force: bool) -> Result<Option<Fortune>, CreateError> {
+    let previous = {
+        let mut fortunes = store.write().await;
+        if let Some(duplicate) = fortunes.find_duplicate(&fortune.id, &fortune.message).filter(|_| !force).cloned() {
+            drop(fortunes);
+            return Err(CreateError::Duplicate(existing(store, &duplicate).await));
+        }
+        fortunes.insert(fortune.id.clone(), fortune.clone())
+    };
+    write_through(store, fortune, previous.as_ref()).await;
+    Ok(previous)
+}
+
+// Everything `persist` does once the in-memory store holds `fortune`
+async fn write_through(store: &FortuneStore, fortune: &Fortune, previous: Option<&Fortune>) {
+    if !is_default(store).await {
+        if let Some(storage) = storage_for(store).await {
+            if let Err(e) = storage.set(fortune).await {
+                error!("Redis hset failed: {}", e);
+            }
+        }
+        return;
+    }
+
+    // Save to the backing storage if there is one
+    if let Some(storage) = storage_for(store).await {
+        if let Err(e) = storage.set(fortune).await {
+            error!("Redis hset failed: {}", e);
+            write_queue::enqueue(fortune);
+        }
+        storage.publish(pubsub::FortuneEvent::Create { fortune: fortune.clone() }).await;
+    }
+
+    // Save to the database if configured
+    if let Some(pool) = db::get_pool().await {
+        if let Err(e) = db::set_fortune(&pool, fortune).await {
+            error!("Database insert failed: {}", e);
+        }
+    }
+
+    snapshot::mark_dirty();
+    if let Some(kind) = announcement(fortune, previous) {
+        announce(kind, fortune);
+        events::append(&[(kind, fortune.id.as_str(), announced(kind, fortune))]).await;
+    }
+}
+
+// What the WebSocket, webhooks and event log hear about storing `fortune`
+// over `previous`, if anything. Only approved fortunes are announced, so
+// submissions waiting for moderation or verification stay private until they
+// are approved, which announces them as created; one taken out of view again
+// is announced as deleted.
+pub(crate) fn announcement(fortune: &Fortune, previous: Option<&Fortune>) -> Option<webhooks::Kind> {
+    let was_public = previous.is_some_and(|previous| previous.status == Status::Approved);
+    match (fortune.status == Status::Approved, was_public) {
+        (true, true) => Some(webhooks::Kind::Updated),
+        (true, false) => Some(webhooks::Kind::Created),
+        (false, true) => Some(webhooks::Kind::Deleted),
+        (false, false) => None,
+    }
+}
+
+// A deleted fortune is announced by its id alone
+fn announced(kind: webhooks::Kind, fortune: &Fortune) -> Option<&Fortune> {
+    (kind != webhooks::Kind::Deleted).then_some(fortune)
+}
+
+pub(crate) fn live_event(kind: webhooks::Kind, fortune: &Fortune) -> pubsub::FortuneEvent {
+    match kind {
+        webhooks::Kind::Deleted => pubsub::FortuneEvent::Delete { id: fortune.id.clone() },
+        _ => pubsub::FortuneEvent::Create { fortune: fortune.clone() },
+    }
+}
+
+fn announce(kind: webhooks::Kind, fortune: &Fortune) {
+    live::notify(live_event(kind, fortune));
+    webhooks::notify(kind, &fortune.id, announced(kind, fortune));
+}
+
+// `persist` for many fortunes at once, with one Redis round trip and one
+// store lock. Unless `force` is set, a fortune whose message another one
+// already has is refused, as by `persist_unique`. Returns, in order, the
+// fortunes they replaced or why they were refused.
+async fn persist_many(store: &FortuneStore, fortunes: &[Fortune], force: bool) -> Vec<Result<Option<Fortune>, CreateError>> {
+    if fortunes.is_empty() {
+        return Vec::new();
+    }
+
+    // Refusals carry the duplicated id until the lock is released
+    let mut inserted = Vec::with_capacity(fortunes.len());
+    {
+        let mut store_write = store.write().await;
+        for fortune in fortunes {
+            inserted.push(match store_write.find_duplicate(&fortune.id, &fortune.message).filter(|_| !force) {
+                Some(duplicate) => Err(duplicate.clone()),
+                None => Ok(store_write.insert(fortune.id.clone(), fortune.clone())),
+            });
+        }
+    }
+    let mut outcomes = Vec::with_capacity(fortunes.len());
+    for outcome in inserted {
+        outcomes.push(match outcome {
+            Ok(previous) => Ok(previous),
+            Err(duplicate) => Err(CreateError::Duplicate(existing(store, &duplicate).await)),
+        });
+    }
+    let stored: Vec<(&Fortune, Option<&Fortune>)> = fortunes
+        .iter()
+        .zip(&outcomes)
+        .filter_map(|(fortune, outcome)| outcome.as_ref().ok().map(|previous| (fortune, previous.as_ref())))
+        .collect();
+    let written: Vec<Fortune> = stored.iter().map(|(fortune, _)| (*fortune).clone()).collect();
+
+    if !is_default(store).await {
+        if let Some(storage) = storage_for(store).await {
+            if let Err(e) = storage.set_many(&written).await {
+                error!("Redis batch hset failed: {}", e);
+            }
+        }
+        return outcomes;
+    }
+
+    if let Some(storage) = storage_for(store).await {
+        if let Err(e) = storage.set_many(&written).await {
+            error!("Redis batch hset failed: {}", e);
+            written.iter().for_each(write_queue::enqueue);
+        }
+        for fortune in &written {
+            storage.publish(pubsub::FortuneEvent::Create { fortune: fortune.clone() }).await;
+        }
+    }
+
+    if let Some(pool) = db::get_pool().await {
+        for fortune in &written {
+            if let Err(e) = db::set_fortune(&pool, fortune).await {
+                error!("Database insert failed: {}", e);
+            }
+        }
+    }
+
+    snapshot::mark_dirty();
+    let mutations: Vec<_> = stored
+        .iter()
+        .filter_map(|&(fortune, previous)| {
+            let kind = announcement(fortune, previous)?;
+            announce(kind, fortune);
+            Some((kind, fortune.id.as_str(), announced(kind, fortune)))
+        })
+        .collect();
+    events::append(&mutations).await;
+    outcomes
+}
+
+// Makes the store hold exactly `fortunes`, as when loading a backup. They are
+// written through without validation, and fortunes missing from them are
+// deleted. Returns how many were deleted.
+pub async fn replace_all(store: &FortuneStore, fortunes: Vec<Fortune>, actor: &str) -> usize {
+    let keep: HashSet<&str> = fortunes.iter().map(|f| f.id.as_str()).collect();
+    let stale: Vec<String> = store
+        .read()
+        .await
+        .values()
+        .filter(|f| !keep.contains(f.id.as_str()))
+        .map(|f| f.id.clone())
+        .collect();
+    for id in &stale {
+        delete(store, id, actor).await;
+    }
+
+    // Forced, so every outcome is Ok
+    let outcomes = persist_many(store, &fortunes, true).await;
+    for (fortune, outcome) in fortunes.iter().zip(outcomes) {
+        let Ok(previous) = outcome else {
+            continue;
+        };
+        if previous.as_ref() != Some(fortune) {
+            record_create(actor, fortune, previous.as_ref()).await;
+        }
+    }
+    stale.len()
+}
+
+// Returns the removed fortune, or None if the id was unknown
+pub async fn delete(store: &FortuneStore, id: &str, actor: &str) -> Option<Fortune> {
+    recache(store, id).await;
+    let removed = store.write().await.remove(id)?;
+    unpersist(store, id, removed.status == Status::Approved).await;
+    audit::record(actor, "delete", id, Some(&removed.message), None).await;
+    Some(removed)
+}
+
+// Removes a fortune from every configured backend after it left the in-memory
+// store, announcing the delete if it was `public` (approved)
+async fn unpersist(store: &FortuneStore, id: &str, public: bool) {
+    if !is_default(store).await {
+        if let Some(storage) = storage_for(store).await {
+            if let Err(e) = storage.delete(id).await {
+                error!("Redis hdel failed: {}", e);
+            }
+        }
+        return;
+    }
+
+    if let Some(storage) = storage_for(store).await {
+        if let Err(e) = storage.delete(id).await {
+            error!("Redis hdel failed: {}", e);
+        }
+        storage.publish(pubsub::FortuneEvent::Delete { id: id.to_string() }).await;
+    }
+
+    if let Some(pool) = db::get_pool().await {
+        if let Err(e) = db::delete_fortune(&pool, id).await {
+            error!("Database delete failed: {}", e);
+        }
+    }
+
+    snapshot::mark_dirty();
+    if public {
+        live::notify(pubsub::FortuneEvent::Delete { id: id.to_string() });
+        webhooks::notify(webhooks::Kind::Deleted, id, None);
+        events::append(&[(webhooks::Kind::Deleted, id, None)]).await;
+    }
+}
+
+// Like `delete`, but keeps the fortune in the trash (and the Redis
+// `fortunes:deleted` hash) so it can be restored
+pub async fn soft_delete(store: &FortuneStore, id: &str, actor: &str) -> Option<TrashedFortune> {
+    recache(store, id).await;
+    let trashed = store.write().await.trash(id, now_secs())?;
+    unpersist(store, id, trashed.fortune.status == Status::Approved).await;
+    audit::record(actor, "delete", id, Some(&trashed.fortune.message), None).await;
+
+    if let Some(storage) = storage_for(store).await {
+        if let Err(e) = storage.set_deleted(&trashed).await {
+            error!("Redis trash write failed: {}", e);
+        }
+    }
+    Some(trashed)
+}
+
+pub async fn trash(store: &FortuneStore) -> Vec<TrashedFortune> {
+    let mut trashed: Vec<TrashedFortune> = store.read().await.trashed().cloned().collect();
+    trashed.sort_by_key(|t| std::cmp::Reverse(t.deleted_at));
+    trashed
+}
+
+#[derive(Debug)]
+pub enum RestoreError {
+    NotInTrash,
+    // A live fortune took the id after this one was deleted
+    IdInUse,
+}
+
+pub async fn restore(store: &FortuneStore, id: &str, actor: &str) -> Result<Fortune, RestoreError> {
+    let trashed = {
+        let mut store = store.write().await;
+        if store.contains_key(id) {
+            return Err(RestoreError::IdInUse);
+        }
+        store.take_trashed(id).ok_or(RestoreError::NotInTrash)?
+    };
+
+    if let Some(storage) = storage_for(store).await {
+        if let Err(e) = storage.remove_deleted(std::slice::from_ref(&trashed.fortune.id)).await {
+            error!("Redis trash delete failed: {}", e);
+        }
+    }
+
+    let fortune = trashed.fortune;
+    persist(store, &fortune).await;
+    audit::record(actor, "restore", &fortune.id, None, Some(&fortune.message)).await;
+    Ok(fortune)
+}
+
+async fn purge_trash(store: &FortuneStore, max_age: Duration) {
+    let cutoff = now_secs().saturating_sub(max_age.as_secs());
+    let purged = store.write().await.purge_trash(cutoff);
+    if purged.is_empty() {
+        return;
+    }
+    // Every replica drops its own copy; one removes them from Redis for all
+    let storage = match leader::is_leader().await {
+        true => storage_for(store).await,
+        false => None,
+    };
+    if let Some(storage) = storage {
+        if let Err(e) = storage.remove_deleted(&purged).await {
+            error!("Redis trash purge failed: {}", e);
+        }
+    }
+    info!("purged {} fortunes from the trash", purged.len());
+}
+
+// Permanently drops trashed fortunes once they are older than `max_age`
+pub fn spawn_trash_purge(store: FortuneStore, max_age: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(max_age.min(Duration::from_secs(60)));
+        loop {
+            interval.tick().await;
+            purge_trash(&store, max_age).await;
+        }
+    });
+}
+
+// Publishes scheduled fortunes whose window has opened and prunes expired
+// ones from the hot set used by the list and random routes
+pub fn spawn_schedule_refresh(store: FortuneStore, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let moved = store.write().await.refresh_schedule(now_secs());
+            if moved > 0 {
+                debug!("schedule refresh: {} fortunes published or expired", moved);
+            }
+        }
+    });
+}
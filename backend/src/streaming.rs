@@ -0,0 +1,85 @@
+use crate::{store, FortuneStore};
+use futures_util::stream;
+use serde::Deserialize;
+use std::convert::Infallible;
+use utoipa::ToSchema;
+use warp::http::header::{HeaderValue, CONTENT_TYPE};
+use warp::hyper::Body;
+
+// Fortunes looked up and serialized per chunk of a streamed list
+const CHUNK: usize = 256;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    // One JSON array
+    #[default]
+    Json,
+    // One JSON object per line
+    Ndjson,
+}
+
+impl ListFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ListFormat::Json => "application/json",
+            ListFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+struct Chunks {
+    store: FortuneStore,
+    ids: std::vec::IntoIter<String>,
+    format: ListFormat,
+    // Whether the opening `[` went out, and how many fortunes followed it
+    opened: bool,
+    written: usize,
+    closed: bool,
+}
+
+async fn next_chunk(mut chunks: Chunks) -> Option<(Result<Vec<u8>, Infallible>, Chunks)> {
+    if chunks.closed {
+        return None;
+    }
+    let ids: Vec<String> = chunks.ids.by_ref().take(CHUNK).collect();
+    let mut out = Vec::new();
+    if chunks.format == ListFormat::Json && !chunks.opened {
+        out.push(b'[');
+    }
+    chunks.opened = true;
+    for fortune in store::get_many(&chunks.store, &ids).await {
+        if chunks.format == ListFormat::Json && chunks.written > 0 {
+            out.push(b',');
+        }
+        serde_json::to_writer(&mut out, &fortune).expect("fortunes serialize");
+        if chunks.format == ListFormat::Ndjson {
+            out.push(b'\n');
+        }
+        chunks.written += 1;
+    }
+    if ids.is_empty() {
+        if chunks.format == ListFormat::Json {
+            out.push(b']');
+        }
+        chunks.closed = true;
+    }
+    Some((Ok(out), chunks))
+}
+
+// Writes the fortunes with `ids` a chunk at a time, each looked up under its
+// own short read lock, so the response holds one chunk in memory rather than
+// the whole list. Fortunes deleted while it is written are skipped.
+pub fn fortunes(store: FortuneStore, ids: Vec<String>, format: ListFormat) -> warp::reply::Response {
+    let chunks = Chunks {
+        store,
+        ids: ids.into_iter(),
+        format,
+        opened: false,
+        written: 0,
+        closed: false,
+    };
+    let mut res = warp::reply::Response::new(Body::wrap_stream(stream::unfold(chunks, next_chunk)));
+    res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    res
+}
@@ -1,5 +0,0 @@
-use std::env;
-
-pub fn get_env(key: &str, fallback: &str) -> String {
-    env::var(key).unwrap_or_else(|_| fallback.to_string())
-}
@@ -0,0 +1,421 @@
+use crate::admin::{self, AdminAccess};
+use crate::collections::{self, Collections};
+use crate::config::Config;
+use crate::fortunes::now_secs;
+use crate::{leader, redis_client, store, Fortune, FortuneStore, Status};
+use fortune_common::{error, info};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+// Verified submissions: with SUBMISSION_VERIFICATION on, a fortune posted
+// without the admin key is stored as `unverified`, hidden like a pending
+// one, until its one-time token is confirmed with POST /verify/{token}. GET
+// only shows a page with the confirm button, so mail scanners that open the
+// link publish nothing. The token is mailed to the address in
+// X-Submitter-Email through VERIFICATION_MAIL_URL when that is set, and
+// handed back in the response otherwise. Fortunes not verified within
+// VERIFICATION_TTL_SECS are deleted.
+
+// Request header with the address the verification link is mailed to
+pub const EMAIL_HEADER: &str = "x-submitter-email";
+
+// Sends the verification link through an HTTP mail relay
+#[derive(Debug)]
+struct Mailer {
+    http: reqwest::Client,
+    url: String,
+    // Public base URL the link starts with, e.g. https://fortunes.example.com
+    link_base: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Verification {
+    ttl: Duration,
+    // Verified fortunes still wait for a moderator
+    moderation: bool,
+    mailer: Option<Arc<Mailer>>,
+}
+
+impl Verification {
+    // None while SUBMISSION_VERIFICATION is off
+    pub fn from_config(config: &Config) -> Option<Verification> {
+        if !config.submission_verification {
+            return None;
+        }
+        let mailer = config.verification_mail_url.clone().map(|url| {
+            let http = reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("failed to build HTTP client");
+            Arc::new(Mailer {
+                http,
+                url,
+                link_base: config.verification_link_base.clone().unwrap_or_default().trim_end_matches('/').to_string(),
+            })
+        });
+        Some(Verification {
+            ttl: Duration::from_secs(config.verification_ttl_secs),
+            moderation: config.moderation,
+            mailer,
+        })
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn moderation(&self) -> bool {
+        self.moderation
+    }
+}
+
+// A submission that has to be verified, with the address given for it
+#[derive(Debug, Clone)]
+pub struct Submitter {
+    email: Option<String>,
+    verification: Verification,
+}
+
+// What a new fortune waits for before it is served
+#[derive(Debug, Clone)]
+pub enum Review {
+    None,
+    Moderation,
+    Verification(Submitter),
+}
+
+impl Review {
+    pub fn new(needs_review: bool, submitter: Option<Submitter>) -> Review {
+        match (submitter, needs_review) {
+            (Some(submitter), _) => Review::Verification(submitter),
+            (None, true) => Review::Moderation,
+            (None, false) => Review::None,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            Review::None => Status::Approved,
+            Review::Moderation => Status::Pending,
+            Review::Verification(_) => Status::Unverified,
+        }
+    }
+}
+
+// The submitter of a fortune that has to be verified; None while
+// verification is off or for requests from an admin
+pub fn submitter(
+    verification: Option<Verification>,
+    access: AdminAccess,
+) -> impl Filter<Extract = (Option<Submitter>,), Error = Rejection> + Clone {
+    admin::needs_review(verification.is_some(), access)
+        .and(warp::header::optional::<String>(EMAIL_HEADER))
+        .map(move |required: bool, email: Option<String>| {
+            let verification = verification.clone().filter(|_| required)?;
+            Some(Submitter { email, verification })
+        })
+}
+
+fn valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    email.len() <= 254
+        && !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.contains(|c: char| c.is_whitespace() || c.is_control() || c == ',' || c == ';')
+        && !domain.contains('@')
+}
+
+// Refuses a submission before it is stored: an address is required when
+// links are mailed, and has to look like one
+pub fn check(submitter: &Submitter) -> Result<(), &'static str> {
+    match (&submitter.email, &submitter.verification.mailer) {
+        (None, Some(_)) => Err("X-Submitter-Email is required to verify the submission"),
+        (Some(email), Some(_)) if !valid_email(email) => Err("X-Submitter-Email is not a valid email address"),
+        _ => Ok(()),
+    }
+}
+
+// What a token stands for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Pending {
+    collection: String,
+    id: String,
+}
+
+// Fallback for when Redis is not configured
+static LOCAL: OnceLock<Mutex<HashMap<String, (Pending, Instant)>>> = OnceLock::new();
+
+fn local() -> &'static Mutex<HashMap<String, (Pending, Instant)>> {
+    LOCAL.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn key(token: &str) -> String {
+    format!("fortunes:verification:{}", token)
+}
+
+// 32 random bytes, hex-encoded
+fn valid_token(token: &str) -> bool {
+    token.len() == 64 && token.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+async fn issue(pending: Pending, ttl: Duration) -> Result<String, String> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    if let Some(redis) = redis_client::get_store().await {
+        let value = serde_json::to_string(&pending).expect("verification entries serialize");
+        redis
+            .connection()
+            .and_then(|mut conn| {
+                redis::cmd("SET")
+                    .arg(key(&token))
+                    .arg(value)
+                    .arg("EX")
+                    .arg(ttl.as_secs())
+                    .query::<()>(&mut conn)
+            })
+            .map_err(|e| e.to_string())?;
+        return Ok(token);
+    }
+    let now = Instant::now();
+    let mut local = local().lock().unwrap();
+    local.retain(|_, (_, expires)| *expires > now);
+    local.insert(token.clone(), (pending, now + ttl));
+    Ok(token)
+}
+
+// The fortune a token was issued for, leaving the token in place
+async fn pending(token: &str) -> Option<Pending> {
+    if !valid_token(token) {
+        return None;
+    }
+    if let Some(redis) = redis_client::get_store().await {
+        let result: redis::RedisResult<Option<String>> =
+            redis.connection().and_then(|mut conn| redis::cmd("GET").arg(key(token)).query(&mut conn));
+        return match result {
+            Ok(value) => value.and_then(|value| serde_json::from_str(&value).ok()),
+            Err(e) => {
+                error!("Redis verification lookup failed: {}", e);
+                None
+            }
+        };
+    }
+    let local = local().lock().unwrap();
+    let (pending, expires) = local.get(token)?;
+    (*expires > Instant::now()).then(|| pending.clone())
+}
+
+// The fortune a token was issued for; a token can be redeemed once
+async fn redeem(token: &str) -> Option<Pending> {
+    if !valid_token(token) {
+        return None;
+    }
+    if let Some(redis) = redis_client::get_store().await {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("GET").arg(key(token));
+        pipe.cmd("DEL").arg(key(token)).ignore();
+        let result: redis::RedisResult<(Option<String>,)> = redis.connection().and_then(|mut conn| pipe.query(&mut conn));
+        return match result {
+            Ok((value,)) => value.and_then(|value| serde_json::from_str(&value).ok()),
+            Err(e) => {
+                error!("Redis verification lookup failed: {}", e);
+                None
+            }
+        };
+    }
+    let (pending, expires) = local().lock().unwrap().remove(token)?;
+    (expires > Instant::now()).then_some(pending)
+}
+
+impl Mailer {
+    // Posts `{"to", "subject", "text"}` to the relay
+    async fn send(&self, to: &str, token: &str, fortune: &Fortune) -> Result<(), String> {
+        let link = format!("{}/verify/{}", self.link_base, token);
+        let text = format!(
+            "Someone, hopefully you, submitted this fortune:\n\n{}\n\nOpen {} and confirm to publish it. If you did not submit it, ignore this message.",
+            fortune.message, link,
+        );
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&json!({ "to": to, "subject": "Confirm your fortune", "text": text }))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("mail relay answered {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+fn unavailable(message: &str) -> Response {
+    warp::reply::with_status(warp::reply::json(&message), StatusCode::SERVICE_UNAVAILABLE).into_response()
+}
+
+// Answers a stored unverified fortune: 202 with the fortune, and the token
+// when it is not mailed. Without a token the fortune could never be
+// verified, so it is deleted again if the token cannot be issued or sent.
+pub async fn started(store: &FortuneStore, fortune: Fortune, submitter: Submitter, actor: &str) -> Response {
+    let collection = store.read().await.collection().unwrap_or(collections::DEFAULT).to_string();
+    let pending = Pending {
+        collection,
+        id: fortune.id.clone(),
+    };
+    let verification = &submitter.verification;
+    let token = match issue(pending, verification.ttl).await {
+        Ok(token) => token,
+        Err(e) => {
+            error!("verification token for fortune {} not stored: {}", fortune.id, e);
+            store::delete(store, &fortune.id, actor).await;
+            return unavailable("could not start the verification");
+        }
+    };
+    let mut body = serde_json::to_value(&fortune).expect("fortunes serialize");
+    match (&verification.mailer, submitter.email) {
+        (Some(mailer), Some(email)) => {
+            if let Err(e) = mailer.send(&email, &token, &fortune).await {
+                error!("verification mail for fortune {} not sent: {}", fortune.id, e);
+                store::delete(store, &fortune.id, actor).await;
+                return unavailable("could not send the verification email");
+            }
+        }
+        _ => body["verification_token"] = json!(token),
+    }
+    warp::reply::with_status(warp::reply::json(&body), StatusCode::ACCEPTED).into_response()
+}
+
+fn not_found() -> Response {
+    warp::reply::with_status(warp::reply::json(&"unknown or expired token"), StatusCode::NOT_FOUND).into_response()
+}
+
+// The store of the collection a token was issued in
+fn store_of(pending: &Pending, default: FortuneStore, collections: &Collections) -> Option<FortuneStore> {
+    match pending.collection.as_str() {
+        collections::DEFAULT => Some(default),
+        name => collections.get(name).map(|collection| collection.store.clone()),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// The form posts back to the same URL
+fn confirm_page(fortune: &Fortune) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Confirm your fortune</title>
+</head>
+<body>
+    <p>Someone, hopefully you, submitted this fortune:</p>
+    <blockquote>{}</blockquote>
+    <form method="post">
+        <button type="submit">Publish it</button>
+    </form>
+    <p>If you did not submit it, close this page.</p>
+</body>
+</html>
+"#,
+        escape(&fortune.message),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/verify/{token}",
+    tag = "fortunes",
+    params(("token" = String, Path, description = "The one-time token from the submission response or email")),
+    responses(
+        (status = 200, description = "A page with the fortune and a button that confirms it", content_type = "text/html", body = String),
+        (status = 404, description = "Unknown, used or expired token", body = String),
+    )
+)]
+pub async fn confirm_handler(token: String, default: FortuneStore, collections: Collections) -> Result<impl Reply, Infallible> {
+    let Some(pending) = pending(&token).await else {
+        return Ok(not_found());
+    };
+    let Some(store) = store_of(&pending, default, &collections) else {
+        return Ok(not_found());
+    };
+    Ok(match store::peek(&store, &pending.id).await {
+        Some(fortune) if fortune.status == Status::Unverified => warp::reply::html(confirm_page(&fortune)).into_response(),
+        _ => not_found(),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/verify/{token}",
+    tag = "fortunes",
+    params(("token" = String, Path, description = "The one-time token from the submission response or email")),
+    responses(
+        (status = 200, description = "The fortune is published", body = Fortune),
+        (status = 202, description = "The fortune now awaits moderation", body = Fortune),
+        (status = 404, description = "Unknown, used or expired token", body = String),
+    )
+)]
+pub async fn verify_handler(
+    token: String,
+    moderation: bool,
+    actor: String,
+    default: FortuneStore,
+    collections: Collections,
+) -> Result<impl Reply, Infallible> {
+    let Some(pending) = redeem(&token).await else {
+        return Ok(not_found());
+    };
+    let Some(store) = store_of(&pending, default, &collections) else {
+        return Ok(not_found());
+    };
+    Ok(match store::verify(&store, &pending.id, moderation, &actor).await {
+        Some(fortune) if fortune.status == Status::Pending => {
+            warp::reply::with_status(warp::reply::json(&fortune), StatusCode::ACCEPTED).into_response()
+        }
+        Some(fortune) => warp::reply::json(&fortune).into_response(),
+        None => not_found(),
+    })
+}
+
+// Deletes fortunes that were not verified in time. Every replica holds them,
+// so only the leader deletes them, for all.
+pub fn spawn_purge(store: FortuneStore, ttl: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ttl.min(Duration::from_secs(60)));
+        loop {
+            interval.tick().await;
+            if !leader::is_leader().await {
+                continue;
+            }
+            let cutoff = now_secs().saturating_sub(ttl.as_secs());
+            let expired: Vec<String> = store
+                .read()
+                .await
+                .values()
+                .filter(|f| f.status == Status::Unverified && f.created_at.is_none_or(|at| at <= cutoff))
+                .map(|f| f.id.clone())
+                .collect();
+            for id in &expired {
+                store::delete(&store, id, "system").await;
+            }
+            if !expired.is_empty() {
+                info!("deleted {} fortunes that were not verified in time", expired.len());
+            }
+        }
+    });
+}
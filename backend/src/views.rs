@@ -0,0 +1,88 @@
+use crate::redis_client::RedisStore;
+use crate::FortuneStore;
+use fortune_common::error;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+// Views counted in memory but not yet added to Redis, by collection (None is
+// the default one) and id
+type Pending = HashMap<(Option<String>, String), u64>;
+
+static PENDING: OnceLock<Mutex<Pending>> = OnceLock::new();
+// Collections whose views are flushed in batches
+static BATCHED: OnceLock<Mutex<HashSet<Option<String>>>> = OnceLock::new();
+
+fn pending() -> &'static Mutex<Pending> {
+    PENDING.get_or_init(Mutex::default)
+}
+
+fn batched() -> &'static Mutex<HashSet<Option<String>>> {
+    BATCHED.get_or_init(Mutex::default)
+}
+
+// Whether the collection's views wait for a flush instead of going to Redis
+// one by one
+pub fn is_batched(collection: Option<&str>) -> bool {
+    batched().lock().unwrap().contains(&collection.map(str::to_string))
+}
+
+// Remembers a view to add to the collection's Redis counter on the next flush
+pub fn queue(collection: Option<&str>, id: &str) {
+    *pending()
+        .lock()
+        .unwrap()
+        .entry((collection.map(str::to_string), id.to_string()))
+        .or_default() += 1;
+}
+
+// Adds the views queued for `store`'s collection to `redis` every `interval`
+// and adopts the totals, which include other replicas' views
+pub fn spawn_flush(redis: RedisStore, store: FortuneStore, interval: Duration) {
+    tokio::spawn(async move {
+        let collection = store.read().await.collection().map(str::to_string);
+        batched().lock().unwrap().insert(collection);
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            flush(&redis, &store).await;
+        }
+    });
+}
+
+pub async fn flush(redis: &RedisStore, store: &FortuneStore) {
+    let collection = store.read().await.collection().map(str::to_string);
+    let counts: Vec<(String, u64)> = {
+        let mut pending = pending().lock().unwrap();
+        let ids: Vec<String> = pending
+            .keys()
+            .filter(|(owner, _)| *owner == collection)
+            .map(|(_, id)| id.clone())
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| pending.remove(&(collection.clone(), id.clone())).map(|count| (id, count)))
+            .collect()
+    };
+    if counts.is_empty() {
+        return;
+    }
+    match redis.add_views(&counts).await {
+        Ok(totals) => {
+            let fortunes = store.read().await;
+            let pending = pending().lock().unwrap();
+            for ((id, _), total) in counts.iter().zip(totals) {
+                // Views served since the drain are not in the total yet
+                let unflushed = pending.get(&(collection.clone(), id.clone())).copied().unwrap_or_default();
+                fortunes.set_views(id, total + unflushed);
+            }
+        }
+        Err(e) => {
+            error!("redis view flush failed: {}", e);
+            // Kept for the next flush
+            let mut pending = pending().lock().unwrap();
+            for (id, count) in counts {
+                *pending.entry((collection.clone(), id)).or_default() += count;
+            }
+        }
+    }
+}
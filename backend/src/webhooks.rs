@@ -0,0 +1,219 @@
+use crate::config::Config;
+use crate::fortunes::now_secs;
+use crate::Fortune;
+use fortune_common::{error, info, warn};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+// Outgoing webhooks: every fortune created, updated or deleted on this replica
+// is POSTed as JSON to each of WEBHOOK_URLS. Each URL has its own queue and
+// worker, so a slow receiver delays only its own events, which it gets in
+// order. Failed deliveries are retried with exponential backoff and, once
+// WEBHOOK_MAX_ATTEMPTS is used up, written to WEBHOOK_DEAD_LETTER_FILE.
+//
+// Requests carry `X-Fortune-Signature: t=<unix secs>,v1=<hex>`, the
+// HMAC-SHA256 of `<t>.<body>` keyed with WEBHOOK_SECRET.
+
+pub const SIGNATURE_HEADER: &str = "x-fortune-signature";
+// Events waiting per URL before new ones are dropped
+const QUEUE_SIZE: usize = 1024;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Created => "fortune.created",
+            Kind::Updated => "fortune.updated",
+            Kind::Deleted => "fortune.deleted",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload {
+    // Unique per event, the same for every URL and retry, so receivers can
+    // drop duplicates
+    id: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    timestamp: u64,
+    // The fortune as stored; only `id` for deletions
+    fortune: serde_json::Value,
+}
+
+struct Event {
+    id: String,
+    kind: Kind,
+    body: String,
+}
+
+struct Settings {
+    http: reqwest::Client,
+    secret: Vec<u8>,
+    max_attempts: u32,
+    retry_base: Duration,
+    dead_letter_file: Option<PathBuf>,
+}
+
+static QUEUES: OnceLock<Vec<(String, mpsc::Sender<Arc<Event>>)>> = OnceLock::new();
+static DELIVERED: AtomicU64 = AtomicU64::new(0);
+static DEAD_LETTERED: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// Starts a delivery worker per configured URL; nothing is sent without any
+pub fn init(config: &Config) {
+    let urls = config.webhook_urls();
+    let Some(secret) = config.webhook_secret.clone().filter(|_| !urls.is_empty()) else {
+        return;
+    };
+    let settings = Arc::new(Settings {
+        http: reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("failed to build HTTP client"),
+        secret: secret.into_bytes(),
+        max_attempts: config.webhook_max_attempts,
+        retry_base: Duration::from_millis(config.webhook_retry_base_ms),
+        dead_letter_file: config.webhook_dead_letter_file.clone(),
+    });
+    let queues = urls
+        .into_iter()
+        .map(|url| {
+            let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+            tokio::spawn(worker(url.clone(), rx, settings.clone()));
+            (url, tx)
+        })
+        .collect::<Vec<_>>();
+    info!("Sending fortune events to {} webhook(s)", queues.len());
+    QUEUES.set(queues).ok();
+}
+
+// Queues an event for every webhook. `fortune` is the stored fortune, or for
+// a deletion one with just the id.
+pub fn notify(kind: Kind, id: &str, fortune: Option<&Fortune>) {
+    let Some(queues) = QUEUES.get() else {
+        return;
+    };
+    let payload = Payload {
+        id: hex::encode(rand::random::<[u8; 16]>()),
+        kind: kind.as_str(),
+        timestamp: now_secs(),
+        fortune: match fortune {
+            Some(fortune) => json!(fortune),
+            None => json!({ "id": id }),
+        },
+    };
+    let body = match serde_json::to_string(&payload) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("failed to encode webhook event: {}", e);
+            return;
+        }
+    };
+    let event = Arc::new(Event { id: payload.id, kind, body });
+    for (url, queue) in queues {
+        if queue.try_send(event.clone()).is_err() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            warn!("webhook queue for {} is full, dropped event {}", url, event.id);
+        }
+    }
+}
+
+pub fn sign(secret: &[u8], timestamp: u64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn worker(url: String, mut events: mpsc::Receiver<Arc<Event>>, settings: Arc<Settings>) {
+    while let Some(event) = events.recv().await {
+        let mut attempt = 1;
+        loop {
+            match send(&url, &event, &settings).await {
+                Ok(()) => {
+                    DELIVERED.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+                Err(e) if attempt >= settings.max_attempts => {
+                    error!("webhook {} failed for event {} after {} attempts: {}", url, event.id, attempt, e);
+                    dead_letter(&url, &event, attempt, &e, &settings).await;
+                    break;
+                }
+                Err(e) => {
+                    let delay = settings.retry_base.saturating_mul(1 << (attempt - 1).min(16)).min(MAX_RETRY_DELAY);
+                    warn!("webhook {} failed for event {} (attempt {}): {}; retrying in {:?}", url, event.id, attempt, e, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+async fn send(url: &str, event: &Event, settings: &Settings) -> Result<(), String> {
+    let response = settings
+        .http
+        .post(url)
+        .header("content-type", "application/json")
+        .header("x-fortune-event", event.kind.as_str())
+        .header("x-fortune-delivery", &event.id)
+        .header(SIGNATURE_HEADER, sign(&settings.secret, now_secs(), &event.body))
+        .body(event.body.clone())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("answered {}", response.status()));
+    }
+    Ok(())
+}
+
+// One JSON line per event that could not be delivered, to be replayed by hand
+async fn dead_letter(url: &str, event: &Event, attempts: u32, error: &str, settings: &Settings) {
+    DEAD_LETTERED.fetch_add(1, Ordering::Relaxed);
+    let Some(path) = &settings.dead_letter_file else {
+        return;
+    };
+    let entry = json!({
+        "failed_at": now_secs(),
+        "url": url,
+        "attempts": attempts,
+        "error": error,
+        "event": serde_json::from_str::<serde_json::Value>(&event.body).unwrap_or_default(),
+    });
+    let line = format!("{}\n", entry);
+    let result = async {
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+        file.write_all(line.as_bytes()).await
+    }
+    .await;
+    if let Err(e) = result {
+        error!("failed to append to webhook dead-letter file {}: {}", path.display(), e);
+    }
+}
+
+pub fn metrics() -> String {
+    format!(
+        "backend_webhook_delivered_total {}\n\
+         backend_webhook_dead_lettered_total {}\n\
+         backend_webhook_dropped_total {}\n",
+        DELIVERED.load(Ordering::Relaxed),
+        DEAD_LETTERED.load(Ordering::Relaxed),
+        DROPPED.load(Ordering::Relaxed),
+    )
+}
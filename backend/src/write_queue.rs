@@ -0,0 +1,83 @@
+use crate::redis_client::RedisStore;
+use crate::storage::Storage;
+use crate::Fortune;
+use fortune_common::{debug, error, warn};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+struct WriteQueue {
+    sender: mpsc::Sender<Fortune>,
+    depth: AtomicU64,
+    dropped: AtomicU64,
+    replayed: AtomicU64,
+}
+
+static WRITE_QUEUE: OnceLock<WriteQueue> = OnceLock::new();
+
+// Starts the worker that replays buffered Redis writes in order, retrying each
+// one until Redis accepts it.
+pub fn init(redis: RedisStore, capacity: usize, retry_delay: Duration) {
+    let (sender, mut receiver) = mpsc::channel::<Fortune>(capacity);
+    let queue = WriteQueue {
+        sender,
+        depth: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+        replayed: AtomicU64::new(0),
+    };
+    if WRITE_QUEUE.set(queue).is_err() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        while let Some(write) = receiver.recv().await {
+            loop {
+                match redis.set(&write).await {
+                    Ok(()) => break,
+                    Err(e) => {
+                        error!("write-behind replay of fortune {} failed: {}", write.id, e);
+                        tokio::time::sleep(retry_delay).await;
+                    }
+                }
+            }
+            if let Some(queue) = WRITE_QUEUE.get() {
+                queue.depth.fetch_sub(1, Ordering::Relaxed);
+                queue.replayed.fetch_add(1, Ordering::Relaxed);
+            }
+            debug!("write-behind replayed fortune {}", write.id);
+        }
+    });
+}
+
+pub fn enqueue(fortune: &Fortune) {
+    let Some(queue) = WRITE_QUEUE.get() else {
+        return;
+    };
+    match queue.sender.try_send(fortune.clone()) {
+        Ok(()) => {
+            queue.depth.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(_) => {
+            queue.dropped.fetch_add(1, Ordering::Relaxed);
+            warn!("write-behind queue full, dropping redis write for fortune {}", fortune.id);
+        }
+    }
+}
+
+pub fn metrics() -> String {
+    let (depth, dropped, replayed) = match WRITE_QUEUE.get() {
+        Some(queue) => (
+            queue.depth.load(Ordering::Relaxed),
+            queue.dropped.load(Ordering::Relaxed),
+            queue.replayed.load(Ordering::Relaxed),
+        ),
+        None => (0, 0, 0),
+    };
+    format!(
+        "backend_write_queue_depth {}\n\
+         backend_write_queue_dropped_total {}\n\
+         backend_write_queue_replayed_total {}\n",
+        depth, dropped, replayed,
+    )
+}
@@ -0,0 +1,2363 @@
+use fortune_backend::client_ip::TrustedProxies;
+use fortune_backend::config::Config;
+use fortune_backend::fortunes::{self, Fortunes};
+use fortune_backend::{collections, compression, create_default_store, endpoints, negative_cache, recorder, routes, signing, store, Fortune, Sort};
+use fortune_common::log;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use warp::http::StatusCode;
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn test_config(extra: &[(&str, &str)]) -> Config {
+    envy::from_iter(extra.iter().map(|(k, v)| (k.to_string(), v.to_string()))).unwrap()
+}
+
+#[tokio::test]
+async fn list_returns_default_fortunes() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().method("GET").path("/fortunes").reply(&api).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body.len(), 4);
+}
+
+#[tokio::test]
+async fn get_returns_fortune_by_id() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().method("GET").path("/fortunes/4").reply(&api).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body, json!({"id": "4", "message": "It ain't over till it's EOF.", "lang": "en", "status": "approved", "views": 1}));
+}
+
+#[tokio::test]
+async fn get_unknown_id_is_not_found() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().method("GET").path("/fortunes/nope").reply(&api).await;
+
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn error_responses_echo_request_id() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/fortunes/nope")
+        .header("x-request-id", "abc-123")
+        .reply(&api)
+        .await;
+
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(res.headers()["x-request-id"], "abc-123");
+}
+
+#[tokio::test]
+async fn random_picks_an_existing_fortune() {
+    let fortunes = create_default_store();
+
+    let fortune = store::random(&fortunes, &[], None, false).await.expect("store is not empty");
+
+    assert!(fortunes.read().await.contains_key(&fortune.id));
+}
+
+#[tokio::test]
+async fn random_skips_deleted_fortunes() {
+    let fortunes = create_default_store();
+    for id in ["1", "2", "4"] {
+        store::delete(&fortunes, id, "test").await.expect("default fortune exists");
+    }
+
+    for _ in 0..10 {
+        let fortune = store::random(&fortunes, &[], None, false).await.expect("one fortune is left");
+        assert_eq!(fortune.id, "3");
+    }
+}
+
+#[tokio::test]
+async fn random_route_is_not_captured_by_id_route() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().method("GET").path("/fortunes/random").reply(&api).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert!(["1", "2", "3", "4"].contains(&body["id"].as_str().unwrap()));
+}
+
+#[tokio::test]
+async fn random_does_not_repeat_within_a_session() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let random = |path: &'static str, cookie: Option<&'static str>| {
+        let api = api.clone();
+        async move {
+            let mut req = warp::test::request().path(path);
+            if let Some(cookie) = cookie {
+                req = req.header("cookie", cookie);
+            }
+            let res = req.reply(&api).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            serde_json::from_slice::<Value>(res.body()).unwrap()["id"].as_str().unwrap().to_string()
+        }
+    };
+
+    let mut ids = HashSet::new();
+    for _ in 0..4 {
+        ids.insert(random("/fortunes/random?session=no-repeat", None).await);
+    }
+    assert_eq!(ids.len(), 4);
+    // Exhausted sessions start over instead of failing
+    random("/fortunes/random?session=no-repeat", None).await;
+
+    let mut ids = HashSet::new();
+    for _ in 0..4 {
+        ids.insert(random("/fortunes/random", Some("fortune_session=by-cookie")).await);
+    }
+    assert_eq!(ids.len(), 4);
+}
+
+#[tokio::test]
+async fn random_honors_requested_language() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    for (id, lang, message) in [("de1", "de", "Ein neuer Weg."), ("fr1", "fr-CA", "Un nouveau chemin.")] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"id": id, "message": message, "lang": lang, "group": "voyage"}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    let random = |path: &'static str, accept: &'static str| {
+        let api = api.clone();
+        async move {
+            let res = warp::test::request()
+                .path(path)
+                .header("accept-language", accept)
+                .reply(&api)
+                .await;
+            serde_json::from_slice::<Value>(res.body()).unwrap()
+        }
+    };
+    assert_eq!(random("/fortunes/random", "de-AT, en;q=0.5").await["id"], "de1");
+    assert_eq!(random("/fortunes/random", "fr-ca").await["group"], "voyage");
+    assert_eq!(random("/fortunes/random?lang=de", "fr-CA").await["lang"], "de");
+    // No Japanese fortunes, so English is the fallback
+    assert_eq!(random("/fortunes/random", "ja").await["lang"], "en");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "x", "message": "Bad tag.", "lang": "en us"}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn scheduled_fortunes_are_listed_only_inside_their_window() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let now = fortunes::now_secs();
+    for (id, publish_at, expires_at) in [
+        ("future", Some(now + 3600), None),
+        ("expired", None, Some(now - 1)),
+        ("current", Some(now - 60), Some(now + 3600)),
+    ] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"id": id, "message": id, "publish_at": publish_at, "expires_at": expires_at}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    let res = warp::test::request().path("/fortunes").reply(&api).await;
+    let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    let ids: Vec<&str> = body.iter().map(|f| f["id"].as_str().unwrap()).collect();
+    assert_eq!(ids.len(), 5);
+    assert!(ids.contains(&"current"));
+    // Still reachable directly by id
+    let res = warp::test::request().path("/fortunes/future").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "backwards", "message": "backwards", "publish_at": now, "expires_at": now}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn refresh_schedule_moves_fortunes_in_and_out_of_the_hot_set() {
+    let mut store = Fortunes::new();
+    store.insert("seasonal".to_string(), Fortune {
+        id: "seasonal".to_string(),
+        message: "Happy new year!".to_string(),
+        publish_at: Some(u64::MAX - 10),
+        expires_at: Some(u64::MAX - 5),
+        ..Default::default()
+    });
+    assert_eq!(store.active().count(), 0);
+
+    assert_eq!(store.refresh_schedule(u64::MAX - 10), 1);
+    assert_eq!(store.active().count(), 1);
+    assert_eq!(store.refresh_schedule(u64::MAX - 9), 0);
+    assert_eq!(store.refresh_schedule(u64::MAX - 5), 1);
+    assert_eq!(store.active().count(), 0);
+    assert!(store.random(&mut rand::thread_rng()).is_none());
+}
+
+#[test]
+fn each_collection_allocates_ids_past_its_own_numeric_ids() {
+    let fortune = |id: &str| Fortune {
+        id: id.to_string(),
+        message: format!("Fortune {}.", id),
+        ..Default::default()
+    };
+    let mut store = Fortunes::new();
+    let mut named = Fortunes::named("quotes");
+    assert_eq!(store.allocate_id(), "1");
+    store.insert("41".to_string(), fortune("41"));
+    store.insert("not-a-number".to_string(), fortune("not-a-number"));
+    assert_eq!(store.allocate_id(), "42");
+    store.remove("41");
+    assert_eq!(store.allocate_id(), "43");
+    assert_eq!(named.allocate_id(), "1");
+}
+
+#[test]
+fn capped_store_evicts_the_least_recently_used_fortunes() {
+    let fortune = |id: &str| Fortune {
+        id: id.to_string(),
+        message: format!("Fortune {}.", id),
+        ..Default::default()
+    };
+    let mut store = Fortunes::new();
+    store.set_capacity(Some(3));
+    for id in ["a", "b", "c"] {
+        store.insert(id.to_string(), fortune(id));
+    }
+    assert_eq!(store.len(), 3);
+
+    // Reading `a` makes `b` the least recently used
+    store.touch("a");
+    store.insert("d".to_string(), fortune("d"));
+    assert_eq!(store.len(), 3);
+    assert!(store.get("b").is_none() && store.is_evicted("b"));
+    assert!(store.get("a").is_some());
+    // Only the body is gone; `b` is still listed and matched
+    assert_eq!(store.active().count(), 3);
+    assert_eq!(store.active_ids().count(), 4);
+    assert_eq!(store.find_duplicate("x", "Fortune b.").map(String::as_str), Some("b"));
+
+    // Replacing a cached fortune does not evict anything
+    store.insert("c".to_string(), fortune("c"));
+    assert_eq!(store.len(), 3);
+    store.insert("e".to_string(), fortune("e"));
+    assert!(store.is_evicted("a"));
+
+    // Shrinking the cap evicts at once
+    store.set_capacity(Some(1));
+    assert_eq!(store.len(), 1);
+    assert!(store.get("e").is_some());
+
+    // Removing an evicted fortune takes it out of the indexes too
+    store.remove("b");
+    assert!(!store.contains_key("b"));
+    assert!(store.find_duplicate("x", "Fortune b.").is_none());
+    assert_eq!(store.active_ids().count(), 4);
+}
+
+#[tokio::test]
+async fn reads_never_wait_for_a_writer() {
+    let store = create_default_store();
+    let mut writing = store.write().await;
+    writing.insert("5".to_string(), Fortune { id: "5".to_string(), message: "Fortune 5.".to_string(), ..Default::default() });
+    writing.remove("1");
+
+    // Readers carry on with the last published fortunes, views included
+    let read = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+        let fortunes = store.read().await;
+        assert!(fortunes.contains_key("1") && !fortunes.contains_key("5"));
+        store::get(&store, "2").await
+    });
+    assert_eq!(read.await.expect("the read waited for the writer").unwrap().views, 1);
+
+    // and see the whole write once it is done
+    drop(writing);
+    let fortunes = store.read().await;
+    assert!(!fortunes.contains_key("1") && fortunes.contains_key("5"));
+    assert_eq!(fortunes.views("2"), 1);
+}
+
+#[tokio::test]
+async fn view_counts_change_only_when_a_write_is_published() {
+    let store = create_default_store();
+    store::get(&store, "1").await;
+    store::get(&store, "2").await;
+
+    let mut writing = store.write().await;
+    writing.remove("1");
+    assert_eq!(store.read().await.views("1"), 1);
+    drop(writing);
+    assert_eq!(store.read().await.views("1"), 0);
+
+    // Clearing leaves the counts of the published fortunes alone
+    let before = store.read().await;
+    let mut writing = store.write().await;
+    writing.clear();
+    assert_eq!(store.read().await.views("2"), 1);
+    drop(writing);
+    assert_eq!(before.views("2"), 1);
+    assert_eq!(store.read().await.views("2"), 0);
+}
+
+#[test]
+fn negative_cache_forgets_ids_once_a_fortune_is_stored() {
+    negative_cache::init(&test_config(&[("NEGATIVE_CACHE_TTL_MS", "60000")]));
+    negative_cache::remember(None, "404");
+    negative_cache::remember(Some("team-a"), "404");
+    assert!(negative_cache::is_missing(None, "404"));
+    assert!(!negative_cache::is_missing(None, "405"));
+
+    let mut store = Fortunes::new();
+    store.insert("404".to_string(), Fortune {
+        id: "404".to_string(),
+        message: "Found at last.".to_string(),
+        ..Default::default()
+    });
+    assert!(!negative_cache::is_missing(None, "404"));
+    // Collections keep their own entries
+    assert!(negative_cache::is_missing(Some("team-a"), "404"));
+    assert!(negative_cache::metrics().starts_with("backend_negative_cache_hits_total "));
+}
+
+#[tokio::test]
+async fn fortunes_can_be_listed_by_author() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    for (id, author, message) in [
+        ("c1", "Confucius", "Real knowledge is to know the extent of one's ignorance."),
+        ("c2", " confucius ", "It does not matter how slowly you go."),
+        ("l1", "Lao Tzu", "A journey of a thousand miles begins with a single step."),
+    ] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"id": id, "message": message, "author": author}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    let res = warp::test::request().path("/fortunes?author=CONFUCIUS").reply(&api).await;
+    let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    let mut ids: Vec<&str> = body.iter().map(|f| f["id"].as_str().unwrap()).collect();
+    ids.sort();
+    assert_eq!(ids, ["c1", "c2"]);
+    assert_eq!(body[0]["author"].as_str().unwrap().to_lowercase(), "confucius");
+
+    let res = warp::test::request().path("/fortunes/authors").reply(&api).await;
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body[0]["count"], 2);
+    assert_eq!(body[1], json!({"author": "Lao Tzu", "count": 1}));
+}
+
+#[tokio::test]
+async fn popular_ranks_fortunes_by_views() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    for (id, times) in [("2", 3), ("4", 1)] {
+        for _ in 0..times {
+            warp::test::request().path(&format!("/fortunes/{}", id)).reply(&api).await;
+        }
+    }
+
+    let res = warp::test::request().path("/fortunes/popular?limit=2").reply(&api).await;
+
+    let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    let ranked: Vec<(&str, u64)> = body
+        .iter()
+        .map(|f| (f["id"].as_str().unwrap(), f["views"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(ranked, [("2", 3), ("4", 1)]);
+
+    let res = warp::test::request().path("/fortunes").reply(&api).await;
+    let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    let total: u64 = body.iter().map(|f| f["views"].as_u64().unwrap()).sum();
+    assert_eq!(total, 4);
+}
+
+#[tokio::test]
+async fn create_rejects_reserved_ids() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    for id in ["authors", "events", "popular", "random", "trash", "ws"] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"id": id, "message": "Shadowed by a route."}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "id {}", id);
+    }
+
+    let res = warp::test::request().method("GET").path("/fortunes").reply(&api).await;
+    let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body.len(), 4);
+}
+
+#[tokio::test]
+async fn event_log_needs_redis_and_a_valid_since() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    for since in ["abc", "1-", "-1", "1-2-3"] {
+        let res = warp::test::request().path(&format!("/fortunes/events?since={}", since)).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "since {}", since);
+    }
+
+    // Without Redis there is no log to read, and the route is not taken for a fortune id
+    let res = warp::test::request().path("/fortunes/events?since=1767225600000-0").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let res = warp::test::request().method("OPTIONS").path("/fortunes/events").reply(&api).await;
+    assert_eq!(res.headers()["allow"], "GET, HEAD, OPTIONS");
+}
+
+#[tokio::test]
+async fn create_then_get_round_trips() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "42", "message": "Tests bring good fortune."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request().method("GET").path("/fortunes/42").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["message"], "Tests bring good fortune.");
+}
+
+#[tokio::test]
+async fn retried_creates_with_an_idempotency_key_replay_the_first_response() {
+    let store = create_default_store();
+    let api = routes(store.clone(), &test_config(&[]));
+    let create = |key: &str, message: &str| {
+        warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .header("idempotency-key", key)
+            .json(&json!({"message": message}))
+    };
+
+    let first = create("retry-7f3a", "Retries are safe now.").reply(&api).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    assert!(first.headers().get("idempotent-replayed").is_none());
+
+    let retry = create("retry-7f3a", "Retries are safe now.").reply(&api).await;
+    assert_eq!(retry.status(), StatusCode::OK);
+    assert_eq!(retry.headers()["idempotent-replayed"], "true");
+    assert_eq!(retry.body(), first.body());
+    assert_eq!(store::list(&store).await.len(), 5);
+
+    let res = create("retry-7f3a", "A different fortune.").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let res = create("not a key", "Spaces are not allowed.").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(store::list(&store).await.len(), 5);
+}
+
+// Messages that are easy to mangle: CJK, right-to-left and Devanagari
+// scripts, emoji sequences and diacritics
+const MULTILINGUAL: &[(&str, &str)] = &[
+    ("ja", "七転び八起き。"),
+    ("zh", "千里之行，始于足下。"),
+    ("ar", "الصبر مفتاح الفرج"),
+    ("hi", "नमस्ते दुनिया"),
+    ("en", "Family first 👨‍👩‍👧‍👦 and flags 🇳🇴"),
+    ("vi", "Tiếng Việt có dấu"),
+];
+
+#[tokio::test]
+async fn multilingual_messages_round_trip() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    for (i, (lang, message)) in MULTILINGUAL.iter().enumerate() {
+        let id = format!("ml{}", i);
+        let res = warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"id": id, "message": message, "lang": lang}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK, "{}", message);
+
+        let res = warp::test::request().path(&format!("/fortunes/{}", id)).reply(&api).await;
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["message"], *message);
+    }
+}
+
+#[tokio::test]
+async fn messages_are_stored_in_nfc() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    // "Café" spelled with a combining acute accent
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "nfc", "message": "Cafe\u{301} au lait.", "author": "Ame\u{301}lie"}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["message"], "Caf\u{e9} au lait.");
+    assert_eq!(body["author"], "Am\u{e9}lie");
+
+    // The precomposed spelling is the same message
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"message": "Caf\u{e9} au lait."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn message_length_is_counted_in_graphemes() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let post = |id: &str, message: String| {
+        warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"id": id, "message": message}))
+            .reply(&api)
+    };
+
+    // 500 family emoji are 500 characters to a reader but over 12,000 bytes
+    let res = post("emoji", "👨‍👩‍👧‍👦".repeat(500)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = post("decomposed", "e\u{301}".repeat(500)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = post("long", "字".repeat(501)).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.body(), "\"message is longer than 500 characters\"");
+}
+
+#[tokio::test]
+async fn create_without_an_id_allocates_one() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let mut ids = Vec::new();
+    for message in ["Fortune favors the nameless.", "So does the second one."] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"message": message}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        let id: u64 = body["id"].as_str().unwrap().parse().expect("allocated ids are numeric");
+        // Past the ids of the default fortunes
+        assert!(id > 4, "id {}", id);
+        ids.push(id);
+    }
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[tokio::test]
+async fn create_duplicate_message_conflicts_unless_forced() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let duplicate = json!({"id": "50", "message": "  it AIN'T over\ttill   it's eof. "});
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&duplicate)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["id"], "4");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes?force=true")
+        .json(&duplicate)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn concurrent_creates_of_one_message_store_it_once() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let create = || {
+        warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"message": "Only one of us gets in."}))
+            .reply(&api)
+    };
+
+    let (first, second) = tokio::join!(create(), create());
+    let mut statuses = [first.status(), second.status()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::CONFLICT]);
+
+    let res = warp::test::request().path("/fortunes").reply(&api).await;
+    let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body.len(), 5);
+}
+
+#[tokio::test]
+async fn batch_create_reports_each_entry() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes/batch")
+        .json(&json!([
+            {"id": "b1", "message": "Batches save round trips."},
+            {"id": "random", "message": "Reserved ids still fail."},
+            {"id": "b2", "message": "It ain't over till it's EOF."},
+            {"id": "b3", "message": "batches save ROUND trips."},
+            {"id": "b4", "message": "Bad lang.", "lang": "not a tag"},
+        ]))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body[0]["result"], "created");
+    assert_eq!(body[0]["fortune"]["id"], "b1");
+    assert_eq!(body[1], json!({"result": "failed", "id": "random", "reason": "fortune id is reserved"}));
+    assert_eq!(body[2]["reason"], "message duplicates fortune 4");
+    // Duplicates within the batch are caught too
+    assert_eq!(body[3]["reason"], "message duplicates fortune b1");
+    assert_eq!(body[4]["reason"], "invalid language tag");
+
+    let res = warp::test::request().path("/fortunes/b1").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = warp::test::request().path("/fortunes/b3").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn client_address_is_read_through_trusted_proxies() {
+    let proxies = TrustedProxies::parse("10.0.0.0/8, 192.168.1.7, fd00::/8").unwrap();
+    let resolve = |peer: &str, headers: &[(&'static str, &str)]| {
+        let mut map = warp::http::HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, value.parse().unwrap());
+        }
+        proxies.resolve(Some(peer.parse().unwrap()), &map).map(|ip| ip.to_string())
+    };
+
+    assert_eq!(resolve("203.0.113.5:1", &[("x-forwarded-for", "192.0.2.1")]).unwrap(), "203.0.113.5");
+    assert_eq!(resolve("10.0.0.2:1", &[]).unwrap(), "10.0.0.2");
+    // The nearest untrusted hop wins, whatever the client put before it
+    let chain = [("x-forwarded-for", "192.0.2.1, 198.51.100.9"), ("x-forwarded-for", "192.168.1.7")];
+    assert_eq!(resolve("10.0.0.2:1", &chain).unwrap(), "198.51.100.9");
+    let forwarded = [("forwarded", r#"for=192.0.2.60;proto=https, For="[2001:db8:cafe::17]:4711""#)];
+    assert_eq!(resolve("[fd00::1]:1", &forwarded).unwrap(), "2001:db8:cafe::17");
+    assert_eq!(resolve("10.0.0.2:1", &[("forwarded", "for=198.51.100.9:80;by=10.0.0.2")]).unwrap(), "198.51.100.9");
+    // Forwarded is preferred over X-Forwarded-For
+    let both = [("forwarded", "for=198.51.100.9"), ("x-forwarded-for", "192.0.2.1")];
+    assert_eq!(resolve("10.0.0.2:1", &both).unwrap(), "198.51.100.9");
+    // An obfuscated hop stops the walk at the proxy that reported it
+    assert_eq!(resolve("10.0.0.2:1", &[("forwarded", "for=unknown, for=10.0.0.9")]).unwrap(), "10.0.0.9");
+
+    assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+    assert_eq!(
+        TrustedProxies::parse("10.0.0.0/8, ingress").unwrap_err(),
+        "'ingress' is not an IP address or CIDR"
+    );
+}
+
+#[tokio::test]
+async fn access_log_records_requests_and_rotates() {
+    let dir = std::env::temp_dir().join(format!("fortune-access-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("access.log");
+    let file = path.to_str().unwrap();
+
+    let api = routes(create_default_store(), &test_config(&[("ACCESS_LOG", "json"), ("ACCESS_LOG_FILE", file)]));
+    warp::test::request()
+        .path("/fortunes/1")
+        .remote_addr("203.0.113.5:41000".parse().unwrap())
+        .header("user-agent", "curl/8.0")
+        .header("x-request-id", "abc-123")
+        .reply(&api)
+        .await;
+    warp::test::request().path("/fortunes/nope").reply(&api).await;
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["remote"], "203.0.113.5");
+    assert_eq!(lines[0]["method"], "GET");
+    assert_eq!(lines[0]["path"], "/fortunes/1");
+    assert_eq!(lines[0]["status"], 200);
+    assert!(lines[0]["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(lines[0]["user_agent"], "curl/8.0");
+    assert_eq!(lines[0]["request_id"], "abc-123");
+    assert_eq!(lines[1]["status"], 404);
+
+    // Combined format, rotated after every line and keeping two old files
+    let path = dir.join("combined.log");
+    let file = path.to_str().unwrap();
+    let config = test_config(&[
+        ("ACCESS_LOG", "combined"),
+        ("ACCESS_LOG_FILE", file),
+        ("ACCESS_LOG_MAX_BYTES", "10"),
+        ("ACCESS_LOG_MAX_FILES", "2"),
+    ]);
+    let api = routes(create_default_store(), &config);
+    for n in 1..=4 {
+        warp::test::request()
+            .path(&format!("/fortunes/{}", n))
+            .header("referer", "http://example.com/")
+            .reply(&api)
+            .await;
+    }
+    let newest = std::fs::read_to_string(&path).unwrap();
+    assert!(newest.contains(r#""GET /fortunes/4" 200 "#), "{}", newest);
+    assert!(newest.contains(r#" "http://example.com/" "-" "-" "#), "{}", newest);
+    assert!(std::fs::read_to_string(dir.join("combined.log.1")).unwrap().contains("/fortunes/3"));
+    assert!(std::fs::read_to_string(dir.join("combined.log.2")).unwrap().contains("/fortunes/2"));
+    assert!(!dir.join("combined.log.3").exists());
+
+    assert!(envy::from_iter::<_, Config>([("ACCESS_LOG".to_string(), "apache".to_string())]).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn head_matches_get_without_a_body() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let get = warp::test::request().path("/fortunes/4").reply(&api).await;
+    let head = warp::test::request().method("HEAD").path("/fortunes/4").reply(&api).await;
+
+    assert_eq!(head.status(), StatusCode::OK);
+    assert!(head.body().is_empty());
+    assert_eq!(head.headers()["content-length"], get.body().len().to_string());
+    assert_eq!(head.headers()["etag"], get.headers()["etag"]);
+}
+
+#[tokio::test]
+async fn options_and_405_list_allowed_methods() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let res = warp::test::request().method("OPTIONS").path("/fortunes").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(res.headers()["allow"], "GET, HEAD, POST, OPTIONS");
+
+    let res = warp::test::request().method("PUT").path("/fortunes/4").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(res.headers()["allow"], "GET, HEAD, DELETE, OPTIONS");
+
+    // Admin routes are unknown without ADMIN_API_KEY
+    let res = warp::test::request().method("OPTIONS").path("/admin/stats").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn head_counts_no_views_and_leaves_sessions_alone() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    for _ in 0..3 {
+        let res = warp::test::request().method("HEAD").path("/fortunes/4").reply(&api).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        warp::test::request().method("HEAD").path("/fortunes/random?session=peeker").reply(&api).await;
+        warp::test::request().method("HEAD").path("/fortunes/rotation?tags=zen:1&session=peeker").reply(&api).await;
+    }
+    let res = warp::test::request().path("/fortunes/4").reply(&api).await;
+    assert_eq!(serde_json::from_slice::<Value>(res.body()).unwrap()["views"], 1);
+
+    // Four fortunes, all new to a session that only ever sent HEAD
+    let res = warp::test::request().path("/fortunes/random?count=4&session=peeker").reply(&api).await;
+    let served: HashSet<String> = serde_json::from_slice::<Vec<Value>>(res.body())
+        .unwrap()
+        .iter()
+        .map(|f| f["id"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(served.len(), 4);
+}
+
+// Checks the hand-kept table behind Allow against the router: every
+// documented path lists its documented methods, the methods it lists are
+// answered, and the others are not
+#[tokio::test]
+async fn allow_matches_the_routes() {
+    let config = test_config(&[
+        ("ADMIN_API_KEY", "secret"),
+        ("SOFT_DELETE", "true"),
+        ("GRAPHIQL", "true"),
+        ("SUBMISSION_VERIFICATION", "true"),
+        ("COLLECTIONS", "c"),
+    ]);
+    let api = routes(create_default_store(), &config);
+    let spec: Value = serde_json::from_slice(warp::test::request().path("/openapi.json").reply(&api).await.body()).unwrap();
+    let mut paths: Vec<(String, Vec<String>)> = spec["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(path, operations)| {
+            let documented = operations.as_object().unwrap().keys().map(|m| m.to_uppercase()).collect();
+            let path = path.replace("{decision}", "approve").replace("{token}", "t0k3n").replace("{id}", "1");
+            (path, documented)
+        })
+        .collect();
+    for undocumented in ["/fortunes/ws", "/graphql", "/openapi.json", "/docs", "/collections/c/fortunes"] {
+        paths.push((undocumented.to_string(), Vec::new()));
+    }
+
+    for (path, documented) in paths {
+        let res = warp::test::request().method("OPTIONS").path(&path).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT, "OPTIONS {}", path);
+        let allow: Vec<String> = res.headers()["allow"].to_str().unwrap().split(", ").map(str::to_string).collect();
+        for method in &documented {
+            assert!(allow.contains(method), "{} {} is not in Allow: {:?}", method, path, allow);
+        }
+        for method in ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"] {
+            let res = warp::test::request().method(method).path(&path).header("x-api-key", "secret").reply(&api).await;
+            // An unlisted method may still fall through to a wider route,
+            // such as DELETE /fortunes/{id} for /fortunes/authors, which
+            // then finds nothing
+            let answered = match allow.iter().any(|m| m == method) {
+                true => res.status() != StatusCode::METHOD_NOT_ALLOWED,
+                false => matches!(res.status(), StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_FOUND),
+            };
+            assert!(answered, "{} {} answered {} with Allow {:?}", method, path, res.status(), allow);
+        }
+    }
+}
+
+#[tokio::test]
+async fn analytics_need_redis_and_a_sane_range() {
+    let api = routes(create_default_store(), &test_config(&[("ADMIN_API_KEY", "secret")]));
+
+    let res = warp::test::request().path("/admin/analytics").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = warp::test::request().path("/admin/analytics?days=0").header("x-api-key", "secret").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    // Counting only starts with the server, and only when Redis is configured
+    let res = warp::test::request().path("/admin/analytics").header("x-api-key", "secret").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(serde_json::from_slice::<Value>(res.body()).unwrap(), "analytics are off");
+
+    let res = warp::test::request().method("OPTIONS").path("/admin/analytics").reply(&api).await;
+    assert_eq!(res.headers()["allow"], "GET, HEAD, OPTIONS");
+}
+
+#[tokio::test]
+async fn disabled_endpoints_answer_as_missing() {
+    let api = routes(create_default_store(), &test_config(&[("DISABLE_ENDPOINTS", "create, delete,docs")]));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "9", "message": "nope"}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(res.headers()["allow"], "GET, HEAD, OPTIONS");
+
+    let res = warp::test::request().method("DELETE").path("/fortunes/1").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(res.headers()["allow"], "GET, HEAD, OPTIONS");
+
+    // A path with nothing left on it does not exist
+    let res = warp::test::request().path("/docs").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = warp::test::request().method("OPTIONS").path("/openapi.json").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = warp::test::request().path("/fortunes/1").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = warp::test::request().method("OPTIONS").path("/fortunes").reply(&api).await;
+    assert_eq!(res.headers()["allow"], "GET, HEAD, OPTIONS");
+
+    assert_eq!(
+        endpoints::Disabled::parse("create,update").unwrap_err(),
+        "unknown endpoint 'update', expected one of list, get, random, rotation, popular, authors, events, ws, trash, \
+         restore, create, batch, delete, graphql, admin, discord, docs, metrics"
+    );
+}
+
+#[tokio::test]
+async fn create_rejects_invalid_json() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .header("content-type", "application/json")
+        .body("{not json")
+        .reply(&api)
+        .await;
+
+    assert!(!res.status().is_success());
+}
+
+#[tokio::test]
+async fn delete_removes_fortune() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().method("DELETE").path("/fortunes/1").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request().method("GET").path("/fortunes/1").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn openapi_spec_lists_fortune_routes() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().method("GET").path("/openapi.json").reply(&api).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let spec: Value = serde_json::from_slice(res.body()).unwrap();
+    assert!(spec["paths"]["/fortunes/{id}"].is_object());
+    assert!(spec["paths"]["/fortunes/events"].is_object());
+}
+
+#[tokio::test]
+async fn list_is_revalidated_with_etag() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().method("GET").path("/fortunes").reply(&api).await;
+    assert_eq!(res.headers()["cache-control"], "no-cache");
+    let etag = res.headers()["etag"].to_str().unwrap().to_string();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/fortunes")
+        .header("if-none-match", &etag)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert!(res.body().is_empty());
+
+    warp::test::request().method("DELETE").path("/fortunes/1").reply(&api).await;
+    let res = warp::test::request()
+        .method("GET")
+        .path("/fortunes")
+        .header("if-none-match", &etag)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_ne!(res.headers()["etag"], etag.as_str());
+}
+
+#[tokio::test]
+async fn get_answers_not_modified_for_matching_etag() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().method("GET").path("/fortunes/2").reply(&api).await;
+    let etag = res.headers()["etag"].to_str().unwrap().to_string();
+
+    let res = warp::test::request()
+        .method("GET")
+        .path("/fortunes/2")
+        .header("if-none-match", format!("W/{}", etag))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+    // The views are in the body, so a read by someone else changes the tag;
+    // the 304 above was not counted
+    let res = warp::test::request().path("/fortunes/2").reply(&api).await;
+    assert_eq!(serde_json::from_slice::<Value>(res.body()).unwrap()["views"], 2);
+    let res = warp::test::request().path("/fortunes/2").header("if-none-match", &etag).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(serde_json::from_slice::<Value>(res.body()).unwrap()["views"], 3);
+
+    let res = warp::test::request().path("/fortunes").reply(&api).await;
+    let listed = res.headers()["etag"].to_str().unwrap().to_string();
+    warp::test::request().path("/fortunes/2").reply(&api).await;
+    let res = warp::test::request().path("/fortunes").header("if-none-match", &listed).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn responses_are_compressed_when_accepted() {
+    let api = compression::wrap(routes(create_default_store(), &test_config(&[])), true);
+
+    let res = warp::test::request()
+        .path("/fortunes")
+        .header("accept-encoding", "gzip, br;q=0")
+        .reply(&api)
+        .await;
+    assert_eq!(res.headers()["content-encoding"], "gzip");
+    assert_eq!(res.headers()["vary"], "accept-encoding");
+
+    let res = warp::test::request().path("/fortunes").reply(&api).await;
+    assert!(res.headers().get("content-encoding").is_none());
+    assert_eq!(res.headers()["vary"], "accept-encoding");
+    let plain = res.headers()["etag"].to_str().unwrap().to_string();
+
+    // The compressed copy has a tag of its own, which still revalidates
+    let res = warp::test::request().path("/fortunes").header("accept-encoding", "br").reply(&api).await;
+    let compressed = res.headers()["etag"].to_str().unwrap().to_string();
+    assert_eq!(compressed, format!("{}-br\"", plain.trim_end_matches('"')));
+    let res = warp::test::request()
+        .path("/fortunes")
+        .header("accept-encoding", "br")
+        .header("if-none-match", &compressed)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+    let api = compression::wrap(routes(create_default_store(), &test_config(&[])), false);
+    let res = warp::test::request()
+        .path("/fortunes")
+        .header("accept-encoding", "br")
+        .reply(&api)
+        .await;
+    assert!(res.headers().get("content-encoding").is_none());
+}
+
+#[tokio::test]
+async fn create_rejects_oversized_body() {
+    let api = routes(create_default_store(), &test_config(&[("MAX_BODY_BYTES", "64")]));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "77", "message": "x".repeat(100)}))
+        .reply(&api)
+        .await;
+
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body, "request body too large");
+}
+
+#[tokio::test]
+async fn admin_routes_require_the_api_key() {
+    let api = routes(create_default_store(), &test_config(&[("ADMIN_API_KEY", "s3cret")]));
+
+    let res = warp::test::request().path("/admin/stats").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = warp::test::request()
+        .path("/admin/stats")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["fortunes"], 4);
+    assert_eq!(body["redis"], "disabled");
+
+    let api = routes(create_default_store(), &test_config(&[]));
+    let res = warp::test::request()
+        .path("/admin/stats")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn discord_test_posts_the_fortune_of_the_day() {
+    let discord = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/webhook"))
+        .and(body_partial_json(json!({"embeds": [{"title": "Fortune of the day"}]})))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(2)
+        .mount(&discord)
+        .await;
+    let webhook = format!("{}/webhook", discord.uri());
+    let api = routes(
+        create_default_store(),
+        &test_config(&[("ADMIN_API_KEY", "s3cret"), ("DISCORD_WEBHOOK_URL", &webhook)]),
+    );
+    let post = || {
+        warp::test::request()
+            .method("POST")
+            .path("/integrations/discord/test")
+            .header("x-api-key", "s3cret")
+            .reply(&api)
+    };
+
+    let res = post().await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let first: Value = serde_json::from_slice(res.body()).unwrap();
+    // The pick stays the same for the whole day
+    let second: Value = serde_json::from_slice(post().await.body()).unwrap();
+    assert_eq!(first["id"], second["id"]);
+
+    let res = warp::test::request().method("POST").path("/integrations/discord/test").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let api = routes(create_default_store(), &test_config(&[("ADMIN_API_KEY", "s3cret")]));
+    let res = warp::test::request()
+        .method("POST")
+        .path("/integrations/discord/test")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn admin_flush_cache_empties_the_store() {
+    let fortunes = create_default_store();
+    let api = routes(fortunes.clone(), &test_config(&[("ADMIN_API_KEY", "s3cret")]));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/admin/flush-cache")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.body(), "4");
+    assert!(fortunes.read().await.is_empty());
+}
+
+#[tokio::test]
+async fn anonymous_submissions_wait_for_moderation() {
+    let api = routes(
+        create_default_store(),
+        &test_config(&[("ADMIN_API_KEY", "s3cret"), ("MODERATION", "true")]),
+    );
+    let submit = |id: &'static str, key: Option<&'static str>| {
+        let mut request = warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"id": id, "message": format!("Fortune {}", id), "status": "approved"}));
+        if let Some(key) = key {
+            request = request.header("x-api-key", key);
+        }
+        request.reply(&api)
+    };
+    let res = submit("anon", None).await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["status"], "pending");
+    assert_eq!(submit("admin", Some("s3cret")).await.status(), StatusCode::OK);
+    submit("spam", None).await;
+
+    let listed = || async {
+        let res = warp::test::request().path("/fortunes").reply(&api).await;
+        let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+        body.iter().map(|f| f["id"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+    };
+    assert!(listed().await.contains(&"admin".to_string()));
+    assert!(!listed().await.contains(&"anon".to_string()));
+
+    let res = warp::test::request()
+        .path("/admin/moderation")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    let pending: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(pending.len(), 2);
+
+    for (id, decision) in [("anon", "approve"), ("spam", "reject")] {
+        let res = warp::test::request()
+            .method("POST")
+            .path(&format!("/admin/fortunes/{}/{}", id, decision))
+            .header("x-api-key", "s3cret")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    assert!(listed().await.contains(&"anon".to_string()));
+    assert!(!listed().await.contains(&"spam".to_string()));
+
+    let res = warp::test::request()
+        .path("/admin/stats")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    let stats: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(stats["moderation"], json!({"pending": 0, "approved": 6, "rejected": 1, "unverified": 0}));
+}
+
+#[tokio::test]
+async fn soft_deleted_fortunes_can_be_restored() {
+    let api = routes(create_default_store(), &test_config(&[("SOFT_DELETE", "true")]));
+
+    let res = warp::test::request().method("DELETE").path("/fortunes/2").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = warp::test::request().path("/fortunes/2").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = warp::test::request().path("/fortunes/trash").reply(&api).await;
+    let trash: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0]["id"], "2");
+    assert!(trash[0]["deleted_at"].as_u64().unwrap() > 0);
+
+    let res = warp::test::request().method("POST").path("/fortunes/2/restore").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = warp::test::request().path("/fortunes/2").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request().method("POST").path("/fortunes/2/restore").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let res = warp::test::request().method("POST").path("/fortunes/99/restore").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn trash_routes_are_hidden_without_soft_delete() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().path("/fortunes/trash").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = warp::test::request().method("POST").path("/fortunes/1/restore").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn timestamps_are_set_by_the_server() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let before = fortunes::now_secs();
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "t1", "message": "Time flies.", "created_at": 1, "updated_at": 1}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let created: Value = serde_json::from_slice(res.body()).unwrap();
+    let created_at = created["created_at"].as_u64().unwrap();
+    assert!(created_at >= before);
+    assert_eq!(created["updated_at"], created["created_at"]);
+
+    // Replacing the fortune keeps its creation time
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes?force=true")
+        .json(&json!({"id": "t1", "message": "Time flies like an arrow."}))
+        .reply(&api)
+        .await;
+    let updated: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(updated["created_at"].as_u64(), Some(created_at));
+    assert!(updated["updated_at"].as_u64().unwrap() >= created_at);
+
+    // The built-in fortunes predate timestamps
+    let res = warp::test::request().path("/fortunes/1").reply(&api).await;
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert!(body.get("created_at").is_none());
+}
+
+#[tokio::test]
+async fn list_sorts_by_newest_and_filters_by_creation_time() {
+    let store = create_default_store();
+    for (id, created_at) in [("a", 100), ("b", 300), ("c", 200)] {
+        let fortune = Fortune {
+            id: id.to_string(),
+            message: format!("Fortune {}", id),
+            created_at: Some(created_at),
+            ..Default::default()
+        };
+        store.write().await.insert(id.to_string(), fortune);
+    }
+    let api = routes(store, &test_config(&[]));
+
+    let ids = |body: &[u8]| -> Vec<String> {
+        let body: Vec<Value> = serde_json::from_slice(body).unwrap();
+        body.iter().map(|f| f["id"].as_str().unwrap().to_string()).collect()
+    };
+
+    let res = warp::test::request().path("/fortunes?sort=newest").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(ids(res.body()), ["b", "c", "a", "1", "2", "3", "4"]);
+
+    let res = warp::test::request().path("/fortunes?sort=newest&since=200").reply(&api).await;
+    assert_eq!(ids(res.body()), ["b", "c"]);
+
+    let res = warp::test::request().path("/fortunes?sort=oldest").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn list_order_is_stable_and_selectable() {
+    let store = create_default_store();
+    store.write().await.clear();
+    for (id, message, created_at) in [("10", "Apple", Some(200)), ("2", "banana", Some(200)), ("x", "cherry", Some(100)), ("9", "apple  pie", None)] {
+        let fortune = Fortune { id: id.to_string(), message: message.to_string(), created_at, ..Default::default() };
+        store.write().await.insert(id.to_string(), fortune);
+    }
+    let api = routes(store.clone(), &test_config(&[]));
+    let list = |query: &'static str| {
+        let api = api.clone();
+        async move {
+            let res = warp::test::request().path(&format!("/fortunes?{}", query)).reply(&api).await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", query);
+            let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+            body.iter().map(|f| f["id"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        }
+    };
+
+    // Numeric ids by value, then the rest
+    assert_eq!(list("").await, ["2", "9", "10", "x"]);
+    assert_eq!(list("sort=id&order=desc").await, ["x", "10", "9", "2"]);
+    assert_eq!(list("sort=message").await, ["10", "9", "2", "x"]);
+    assert_eq!(list("sort=message&order=desc&max_len=6").await, ["x", "2", "10"]);
+    // Ties go to the lower id either way round, undated fortunes come last
+    assert_eq!(list("sort=created_at").await, ["x", "2", "10", "9"]);
+    assert_eq!(list("sort=created_at&order=desc").await, ["2", "10", "x", "9"]);
+    assert_eq!(list("sort=newest").await, ["2", "10", "x", "9"]);
+    assert_eq!(list("sort=newest&order=asc").await, ["x", "2", "10", "9"]);
+
+    // The order follows changes to the fortunes
+    let fortune = Fortune { id: "10".to_string(), message: "Zebra".to_string(), created_at: Some(50), ..Default::default() };
+    store.write().await.insert("10".to_string(), fortune);
+    store.write().await.remove("x");
+    assert_eq!(list("sort=message").await, ["9", "2", "10"]);
+    assert_eq!(list("sort=created_at").await, ["10", "2", "9"]);
+
+    for query in ["sort=views", "order=up", "ids=2&order=asc"] {
+        let res = warp::test::request().path(&format!("/fortunes?{}", query)).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn fortunes_can_be_limited_by_length() {
+    let store = create_default_store();
+    for (id, message) in [
+        ("short", "Be brief."),
+        ("emoji", "👨‍👩‍👧‍👦 First."),
+        ("long", "A journey of a thousand miles begins with a single step."),
+    ] {
+        let fortune = Fortune {
+            id: id.to_string(),
+            message: message.to_string(),
+            ..Default::default()
+        };
+        store.write().await.insert(id.to_string(), fortune);
+    }
+    let api = routes(store.clone(), &test_config(&[]));
+
+    let ids = |body: &[u8]| -> HashSet<String> {
+        let body: Vec<Value> = serde_json::from_slice(body).unwrap();
+        body.iter().map(|f| f["id"].as_str().unwrap().to_string()).collect()
+    };
+    // The family emoji is one character, so "👨‍👩‍👧‍👦 First." is 8 long
+    let res = warp::test::request().path("/fortunes?max_len=9").reply(&api).await;
+    assert_eq!(ids(res.body()), HashSet::from(["short".to_string(), "emoji".to_string()]));
+
+    for _ in 0..20 {
+        let res = warp::test::request().path("/fortunes/random?max_len=8").reply(&api).await;
+        let fortune: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(fortune["id"], "emoji");
+    }
+
+    // A fortune that grows past the limit drops out of it
+    let mut grown = store.read().await.get("short").cloned().unwrap();
+    grown.message = "Be brief, but not that brief.".to_string();
+    store.write().await.insert(grown.id.clone(), grown);
+    let res = warp::test::request().path("/fortunes?max_len=9").reply(&api).await;
+    assert_eq!(ids(res.body()), HashSet::from(["emoji".to_string()]));
+
+    let res = warp::test::request().path("/fortunes/random?max_len=3").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = warp::test::request().path("/fortunes/random?max_len=short").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn length_limited_draws_reach_every_bucket_that_fits() {
+    let mut store = Fortunes::new();
+    for len in 1..=5 {
+        for copy in ["a", "b"] {
+            let id = format!("{}{}", copy, len);
+            let message = copy.repeat(len);
+            store.insert(id.clone(), Fortune { id, message, ..Default::default() });
+        }
+    }
+    let rng = &mut rand::thread_rng();
+
+    let (picked, exhausted) = store.random_distinct(&[], Some(3), 10, &HashSet::new(), rng);
+    let picked: HashSet<&str> = picked.iter().map(|id| id.as_str()).collect();
+    assert_eq!(picked, HashSet::from(["a1", "b1", "a2", "b2", "a3", "b3"]));
+    assert!(!exhausted);
+
+    let drawn: HashSet<String> = (0..500).map(|_| store.random_in(&[], Some(100), rng).unwrap().clone()).collect();
+    assert_eq!(drawn.len(), 10);
+    assert!((0..50).all(|_| store[store.random_in(&[], Some(1), rng).unwrap()].message.len() == 1));
+}
+
+#[test]
+fn length_limited_lists_keep_the_list_order() {
+    let mut store = Fortunes::new();
+    for (id, message, created_at) in [
+        ("10", "bb", Some(5)),
+        ("9", "Aa", Some(7)),
+        ("x", "cc", None),
+        ("2", "a long message", Some(1)),
+        ("3", "b", Some(5)),
+        ("a", "dd", None),
+        ("1", "ab", Some(9)),
+    ] {
+        store.insert(id.to_string(), Fortune {
+            id: id.to_string(),
+            message: message.to_string(),
+            created_at,
+            ..Default::default()
+        });
+    }
+
+    for sort in [Sort::Id, Sort::Message, Sort::CreatedAt, Sort::Newest] {
+        for descending in [false, true] {
+            let expected: Vec<&str> = store.sorted(sort, descending).filter(|id| store[id].message.len() <= 2).collect();
+            assert_eq!(store.sorted_within(2, sort, descending), expected, "{:?} descending {}", sort, descending);
+        }
+    }
+    assert_eq!(store.sorted_within(2, Sort::CreatedAt, true), ["1", "9", "3", "10", "a", "x"]);
+}
+
+#[tokio::test]
+async fn rotations_alternate_tags_by_weight_per_session() {
+    let store = create_default_store();
+    let api = routes(store.clone(), &test_config(&[]));
+    for (id, tag) in [("zen-1", "Zen"), ("zen-2", "zen"), ("zen-3", " zen "), ("prog-1", "programming"), ("prog-2", "programming")] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"id": id, "message": format!("Fortune {}", id), "tags": [tag]}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let fortune: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(fortune["tags"], json!([tag.trim().to_lowercase()]));
+    }
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"message": "Bad tag", "tags": ["not a tag"]}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let next = |session: &'static str| {
+        let api = api.clone();
+        async move {
+            let path = format!("/fortunes/rotation?tags=zen:3,programming:1&session={}", session);
+            let res = warp::test::request().path(&path).reply(&api).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let fortune: Value = serde_json::from_slice(res.body()).unwrap();
+            fortune["id"].as_str().unwrap().to_string()
+        }
+    };
+    let mut served = Vec::new();
+    for _ in 0..8 {
+        served.push(next("rotation-sign-a").await);
+    }
+    assert_eq!(served, ["zen-1", "zen-2", "prog-1", "zen-3", "zen-1", "zen-2", "prog-2", "zen-3"]);
+    // Each session keeps its own place
+    assert_eq!(next("rotation-sign-b").await, "zen-1");
+    assert_eq!(next("rotation-sign-a").await, "zen-1");
+
+    let res = warp::test::request().path("/fortunes/rotation?tags=zen:3").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    for tags in ["zen:0", "zen,zen", "not%20a%20tag"] {
+        let path = format!("/fortunes/rotation?tags={}&session=rotation-sign-c", tags);
+        let res = warp::test::request().path(&path).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", tags);
+    }
+    let res = warp::test::request().path("/fortunes/rotation?tags=unused&session=rotation-sign-c").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn writes_need_a_signature_when_a_signing_secret_is_set() {
+    let secret = "a-shared-secret-of-some-length";
+    let config = test_config(&[("REQUEST_SIGNING_SECRET", secret), ("ADMIN_API_KEY", "admin-key")]);
+    let api = routes(create_default_store(), &config);
+    let signed = |path: &str, body: &str, timestamp: u64| {
+        let content_hash = signing::content_hash(body.as_bytes());
+        let signature = signing::sign(secret.as_bytes(), &signing::canonical("POST", path, timestamp, &content_hash));
+        warp::test::request()
+            .method("POST")
+            .path(path)
+            .header("content-type", "application/json")
+            .header(signing::TIMESTAMP_HEADER, timestamp.to_string())
+            .header(signing::CONTENT_HEADER, content_hash)
+            .header(signing::SIGNATURE_HEADER, signature)
+            .body(body)
+    };
+    let now = fortunes::now_secs();
+    let body = r#"{"id": "signed", "message": "Signed and sealed."}"#;
+
+    // Reads stay open
+    let res = warp::test::request().path("/fortunes/1").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "unsigned", "message": "Anyone can write?"}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = signed("/fortunes", body, now).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // A stale timestamp, a signature for another path or a swapped body is refused
+    let res = signed("/fortunes", body, now - 3600).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = signed("/fortunes?force=true", body, now).path("/fortunes").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = signed("/fortunes", body, now)
+        .body(r#"{"id": "swapped", "message": "Not what was signed."}"#)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // Admin tooling can use the admin key instead
+    let res = warp::test::request()
+        .method("DELETE")
+        .path("/fortunes/signed")
+        .header("x-api-key", "admin-key")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn large_lists_stream_as_json_or_ndjson() {
+    let store = create_default_store();
+    for i in 0..600 {
+        let fortune = Fortune {
+            id: format!("bulk-{:03}", i),
+            message: format!("Bulk fortune {}", i),
+            ..Default::default()
+        };
+        store.write().await.insert(fortune.id.clone(), fortune);
+    }
+    let api = routes(store, &test_config(&[]));
+
+    let res = warp::test::request().path("/fortunes").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "application/json");
+    let body: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body.len(), 604);
+
+    let res = warp::test::request().path("/fortunes?format=ndjson&author=nobody").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.body().is_empty());
+
+    let res = warp::test::request().path("/fortunes?format=ndjson").reply(&api).await;
+    assert_eq!(res.headers()["content-type"], "application/x-ndjson");
+    let lines: Vec<Value> = std::str::from_utf8(res.body())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines, body);
+
+    let res = warp::test::request().path("/fortunes?format=xml").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn read_only_rejects_mutations() {
+    let api = routes(create_default_store(), &test_config(&[("READ_ONLY", "true"), ("ADMIN_API_KEY", "secret")]));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "9", "message": "Not today."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(res.body(), "\"the fortune store is read-only\"");
+
+    let res = warp::test::request().method("DELETE").path("/fortunes/1").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = warp::test::request()
+        .method("POST")
+        .path("/admin/fortunes/1/reject")
+        .header("x-api-key", "secret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // Reads still work, and the mode is reported
+    let res = warp::test::request().path("/fortunes/1").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = warp::test::request().path("/healthz").reply(&api).await;
+    let health: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["read_only"], true);
+    let res = warp::test::request().path("/admin/stats").header("x-api-key", "secret").reply(&api).await;
+    let stats: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(stats["read_only"], true);
+    // Leader election only starts with the server
+    assert_eq!(stats["leader"], Value::Null);
+}
+
+#[tokio::test]
+async fn health_reports_version_and_components_when_verbose() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request().path("/healthz").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let health: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["version"], env!("CARGO_PKG_VERSION"));
+    assert!(health["commit"].is_string());
+    assert!(health.get("components").is_none());
+
+    let res = warp::test::request().path("/healthz?verbose=true").reply(&api).await;
+    let health: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(
+        health["components"],
+        json!({"redis": "disabled", "database": "disabled", "store": {"count": 4}})
+    );
+}
+
+#[tokio::test]
+async fn random_can_be_drawn_as_ascii_art() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({
+            "id": "eo1",
+            "lang": "eo",
+            "author": "Anon",
+            "message": "Never trust a cookie that talks back. It has seen things you would not believe."
+        }))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request().path("/fortunes/random?lang=eo&format=box").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "text/plain; charset=utf-8");
+    assert_eq!(
+        res.body(),
+        concat!(
+            "+------------------------------------------+\n",
+            "| Never trust a cookie that talks back. It |\n",
+            "| has seen things you would not believe.   |\n",
+            "|                                  -- Anon |\n",
+            "+------------------------------------------+\n",
+        )
+    );
+
+    let res = warp::test::request().path("/fortunes/random?lang=eo&format=cowsay").reply(&api).await;
+    let cow = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(cow.starts_with(concat!(
+        " __________________________________________\n",
+        "/ Never trust a cookie that talks back. It \\\n",
+        "| has seen things you would not believe.   |\n",
+        "\\                                  -- Anon /\n",
+        " ------------------------------------------\n",
+        "        \\   ^__^\n",
+    )));
+
+    let res = warp::test::request().path("/fortunes/random?format=sheep").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn named_collections_are_kept_apart() {
+    let api = routes(create_default_store(), &test_config(&[("COLLECTIONS", "team-a:s3cret,team-b")]));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/collections/team-a/fortunes")
+        .json(&json!({"message": "Team A only."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/collections/team-a/fortunes")
+        .header("x-api-key", "s3cret")
+        .json(&json!({"message": "Team A only."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let created: Value = serde_json::from_slice(res.body()).unwrap();
+
+    // The prefix and the header address the same collection
+    let res = warp::test::request().path("/fortunes").header("x-collection", "team-a").reply(&api).await;
+    let listed: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["message"], "Team A only.");
+    let res = warp::test::request()
+        .path(&format!("/collections/team-a/fortunes/{}", created["id"].as_str().unwrap()))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Without a key, team-b takes writes from anyone
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .header("x-collection", "team-b")
+        .json(&json!({"message": "Team B only."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // The default collection is untouched
+    for path in ["/fortunes", "/collections/default/fortunes"] {
+        let res = warp::test::request().path(path).reply(&api).await;
+        let listed: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(listed.len(), 4);
+    }
+
+    let res = warp::test::request().path("/collections/team-c/fortunes").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = warp::test::request().path("/fortunes/1").header("x-collection", "team-c").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_slice::<Value>(res.body()).unwrap(), "unknown collection");
+}
+
+#[test]
+fn collection_names_are_validated() {
+    let err = |list: &str| collections::parse(list).unwrap_err();
+    assert!(err("Team A").contains("invalid collection name"));
+    assert!(err("a,a:key").contains("listed twice"));
+    assert!(err("default").contains("without a name"));
+    assert_eq!(
+        collections::parse("a:key, b").unwrap(),
+        [("a".to_string(), Some("key".to_string())), ("b".to_string(), None)]
+    );
+}
+
+#[test]
+fn config_files_fill_in_what_flags_and_the_environment_leave_out() {
+    let path = std::env::temp_dir().join(format!("fortune-backend-{}.env", std::process::id()));
+    std::fs::write(
+        &path,
+        "# staging\nexport ADMIN_API_KEY=\"from-the-file\"\nBACKEND_PORT=9050\n\nCOLLECTIONS='team-a:s3cret,team-b'\n",
+    )
+    .unwrap();
+
+    let config = Config::load(Some(&path), &[("BACKEND_PORT", "9100".to_string())]).unwrap();
+    assert_eq!(config.backend_port, 9100);
+    assert_eq!(config.admin_api_key.as_deref(), Some("from-the-file"));
+
+    let shown = config.redacted();
+    assert_eq!(shown.admin_api_key.as_deref(), Some("<redacted>"));
+    assert_eq!(shown.collections.as_deref(), Some("team-a:<redacted>,team-b"));
+    assert!(!format!("{:?}", shown).contains("s3cret"));
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn duplicates_are_reported_in_clusters() {
+    let store = create_default_store();
+    for (id, message) in [
+        ("10", "A new voyage will fill your life with untold memories!"),
+        ("11", "a new VOYAGE will  fill your life with untold memories."),
+        ("12", "Every cloud has a silver lining."),
+        ("13", "every cloud has a silver lining"),
+        ("14", "Every cloud has a silver lining, they say."),
+        ("15", "Look  before you leap."),
+        ("16", "look before you leap."),
+    ] {
+        store.write().await.insert(id.to_string(), Fortune {
+            id: id.to_string(),
+            message: message.to_string(),
+            ..Default::default()
+        });
+    }
+    let api = routes(store, &test_config(&[("ADMIN_API_KEY", "s3cret")]));
+
+    let res = warp::test::request()
+        .path("/admin/duplicates")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let clusters: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    let summary: Vec<(&str, Vec<&str>)> = clusters
+        .iter()
+        .map(|c| {
+            let ids = c["fortunes"].as_array().unwrap().iter().map(|f| f["id"].as_str().unwrap()).collect();
+            (c["kind"].as_str().unwrap(), ids)
+        })
+        .collect();
+    // Punctuation and case aside, 10 and 11 are the same words as 1
+    assert_eq!(
+        summary,
+        [("near", vec!["1", "10", "11"]), ("near", vec!["12", "13", "14"]), ("exact", vec!["15", "16"])]
+    );
+    assert!(clusters[1]["similarity"].as_f64().unwrap() < 1.0);
+
+    // At 1 only messages with the same words are joined, which leaves 14 out
+    let res = warp::test::request()
+        .path("/admin/duplicates?threshold=1")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    let clusters: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(clusters.len(), 3);
+    assert_eq!(clusters[1]["fortunes"].as_array().unwrap().len(), 2);
+
+    let res = warp::test::request()
+        .path("/admin/duplicates?threshold=2")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn response_headers_are_added_without_replacing_the_handlers_own() {
+    let api = routes(
+        create_default_store(),
+        &test_config(&[("RESPONSE_HEADERS", r#"{"X-Frame-Options":"DENY","Content-Type":"text/plain"}"#)]),
+    );
+
+    let res = warp::test::request().method("GET").path("/fortunes/4").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-frame-options"], "DENY");
+    assert_eq!(res.headers()["content-type"], "application/json");
+
+    let res = warp::test::request().method("GET").path("/fortunes/404").reply(&api).await;
+    assert_eq!(res.headers()["x-frame-options"], "DENY");
+
+    for bad in [r#"["DENY"]"#, r#"{"bad header":"x"}"#, r#"{"X-Ok":"line\nbreak"}"#] {
+        assert!(Config::load(None, &[("RESPONSE_HEADERS", bad.to_string())]).is_err(), "{}", bad);
+    }
+}
+
+// A port nothing is listening on, for servers that bind their own
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+#[tokio::test]
+async fn the_tuned_listener_serves_http2_and_knows_the_client() {
+    use fortune_backend::{client_ip, server};
+    use std::net::{IpAddr, SocketAddr};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use warp::{Filter, Reply};
+
+    let serve = |extra: &[(&str, &str)]| {
+        let config = test_config(extra);
+        let whoami = warp::path("whoami")
+            .and(client_ip::filter(TrustedProxies::default()))
+            .map(|ip: Option<IpAddr>| format!("{:?}", ip).into_response());
+        let api = whoami.or(compression::wrap(routes(create_default_store(), &config), false)).unify().boxed();
+        let addr = SocketAddr::from(([127, 0, 0, 1], free_port()));
+        tokio::spawn(server::run(api, server::Listen::Tcp(addr), config.server_tuning()));
+        format!("http://{}", addr)
+    };
+    let http1 = reqwest::Client::new();
+    let http2 = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+
+    let url = serve(&[("KEEP_ALIVE_TIMEOUT_SECS", "30"), ("HTTP2_MAX_CONCURRENT_STREAMS", "8")]);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let res = http1.get(format!("{}/fortunes/4", url)).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.version(), reqwest::Version::HTTP_11);
+    let res = http2.get(format!("{}/fortunes/4", url)).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.version(), reqwest::Version::HTTP_2);
+    let res = http1.get(format!("{}/whoami", url)).send().await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "Some(127.0.0.1)", "the client address reaches the filters");
+
+    let url = serve(&[("HTTP2", "false"), ("HTTP_KEEP_ALIVE", "false"), ("TCP_NODELAY", "false")]);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let res = http1.get(format!("{}/fortunes/4", url)).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    // Without keep-alive the server hangs up after the first response
+    let mut conn = tokio::net::TcpStream::connect(url.trim_start_matches("http://")).await.unwrap();
+    conn.write_all(b"GET /fortunes/4 HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = Vec::new();
+    tokio::time::timeout(std::time::Duration::from_secs(5), conn.read_to_end(&mut response))
+        .await
+        .expect("the connection is closed")
+        .unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+    assert!(http2.get(format!("{}/fortunes/4", url)).send().await.is_err(), "HTTP/2 is turned off");
+}
+
+#[tokio::test]
+async fn the_api_can_be_served_on_a_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let path = std::env::temp_dir().join(format!("fortune-backend-{}.sock", std::process::id()));
+    // A socket left behind by an earlier run is replaced
+    drop(std::os::unix::net::UnixListener::bind(&path));
+    let socket = path.to_str().unwrap();
+    let config = test_config(&[("UNIX_SOCKET", socket), ("UNIX_SOCKET_MODE", "600")]);
+    let listen = config.listen();
+    assert_eq!(listen.to_string(), format!("unix:{}", socket));
+    let api = compression::wrap(routes(create_default_store(), &config), false);
+    tokio::spawn(fortune_backend::server::run(api, listen, config.server_tuning()));
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    let mut conn = tokio::net::UnixStream::connect(&path).await.unwrap();
+    conn.write_all(b"GET /fortunes/4 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("It ain't over till it's EOF."));
+    let _ = std::fs::remove_file(&path);
+
+    for bad in [vec![("UNIX_SOCKET_MODE", "660")], vec![("UNIX_SOCKET", socket), ("UNIX_SOCKET_MODE", "rw-rw----")]] {
+        let overrides: Vec<_> = bad.iter().map(|(k, v)| (*k, v.to_string())).collect();
+        assert!(Config::load(None, &overrides).is_err(), "{:?}", bad);
+    }
+}
+
+#[tokio::test]
+async fn several_fortunes_come_back_in_one_response() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let ids = |body: &[u8]| -> Vec<String> {
+        let fortunes: Vec<Value> = serde_json::from_slice(body).unwrap();
+        fortunes.iter().map(|f| f["id"].as_str().unwrap().to_string()).collect()
+    };
+
+    let res = warp::test::request().path("/fortunes/random?count=3").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let drawn = ids(res.body());
+    assert_eq!(drawn.len(), 3);
+    assert_eq!(drawn.iter().collect::<HashSet<_>>().len(), 3, "no duplicates: {:?}", drawn);
+
+    // Asking for more than there are returns them all, once each
+    let res = warp::test::request().path("/fortunes/random?count=10").reply(&api).await;
+    let mut drawn = ids(res.body());
+    drawn.sort();
+    assert_eq!(drawn, ["1", "2", "3", "4"]);
+
+    for query in ["count=0", "count=101", "count=many", "count=2&format=box"] {
+        let res = warp::test::request().path(&format!("/fortunes/random?{}", query)).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+
+    // A session is served the fortunes it has not seen before any repeats
+    let res = warp::test::request().path("/fortunes/random?count=3&session=batch").reply(&api).await;
+    let first = ids(res.body());
+    let res = warp::test::request().path("/fortunes/random?count=2&session=batch").reply(&api).await;
+    let second = ids(res.body());
+    assert_eq!(second.len(), 2);
+    let unseen = ["1", "2", "3", "4"].into_iter().find(|id| !first.iter().any(|f| f == id)).unwrap();
+    assert!(second.iter().any(|id| id == unseen), "{:?} then {:?}", first, second);
+
+    let res = warp::test::request().path("/fortunes?ids=4,%201,4,nope").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(ids(res.body()), ["4", "1"]);
+
+    let many = (0..101).map(|i| i.to_string()).collect::<Vec<_>>().join(",");
+    for query in [format!("ids={}", many), "ids=1&author=Anonymous".to_string()] {
+        let res = warp::test::request().path(&format!("/fortunes?{}", query)).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn the_flight_recorder_keeps_the_last_requests() {
+    let api = routes(
+        create_default_store(),
+        &test_config(&[("ADMIN_API_KEY", "s3cret"), ("FLIGHT_RECORDER_SIZE", "2"), ("FLIGHT_RECORDER_BODY_BYTES", "16")]),
+    );
+
+    warp::test::request().path("/fortunes/1").reply(&api).await;
+    warp::test::request().path("/fortunes/nope?lang=en").header("x-request-id", "abc-123").reply(&api).await;
+    // The listener gives every request a slot for its body
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .extension(recorder::RequestBody::default())
+        .json(&json!({"message": "A fortune long enough to be cut short"}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request().path("/admin/recent-requests").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = warp::test::request().method("DELETE").path("/admin/recent-requests").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(res.headers()["allow"], "GET, HEAD, OPTIONS");
+    let res = warp::test::request()
+        .path("/admin/recent-requests")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let recent: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    // Only the last two, oldest first, and not the reads of the recorder
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[0]["method"], "GET");
+    assert_eq!(recent[0]["path"], "/fortunes/nope?lang=en");
+    assert_eq!(recent[0]["status"], 404);
+    assert_eq!(recent[0]["request_id"], "abc-123");
+    assert_eq!(recent[0]["request_body"], Value::Null);
+    assert_eq!(recent[1]["method"], "POST");
+    assert_eq!(recent[1]["status"], 200);
+    assert!(recent[1]["latency_ms"].is_u64());
+    let sent = &recent[1]["request_body"];
+    assert_eq!(sent["text"], r#"{"message":"A fo"#);
+    assert_eq!(sent["truncated"], true);
+    assert_eq!(sent["bytes"], 51);
+    assert!(recent[1]["response_body"]["text"].as_str().unwrap().starts_with('{'));
+
+    let api = routes(create_default_store(), &test_config(&[("ADMIN_API_KEY", "s3cret")]));
+    let res = warp::test::request()
+        .path("/admin/recent-requests")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn submissions_wait_for_their_verification_link() {
+    let api = routes(
+        create_default_store(),
+        &test_config(&[("ADMIN_API_KEY", "s3cret"), ("SUBMISSION_VERIFICATION", "true")]),
+    );
+    let submit = |message: &str| warp::test::request().method("POST").path("/fortunes").json(&json!({"message": message}));
+
+    let res = submit("Check your inbox, then your fortune.").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["status"], "unverified");
+    let id = body["id"].as_str().unwrap().to_string();
+    let token = body["verification_token"].as_str().unwrap().to_string();
+
+    let listed = |body: &[u8]| serde_json::from_slice::<Vec<Value>>(body).unwrap().iter().any(|f| f["id"] == id.as_str());
+    let res = warp::test::request().path("/fortunes").reply(&api).await;
+    assert!(!listed(res.body()));
+
+    // Nothing gets around it without the admin key
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes/batch")
+        .json(&json!([{"message": "Many at once"}]))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = submit("Trusted").header("x-api-key", "s3cret").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Opening the link only shows the confirm page, as often as it is opened
+    let verify_path = format!("/verify/{}", token);
+    for _ in 0..2 {
+        let res = warp::test::request().path(&verify_path).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+        let page = String::from_utf8_lossy(res.body());
+        assert!(page.contains("Check your inbox, then your fortune."));
+        assert!(page.contains(r#"<form method="post">"#));
+    }
+    let res = warp::test::request().path("/fortunes").reply(&api).await;
+    assert!(!listed(res.body()));
+
+    let res = warp::test::request().method("POST").path(&verify_path).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["status"], "approved");
+    let res = warp::test::request().path("/fortunes").reply(&api).await;
+    assert!(listed(res.body()));
+    // The token works once
+    let res = warp::test::request().method("POST").path(&verify_path).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = warp::test::request().path(&verify_path).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = warp::test::request().method("DELETE").path(&verify_path).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(res.headers()["allow"], "GET, POST, OPTIONS");
+
+    // With a mail relay the link goes to the submitter's address only
+    let relay = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/send"))
+        .and(body_partial_json(json!({"to": "someone@example.com", "subject": "Confirm your fortune"})))
+        .respond_with(ResponseTemplate::new(202))
+        .expect(1)
+        .mount(&relay)
+        .await;
+    let mail_url = format!("{}/send", relay.uri());
+    let api = routes(
+        create_default_store(),
+        &test_config(&[
+            ("SUBMISSION_VERIFICATION", "true"),
+            ("VERIFICATION_MAIL_URL", &mail_url),
+            ("VERIFICATION_LINK_BASE", "https://fortunes.example.com/"),
+        ]),
+    );
+    let res = submit("No address given").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = submit("Mailed to me").header("x-submitter-email", "someone@example.com").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["verification_token"], Value::Null);
+    let sent: Value = serde_json::from_slice(&relay.received_requests().await.unwrap()[0].body).unwrap();
+    let text = sent["text"].as_str().unwrap();
+    let token = text.split("https://fortunes.example.com/verify/").nth(1).unwrap().split_whitespace().next().unwrap();
+    let res = warp::test::request().path(&format!("/verify/{}", token)).reply(&api).await;
+    assert!(String::from_utf8_lossy(res.body()).contains("Mailed to me"));
+    let res = warp::test::request().method("POST").path(&format!("/verify/{}", token)).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn api_keys_are_held_to_their_daily_quotas() {
+    let api = routes(
+        create_default_store(),
+        &test_config(&[("ADMIN_API_KEY", "s3cret"), ("API_KEYS", "team-a:k3y-a:4:1,team-b:k3y-b")]),
+    );
+
+    let res = warp::test::request().path("/fortunes/1").header("x-api-key", "k3y-a").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-ratelimit-limit"], "4");
+    assert_eq!(res.headers()["x-ratelimit-remaining"], "3");
+    assert!(res.headers().contains_key("x-ratelimit-reset"));
+    assert!(!res.headers().contains_key("x-ratelimit-creates-remaining"));
+
+    let create = |message: &str| {
+        warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .header("x-api-key", "k3y-a")
+            .json(&json!({ "message": message }))
+    };
+    let res = create("Quotas keep the peace").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-ratelimit-remaining"], "2");
+    assert_eq!(res.headers()["x-ratelimit-creates-limit"], "1");
+    assert_eq!(res.headers()["x-ratelimit-creates-remaining"], "0");
+    // Out of creates, and the refused request does not count
+    let res = create("One fortune too many").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()["x-ratelimit-remaining"], "2");
+
+    for remaining in ["1", "0"] {
+        let res = warp::test::request().path("/fortunes/1").header("x-api-key", "k3y-a").reply(&api).await;
+        assert_eq!(res.headers()["x-ratelimit-remaining"], remaining);
+    }
+    let res = warp::test::request().path("/fortunes/1").header("x-api-key", "k3y-a").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()["x-ratelimit-remaining"], "0");
+
+    // Unlimited keys and requests without a team key are not held back
+    let res = warp::test::request().path("/fortunes/1").header("x-api-key", "k3y-b").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!res.headers().contains_key("x-ratelimit-remaining"));
+    let res = warp::test::request().path("/fortunes/1").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request().path("/admin/keys").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = warp::test::request().path("/admin/keys?days=2").header("x-api-key", "s3cret").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let keys: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys[0]["name"], "team-a");
+    assert_eq!(keys[0]["requests_per_day"], 4);
+    assert_eq!(keys[0]["creates_per_day"], 1);
+    assert_eq!(keys[0]["days"].as_array().unwrap().len(), 2);
+    assert_eq!(keys[0]["days"][1]["requests"], 4);
+    assert_eq!(keys[0]["days"][1]["creates"], 1);
+    assert_eq!(keys[1]["requests_per_day"], Value::Null);
+    assert!(!res.body().windows(5).any(|w| w == b"k3y-a"));
+
+    for bad in ["team-a:k3y:many", "Team A:k3y", "team-a:", "team-a:one,team-a:two", "team-a:s3cret"] {
+        let overrides = [("ADMIN_API_KEY", "s3cret".to_string()), ("API_KEYS", bad.to_string())];
+        assert!(Config::load(None, &overrides).is_err(), "{}", bad);
+    }
+}
+
+#[tokio::test]
+async fn rejected_creates_give_their_create_back() {
+    let api = routes(create_default_store(), &test_config(&[("API_KEYS", "team-a:k3y-a:10:2")]));
+    let create = |body: &str| {
+        warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .header("x-api-key", "k3y-a")
+            .header("content-type", "application/json")
+            .body(body)
+    };
+
+    let res = create("{not json").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = create(r#"{"message":"Counted once."}"#).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-ratelimit-creates-remaining"], "1");
+}
+
+#[test]
+fn log_level_silences_the_lines_below_it() {
+    assert!(Config::load(None, &[("LOG_LEVEL", "verbose".to_string())]).is_err());
+    let config = Config::load(None, &[("LOG_LEVEL", "warn".to_string())]).unwrap();
+
+    log::set_level(config.log_level.parse().unwrap());
+    assert!(log::enabled(log::Level::Error) && log::enabled(log::Level::Warn));
+    assert!(!log::enabled(log::Level::Info) && !log::enabled(log::Level::Debug));
+    log::set_level(log::Level::Info);
+}
+
+// A throwaway 2048-bit RSA key (PKCS#1 DER) for the RS256 tokens
+const TEST_RSA_KEY: &str = concat!(
+    "MIIEowIBAAKCAQEAwo/M8KcNAunPzp4qxHMeFVDNU0lEzfCEYuAP4vxkkr7EiUn04OYkdcPIhnABigHhzUrEyF3k8L9JF3KF+a3u",
+    "WMc1xPqKZh8awE9v1fQHsJUPf1/qZpXOV6MbrnU+DLJvUOTG3FKUr2j7OiTlcWn+0bSJlX0dcz4nh/kFO4yYPwgy/V8+3jsjbWvJ",
+    "Qir2jDSte0/X+9eF8ND18q/Wk3653qBad6PDk2K7nB51rHUtKVt9nuQmaGs/uq2Ddmh7+Wnxt77RS97dpN8XLtHEZ0GFYT3Rya7Q",
+    "KxXbOvo1bxqyQ7XgeSveLGPnrgEFCJD3WVUSgENOqy5zMpJKVyFI/OVBnQIDAQABAoIBABZg19+uNy7omcBYLO8flow5GQTFXqaZ",
+    "TYDZ6Lng8eh3DMAFw6bCkdsBw+TCqUqoYMJuISgytIyEU/HgeEx5lvqbHzIi5xxL45XPDTv8r42992RdMebOwEcQObTdQ1/OfkM9",
+    "rSnWTcfHOW48pC9hU6/nJhQNqf+BXKalG9b3muNU6Hc4lPez+Zjh1hhKoON0iZrb2h5OYS9pOZq/fIHEvzVs+oD+6Es3P/ASAD1K",
+    "/XscKvl9U1mGgEz4RNJ1QRRdcSI/NB2QpWEc7HfnLRspgSTsEZNfo8rWiR67+MgARMGxDNfc4BCZgEXLStvq9aFIAxyEIQBelUHB",
+    "H5WPlDuqX4ECgYEA+DJyhlxqN/J6JrhxwyFhwnhIRbKiHj6QknQw9ThSea9a0/HibLZ/jLhZlt1MWDKt/GKu/gUM7A0THU8Mqk/t",
+    "7kSIf4uzzG072r1MtTB+NZZ2OVqqswaGVflZchpHfFxKi8JhBQMevMK9mJ7aPE+4JlCi1cxTIWDsq063XKZI+A0CgYEAyK2us3id",
+    "Uo66hD19Y5JObt61n6lNVMDF48z6HfflPQ3qTtOur0s1iLTWLhlKBQyg77BYUClvhGWEmlyKFwYc8F/XwkWtlRAqE5gLPXUpunvh",
+    "Y5vfH9fu0TfyJpEsKdCr+ZlS1OZhmPo4mgQKOQaRkPK55D03GHsZyoxHoRsW+9ECgYEAmG2bCkuNbGi9fjQ+ocFJob5vXGa1y+9O",
+    "qLM1MhTyCguYs5vVHEEEmq7JTq8AaRZO5jbRE+6UHhB1kcVvmNVX4cIzNmnc9YqMfO1PQ+PRTyXl25mERkiWjgyCU2IyTvz7R1QM",
+    "c6CT6weKm0XL78hh5x4Us0s2LsSw9RB16i+xlq0CgYA7N51E0oPCF+IMIcT9e+/vIYMA4Umzh8sdZKAt9bfMo22P40E58vySIaLO",
+    "KaJBCS/4atY0mXWR4Q8XKdWJ48DoYa/eYQdO5F02OPigIxG0ONgSt61IzURospSfMtmQYUZOOiNKNc15AeVv9V864qhT2HxoUHQQ",
+    "okrDDVAZx0+p8QKBgA7Yh71Ofk/jPzYrbmaZ+u7iAZc1WNHqPDLDJFUrV5SIltkj9s9CufacL5GS/U6tgqjLQZivlq1gfC0rLZez",
+    "q5YPcc52zVqU1yDwXMccDHD2Pm4hGcJMkL+YMPpwj8IigWZA78T+GVDX4LOXKpEvMpylMVo1E9pJsL5ki9r7qxfc",
+);
+
+fn jwt_part(value: &Value) -> String {
+    use base64::Engine;
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(value).unwrap())
+}
+
+fn hs256_token(secret: &str, claims: Value) -> String {
+    use base64::Engine;
+    use hmac::Mac;
+    let signed = format!("{}.{}", jwt_part(&json!({"alg": "HS256", "typ": "JWT"})), jwt_part(&claims));
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(signed.as_bytes());
+    let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    format!("{}.{}", signed, signature)
+}
+
+#[tokio::test]
+async fn jwt_roles_gate_the_route_groups() {
+    let secret = "a-secret-of-at-least-thirty-two-chars";
+    let api = routes(
+        create_default_store(),
+        &test_config(&[
+            ("ADMIN_API_KEY", "s3cret"),
+            ("JWT_HS256_SECRET", secret),
+            ("JWT_ISSUER", "https://id.example.com"),
+            ("JWT_ANONYMOUS_ROLE", "none"),
+        ]),
+    );
+    let exp = fortunes::now_secs() + 300;
+    let token = |roles: Value| format!("Bearer {}", hs256_token(secret, json!({"iss": "https://id.example.com", "sub": "ada", "exp": exp, "roles": roles})));
+    let (reader, contributor, admin) = (token(json!(["reader"])), token(json!("reader contributor")), token(json!(["admin"])));
+    let send = |method: &str, path: &str, authorization: Option<&str>| {
+        let mut request = warp::test::request().method(method).path(path).json(&json!({"message": "Roles keep order"}));
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request
+    };
+
+    assert_eq!(send("GET", "/fortunes", None).reply(&api).await.status(), StatusCode::UNAUTHORIZED);
+    // Routes without a role stay open
+    assert_eq!(send("GET", "/healthz", None).reply(&api).await.status(), StatusCode::OK);
+    assert_eq!(send("GET", "/fortunes", Some(&reader)).reply(&api).await.status(), StatusCode::OK);
+    assert_eq!(send("POST", "/fortunes", Some(&reader)).reply(&api).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(send("POST", "/fortunes", Some(&contributor)).reply(&api).await.status(), StatusCode::OK);
+    assert_eq!(send("DELETE", "/fortunes/1", Some(&contributor)).reply(&api).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(send("GET", "/admin/stats", Some(&contributor)).reply(&api).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(send("GET", "/admin/stats", Some(&admin)).reply(&api).await.status(), StatusCode::OK);
+    assert_eq!(send("DELETE", "/fortunes/1", Some(&admin)).reply(&api).await.status(), StatusCode::OK);
+    // The admin API key still counts as admin
+    let res = warp::test::request().method("DELETE").path("/fortunes/2").header("x-api-key", "s3cret").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = send("POST", "/graphql", Some(&reader))
+        .json(&json!({"query": "mutation { createFortune(input: {message: \"Readers only read\"}) { id } }"}))
+        .reply(&api)
+        .await;
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN");
+
+    let expired = hs256_token(secret, json!({"iss": "https://id.example.com", "exp": 1000, "roles": ["admin"]}));
+    let stranger = hs256_token(secret, json!({"iss": "https://evil.example.com", "exp": exp, "roles": ["admin"]}));
+    let forged = hs256_token("another-secret-of-thirty-two-chars!", json!({"iss": "https://id.example.com", "exp": exp, "roles": ["admin"]}));
+    let unsigned = format!("{}.{}.", jwt_part(&json!({"alg": "none"})), jwt_part(&json!({"iss": "https://id.example.com", "exp": exp, "roles": ["admin"]})));
+    for bad in [expired, stranger, forged, unsigned] {
+        let res = send("GET", "/fortunes", Some(&format!("Bearer {}", bad))).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{}", bad);
+    }
+
+    for bad in [("JWT_HS256_SECRET", "short"), ("JWT_ANONYMOUS_ROLE", "admin"), ("JWT_ROLE_MAP", "staff:owner"), ("JWT_JWKS_URL", "ftp://keys")] {
+        assert!(Config::load(None, &[(bad.0, bad.1.to_string())]).is_err(), "{:?}", bad);
+    }
+}
+
+#[tokio::test]
+async fn rs256_tokens_are_checked_against_the_jwks() {
+    use base64::Engine;
+    let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let der = base64::engine::general_purpose::STANDARD.decode(TEST_RSA_KEY).unwrap();
+    let key = ring::signature::RsaKeyPair::from_der(&der).unwrap();
+    let public = ring::rsa::PublicKeyComponents::<Vec<u8>>::from(key.public());
+    let idp = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/jwks"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "keys": [{"kty": "RSA", "kid": "k1", "alg": "RS256", "use": "sig", "n": engine.encode(&public.n), "e": engine.encode(&public.e)}]
+        })))
+        .expect(1)
+        .mount(&idp)
+        .await;
+    let api = routes(
+        create_default_store(),
+        &test_config(&[
+            ("JWT_JWKS_URL", &format!("{}/jwks", idp.uri())),
+            ("JWT_AUDIENCE", "fortunes"),
+            ("JWT_ROLES_CLAIM", "realm_access.roles"),
+            ("JWT_ROLE_MAP", "fortune-admins:admin"),
+        ]),
+    );
+    let sign = |kid: &str, claims: Value| {
+        let signed = format!("{}.{}", jwt_part(&json!({"alg": "RS256", "kid": kid})), jwt_part(&claims));
+        let mut signature = vec![0; key.public().modulus_len()];
+        key.sign(&ring::signature::RSA_PKCS1_SHA256, &ring::rand::SystemRandom::new(), signed.as_bytes(), &mut signature).unwrap();
+        format!("Bearer {}.{}", signed, engine.encode(signature))
+    };
+    let claims = json!({"aud": ["account", "fortunes"], "exp": fortunes::now_secs() + 300, "realm_access": {"roles": ["fortune-admins"]}});
+
+    let res = warp::test::request().path("/admin/stats").header("authorization", sign("k1", claims.clone())).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    // Anonymous callers read by default, but are no admins
+    assert_eq!(warp::test::request().path("/fortunes/1").reply(&api).await.status(), StatusCode::OK);
+    assert_eq!(warp::test::request().path("/admin/stats").reply(&api).await.status(), StatusCode::UNAUTHORIZED);
+    let mut other_audience = claims.clone();
+    other_audience["aud"] = json!("billing");
+    let res = warp::test::request().path("/fortunes/1").header("authorization", sign("k1", other_audience)).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    // An unknown kid fetches the keys again, but not more than every 30 seconds
+    let res = warp::test::request().path("/fortunes/1").header("authorization", sign("k2", claims)).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
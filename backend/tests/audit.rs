@@ -0,0 +1,84 @@
+use fortune_backend::config::Config;
+use fortune_backend::{audit, create_default_store, routes};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+
+fn test_config(extra: &[(&str, &str)]) -> Config {
+    envy::from_iter(extra.iter().map(|(k, v)| (k.to_string(), v.to_string()))).unwrap()
+}
+
+// The audit log is process-wide, so everything that needs it lives in this one test binary
+#[tokio::test]
+async fn mutations_are_recorded_in_the_audit_log() {
+    let path = std::env::temp_dir().join(format!("fortune-audit-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    audit::init(Some(path.clone()));
+    let config = test_config(&[
+        ("ADMIN_API_KEY", "s3cret"),
+        ("API_KEYS", "team-a:k3y-a"),
+        ("TRUSTED_PROXIES", "10.0.0.0/8"),
+    ]);
+    let api = routes(create_default_store(), &config);
+
+    // Forwarding headers from a client that is not a trusted proxy are ignored
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .remote_addr("203.0.113.5:41000".parse().unwrap())
+        .header("x-forwarded-for", "192.0.2.1")
+        .header("x-api-key", "made-up")
+        .json(&json!({"id": "9", "message": "first"}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .header("x-api-key", "s3cret")
+        .json(&json!({"id": "9", "message": "second"}))
+        .reply(&api)
+        .await;
+    warp::test::request()
+        .method("DELETE")
+        .path("/fortunes/9")
+        .remote_addr("10.0.0.2:41000".parse().unwrap())
+        .header("x-forwarded-for", "198.51.100.9, 10.0.0.7")
+        .reply(&api)
+        .await;
+    warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .header("x-api-key", "k3y-a")
+        .json(&json!({"id": "9", "message": "third"}))
+        .reply(&api)
+        .await;
+
+    let res = warp::test::request()
+        .path("/admin/audit?since=0")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let entries: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    let ops: Vec<&str> = entries.iter().map(|e| e["op"].as_str().unwrap()).collect();
+    assert_eq!(ops, ["create", "update", "delete", "create"]);
+    // An unknown key is no identity, so the caller is recorded by address
+    assert_eq!(entries[0]["actor"], "ip:203.0.113.5");
+    assert_eq!(entries[2]["actor"], "ip:198.51.100.9");
+    assert_eq!(entries[1]["before"], "first");
+    assert_eq!(entries[1]["after"], "second");
+    // The first 8 hex digits of SHA-256("s3cret")
+    assert_eq!(entries[1]["actor"], "key:1ec1c26b");
+    assert_eq!(entries[3]["actor"], "key:team-a");
+    assert_eq!(entries[2]["before"], "second");
+    assert_eq!(entries[2]["after"], Value::Null);
+
+    let res = warp::test::request()
+        .path("/admin/audit?since=99999999999")
+        .header("x-api-key", "s3cret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.body(), "[]");
+
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,72 @@
+// In their own binary because the Redis fault rate is process-wide
+use fortune_backend::config::Config;
+use fortune_backend::redis_client::RedisStore;
+use fortune_backend::{chaos, create_default_store, routes};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+
+fn test_config(extra: &[(&str, &str)]) -> Config {
+    envy::from_iter(extra.iter().map(|(k, v)| (k.to_string(), v.to_string()))).unwrap()
+}
+
+#[tokio::test]
+async fn injected_failures_answer_before_the_handler() {
+    let store = create_default_store();
+    let api = routes(store.clone(), &test_config(&[("CHAOS_ERROR_PERCENT", "100"), ("CHAOS_LATENCY_MS", "20")]));
+
+    let res = warp::test::request().method("GET").path("/fortunes").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(serde_json::from_slice::<Value>(res.body()).unwrap(), json!("chaos: injected failure"));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"message": "Never stored"}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(store.read().await.len(), 4, "the write never ran");
+
+    // Health checks and metrics are spared
+    let res = warp::test::request().method("GET").path("/healthz").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = warp::test::request().method("GET").path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8(res.body().to_vec()).unwrap();
+    for fault in ["latency", "error"] {
+        let line = metrics
+            .lines()
+            .find(|line| line.starts_with(&format!("backend_chaos_faults_total{{fault=\"{}\"}}", fault)))
+            .unwrap();
+        assert!(!line.ends_with(" 0"), "{}", line);
+    }
+
+    // Nothing is injected by default
+    let api = routes(create_default_store(), &test_config(&[]));
+    for _ in 0..20 {
+        let res = warp::test::request().method("GET").path("/fortunes").reply(&api).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn redis_connections_fail_as_timeouts() {
+    let redis = RedisStore::new(redis::Client::open("redis://127.0.0.1:1").unwrap());
+
+    chaos::init(&test_config(&[("CHAOS_REDIS_TIMEOUT_PERCENT", "100")]));
+    let err = redis.connection().err().expect("the connection fails");
+    assert!(err.is_timeout(), "{}", err);
+    assert!(err.to_string().contains("chaos"), "{}", err);
+    assert!(!redis.ping().await);
+
+    chaos::init(&test_config(&[]));
+    let err = redis.connection().err().expect("nothing listens on port 1");
+    assert!(!err.to_string().contains("chaos"), "{}", err);
+}
+
+#[test]
+fn percentages_are_checked() {
+    for key in ["CHAOS_LATENCY_PERCENT", "CHAOS_ERROR_PERCENT", "CHAOS_REDIS_TIMEOUT_PERCENT"] {
+        assert!(Config::load(None, &[(key, "101".to_string())]).is_err(), "{}", key);
+        assert!(Config::load(None, &[(key, "100".to_string())]).is_ok(), "{}", key);
+    }
+}
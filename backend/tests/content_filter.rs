@@ -0,0 +1,42 @@
+use fortune_backend::config::Config;
+use fortune_backend::content_filter::{self, ContentFilter, FilterMode, Verdict};
+use fortune_backend::{create_default_store, routes};
+use serde_json::json;
+use warp::http::StatusCode;
+
+fn test_config(extra: &[(&str, &str)]) -> Config {
+    envy::from_iter(extra.iter().map(|(k, v)| (k.to_string(), v.to_string()))).unwrap()
+}
+
+#[test]
+fn leetspeak_and_stretched_words_are_caught() {
+    let filter = ContentFilter::new(["darn", "heck"], FilterMode::Reject);
+
+    assert_eq!(filter.check("Have a lovely day"), Verdict::Clean);
+    assert!(matches!(filter.check("D4RN it all"), Verdict::Rejected(reason) if reason.contains("darn")));
+    assert!(matches!(filter.check("what the h3eeeck?"), Verdict::Rejected(_)));
+    // Only whole words match
+    assert_eq!(filter.check("Darning socks is a virtue"), Verdict::Clean);
+
+    let flagging = ContentFilter::new(["darn"], FilterMode::Flag);
+    assert!(matches!(flagging.check("darn"), Verdict::Flagged(_)));
+}
+
+// The filter is process-wide, so the route check lives in its own test binary
+#[tokio::test]
+async fn create_rejects_blocked_messages_with_422() {
+    content_filter::init(Some(ContentFilter::new(["darn"], FilterMode::Reject)));
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "9", "message": "Darn, no luck today."}))
+        .reply(&api)
+        .await;
+
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(res.body(), "\"message contains blocked word \\\"darn\\\"\"");
+    let res = warp::test::request().path("/fortunes/9").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,139 @@
+use fortune_backend::config::Config;
+use fortune_backend::{create_default_store, routes};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+use warp::Filter;
+
+fn test_config(extra: &[(&str, &str)]) -> Config {
+    envy::from_iter(extra.iter().map(|(k, v)| (k.to_string(), v.to_string()))).unwrap()
+}
+
+async fn graphql<F>(api: &F, query: &str, api_key: Option<&str>) -> Value
+where
+    F: Filter + 'static,
+    F::Extract: warp::Reply + Send,
+{
+    let mut request = warp::test::request().method("POST").path("/graphql").json(&json!({"query": query}));
+    if let Some(key) = api_key {
+        request = request.header("x-api-key", key);
+    }
+    let res = request.reply(api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    serde_json::from_slice(res.body()).unwrap()
+}
+
+#[tokio::test]
+async fn queries_read_the_store() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let body = graphql(&api, "{ fortunes { id } }", None).await;
+    assert_eq!(body["data"]["fortunes"].as_array().unwrap().len(), 4);
+
+    let body = graphql(&api, r#"{ fortune(id: "4") { message lang status views } }"#, None).await;
+    assert_eq!(
+        body["data"]["fortune"],
+        json!({"message": "It ain't over till it's EOF.", "lang": "en", "status": "approved", "views": 1})
+    );
+    let body = graphql(&api, r#"{ fortune(id: "nope") { id } }"#, None).await;
+    assert_eq!(body["data"]["fortune"], Value::Null);
+
+    let body = graphql(&api, r#"{ search(query: "  eof ") { id } }"#, None).await;
+    assert_eq!(body["data"]["search"], json!([{"id": "4"}]));
+
+    let body = graphql(&api, "{ random { id } }", None).await;
+    assert!(body["data"]["random"]["id"].is_string());
+}
+
+#[tokio::test]
+async fn mutations_create_and_delete_fortunes() {
+    let api = routes(create_default_store(), &test_config(&[]));
+
+    let body = graphql(&api, r#"mutation { createFortune(input: {message: "From GraphQL.", author: "Ada"}) { id author status } }"#, None).await;
+    let created = &body["data"]["createFortune"];
+    assert_eq!(created["author"], "Ada");
+    assert_eq!(created["status"], "approved");
+    let id = created["id"].as_str().unwrap();
+
+    let res = warp::test::request().path(&format!("/fortunes/{}", id)).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = graphql(&api, r#"mutation { createFortune(input: {message: "from  graphql."}) { id } }"#, None).await;
+    assert_eq!(body["data"], Value::Null);
+    assert_eq!(body["errors"][0]["extensions"]["code"], "CONFLICT");
+
+    let body = graphql(&api, &format!(r#"mutation {{ deleteFortune(id: "{}") {{ id }} }}"#, id), None).await;
+    assert_eq!(body["data"]["deleteFortune"]["id"], id);
+    let body = graphql(&api, &format!(r#"mutation {{ deleteFortune(id: "{}") {{ id }} }}"#, id), None).await;
+    assert_eq!(body["data"]["deleteFortune"], Value::Null);
+}
+
+#[tokio::test]
+async fn mutations_follow_moderation_and_read_only() {
+    let config = test_config(&[("MODERATION", "true"), ("ADMIN_API_KEY", "secret")]);
+    let api = routes(create_default_store(), &config);
+    let create = r#"mutation { createFortune(input: {message: "Needs a look."}) { status } }"#;
+
+    let body = graphql(&api, create, None).await;
+    assert_eq!(body["data"]["createFortune"]["status"], "pending");
+    let body = graphql(&api, r#"mutation { createFortune(input: {message: "Trusted."}) { status } }"#, Some("secret")).await;
+    assert_eq!(body["data"]["createFortune"]["status"], "approved");
+
+    let api = routes(create_default_store(), &test_config(&[("READ_ONLY", "true")]));
+    let body = graphql(&api, create, None).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN");
+    let body = graphql(&api, r#"mutation { deleteFortune(id: "1") { id } }"#, None).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN");
+}
+
+#[tokio::test]
+async fn disabled_endpoints_switch_off_the_matching_mutations() {
+    let api = routes(create_default_store(), &test_config(&[("DISABLE_ENDPOINTS", "create,delete")]));
+
+    let body = graphql(&api, r#"mutation { createFortune(input: {message: "Not now."}) { id } }"#, None).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN");
+    assert_eq!(body["errors"][0]["message"], "the create endpoint is disabled");
+    let body = graphql(&api, r#"mutation { deleteFortune(id: "1") { id } }"#, None).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN");
+    let body = graphql(&api, r#"{ fortune(id: "1") { id } }"#, None).await;
+    assert_eq!(body["data"]["fortune"]["id"], "1", "queries are not affected");
+}
+
+#[tokio::test]
+async fn team_key_creates_count_against_the_same_quota_as_rest() {
+    let api = routes(create_default_store(), &test_config(&[("API_KEYS", "team-c:k3y-c::2")]));
+    let create = |message: &str| {
+        warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .header("x-api-key", "k3y-c")
+            .json(&json!({ "message": message }))
+    };
+
+    // A create that fails gives back the create it took
+    let res = create("It ain't over till it's EOF.").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    assert_eq!(res.headers()["x-ratelimit-creates-remaining"], "2");
+
+    let res = create("Counted over REST.").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-ratelimit-creates-remaining"], "1");
+    let body = graphql(&api, r#"mutation { createFortune(input: {message: "Counted over GraphQL."}) { id } }"#, Some("k3y-c")).await;
+    assert!(body["data"]["createFortune"]["id"].is_string());
+    let body = graphql(&api, r#"mutation { createFortune(input: {message: "One too many."}) { id } }"#, Some("k3y-c")).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "TOO_MANY_REQUESTS");
+    let res = create("Still one too many.").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn graphiql_is_served_only_when_enabled() {
+    let api = routes(create_default_store(), &test_config(&[]));
+    let res = warp::test::request().path("/graphql").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(res.headers()["allow"], "POST, OPTIONS");
+
+    let api = routes(create_default_store(), &test_config(&[("GRAPHIQL", "true")]));
+    let res = warp::test::request().path("/graphql").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(String::from_utf8(res.body().to_vec()).unwrap().contains("graphiql"));
+}
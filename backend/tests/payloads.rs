@@ -0,0 +1,105 @@
+// Contract tests for the request body layouts of POST /fortunes and
+// /fortunes/batch. Clients written against older versions must keep working.
+use fortune_backend::config::Config;
+use fortune_backend::{create_default_store, routes};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+
+fn test_config() -> Config {
+    envy::from_iter(std::iter::empty::<(String, String)>()).unwrap()
+}
+
+async fn create(body: Value) -> (StatusCode, Value) {
+    let api = routes(create_default_store(), &test_config());
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&body)
+        .reply(&api)
+        .await;
+    (res.status(), serde_json::from_slice(res.body()).unwrap())
+}
+
+#[tokio::test]
+async fn legacy_id_and_message_bodies_are_accepted() {
+    let (status, body) = create(json!({"id": "legacy", "message": "Old clients still work."})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], "legacy");
+    assert_eq!(body["message"], "Old clients still work.");
+    assert_eq!(body["lang"], "en");
+
+    // Some clients sent numeric ids
+    let (status, body) = create(json!({"id": 42, "message": "Numbers are ids too."})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], "42");
+}
+
+#[tokio::test]
+async fn version_1_is_the_flat_layout() {
+    for api_version in [None, Some(1)] {
+        let mut request = json!({
+            "message": "Flat fields.",
+            "lang": "de",
+            "group": "g1",
+            "author": "Ada",
+            "publish_at": 1,
+            "expires_at": 4_000_000_000u64,
+        });
+        if let Some(version) = api_version {
+            request["api_version"] = json!(version);
+        }
+        let (status, body) = create(request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (&body["lang"], &body["group"], &body["author"], &body["publish_at"], &body["expires_at"]),
+            (&json!("de"), &json!("g1"), &json!("Ada"), &json!(1), &json!(4_000_000_000u64))
+        );
+    }
+}
+
+#[tokio::test]
+async fn version_2_groups_translation_and_schedule() {
+    let (status, body) = create(json!({
+        "api_version": 2,
+        "id": "v2",
+        "message": "Nested fields.",
+        "author": "Ada",
+        "translation": {"lang": "pt-br", "group": "g2"},
+        "schedule": {"publish_at": 1, "expires_at": 4_000_000_000u64},
+    }))
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], "v2");
+    assert_eq!(body["lang"], "pt-br");
+    assert_eq!(body["group"], "g2");
+    assert_eq!(body["author"], "Ada");
+    assert_eq!(body["publish_at"], 1);
+    assert_eq!(body["expires_at"], 4_000_000_000u64);
+
+    // Everything but the message is optional
+    let (status, body) = create(json!({"api_version": 2, "message": "Just a message."})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["lang"], "en");
+}
+
+#[tokio::test]
+async fn batches_mix_versions_and_unknown_versions_are_refused() {
+    let api = routes(create_default_store(), &test_config());
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes/batch")
+        .json(&json!([
+            {"id": "a", "message": "Legacy entry."},
+            {"api_version": 2, "id": "b", "message": "New entry.", "translation": {"lang": "fr"}},
+        ]))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let results: Vec<Value> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(results[0]["fortune"]["id"], "a");
+    assert_eq!(results[1]["fortune"]["lang"], "fr");
+
+    let (status, body) = create(json!({"api_version": 3, "message": "From the future."})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.as_str().unwrap().contains("unsupported api_version 3"), "{}", body);
+}
@@ -0,0 +1,123 @@
+// Property tests throwing arbitrary input at the HTTP API. Whatever the
+// input, the backend must answer with a JSON body and never a 5xx.
+use fortune_backend::config::Config;
+use fortune_backend::{create_default_store, routes};
+use proptest::prelude::*;
+use serde_json::{json, Value};
+
+fn test_config() -> Config {
+    envy::from_iter(std::iter::empty::<(String, String)>()).unwrap()
+}
+
+// Sends each request through a fresh copy of the routes and checks the reply;
+// with `rejected` every request must fail with a 4xx
+fn check(rejected: bool, requests: impl FnOnce() -> Vec<warp::test::RequestBuilder>) -> Result<(), TestCaseError> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let api = routes(create_default_store(), &test_config());
+        for request in requests() {
+            let res = request.reply(&api).await;
+            prop_assert!(!res.status().is_server_error(), "status {}", res.status());
+            prop_assert!(!rejected || res.status().is_client_error(), "status {}", res.status());
+            prop_assert!(
+                serde_json::from_slice::<Value>(res.body()).is_ok(),
+                "status {} with a body that is not JSON: {:?}",
+                res.status(),
+                String::from_utf8_lossy(res.body())
+            );
+        }
+        Ok(())
+    })
+}
+
+fn post(path: &str, body: impl Into<Vec<u8>>) -> warp::test::RequestBuilder {
+    warp::test::request()
+        .method("POST")
+        .path(path)
+        .header("content-type", "application/json")
+        .body(body.into())
+}
+
+// Every byte percent-encoded, so any string makes a valid request path
+fn encode(segment: &str) -> String {
+    segment.bytes().map(|b| format!("%{:02X}", b)).collect()
+}
+
+fn nested(depth: usize) -> String {
+    format!("{}{}", "[".repeat(depth), "]".repeat(depth))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn create_then_get_handles_any_unicode(id in "\\PC{0,64}", message in "\\PC{0,256}") {
+        check(false, || {
+            let body = json!({"id": id, "message": message}).to_string();
+            vec![
+                post("/fortunes", body),
+                warp::test::request().path(&format!("/fortunes/{}", encode(&id))),
+            ]
+        })?;
+    }
+
+    #[test]
+    fn huge_ids_are_handled(id in "[0-9a-z]{1000,20000}") {
+        check(false, || {
+            vec![
+                post("/fortunes", json!({"id": id, "message": "A long way home."}).to_string()),
+                warp::test::request().path(&format!("/fortunes/{}", id)),
+                warp::test::request().method("DELETE").path(&format!("/fortunes/{}", id)),
+            ]
+        })?;
+    }
+
+    #[test]
+    fn deeply_nested_json_is_rejected(depth in 2usize..5000) {
+        check(true, || {
+            vec![
+                post("/fortunes", nested(depth)),
+                post("/fortunes", format!(r#"{{"id":"1","message":{}}}"#, nested(depth))),
+                post("/fortunes/batch", nested(depth)),
+            ]
+        })?;
+    }
+
+    #[test]
+    fn malformed_bodies_never_cause_server_errors(body in proptest::collection::vec(any::<u8>(), 0..512)) {
+        check(false, || {
+            vec![
+                post("/fortunes", body.clone()),
+                post("/fortunes/batch", body),
+            ]
+        })?;
+    }
+
+    #[test]
+    fn wrongly_typed_fields_never_cause_server_errors(value in prop_oneof![
+        Just(json!(null)),
+        any::<i64>().prop_map(|n| json!(n)),
+        any::<bool>().prop_map(|b| json!(b)),
+        "\\PC{0,32}".prop_map(|s| json!([s])),
+    ]) {
+        check(false, || {
+            vec![
+                post("/fortunes", json!({"id": value, "message": "Typed."}).to_string()),
+                post("/fortunes", json!({"id": "1", "message": value}).to_string()),
+                post("/fortunes", json!({"message": "Scheduled.", "publish_at": value}).to_string()),
+                post("/fortunes/batch", json!([{"message": value}]).to_string()),
+            ]
+        })?;
+    }
+
+    #[test]
+    fn query_strings_never_cause_server_errors(query in "\\PC{0,64}") {
+        check(false, || {
+            vec![
+                warp::test::request().path(&format!("/fortunes?{}", encode(&query))),
+                warp::test::request().path(&format!("/fortunes/popular?limit={}", encode(&query))),
+                post(&format!("/fortunes?force={}", encode(&query)), r#"{"message":"Forced."}"#),
+            ]
+        })?;
+    }
+}
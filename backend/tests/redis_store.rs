@@ -0,0 +1,398 @@
+// The ignored tests start a real Redis in Docker. Run them with
+// `cargo test --test redis_store -- --ignored`.
+use fortune_backend::fortunes::TrashedFortune;
+use fortune_backend::{analytics, leader, views};
+use fortune_backend::redis_client::{self, Backoff, RedisStore};
+use fortune_backend::storage::Storage;
+use fortune_backend::{create_default_store, store, Fortune, Status};
+use std::sync::Arc;
+use std::time::Duration;
+use testcontainers_modules::redis::{Redis, REDIS_PORT};
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+use testcontainers_modules::testcontainers::ContainerAsync;
+
+async fn start_redis(hash: &str) -> (ContainerAsync<Redis>, RedisStore) {
+    let container = Redis::default().start().await.expect("failed to start redis container");
+    let host = container.get_host().await.unwrap();
+    let port = container.get_host_port_ipv4(REDIS_PORT).await.unwrap();
+    let client = redis::Client::open(format!("redis://{}:{}", host, port)).unwrap();
+    (container, RedisStore::with_hash(client, hash))
+}
+
+fn fortune(id: &str, message: &str) -> Fortune {
+    Fortune {
+        id: id.to_string(),
+        message: message.to_string(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn set_then_get_round_trips() {
+    let (_container, redis) = start_redis("fortunes").await;
+
+    redis.set(&fortune("7", "Lucky number seven.")).await.unwrap();
+
+    let stored = redis.get("7").await.unwrap().expect("fortune was stored");
+    assert_eq!(stored.message, "Lucky number seven.");
+    assert!(redis.get("8").await.unwrap().is_none());
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn language_and_group_round_trip() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let translated = Fortune {
+        lang: "de".to_string(),
+        group: Some("seven".to_string()),
+        ..fortune("7de", "Glückszahl sieben.")
+    };
+
+    redis.set(&translated).await.unwrap();
+    redis.set(&fortune("7", "Lucky number seven.")).await.unwrap();
+
+    assert_eq!(redis.get("7de").await.unwrap(), Some(translated));
+    assert_eq!(redis.get("7").await.unwrap().unwrap().lang, "en");
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn author_index_follows_updates_and_deletes() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let by = |author: &str| Fortune {
+        author: Some(author.to_string()),
+        ..fortune("9", "Attributed.")
+    };
+
+    redis.set(&by("Confucius")).await.unwrap();
+    assert_eq!(redis.ids_by_author("confucius").await.unwrap(), ["9"]);
+    redis.set(&by("Lao Tzu")).await.unwrap();
+    assert!(redis.ids_by_author("Confucius").await.unwrap().is_empty());
+    redis.delete("9").await.unwrap();
+    assert!(redis.ids_by_author("Lao Tzu").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn set_many_writes_every_fortune() {
+    let (_container, redis) = start_redis("fortunes").await;
+    redis
+        .set(&Fortune {
+            author: Some("Confucius".to_string()),
+            ..fortune("1", "Before the batch.")
+        })
+        .await
+        .unwrap();
+
+    redis
+        .set_many(&[fortune("1", "Rewritten."), fortune("2", "Added.")])
+        .await
+        .unwrap();
+
+    assert_eq!(redis.get("1").await.unwrap().unwrap().message, "Rewritten.");
+    assert_eq!(redis.get("2").await.unwrap().unwrap().message, "Added.");
+    assert!(redis.ids_by_author("Confucius").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn view_counts_are_shared_through_redis() {
+    let (_container, redis) = start_redis("fortunes").await;
+    redis.set(&fortune("1", "Seen twice.")).await.unwrap();
+
+    assert_eq!(redis.add_view("1").await.unwrap(), 1);
+    assert_eq!(redis.add_view("1").await.unwrap(), 2);
+    let store = create_default_store();
+    redis.load_views_into(&store).await;
+    assert_eq!(store.read().await.views("1"), 2);
+
+    redis.delete("1").await.unwrap();
+    assert_eq!(redis.add_view("1").await.unwrap(), 1);
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn queued_views_reach_redis_in_one_flush() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let store = create_default_store();
+    // Another replica's view
+    redis.add_view("1").await.unwrap();
+
+    views::queue(None, "1");
+    views::queue(None, "1");
+    views::queue(None, "2");
+    views::flush(&redis, &store).await;
+
+    let totals = redis.views().await.unwrap();
+    assert_eq!((totals["1"], totals["2"]), (3, 1));
+    assert_eq!(store.read().await.views("1"), 3);
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn delete_removes_fortune() {
+    let (_container, redis) = start_redis("fortunes").await;
+    redis.set(&fortune("1", "Soon gone.")).await.unwrap();
+
+    redis.delete("1").await.unwrap();
+
+    assert!(redis.get("1").await.unwrap().is_none());
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn load_all_returns_every_fortune_in_the_hash() {
+    let (_container, redis) = start_redis("test:fortunes").await;
+    redis.set(&fortune("a", "First.")).await.unwrap();
+    redis.set(&fortune("b", "Second.")).await.unwrap();
+
+    let mut fortunes = redis.load_all().await.unwrap();
+    fortunes.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let ids: Vec<&str> = fortunes.iter().map(|f| f.id.as_str()).collect();
+    assert_eq!(ids, ["a", "b"]);
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn load_into_merges_with_default_store() {
+    let (_container, redis) = start_redis("fortunes").await;
+    redis.set(&fortune("99", "From Redis.")).await.unwrap();
+    let store = create_default_store();
+
+    redis.load_into(store.clone()).await;
+
+    let store = store.read().await;
+    assert_eq!(store.len(), 5);
+    assert_eq!(store["99"].message, "From Redis.");
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn trashed_fortunes_are_reloaded() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let trashed = TrashedFortune {
+        fortune: fortune("13", "Unlucky, but recoverable."),
+        deleted_at: 1_700_000_000,
+    };
+    redis.set_deleted(&trashed).await.unwrap();
+    let store = create_default_store();
+
+    redis.load_deleted_into(&store).await;
+    redis.remove_deleted(&["13".to_string()]).await.unwrap();
+    let reloaded = create_default_store();
+    redis.load_deleted_into(&reloaded).await;
+
+    assert_eq!(store.read().await.trashed().count(), 1);
+    assert_eq!(reloaded.read().await.trashed().count(), 0);
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn migrate_converts_the_schema_1_layout() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let mut conn = redis.client().get_connection().unwrap();
+    redis::pipe()
+        .cmd("HSET").arg("fortunes").arg("1").arg("Plain message.")
+        .cmd("HSET").arg("fortunes").arg("2").arg("With metadata.")
+        .cmd("HSET").arg("fortunes:meta").arg("2").arg(r#"{"lang":"de","author":"Ann","status":"pending"}"#)
+        .query::<()>(&mut conn)
+        .unwrap();
+
+    assert_eq!(redis.migrate().await.unwrap(), 2);
+    // Already current: nothing left to convert
+    assert_eq!(redis.migrate().await.unwrap(), 0);
+
+    assert_eq!(redis.get("1").await.unwrap(), Some(fortune("1", "Plain message.")));
+    let migrated = redis.get("2").await.unwrap().unwrap();
+    assert_eq!(migrated.message, "With metadata.");
+    assert_eq!(migrated.lang, "de");
+    assert_eq!(migrated.author.as_deref(), Some("Ann"));
+    assert_eq!(migrated.status, Status::Pending);
+    let schema: u32 = redis::cmd("GET").arg("fortunes:schema").query(&mut conn).unwrap();
+    assert_eq!(schema, 2);
+    let meta_left: bool = redis::cmd("EXISTS").arg("fortunes:meta").query(&mut conn).unwrap();
+    assert!(!meta_left);
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap() {
+    let backoff = Backoff {
+        base: Duration::from_secs(2),
+        max: Duration::from_secs(20),
+    };
+    let delays: Vec<u64> = (1..=6).map(|attempt| backoff.delay(attempt).as_secs()).collect();
+    assert_eq!(delays, [2, 4, 8, 16, 20, 20]);
+    assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(20));
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn concurrent_id_allocation_never_collides() {
+    let (_container, redis) = start_redis("fortunes").await;
+    // A client-chosen id the counter will run into
+    redis.set(&fortune("3", "Picked by hand.")).await.unwrap();
+
+    let allocations = (0..20).map(|i| {
+        let redis = redis.clone();
+        tokio::spawn(async move { redis.allocate_id(&fortune("", &format!("Fortune {}", i))).await.unwrap() })
+    });
+    let mut ids = Vec::new();
+    for allocation in allocations {
+        ids.push(allocation.await.unwrap());
+    }
+
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 20);
+    assert!(!ids.contains(&"3".to_string()));
+    assert_eq!(redis.get("3").await.unwrap().unwrap().message, "Picked by hand.");
+    assert_eq!(redis.load_all().await.unwrap().len(), 21);
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn refused_concurrent_create_gives_back_its_id() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let store = create_default_store();
+    store.write().await.set_storage(Some(Arc::new(redis.clone())));
+
+    // Both pass the early duplicate check and claim an id before either
+    // reaches the store
+    let held = store.write().await;
+    let creates: Vec<_> = (0..2)
+        .map(|_| {
+            let store = store.clone();
+            tokio::spawn(async move { store::create(&store, fortune("", "Said only once."), false, "test").await })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    drop(held);
+    let mut created = 0;
+    for create in creates {
+        match create.await.unwrap() {
+            Ok(_) => created += 1,
+            Err(e) => assert!(matches!(e, store::CreateError::Duplicate(_)), "{}", e),
+        }
+    }
+    assert_eq!(created, 1);
+
+    let mut conn = redis.client().get_connection().unwrap();
+    let fields: usize = redis::cmd("HLEN").arg("fortunes").query(&mut conn).unwrap();
+    assert_eq!(fields, 1);
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn events_are_read_back_after_an_entry_id() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let events: Vec<Vec<(&str, String)>> = (0..5).map(|i| vec![("type", "fortune.created".to_string()), ("n", i.to_string())]).collect();
+    redis.append_events(&events, 1000).await.unwrap();
+
+    let all = redis.read_events("0", 100).await.unwrap();
+    assert_eq!(all.len(), 5);
+    assert_eq!(all[0].1["type"], "fortune.created");
+    assert_eq!(all[0].1["n"], "0");
+
+    let rest = redis.read_events(&all[2].0, 2).await.unwrap();
+    let n: Vec<&str> = rest.iter().map(|(_, fields)| fields["n"].as_str()).collect();
+    assert_eq!(n, ["3", "4"]);
+    assert!(redis.read_events(&all[4].0, 100).await.unwrap().is_empty());
+}
+
+#[test]
+fn shards_are_balanced_and_adding_one_moves_few_ids() {
+    let ids: Vec<String> = (0..10_000).map(|i| i.to_string()).collect();
+    let mut counts = [0; 8];
+    for id in &ids {
+        counts[redis_client::shard_of(id, 8) as usize] += 1;
+    }
+    assert!(counts.iter().all(|&count| (1000..1500).contains(&count)), "{:?}", counts);
+
+    // Going from 8 to 9 shards only moves ids into the new shard
+    let moved: Vec<&String> = ids.iter().filter(|id| redis_client::shard_of(id, 8) != redis_client::shard_of(id, 9)).collect();
+    assert!(moved.iter().all(|id| redis_client::shard_of(id, 9) == 8));
+    assert!((900..1400).contains(&moved.len()), "{}", moved.len());
+    assert_eq!(redis_client::shard_of("42", 1), 0);
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn reshard_spreads_fortunes_and_sharded_reads_gather_them() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let fortunes: Vec<Fortune> = (0..50).map(|i| fortune(&i.to_string(), &format!("Fortune {}.", i))).collect();
+    redis.set_many(&fortunes).await.unwrap();
+
+    let sharded = redis.clone().with_shards(4);
+    assert_eq!(sharded.layout_mismatch().await.unwrap(), Some(1));
+    assert_eq!(sharded.reshard().await.unwrap(), 50);
+    assert_eq!(sharded.layout_mismatch().await.unwrap(), None);
+    assert_eq!(redis.layout_mismatch().await.unwrap(), Some(4));
+
+    assert_eq!(sharded.load_all().await.unwrap().len(), 50);
+    assert_eq!(sharded.get("7").await.unwrap().unwrap().message, "Fortune 7.");
+    let id = sharded.allocate_id(&fortune("", "Allocated.")).await.unwrap();
+    assert_eq!(sharded.get(&id).await.unwrap().unwrap().message, "Allocated.");
+    sharded.delete("7").await.unwrap();
+    assert!(sharded.get("7").await.unwrap().is_none());
+
+    // Growing the layout only moves the ids that change shard
+    let grown = redis.clone().with_shards(5);
+    let moved = grown.reshard().await.unwrap();
+    assert!(moved < 50, "{}", moved);
+    assert_eq!(grown.load_all().await.unwrap().len(), 50);
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn leader_lease_has_one_holder_until_it_lapses() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let ttl = Duration::from_millis(500);
+
+    assert_eq!(leader::claim(&redis, "a", ttl).await.unwrap().as_deref(), Some("a"));
+    assert_eq!(leader::claim(&redis, "b", ttl).await.unwrap().as_deref(), Some("a"));
+    // Renewing keeps it with the holder past the first lease
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(leader::claim(&redis, "a", ttl).await.unwrap().as_deref(), Some("a"));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(leader::claim(&redis, "b", ttl).await.unwrap().as_deref(), Some("a"));
+
+    // Once the holder stops renewing, the next claim takes over
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(leader::claim(&redis, "b", ttl).await.unwrap().as_deref(), Some("b"));
+    assert_eq!(leader::claim(&redis, "a", ttl).await.unwrap().as_deref(), Some("b"));
+}
+
+#[tokio::test]
+#[ignore = "requires Docker"]
+async fn analytics_report_submissions_and_the_most_viewed_fortunes() {
+    let (_container, redis) = start_redis("fortunes").await;
+    let config: fortune_backend::config::Config =
+        envy::from_iter([("REDIS_DNS".to_string(), "localhost".to_string())]).unwrap();
+    analytics::init(&config);
+    let store = create_default_store();
+    for (id, views) in [("1", 2), ("2", 5), ("3", 1)] {
+        for _ in 0..views {
+            redis.add_view(id).await.unwrap();
+        }
+    }
+
+    analytics::record_submission();
+    analytics::record_submission();
+    let report = serde_json::to_value(analytics::report(&redis, &store, 3, 2).await.unwrap()).unwrap();
+
+    let days = report["days"].as_array().unwrap();
+    assert_eq!(days.len(), 3);
+    assert_eq!(days[2]["submissions"], 2);
+    assert_eq!(days[1]["submissions"], 0);
+    assert_eq!(report["unique_clients"], 0);
+    let served: Vec<(&str, u64)> = report["most_served"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| (f["id"].as_str().unwrap(), f["views"].as_u64().unwrap()))
+        .collect();
+    assert_eq!(served, [("2", 5), ("1", 2)]);
+    assert!(report["most_served"][0]["message"].is_string());
+}
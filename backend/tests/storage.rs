@@ -0,0 +1,207 @@
+// The store against an in-memory `Storage`, so the write-through paths run
+// without Redis
+use async_trait::async_trait;
+use fortune_backend::fortunes::TrashedFortune;
+use fortune_backend::storage::{Storage, StorageResult};
+use fortune_backend::{create_default_store, store, Fortune, FortuneStore, Sort};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Holds what a RedisStore would, with the same id allocation: the counter
+// skips ids that are already taken
+#[derive(Debug, Default)]
+struct MemoryStorage {
+    fortunes: Mutex<HashMap<String, Fortune>>,
+    next_id: Mutex<u64>,
+    views: Mutex<HashMap<String, u64>>,
+    deleted: Mutex<HashMap<String, TrashedFortune>>,
+}
+
+impl MemoryStorage {
+    fn ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.fortunes.lock().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn load_all(&self) -> StorageResult<Vec<Fortune>> {
+        Ok(self.fortunes.lock().unwrap().values().cloned().collect())
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Fortune>> {
+        Ok(self.fortunes.lock().unwrap().get(id).cloned())
+    }
+
+    async fn set(&self, fortune: &Fortune) -> StorageResult<()> {
+        self.fortunes.lock().unwrap().insert(fortune.id.clone(), fortune.clone());
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<()> {
+        self.fortunes.lock().unwrap().remove(id);
+        self.views.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    async fn set_many(&self, fortunes: &[Fortune]) -> StorageResult<()> {
+        let mut stored = self.fortunes.lock().unwrap();
+        for fortune in fortunes {
+            stored.insert(fortune.id.clone(), fortune.clone());
+        }
+        Ok(())
+    }
+
+    async fn allocate_id(&self, fortune: &Fortune) -> StorageResult<String> {
+        let mut stored = self.fortunes.lock().unwrap();
+        let mut next_id = self.next_id.lock().unwrap();
+        loop {
+            *next_id += 1;
+            let id = next_id.to_string();
+            if !stored.contains_key(&id) {
+                stored.insert(id.clone(), Fortune { id: id.clone(), ..fortune.clone() });
+                return Ok(id);
+            }
+        }
+    }
+
+    async fn release_ids(&self, ids: &[String]) -> StorageResult<()> {
+        let mut stored = self.fortunes.lock().unwrap();
+        for id in ids {
+            stored.remove(id);
+        }
+        Ok(())
+    }
+
+    async fn add_view(&self, id: &str) -> StorageResult<u64> {
+        let mut views = self.views.lock().unwrap();
+        let count = views.entry(id.to_string()).or_default();
+        *count += 1;
+        Ok(*count)
+    }
+
+    async fn set_deleted(&self, trashed: &TrashedFortune) -> StorageResult<()> {
+        self.deleted.lock().unwrap().insert(trashed.fortune.id.clone(), trashed.clone());
+        Ok(())
+    }
+
+    async fn remove_deleted(&self, ids: &[String]) -> StorageResult<()> {
+        let mut deleted = self.deleted.lock().unwrap();
+        for id in ids {
+            deleted.remove(id);
+        }
+        Ok(())
+    }
+}
+
+fn fortune(id: &str, message: &str) -> Fortune {
+    Fortune {
+        id: id.to_string(),
+        message: message.to_string(),
+        ..Default::default()
+    }
+}
+
+async fn store_with(storage: &Arc<MemoryStorage>) -> FortuneStore {
+    let store = create_default_store();
+    store.write().await.set_storage(Some(storage.clone()));
+    store
+}
+
+#[tokio::test]
+async fn creates_and_updates_are_written_through() {
+    let storage = Arc::new(MemoryStorage::default());
+    let store = store_with(&storage).await;
+
+    let created = store::create(&store, fortune("", "Written through."), false, "test").await.unwrap();
+    store::create(&store, fortune("7", "Picked by hand."), false, "test").await.unwrap();
+    assert_eq!(storage.ids(), ["1", "7"]);
+    assert_eq!(storage.get(&created.id).await.unwrap().unwrap().message, "Written through.");
+
+    store::create(&store, fortune("7", "Picked again."), true, "test").await.unwrap();
+    assert_eq!(storage.get("7").await.unwrap().unwrap().message, "Picked again.");
+}
+
+#[tokio::test]
+async fn deletes_reach_the_storage() {
+    let storage = Arc::new(MemoryStorage::default());
+    let store = store_with(&storage).await;
+    store::create(&store, fortune("3", "Here for now."), false, "test").await.unwrap();
+
+    assert!(store::delete(&store, "3", "test").await.is_some());
+    assert!(storage.ids().is_empty());
+    assert!(store::peek(&store, "3").await.is_none());
+}
+
+#[tokio::test]
+async fn ids_come_from_the_storage_and_skip_taken_ones() {
+    let storage = Arc::new(MemoryStorage::default());
+    storage.set(&fortune("1", "Stored by another replica.")).await.unwrap();
+    let store = store_with(&storage).await;
+
+    let created = store::create(&store, fortune("", "Numbered by the storage."), false, "test").await.unwrap();
+    assert_eq!(created.id, "2");
+    assert_eq!(store::peek(&store, "2").await.unwrap().message, "Numbered by the storage.");
+}
+
+#[tokio::test]
+async fn refused_concurrent_create_gives_back_its_id() {
+    let storage = Arc::new(MemoryStorage::default());
+    let store = store_with(&storage).await;
+
+    // Both pass the early duplicate check and claim an id before either
+    // reaches the store
+    let held = store.write().await;
+    let creates: Vec<_> = (0..2)
+        .map(|_| {
+            let store = store.clone();
+            tokio::spawn(async move { store::create(&store, fortune("", "Said only once."), false, "test").await })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(held);
+    let mut created = Vec::new();
+    for create in creates {
+        match create.await.unwrap() {
+            Ok(fortune) => created.push(fortune.id),
+            Err(e) => assert!(matches!(e, store::CreateError::Duplicate(_)), "{}", e),
+        }
+    }
+
+    assert_eq!(created.len(), 1);
+    assert_eq!(storage.ids(), created);
+}
+
+#[tokio::test]
+async fn a_capped_store_still_lists_draws_and_matches_evicted_fortunes() {
+    let storage = Arc::new(MemoryStorage::default());
+    let store = store_with(&storage).await;
+    store.write().await.clear();
+    store.write().await.set_capacity(Some(2));
+    for (id, message) in [("1", "Evicted first."), ("2", "Kept."), ("3", "Also kept, but longer.")] {
+        store::create(&store, fortune(id, message), false, "test").await.unwrap();
+    }
+    assert!(store.read().await.is_evicted("1"));
+
+    let listed: Vec<String> = store::list(&store).await.into_iter().map(|f| f.message).collect();
+    assert_eq!(listed.len(), 3);
+    assert!(listed.contains(&"Evicted first.".to_string()));
+    let ids = store::active_ids(&store, None, None, Some(14), Sort::Id, false).await;
+    assert_eq!(ids, ["1", "2"]);
+    assert_eq!(store::get_many(&store, &ids).await[0].message, "Evicted first.");
+    assert_eq!(store::search(&store, "evicted", 10).await.len(), 1);
+
+    match store::create(&store, fortune("", "evicted  FIRST."), false, "test").await {
+        Err(store::CreateError::Duplicate(existing)) => assert_eq!(existing.message, "Evicted first."),
+        other => panic!("expected a duplicate, got {:?}", other.map(|f| f.id)),
+    }
+
+    // Drawing it reads the body back
+    let drawn = store::random(&store, &[], Some(14), true).await.unwrap();
+    assert!(["Evicted first.", "Kept."].contains(&drawn.message.as_str()));
+    assert!(store::delete(&store, "1", "test").await.is_some());
+    assert!(storage.get("1").await.unwrap().is_none());
+}
@@ -0,0 +1,121 @@
+use fortune_backend::config::Config;
+use fortune_backend::{create_default_store, routes, webhooks};
+use serde_json::{json, Value};
+use std::time::Duration;
+use warp::http::StatusCode;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+const SECRET: &str = "webhook-secret-0123";
+
+fn test_config(extra: &[(&str, &str)]) -> Config {
+    envy::from_iter(extra.iter().map(|(k, v)| (k.to_string(), v.to_string()))).unwrap()
+}
+
+async fn wait_for(server: &MockServer, count: usize) -> Vec<Request> {
+    for _ in 0..200 {
+        let requests = server.received_requests().await.unwrap();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("webhook receiver got fewer than {} requests", count);
+}
+
+// Webhook workers are process-wide, so everything that needs them lives in this one test binary
+#[tokio::test]
+async fn mutations_are_sent_to_webhooks_signed_and_retried() {
+    let receiver = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(204)).mount(&receiver).await;
+    let broken = MockServer::start().await;
+    Mock::given(method("POST")).respond_with(ResponseTemplate::new(500)).mount(&broken).await;
+
+    let dead_letters = std::env::temp_dir().join(format!("fortune-webhooks-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&dead_letters);
+    let urls = format!("{}/hook, {}/hook", receiver.uri(), broken.uri());
+    let config = test_config(&[
+        ("WEBHOOK_URLS", urls.as_str()),
+        ("WEBHOOK_SECRET", SECRET),
+        ("WEBHOOK_MAX_ATTEMPTS", "2"),
+        ("WEBHOOK_RETRY_BASE_MS", "1"),
+        ("WEBHOOK_DEAD_LETTER_FILE", dead_letters.to_str().unwrap()),
+    ]);
+    webhooks::init(&config);
+    let api = routes(create_default_store(), &config);
+
+    for message in ["first", "second"] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/fortunes")
+            .json(&json!({"id": "9", "message": message}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    warp::test::request().method("DELETE").path("/fortunes/9").reply(&api).await;
+
+    let requests = wait_for(&receiver, 3).await;
+    let events: Vec<Value> = requests.iter().map(|r| serde_json::from_slice(&r.body).unwrap()).collect();
+    let types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(types, ["fortune.created", "fortune.updated", "fortune.deleted"]);
+    assert_eq!(events[1]["fortune"]["message"], "second");
+    assert_eq!(events[2]["fortune"], json!({"id": "9"}));
+
+    for (request, event) in requests.iter().zip(&events) {
+        let header = request.headers.get(webhooks::SIGNATURE_HEADER).unwrap().to_str().unwrap();
+        let timestamp: u64 = header.strip_prefix("t=").unwrap().split(',').next().unwrap().parse().unwrap();
+        let body = std::str::from_utf8(&request.body).unwrap();
+        assert_eq!(header, webhooks::sign(SECRET.as_bytes(), timestamp, body));
+        assert_ne!(header, webhooks::sign(b"some-other-secret", timestamp, body));
+        assert_eq!(request.headers.get("x-fortune-event").unwrap(), event["type"].as_str().unwrap());
+        assert_eq!(request.headers.get("x-fortune-delivery").unwrap(), event["id"].as_str().unwrap());
+    }
+
+    // Every event is tried twice against the failing receiver, then dead-lettered
+    wait_for(&broken, 6).await;
+    let mut lines = Vec::new();
+    for _ in 0..200 {
+        lines = std::fs::read_to_string(&dead_letters).unwrap_or_default().lines().map(str::to_string).collect();
+        if lines.len() >= 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(lines.len(), 3);
+    let entry: Value = serde_json::from_str(&lines[0]).unwrap();
+    assert_eq!(entry["url"], format!("{}/hook", broken.uri()));
+    assert_eq!(entry["attempts"], 2);
+    assert_eq!(entry["event"]["id"], events[0]["id"]);
+
+    let res = warp::test::request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8_lossy(res.body());
+    assert!(metrics.contains("backend_webhook_delivered_total 3"));
+    assert!(metrics.contains("backend_webhook_dead_lettered_total 3"));
+
+    // A submission held for moderation is announced once it is approved
+    let moderated = test_config(&[("MODERATION", "true"), ("ADMIN_API_KEY", "admin-key")]);
+    let api = routes(create_default_store(), &moderated);
+    let res = warp::test::request()
+        .method("POST")
+        .path("/fortunes")
+        .json(&json!({"id": "10", "message": "Held back."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(receiver.received_requests().await.unwrap().len(), 3, "pending fortunes are not announced");
+    let res = warp::test::request()
+        .method("POST")
+        .path("/admin/fortunes/10/approve")
+        .header("x-api-key", "admin-key")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let requests = wait_for(&receiver, 4).await;
+    let event: Value = serde_json::from_slice(&requests[3].body).unwrap();
+    assert_eq!(event["type"], "fortune.created");
+    assert_eq!(event["fortune"]["id"], "10");
+
+    let _ = std::fs::remove_file(&dead_letters);
+}
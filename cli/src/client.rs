@@ -0,0 +1,87 @@
+use crate::Fortune;
+use reqwest::{RequestBuilder, StatusCode};
+
+pub struct BackendClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl BackendClient {
+    pub fn new(base_url: &str, api_key: Option<String>) -> Self {
+        BackendClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.base_url, path));
+        match &self.api_key {
+            Some(key) => request.header("X-API-Key", key),
+            None => request,
+        }
+    }
+
+    async fn send(request: RequestBuilder) -> Result<Option<Fortune>, String> {
+        let response = request.send().await.map_err(|e| format!("request failed: {}", e))?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => response
+                .json::<Fortune>()
+                .await
+                .map(Some)
+                .map_err(|e| format!("invalid response: {}", e)),
+            status => Err(format!("backend returned {}", status)),
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<Fortune>, String> {
+        let response = self
+            .request(reqwest::Method::GET, "/fortunes")
+            .send()
+            .await
+            .map_err(|e| format!("request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!("backend returned {}", response.status()));
+        }
+        response.json().await.map_err(|e| format!("invalid response: {}", e))
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Fortune>, String> {
+        Self::send(self.request(reqwest::Method::GET, &format!("/fortunes/{}", id))).await
+    }
+
+    pub async fn random(&self) -> Result<Option<Fortune>, String> {
+        Self::send(self.request(reqwest::Method::GET, "/fortunes/random")).await
+    }
+
+    pub async fn add(&self, fortune: &Fortune, force: bool) -> Result<Fortune, String> {
+        let path = if force { "/fortunes?force=true" } else { "/fortunes" };
+        let response = self
+            .request(reqwest::Method::POST, path)
+            .json(fortune)
+            .send()
+            .await
+            .map_err(|e| format!("request failed: {}", e))?;
+        match response.status() {
+            StatusCode::CONFLICT => {
+                let existing: Fortune = response
+                    .json()
+                    .await
+                    .map_err(|e| format!("invalid response: {}", e))?;
+                Err(format!("duplicate of fortune {} (use --force to add anyway)", existing.id))
+            }
+            status if status.is_success() => response
+                .json()
+                .await
+                .map_err(|e| format!("invalid response: {}", e)),
+            status => Err(format!("backend returned {}", status)),
+        }
+    }
+
+    pub async fn delete(&self, id: &str) -> Result<Option<Fortune>, String> {
+        Self::send(self.request(reqwest::Method::DELETE, &format!("/fortunes/{}", id))).await
+    }
+}
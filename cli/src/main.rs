@@ -0,0 +1,166 @@
+mod client;
+
+use clap::{Parser, Subcommand};
+use client::BackendClient;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Fortune {
+    // Empty on new fortunes; the backend allocates one
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    id: String,
+    message: String,
+}
+
+// Import files may omit ids; the backend allocates the missing ones
+#[derive(Debug, Deserialize)]
+struct ImportedFortune {
+    id: Option<String>,
+    message: String,
+}
+
+#[derive(Parser)]
+#[command(name = "fortune-cli", version, about = "Administer fortunes through the backend HTTP API")]
+struct Cli {
+    /// Base URL of the backend
+    #[arg(long, env = "BACKEND_URL", default_value = "http://localhost:9000")]
+    backend_url: String,
+
+    /// API key sent as X-API-Key
+    #[arg(long, env = "FORTUNE_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List all fortunes
+    List,
+    /// Show a fortune by id
+    Get { id: String },
+    /// Show a random fortune
+    Random,
+    /// Add a fortune
+    Add {
+        message: String,
+        /// Id to use; the backend allocates one when omitted
+        #[arg(long)]
+        id: Option<String>,
+        /// Add the fortune even if one with the same message exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Delete a fortune by id
+    Delete { id: String },
+    /// Import fortunes from a JSON array or a classic `%`-separated fortune file
+    Import {
+        file: PathBuf,
+        /// Import fortunes even if one with the same message exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Export all fortunes as JSON to a file or stdout
+    Export { file: Option<PathBuf> },
+}
+
+fn parse_import(contents: &str) -> Result<Vec<Fortune>, String> {
+    let imported: Vec<ImportedFortune> = if contents.trim_start().starts_with('[') {
+        serde_json::from_str(contents).map_err(|e| format!("invalid JSON: {}", e))?
+    } else {
+        contents
+            .split("\n%")
+            .map(|entry| entry.trim().trim_start_matches('%').trim())
+            .filter(|entry| !entry.is_empty())
+            .map(|message| ImportedFortune {
+                id: None,
+                message: message.to_string(),
+            })
+            .collect()
+    };
+
+    Ok(imported
+        .into_iter()
+        .map(|f| Fortune {
+            id: f.id.unwrap_or_default(),
+            message: f.message,
+        })
+        .collect())
+}
+
+fn print_fortune(fortune: &Fortune) {
+    println!("{}: {}", fortune.id, fortune.message);
+}
+
+async fn run(cli: Cli) -> Result<(), String> {
+    let client = BackendClient::new(&cli.backend_url, cli.api_key);
+
+    match cli.command {
+        Command::List => {
+            for fortune in client.list().await? {
+                print_fortune(&fortune);
+            }
+        }
+        Command::Get { id } => match client.get(&id).await? {
+            Some(fortune) => print_fortune(&fortune),
+            None => return Err(format!("fortune {} not found", id)),
+        },
+        Command::Random => match client.random().await? {
+            Some(fortune) => print_fortune(&fortune),
+            None => return Err("no fortunes available".to_string()),
+        },
+        Command::Add { message, id, force } => {
+            let fortune = Fortune {
+                id: id.unwrap_or_default(),
+                message,
+            };
+            print_fortune(&client.add(&fortune, force).await?);
+        }
+        Command::Delete { id } => match client.delete(&id).await? {
+            Some(fortune) => println!("deleted {}: {}", fortune.id, fortune.message),
+            None => return Err(format!("fortune {} not found", id)),
+        },
+        Command::Import { file, force } => {
+            let contents = std::fs::read_to_string(&file)
+                .map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+            let fortunes = parse_import(&contents)?;
+            let mut failed = 0;
+            for fortune in &fortunes {
+                if let Err(e) = client.add(fortune, force).await {
+                    eprintln!("failed to import '{}': {}", fortune.message, e);
+                    failed += 1;
+                }
+            }
+            println!("imported {} of {} fortunes", fortunes.len() - failed, fortunes.len());
+            if failed > 0 {
+                return Err(format!("{} fortunes failed to import", failed));
+            }
+        }
+        Command::Export { file } => {
+            let mut fortunes = client.list().await?;
+            fortunes.sort_by(|a, b| a.id.cmp(&b.id));
+            let json = serde_json::to_string_pretty(&fortunes).map_err(|e| e.to_string())?;
+            match file {
+                Some(path) => std::fs::write(&path, json + "\n")
+                    .map_err(|e| format!("failed to write {}: {}", path.display(), e))?,
+                None => println!("{}", json),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
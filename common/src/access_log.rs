@@ -0,0 +1,243 @@
+use crate::client_ip::{self, TrustedProxies};
+use crate::REQUEST_ID_HEADER;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use warp::http::{HeaderMap, Method};
+use warp::hyper::body::HttpBody;
+use warp::path::FullPath;
+use warp::{Filter, Reply};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    // `host - - [date] "method path" status bytes`
+    Common,
+    // Common plus the quoted referer and user agent
+    Combined,
+    // One JSON object per line
+    Json,
+}
+
+// One line of the access log in the JSON format
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    // Unix timestamp in seconds
+    timestamp: u64,
+    remote: Option<String>,
+    method: &'a str,
+    path: &'a str,
+    status: u16,
+    // Body size before compression; None for streamed bodies
+    bytes: Option<u64>,
+    latency_ms: u128,
+    user_agent: Option<&'a str>,
+    referer: Option<&'a str>,
+    request_id: Option<&'a str>,
+}
+
+enum Output {
+    Stdout,
+    File(RotatingFile),
+}
+
+// Appends to `path` and, once the next line would take it past `max_bytes`,
+// shifts it to `<path>.1`, `<path>.1` to `<path>.2` and so on, dropping
+// whatever is beyond `max_files`
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    // Opened on the first line
+    file: Option<tokio::fs::File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        self.file = None;
+        for n in (1..self.max_files).rev() {
+            rename_if_exists(&self.rotated(n), &self.rotated(n + 1)).await?;
+        }
+        if self.max_files == 0 {
+            tokio::fs::remove_file(&self.path).await?;
+        } else {
+            rename_if_exists(&self.path, &self.rotated(1)).await?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    async fn open(&mut self) -> std::io::Result<()> {
+        if self.file.is_none() {
+            let file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            self.size = file.metadata().await?.len();
+            self.file = Some(file);
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, line: &str) -> std::io::Result<()> {
+        self.open().await?;
+        if self.max_bytes > 0 && self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+            self.open().await?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(line.as_bytes()).await?;
+            // tokio hands writes to a blocking thread; wait for them to land
+            file.flush().await?;
+        }
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+async fn rename_if_exists(from: &Path, to: &Path) -> std::io::Result<()> {
+    match tokio::fs::rename(from, to).await {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+pub struct AccessLog {
+    format: Format,
+    proxies: TrustedProxies,
+    // Serializes writes so concurrent lines never interleave
+    output: Mutex<Output>,
+}
+
+impl AccessLog {
+    // Lines go to stdout when `path` is None. `max_bytes` of 0 never rotates.
+    pub fn new(format: Format, path: Option<PathBuf>, max_bytes: u64, max_files: usize, proxies: TrustedProxies) -> Self {
+        let output = match path {
+            Some(path) => Output::File(RotatingFile {
+                path,
+                max_bytes,
+                max_files,
+                file: None,
+                size: 0,
+            }),
+            None => Output::Stdout,
+        };
+        AccessLog {
+            format,
+            proxies,
+            output: Mutex::new(output),
+        }
+    }
+
+    async fn write(&self, line: String) {
+        match &mut *self.output.lock().await {
+            Output::Stdout => print!("{}", line),
+            Output::File(file) => {
+                if let Err(e) = file.write(&line).await {
+                    crate::error!("failed to write access log to {}: {}", file.path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+// `[10/Oct/2000:13:55:36 +0000]` as the common log format writes dates
+fn clf_date(now: SystemTime) -> String {
+    // `Tue, 10 Oct 2000 13:55:36 GMT`
+    let http = httpdate::fmt_http_date(now);
+    let parts: Vec<&str> = http.split(' ').collect();
+    match parts.as_slice() {
+        [_, day, month, year, time, _] => format!("[{}/{}/{}:{} +0000]", day, month, year, time),
+        _ => format!("[{}]", http),
+    }
+}
+
+// Quoted for common/combined, with `"` and `\` escaped
+fn quote(value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+        None => "\"-\"".to_string(),
+    }
+}
+
+fn format_line(format: Format, entry: &Entry<'_>, now: SystemTime) -> String {
+    let line = match format {
+        Format::Json => serde_json::to_string(entry).unwrap_or_default(),
+        Format::Common | Format::Combined => {
+            let mut line = format!(
+                "{} - - {} {} {} {}",
+                entry.remote.as_deref().unwrap_or("-"),
+                clf_date(now),
+                quote(Some(&format!("{} {}", entry.method, entry.path))),
+                entry.status,
+                entry.bytes.map_or_else(|| "-".to_string(), |bytes| bytes.to_string()),
+            );
+            if format == Format::Combined {
+                line.push_str(&format!(" {} {}", quote(entry.referer), quote(entry.user_agent)));
+            }
+            // Extra fields at the end, which log parsers skip
+            line.push_str(&format!(" {} {}ms", quote(entry.request_id), entry.latency_ms));
+            line
+        }
+    };
+    line + "\n"
+}
+
+// Writes a line per request when `log` is set. The request id is the one the
+// response carries, so it matches the service's other logs.
+pub fn wrap<F, R>(routes: F, log: Option<AccessLog>) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply + Send + 'static,
+{
+    let log = log.map(Arc::new);
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(client_ip::remote())
+        .and(routes)
+        .then(
+            move |started: Instant, method: Method, path: FullPath, headers: HeaderMap, peer: Option<SocketAddr>, reply: R| {
+                let log = log.clone();
+                async move {
+                    let res = reply.into_response();
+                    let Some(log) = log else {
+                        return res;
+                    };
+                    let now = SystemTime::now();
+                    let header = |map: &HeaderMap, name: &str| map.get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+                    let request_id = header(res.headers(), REQUEST_ID_HEADER).or_else(|| header(&headers, REQUEST_ID_HEADER));
+                    let entry = Entry {
+                        timestamp: now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+                        remote: log.proxies.resolve(peer, &headers).map(|ip| ip.to_string()),
+                        method: method.as_str(),
+                        path: path.as_str(),
+                        status: res.status().as_u16(),
+                        bytes: res.body().size_hint().exact(),
+                        latency_ms: started.elapsed().as_millis(),
+                        user_agent: headers.get("user-agent").and_then(|value| value.to_str().ok()),
+                        referer: headers.get("referer").and_then(|value| value.to_str().ok()),
+                        request_id: request_id.as_deref(),
+                    };
+                    let line = format_line(log.format, &entry, now);
+                    log.write(line).await;
+                    res
+                }
+            },
+        )
+}
@@ -0,0 +1,116 @@
+use ipnet::IpNet;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use warp::http::HeaderMap;
+use warp::Filter;
+
+// The address of the client behind reverse proxies. TRUSTED_PROXIES lists
+// the ingress or load balancer networks; X-Forwarded-For and Forwarded are
+// only believed when the connection comes from one of them. The chain is
+// walked from the nearest hop back, skipping trusted addresses, so a client
+// cannot pick its own address by sending the headers itself.
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<IpNet>>);
+
+impl TrustedProxies {
+    // Comma-separated CIDRs such as `10.0.0.0/8`, or single addresses
+    pub fn parse(list: &str) -> Result<TrustedProxies, String> {
+        let nets = list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| format!("'{}' is not an IP address or CIDR", entry))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TrustedProxies(Arc::new(nets)))
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(&ip))
+    }
+
+    // The client address for a connection from `peer`; None only when the
+    // peer is unknown, as for requests not made over a socket
+    pub fn resolve(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = peer?.ip();
+        if !self.contains(client) {
+            return Some(client);
+        }
+        // An entry that is not an address ends the walk at the hop that sent it
+        for hop in forwarded_chain(headers).into_iter().rev() {
+            let Some(hop) = hop else {
+                break;
+            };
+            client = hop;
+            if !self.contains(hop) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+// The addresses a request passed through, client first: from Forwarded when
+// present, otherwise X-Forwarded-For. Several header lines form one list.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| -> Vec<String> {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|entry| entry.trim().to_string())
+            .collect()
+    };
+    let forwarded = values("forwarded");
+    if !forwarded.is_empty() {
+        return forwarded.iter().map(|element| forwarded_for(element)).collect();
+    }
+    values("x-forwarded-for").iter().map(|entry| parse_node(entry)).collect()
+}
+
+// The `for=` parameter of one Forwarded element, e.g.
+// `for="[2001:db8::17]:4711";proto=https`
+fn forwarded_for(element: &str) -> Option<IpAddr> {
+    element
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+        .and_then(|(_, value)| parse_node(value.trim().trim_matches('"')))
+}
+
+// An address with an optional port; IPv6 with a port is bracketed.
+// Obfuscated identifiers such as `unknown` or `_hidden` are not addresses.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+}
+
+// The connection's address, set by the listener in server.rs
+#[derive(Debug, Clone, Copy)]
+pub struct Peer(pub SocketAddr);
+
+// The address of the connection, whichever listener accepted it
+pub fn remote() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<Peer>())
+        .map(|addr: Option<SocketAddr>, peer: Option<Peer>| addr.or(peer.map(|peer| peer.0)))
+}
+
+// The resolved client address, for handlers and other filters
+pub fn filter(proxies: TrustedProxies) -> impl Filter<Extract = (Option<IpAddr>,), Error = Infallible> + Clone {
+    warp::header::headers_cloned()
+        .and(remote())
+        .map(move |headers: HeaderMap, peer: Option<SocketAddr>| proxies.resolve(peer, &headers))
+}
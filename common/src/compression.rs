@@ -0,0 +1,99 @@
+use std::convert::Infallible;
+use warp::filters::BoxedFilter;
+use warp::http::{header, HeaderValue, Method};
+use warp::path::FullPath;
+use warp::{Filter, Rejection, Reply};
+
+// True if `encoding` is listed in Accept-Encoding without `q=0`
+fn accepts(header: &str, encoding: &str) -> bool {
+    header.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        let refused = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        name.eq_ignore_ascii_case(encoding) && !refused
+    })
+}
+
+fn negotiate(encoding: &'static str, skip: &'static [&'static str]) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(move |method: Method, path: FullPath, header: Option<String>| async move {
+            // HEAD responses have no body to compress
+            let wanted = method != Method::HEAD
+                && !skip.contains(&path.as_str())
+                && header.as_deref().is_some_and(|h| accepts(h, encoding));
+            if wanted {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+// A compressed body is a different representation, so its ETag gets the
+// encoding appended: `"abc"` is sent as `"abc-br"` or `"abc-gzip"`
+fn tag_encoding(mut res: warp::reply::Response, encoding: &str) -> warp::reply::Response {
+    let tagged = res
+        .headers()
+        .get(header::ETAG)
+        .and_then(|tag| tag.to_str().ok())
+        .and_then(|tag| tag.strip_suffix('"'))
+        .and_then(|tag| HeaderValue::from_str(&format!("{}-{}\"", tag, encoding)).ok());
+    if let Some(tagged) = tagged {
+        res.headers_mut().insert(header::ETAG, tagged);
+    }
+    res
+}
+
+// An If-None-Match candidate with the suffix `wrap` adds taken off again, so
+// it can be compared with the tag the route computes
+pub fn strip_encoding(candidate: &str) -> String {
+    for encoding in ["br", "gzip"] {
+        if let Some(tag) = candidate.strip_suffix(&format!("-{}\"", encoding)) {
+            return format!("{}\"", tag);
+        }
+    }
+    candidate.to_string()
+}
+
+// Compresses responses with brotli or gzip when the client asks for it,
+// except on the `skip` paths, such as streams that buffering would stall.
+// Every response then varies by Accept-Encoding. With `enabled` false the
+// routes are returned unchanged.
+pub fn wrap<F, R>(routes: F, enabled: bool, skip: &'static [&'static str]) -> BoxedFilter<(warp::reply::Response,)>
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply + Send + 'static,
+{
+    let plain = routes.clone().map(Reply::into_response);
+    if !enabled {
+        return plain.boxed();
+    }
+
+    let brotli = negotiate("br", skip)
+        .and(routes.clone())
+        .with(warp::compression::brotli())
+        .map(|reply| tag_encoding(Reply::into_response(reply), "br"));
+    let gzip = negotiate("gzip", skip)
+        .and(routes)
+        .with(warp::compression::gzip())
+        .map(|reply| tag_encoding(Reply::into_response(reply), "gzip"));
+
+    brotli
+        .or(gzip)
+        .unify()
+        .or(plain)
+        .unify()
+        .map(|mut res: warp::reply::Response| {
+            res.headers_mut().append(header::VARY, HeaderValue::from_static("accept-encoding"));
+            res
+        })
+        .boxed()
+}
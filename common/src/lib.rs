@@ -0,0 +1,10 @@
+// Request plumbing and logging shared by the backend and the frontend
+pub mod access_log;
+pub mod client_ip;
+pub mod compression;
+pub mod env_file;
+pub mod log;
+pub mod server;
+
+// The header carrying the id that correlates a request across both services
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
@@ -0,0 +1,136 @@
+use crate::client_ip::Peer;
+use std::convert::Infallible;
+use std::fmt;
+use std::net::SocketAddr;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::{UnixListener, UnixStream};
+use warp::filters::BoxedFilter;
+use warp::hyper::server::accept::{self, Accept};
+use warp::hyper::server::conn::{AddrIncoming, AddrStream};
+use warp::hyper::server::Builder;
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+use warp::hyper::{Body, Request, Server};
+
+// The plain HTTP listener, on hyper directly so the connection settings
+// warp::serve fixes can be tuned. The defaults match warp::serve. The HTTPS
+// listener still goes through warp, which does not expose them.
+
+// Where the plain HTTP listener accepts connections: the TCP port, or with
+// UNIX_SOCKET a Unix domain socket for a proxy on the same host
+#[derive(Debug, Clone)]
+pub enum Listen {
+    Tcp(SocketAddr),
+    Unix { path: PathBuf, mode: Option<u32> },
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listen::Tcp(addr) => write!(f, "http://{}", addr),
+            Listen::Unix { path, .. } => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tuning {
+    // HTTP/2 alongside HTTP/1.1 (h2c with prior knowledge); off serves HTTP/1.1 only
+    pub http2: bool,
+    // Reuse HTTP/1.1 connections for further requests
+    pub keep_alive: bool,
+    // TCP keepalive probes after this long idle, and HTTP/2 pings at this
+    // interval that close the connection when not answered within it
+    pub keep_alive_timeout: Option<Duration>,
+    // Streams one HTTP/2 connection may have open at once
+    pub max_concurrent_streams: Option<u32>,
+    pub tcp_nodelay: bool,
+}
+
+// Sees every request before the routes do, e.g. to add an extension a filter
+// expects; warp cannot add one itself
+pub type Prepare = fn(&mut Request<Body>);
+
+pub async fn run(routes: BoxedFilter<(warp::reply::Response,)>, listen: Listen, tuning: Tuning) {
+    run_with(routes, listen, tuning, |_| {}).await
+}
+
+pub async fn run_with(routes: BoxedFilter<(warp::reply::Response,)>, listen: Listen, tuning: Tuning, prepare: Prepare) {
+    match listen {
+        Listen::Tcp(addr) => run_tcp(routes, addr, tuning, prepare).await,
+        Listen::Unix { path, mode } => run_unix(routes, &path, mode, tuning, prepare).await,
+    }
+}
+
+async fn run_tcp(routes: BoxedFilter<(warp::reply::Response,)>, addr: SocketAddr, tuning: Tuning, prepare: Prepare) {
+    let mut incoming = AddrIncoming::bind(&addr).unwrap_or_else(|e| panic!("error binding to {}: {}", addr, e));
+    incoming.set_nodelay(tuning.tcp_nodelay);
+    incoming.set_keepalive(tuning.keep_alive_timeout);
+
+    let service = warp::service(routes);
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        // warp only knows the client address for connections it accepts
+        // itself, so it travels with the request (see client_ip::remote)
+        let peer = Peer(conn.remote_addr());
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req| {
+                req.extensions_mut().insert(peer);
+                prepare(&mut req);
+                service.clone().call(req)
+            }))
+        }
+    });
+    if let Err(e) = builder(incoming, &tuning).serve(make_service).await {
+        crate::error!("server error: {}", e);
+    }
+}
+
+// Requests over the socket have no client address, so the audit log and
+// per-client limits see them as coming from an unknown client. The TCP
+// settings do not apply.
+async fn run_unix(
+    routes: BoxedFilter<(warp::reply::Response,)>,
+    path: &Path,
+    mode: Option<u32>,
+    tuning: Tuning,
+    prepare: Prepare,
+) {
+    // A socket left by an earlier run would make the bind fail; anything
+    // else at the path is left for the bind to complain about
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        let _ = std::fs::remove_file(path);
+    }
+    let listener = UnixListener::bind(path).unwrap_or_else(|e| panic!("error binding to {}: {}", path.display(), e));
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .unwrap_or_else(|e| panic!("error setting the mode of {}: {}", path.display(), e));
+    }
+    let incoming = accept::poll_fn(move |cx| listener.poll_accept(cx).map(|accepted| Some(accepted.map(|(stream, _)| stream))));
+
+    let service = warp::service(routes);
+    let make_service = make_service_fn(move |_: &UnixStream| {
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req| {
+                prepare(&mut req);
+                service.clone().call(req)
+            }))
+        }
+    });
+    if let Err(e) = builder(incoming, &tuning).serve(make_service).await {
+        crate::error!("server error: {}", e);
+    }
+}
+
+fn builder<I: Accept>(incoming: I, tuning: &Tuning) -> Builder<I> {
+    let mut builder = Server::builder(incoming)
+        .http1_only(!tuning.http2)
+        .http1_keepalive(tuning.keep_alive)
+        .http2_max_concurrent_streams(tuning.max_concurrent_streams);
+    if let Some(timeout) = tuning.keep_alive_timeout {
+        builder = builder.http2_keep_alive_interval(timeout).http2_keep_alive_timeout(timeout);
+    }
+    builder
+}
@@ -0,0 +1,40 @@
+use fortune_common::env_file;
+
+fn pairs(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+#[test]
+fn env_files_skip_comments_exports_and_quotes() {
+    let vars = env_file::parse("# staging\nexport ADMIN_API_KEY=\"from-the-file\"\n\n  PORT = 9050 \nCOLLECTIONS='team-a:s3cret,team-b'\nEMPTY=\nURL=redis://host:6379/?a=b\n").unwrap();
+    assert_eq!(
+        vars,
+        pairs(&[
+            ("ADMIN_API_KEY", "from-the-file"),
+            ("PORT", "9050"),
+            ("COLLECTIONS", "team-a:s3cret,team-b"),
+            ("EMPTY", ""),
+            ("URL", "redis://host:6379/?a=b"),
+        ])
+    );
+    // Only matching quotes are taken off
+    assert_eq!(env_file::parse("A=\"half").unwrap(), pairs(&[("A", "\"half")]));
+}
+
+#[test]
+fn malformed_env_file_lines_are_reported_by_number() {
+    assert_eq!(env_file::parse("PORT").unwrap_err(), "line 1: expected KEY=VALUE");
+    assert_eq!(env_file::parse("# ok\nBAD-NAME=1").unwrap_err(), "line 2: invalid name 'BAD-NAME'");
+    assert_eq!(env_file::parse("=1").unwrap_err(), "line 1: invalid name ''");
+}
+
+#[test]
+fn env_file_read_errors_name_the_file() {
+    let path = std::env::temp_dir().join(format!("fortune-common-{}.env", std::process::id()));
+    std::fs::write(&path, "PORT=1\nnope\n").unwrap();
+    assert_eq!(env_file::read(&path).unwrap_err(), format!("{}: line 2: expected KEY=VALUE", path.display()));
+    let _ = std::fs::remove_file(&path);
+
+    let missing = env_file::read(&path).unwrap_err();
+    assert!(missing.starts_with(&format!("{}: ", path.display())), "{}", missing);
+}
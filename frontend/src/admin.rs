@@ -0,0 +1,505 @@
+use crate::i18n::Locale;
+use crate::request_id::{self, RequestId};
+use crate::resilience::{self, BackendError};
+use crate::session::{self, Session, Sessions};
+use crate::{csrf, flash, import, with_state, AppState, SharedState};
+use fortune_common::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::sync::Arc;
+use warp::http::{header, HeaderValue, StatusCode};
+use warp::multipart::FormData;
+use warp::{Filter, Rejection, Reply};
+
+// Server-rendered admin pages under /admin, behind the login in session.rs.
+// They call the backend with BACKEND_API_KEY on the signed-in admin's behalf.
+
+#[derive(Debug, Deserialize)]
+struct LoginForm {
+    user: String,
+    password: String,
+    #[serde(default)]
+    csrf_token: Option<String>,
+}
+
+// A form whose only field is the CSRF token, e.g. a delete button
+#[derive(Debug, Deserialize)]
+struct ActionForm {
+    #[serde(default)]
+    csrf_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EditForm {
+    message: String,
+    #[serde(default)]
+    author: Option<String>,
+    #[serde(default)]
+    csrf_token: Option<String>,
+}
+
+// The cookies a page is rendered with
+struct Visitor {
+    csrf: Option<String>,
+    flash: Option<String>,
+}
+
+fn visitor() -> impl Filter<Extract = (Visitor,), Error = Infallible> + Clone {
+    warp::cookie::optional::<String>(csrf::COOKIE)
+        .and(warp::cookie::optional::<String>(flash::COOKIE))
+        .map(|csrf, flash| Visitor { csrf, flash })
+}
+
+// Renders an admin page with the visitor's CSRF token, the signed-in user and
+// any flash message. Pages are never cached: they show live data.
+fn render(state: &AppState, name: &str, status: StatusCode, mut data: Value, session: Option<&Session>, visitor: &Visitor) -> warp::reply::Response {
+    let token = state.csrf.token_for(visitor.csrf.as_deref());
+    data["csrf_token"] = json!(token.value);
+    data["user"] = json!(session.map(|s| s.user.as_str()));
+    data["flash"] = json!(visitor.flash.as_deref().map(flash::decode));
+    let html = match state.templates.render(name, &data) {
+        Ok(html) => html,
+        Err(e) => {
+            error!("Template rendering failed: {}", e);
+            return warp::reply::with_status(format!("Template error: {}", e), StatusCode::INTERNAL_SERVER_ERROR).into_response();
+        }
+    };
+    let mut res = warp::reply::with_status(warp::reply::html(html), status).into_response();
+    let headers = res.headers_mut();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if token.fresh {
+        if let Ok(cookie) = HeaderValue::from_str(&csrf::cookie(&token)) {
+            headers.append(header::SET_COOKIE, cookie);
+        }
+    }
+    if visitor.flash.is_some() {
+        headers.append(header::SET_COOKIE, HeaderValue::from_static(flash::clear_cookie()));
+    }
+    res
+}
+
+fn redirect(location: &'static str, cookie: Option<&str>) -> warp::reply::Response {
+    let mut res = warp::reply::with_status(warp::reply::reply(), StatusCode::SEE_OTHER).into_response();
+    res.headers_mut().insert(header::LOCATION, HeaderValue::from_static(location));
+    if let Some(Ok(cookie)) = cookie.map(HeaderValue::from_str) {
+        res.headers_mut().insert(header::SET_COOKIE, cookie);
+    }
+    res
+}
+
+// A backend request carrying the admin API key and the caller's request id
+fn backend_admin(state: &AppState, method: reqwest::Method, path: &str, request_id: &RequestId) -> reqwest::RequestBuilder {
+    let mut request = state.http
+        .request(method, state.backends.url(path))
+        .header(request_id::HEADER, request_id.as_str());
+    if let Some(key) = &state.config.backend_api_key {
+        request = request.header("x-api-key", key);
+    }
+    request
+}
+
+async fn login_page(
+    request_id: RequestId,
+    _sessions: Arc<Sessions>,
+    session: Option<Session>,
+    visitor: Visitor,
+    state: SharedState,
+) -> Result<warp::reply::Response, Infallible> {
+    if session.is_some() {
+        return Ok(request_id.attach(redirect("/admin", None)));
+    }
+    Ok(request_id.attach(render(&state, "admin/login", StatusCode::OK, json!({}), None, &visitor)))
+}
+
+async fn login(
+    request_id: RequestId,
+    sessions: Arc<Sessions>,
+    _session: Option<Session>,
+    form: LoginForm,
+    submitted: csrf::Submitted,
+    visitor: Visitor,
+    state: SharedState,
+) -> Result<warp::reply::Response, Infallible> {
+    if !state.csrf.verify(&submitted, form.csrf_token.as_deref()) {
+        return Ok(request_id.attach(crate::csrf_rejected(Locale::default())));
+    }
+    match sessions.login(form.user.trim(), &form.password).await {
+        Some(cookie) => {
+            info!("[{}] admin {} logged in", request_id, form.user.trim());
+            Ok(request_id.attach(redirect("/admin", Some(&cookie))))
+        }
+        None => {
+            warn!("[{}] failed admin login for {:?}", request_id, form.user.trim());
+            let data = json!({"error": "Wrong user or password.", "login_user": form.user});
+            Ok(request_id.attach(render(&state, "admin/login", StatusCode::UNAUTHORIZED, data, None, &visitor)))
+        }
+    }
+}
+
+async fn logout(
+    request_id: RequestId,
+    form: ActionForm,
+    submitted: csrf::Submitted,
+    state: SharedState,
+) -> Result<warp::reply::Response, Infallible> {
+    if !state.csrf.verify(&submitted, form.csrf_token.as_deref()) {
+        return Ok(request_id.attach(crate::csrf_rejected(Locale::default())));
+    }
+    Ok(request_id.attach(redirect("/admin/login", Some(&session::logout_cookie()))))
+}
+
+async fn dashboard(request_id: RequestId, session: Session, visitor: Visitor, state: SharedState) -> Result<warp::reply::Response, Infallible> {
+    let request = backend_admin(&state, reqwest::Method::GET, "/admin/stats", &request_id);
+    let data = match resilience::get_with_retry(request, &request_id, &state.retry, &state.breaker, &state.backends).await {
+        Ok(response) if response.status().is_success() => match response.json::<Value>().await {
+            Ok(stats) => json!({"stats": stats}),
+            Err(e) => json!({"stats_error": format!("invalid response: {}", e)}),
+        },
+        Ok(response) => json!({"stats_error": backend_refusal(response.status())}),
+        Err(e) => json!({"stats_error": e.to_string()}),
+    };
+    Ok(request_id.attach(render(&state, "admin/index", StatusCode::OK, data, Some(&session), &visitor)))
+}
+
+// What to tell the admin when the backend refused an admin call
+fn backend_refusal(status: reqwest::StatusCode) -> String {
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED => "the backend rejected BACKEND_API_KEY".to_string(),
+        reqwest::StatusCode::FORBIDDEN => "the backend is read-only".to_string(),
+        status => format!("the backend answered {}", status),
+    }
+}
+
+// GETs a JSON document from the backend for an admin page, or the reason it
+// could not be had
+async fn backend_json(state: &AppState, path: &str, request_id: &RequestId) -> Result<Value, String> {
+    let request = backend_admin(state, reqwest::Method::GET, path, request_id);
+    match resilience::get_with_retry(request, request_id, &state.retry, &state.breaker, &state.backends).await {
+        Ok(response) if response.status().is_success() => {
+            response.json::<Value>().await.map_err(|e| format!("invalid response: {}", e))
+        }
+        Ok(response) => Err(backend_refusal(response.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// All published fortunes, straight from the backend rather than the cache
+async fn fortunes_page(request_id: RequestId, session: Session, visitor: Visitor, state: SharedState) -> Result<warp::reply::Response, Infallible> {
+    let data = match backend_json(&state, "/fortunes", &request_id).await {
+        Ok(fortunes) => json!({"fortunes": fortunes}),
+        Err(e) => json!({"error": e}),
+    };
+    Ok(request_id.attach(render(&state, "admin/fortunes", StatusCode::OK, data, Some(&session), &visitor)))
+}
+
+async fn moderation_page(request_id: RequestId, session: Session, visitor: Visitor, state: SharedState) -> Result<warp::reply::Response, Infallible> {
+    let data = match backend_json(&state, "/admin/moderation", &request_id).await {
+        Ok(fortunes) => json!({"fortunes": fortunes}),
+        Err(e) => json!({"error": e}),
+    };
+    Ok(request_id.attach(render(&state, "admin/moderation", StatusCode::OK, data, Some(&session), &visitor)))
+}
+
+async fn edit_page(id: String, request_id: RequestId, session: Session, visitor: Visitor, state: SharedState) -> Result<warp::reply::Response, Infallible> {
+    match backend_json(&state, &format!("/fortunes/{}", id), &request_id).await {
+        Ok(fortune) => {
+            let data = json!({"fortune": fortune});
+            Ok(request_id.attach(render(&state, "admin/edit", StatusCode::OK, data, Some(&session), &visitor)))
+        }
+        Err(e) => Ok(request_id.attach(flash::redirect("/admin/fortunes", &format!("Fortune {} cannot be edited: {}.", id, e)))),
+    }
+}
+
+// Saves an edited fortune by storing it again under its id, which keeps its
+// other fields and creation time
+async fn edit(
+    id: String,
+    request_id: RequestId,
+    session: Session,
+    form: EditForm,
+    submitted: csrf::Submitted,
+    state: SharedState,
+) -> Result<warp::reply::Response, Infallible> {
+    if !state.csrf.verify(&submitted, form.csrf_token.as_deref()) {
+        return Ok(request_id.attach(crate::csrf_rejected(Locale::default())));
+    }
+    let message = form.message.trim();
+    if message.is_empty() {
+        return Ok(request_id.attach(flash::redirect("/admin/fortunes", "A fortune needs a message.")));
+    }
+    let mut fortune = match backend_json(&state, &format!("/fortunes/{}", id), &request_id).await {
+        Ok(fortune) => fortune,
+        Err(e) => return Ok(request_id.attach(flash::redirect("/admin/fortunes", &format!("Fortune {} was not saved: {}.", id, e)))),
+    };
+    fortune["message"] = json!(message);
+    fortune["author"] = json!(form.author.as_deref().map(str::trim).filter(|a| !a.is_empty()));
+    let request = backend_admin(&state, reqwest::Method::POST, "/fortunes", &request_id).json(&fortune);
+    let outcome = match resilience::send_once(state.signed(request), &state.breaker, &state.backends).await {
+        Ok(response) if response.status().is_success() => {
+            state.cache.invalidate().await;
+            info!("[{}] admin {} edited fortune {}", request_id, session.user, id);
+            format!("Fortune {} saved.", id)
+        }
+        Ok(response) if response.status() == reqwest::StatusCode::CONFLICT => {
+            let existing = response.json::<Value>().await.ok();
+            let existing = existing.as_ref().and_then(|f| f["id"].as_str()).unwrap_or("?");
+            format!("Fortune {} was not saved: fortune {} has the same message.", id, existing)
+        }
+        Ok(response) if matches!(response.status(), reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::UNPROCESSABLE_ENTITY) => {
+            let reason = response.json::<String>().await.unwrap_or_else(|_| "rejected by the backend".to_string());
+            format!("Fortune {} was not saved: {}.", id, reason)
+        }
+        Ok(response) => format!("Fortune {} was not saved: {}.", id, backend_refusal(response.status())),
+        Err(BackendError::CircuitOpen) => "Backend temporarily unavailable, please try again shortly.".to_string(),
+        Err(e) => format!("Request failed: {}", e),
+    };
+    Ok(request_id.attach(flash::redirect("/admin/fortunes", &outcome)))
+}
+
+async fn import_page(request_id: RequestId, session: Session, visitor: Visitor, state: SharedState) -> Result<warp::reply::Response, Infallible> {
+    Ok(request_id.attach(render(&state, "admin/import", StatusCode::OK, json!({}), Some(&session), &visitor)))
+}
+
+// Imports an uploaded fortune file with the admin key, so nothing is held
+// for moderation
+async fn import(request_id: RequestId, session: Session, form: FormData, submitted: csrf::Submitted, state: SharedState) -> Result<warp::reply::Response, Infallible> {
+    let upload = match import::read_upload(form).await {
+        Ok(upload) => upload,
+        Err(e) => return Ok(request_id.attach(flash::redirect("/admin/import", &format!("The upload failed: {}.", e)))),
+    };
+    if !state.csrf.verify(&submitted, upload.csrf_token.as_deref()) {
+        return Ok(request_id.attach(crate::csrf_rejected(Locale::default())));
+    }
+    let fortunes = match import::parse(&upload) {
+        Ok(fortunes) => fortunes,
+        Err(e) => return Ok(request_id.attach(flash::redirect("/admin/import", &format!("Nothing was imported: {}.", e)))),
+    };
+    let summary = import::send(&state, &request_id, &fortunes, upload.force, true).await;
+    info!("[{}] admin {} imported a file: {}", request_id, session.user, summary.message());
+    Ok(request_id.attach(flash::redirect("/admin/fortunes", &summary.message())))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Delete,
+    Approve,
+    Reject,
+}
+
+impl Action {
+    fn request(self, state: &AppState, id: &str, request_id: &RequestId) -> reqwest::RequestBuilder {
+        match self {
+            Action::Delete => backend_admin(state, reqwest::Method::DELETE, &format!("/fortunes/{}", id), request_id),
+            Action::Approve => backend_admin(state, reqwest::Method::POST, &format!("/admin/fortunes/{}/approve", id), request_id),
+            Action::Reject => backend_admin(state, reqwest::Method::POST, &format!("/admin/fortunes/{}/reject", id), request_id),
+        }
+    }
+
+    // The page the action was taken from
+    fn page(self) -> &'static str {
+        match self {
+            Action::Delete => "/admin/fortunes",
+            Action::Approve | Action::Reject => "/admin/moderation",
+        }
+    }
+
+    fn done(self) -> &'static str {
+        match self {
+            Action::Delete => "deleted",
+            Action::Approve => "approved",
+            Action::Reject => "rejected",
+        }
+    }
+}
+
+// Deletes or moderates a fortune and goes back to its page with the outcome
+async fn act(
+    id: String,
+    action: Action,
+    request_id: RequestId,
+    session: Session,
+    form: ActionForm,
+    submitted: csrf::Submitted,
+    state: SharedState,
+) -> Result<warp::reply::Response, Infallible> {
+    if !state.csrf.verify(&submitted, form.csrf_token.as_deref()) {
+        return Ok(request_id.attach(crate::csrf_rejected(Locale::default())));
+    }
+    let message = match resilience::send_once(state.signed(action.request(&state, &id, &request_id)), &state.breaker, &state.backends).await {
+        Ok(response) if response.status().is_success() => {
+            state.cache.invalidate().await;
+            info!("[{}] admin {} {} fortune {}", request_id, session.user, action.done(), id);
+            format!("Fortune {} {}.", id, action.done())
+        }
+        Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => format!("Fortune {} was not found.", id),
+        Ok(response) => format!("Fortune {} was not {}: {}.", id, action.done(), backend_refusal(response.status())),
+        Err(BackendError::CircuitOpen) => "Backend temporarily unavailable, please try again shortly.".to_string(),
+        Err(e) => format!("Request failed: {}", e),
+    };
+    Ok(request_id.attach(flash::redirect(action.page(), &message)))
+}
+
+pub fn routes(state: SharedState) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone {
+    let sessions = state.sessions.clone();
+    // Without an admin account every admin path is a plain 404, before the
+    // method filters could answer 405
+    let configured = sessions.is_some();
+    let admin = warp::path("admin")
+        .and(warp::any().and_then(move || async move {
+            if configured {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        }))
+        .untuple_one();
+
+    let login_page = admin
+        .and(warp::path("login"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(session::enabled(sessions.clone()))
+        .and(visitor())
+        .and(with_state(state.clone()))
+        .and_then(login_page);
+
+    let login = admin
+        .and(warp::path("login"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(request_id::filter())
+        .and(session::enabled(sessions.clone()))
+        .and(warp::body::form())
+        .and(csrf::submitted())
+        .and(visitor())
+        .and(with_state(state.clone()))
+        .and_then(login);
+
+    let logout = admin
+        .and(warp::path("logout"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(request_id::filter())
+        .and(warp::body::form())
+        .and(csrf::submitted())
+        .and(with_state(state.clone()))
+        .and_then(logout);
+
+    let dashboard = admin
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(session::required(sessions.clone()))
+        .and(visitor())
+        .and(with_state(state.clone()))
+        .and_then(dashboard);
+
+    let fortunes = admin
+        .and(warp::path("fortunes"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(session::required(sessions.clone()))
+        .and(visitor())
+        .and(with_state(state.clone()))
+        .and_then(fortunes_page);
+
+    let moderation = admin
+        .and(warp::path("moderation"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(session::required(sessions.clone()))
+        .and(visitor())
+        .and(with_state(state.clone()))
+        .and_then(moderation_page);
+
+    let edit_page = admin
+        .and(warp::path("fortunes"))
+        .and(warp::path::param())
+        .and(warp::path("edit"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(session::required(sessions.clone()))
+        .and(visitor())
+        .and(with_state(state.clone()))
+        .and_then(edit_page);
+
+    let edit = admin
+        .and(warp::path("fortunes"))
+        .and(warp::path::param())
+        .and(warp::path("edit"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(request_id::filter())
+        .and(session::required(sessions.clone()))
+        .and(warp::body::form())
+        .and(csrf::submitted())
+        .and(with_state(state.clone()))
+        .and_then(edit);
+
+    let import_page = admin
+        .and(warp::path("import"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(session::required(sessions.clone()))
+        .and(visitor())
+        .and(with_state(state.clone()))
+        .and_then(import_page);
+
+    let import = admin
+        .and(warp::path("import"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(request_id::filter())
+        .and(session::required(sessions.clone()))
+        .and(warp::multipart::form().max_length(state.config.max_import_bytes))
+        .and(csrf::submitted())
+        .and(with_state(state.clone()))
+        .and_then(import);
+
+    let action = warp::path("delete")
+        .map(|| Action::Delete)
+        .or(warp::path("approve").map(|| Action::Approve))
+        .unify()
+        .or(warp::path("reject").map(|| Action::Reject))
+        .unify();
+    let act = admin
+        .and(warp::path("fortunes"))
+        .and(warp::path::param())
+        .and(action)
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(request_id::filter())
+        .and(session::required(sessions))
+        .and(warp::body::form())
+        .and(csrf::submitted())
+        .and(with_state(state))
+        .and_then(act);
+
+    login_page
+        .or(login)
+        .unify()
+        .or(logout)
+        .unify()
+        .or(dashboard)
+        .unify()
+        .or(fortunes)
+        .unify()
+        .or(moderation)
+        .unify()
+        .or(edit_page)
+        .unify()
+        .or(edit)
+        .unify()
+        .or(import_page)
+        .unify()
+        .or(import)
+        .unify()
+        .or(act)
+        .unify()
+}
@@ -0,0 +1,277 @@
+use fortune_common::info;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use crate::csrf::{self, Csrf};
+use crate::theme::{self, Theme};
+use fortune_common::compression;
+use warp::http::{header, HeaderValue, Response, StatusCode};
+use warp::hyper::Body;
+use warp::path::Tail;
+use warp::{Filter, Rejection};
+
+// Copies of the static files built into the binary, served when STATIC_DIR
+// does not exist
+const EMBEDDED: &[(&str, &[u8])] = &[
+    ("index.html", include_bytes!("../static/index.html")),
+    ("script.js", include_bytes!("../static/script.js")),
+];
+
+// Versioned URLs never change content, so they may be cached for a year
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+// Everything else is revalidated with the ETag or Last-Modified on each use
+const REVALIDATE: &str = "no-cache";
+
+struct Asset {
+    body: Vec<u8>,
+    content_type: String,
+    // Short content hash, used both as the ETag and as the `?v=` version
+    hash: String,
+    last_modified: SystemTime,
+}
+
+impl Asset {
+    fn new(name: &str, body: Vec<u8>, last_modified: SystemTime) -> Self {
+        Asset {
+            content_type: mime_guess::from_path(name).first_or_octet_stream().to_string(),
+            hash: hash(&body),
+            body,
+            last_modified,
+        }
+    }
+
+    // Pages with a form carry a CSRF field filled in per visitor
+    fn has_csrf_field(&self) -> bool {
+        self.content_type.starts_with("text/html")
+            && String::from_utf8_lossy(&self.body).contains(csrf::PLACEHOLDER)
+    }
+
+    // The page with the visitor's token in place of the placeholder
+    fn with_token(&self, token: &str) -> Asset {
+        let body = String::from_utf8_lossy(&self.body).replace(csrf::PLACEHOLDER, token).into_bytes();
+        Asset {
+            content_type: self.content_type.clone(),
+            hash: hash(&body),
+            body,
+            last_modified: self.last_modified,
+        }
+    }
+
+    fn etag(&self) -> String {
+        format!("\"{}\"", self.hash)
+    }
+}
+
+// Static files held in memory, with HTML references to the other files
+// rewritten to carry their content hash (`script.js?v=<hash>`). The active
+// theme's stylesheet is one of them, as theme.css.
+pub struct Assets {
+    files: HashMap<String, Asset>,
+}
+
+impl Assets {
+    // Reads every file in `dir`; falls back to the embedded copies when the
+    // directory is missing or unreadable
+    pub fn load(dir: &Path, theme: &Theme) -> Self {
+        match read_dir(dir) {
+            Ok(files) => Self::from_files(files, theme),
+            Err(e) => {
+                info!("static files not read from {} ({}); using embedded copies", dir.display(), e);
+                Self::embedded(theme)
+            }
+        }
+    }
+
+    fn embedded(theme: &Theme) -> Self {
+        let built = SystemTime::now();
+        Self::from_files(
+            EMBEDDED
+                .iter()
+                .map(|(name, body)| (name.to_string(), body.to_vec(), built))
+                .collect(),
+            theme,
+        )
+    }
+
+    // HTML pages get the theme's name in place of its placeholder
+    fn from_files(mut files: Vec<(String, Vec<u8>, SystemTime)>, theme: &Theme) -> Self {
+        files.retain(|(name, _, _)| name != theme::FILE);
+        files.push((theme::FILE.to_string(), theme.css.clone().into_bytes(), SystemTime::now()));
+        let mut assets: HashMap<String, Asset> = files
+            .into_iter()
+            .map(|(name, body, modified)| {
+                let body = if name.ends_with(".html") {
+                    String::from_utf8_lossy(&body).replace(theme::PLACEHOLDER, &theme.name).into_bytes()
+                } else {
+                    body
+                };
+                let asset = Asset::new(&name, body, modified);
+                (name, asset)
+            })
+            .collect();
+
+        let versions: Vec<(String, String)> = assets
+            .iter()
+            .filter(|(name, _)| !name.ends_with(".html"))
+            .map(|(name, asset)| (name.clone(), asset.hash.clone()))
+            .collect();
+        for (name, asset) in assets.iter_mut().filter(|(name, _)| name.ends_with(".html")) {
+            let html = String::from_utf8_lossy(&asset.body);
+            let busted = bust(&html, &versions);
+            if busted != html {
+                *asset = Asset::new(name, busted.into_bytes(), asset.last_modified);
+            }
+        }
+        Assets { files: assets }
+    }
+
+    // `/<name>?v=<hash>`, or just `/<name>` for a file that is not here
+    pub fn url(&self, name: &str) -> String {
+        match self.files.get(name) {
+            Some(asset) => format!("/{}?v={}", name, asset.hash),
+            None => format!("/{}", name),
+        }
+    }
+}
+
+fn hash(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn read_dir(dir: &Path) -> std::io::Result<Vec<(String, Vec<u8>, SystemTime)>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        files.push((name, std::fs::read(entry.path())?, metadata.modified()?));
+    }
+    Ok(files)
+}
+
+// Appends `?v=<hash>` to `src` and `href` attributes that point at a known file
+fn bust(html: &str, versions: &[(String, String)]) -> String {
+    let mut html = html.to_string();
+    for (name, hash) in versions {
+        for attr in ["src", "href"] {
+            for prefix in ["", "/"] {
+                let plain = format!("{}=\"{}{}\"", attr, prefix, name);
+                let versioned = format!("{}=\"{}{}?v={}\"", attr, prefix, name, hash);
+                html = html.replace(&plain, &versioned);
+            }
+        }
+    }
+    html
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct Version {
+    v: Option<String>,
+}
+
+// True when the client's cached copy is still current: a matching
+// If-None-Match, or else an If-Modified-Since no older than the file
+fn not_modified(asset: &Asset, if_none_match: Option<&str>, if_modified_since: Option<&str>) -> bool {
+    if let Some(tags) = if_none_match {
+        let etag = asset.etag();
+        // Compressed copies carry the encoding in their tag
+        return tags
+            .split(',')
+            .map(|tag| compression::strip_encoding(tag.trim()))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+    let since = if_modified_since.and_then(|value| httpdate::parse_http_date(value).ok());
+    match (since, asset.last_modified.duration_since(SystemTime::UNIX_EPOCH)) {
+        // HTTP dates have whole seconds
+        (Some(since), Ok(modified)) => since
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .is_ok_and(|since| since.as_secs() >= modified.as_secs()),
+        _ => false,
+    }
+}
+
+fn respond(asset: &Asset, version: Option<&str>, if_none_match: Option<&str>, if_modified_since: Option<&str>) -> Response<Body> {
+    let cache_control = if version == Some(asset.hash.as_str()) { IMMUTABLE } else { REVALIDATE };
+    let mut res = if not_modified(asset, if_none_match, if_modified_since) {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = StatusCode::NOT_MODIFIED;
+        res
+    } else {
+        let mut res = Response::new(Body::from(asset.body.clone()));
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(&asset.content_type).unwrap_or(HeaderValue::from_static("application/octet-stream")),
+        );
+        res
+    };
+    let headers = res.headers_mut();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    if let Ok(etag) = HeaderValue::from_str(&asset.etag()) {
+        headers.insert(header::ETAG, etag);
+    }
+    if let Ok(modified) = HeaderValue::from_str(&httpdate::fmt_http_date(asset.last_modified)) {
+        headers.insert(header::LAST_MODIFIED, modified);
+    }
+    res
+}
+
+// GET (or HEAD) of a static file; `/` serves index.html
+// A page with a CSRF field. A visitor who just got a new token also gets a
+// new cookie, so their cached copy of the page is never reused then.
+fn respond_page(asset: &Asset, token: csrf::Token, if_none_match: Option<&str>, if_modified_since: Option<&str>) -> Response<Body> {
+    let page = asset.with_token(&token.value);
+    if !token.fresh {
+        return respond(&page, None, if_none_match, if_modified_since);
+    }
+    let mut res = respond(&page, None, None, None);
+    if let Ok(cookie) = HeaderValue::from_str(&csrf::cookie(&token)) {
+        res.headers_mut().insert(header::SET_COOKIE, cookie);
+    }
+    res
+}
+
+pub fn filter(assets: Arc<Assets>, csrf: Arc<Csrf>) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    warp::get()
+        .or(warp::head())
+        .unify()
+        .and(warp::path::tail())
+        .and(warp::query::<Version>().or(warp::any().map(Version::default)).unify())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(warp::header::optional::<String>("if-modified-since"))
+        .and(warp::cookie::optional::<String>(csrf::COOKIE))
+        .and_then(move |tail: Tail, version: Version, if_none_match: Option<String>, if_modified_since: Option<String>, cookie: Option<String>| {
+            let assets = assets.clone();
+            let csrf = csrf.clone();
+            async move {
+                let name = match tail.as_str() {
+                    "" => "index.html",
+                    name => name,
+                };
+                match assets.files.get(name) {
+                    Some(asset) if asset.has_csrf_field() => Ok(respond_page(
+                        asset,
+                        csrf.token_for(cookie.as_deref()),
+                        if_none_match.as_deref(),
+                        if_modified_since.as_deref(),
+                    )),
+                    Some(asset) => Ok(respond(
+                        asset,
+                        version.v.as_deref(),
+                        if_none_match.as_deref(),
+                        if_modified_since.as_deref(),
+                    )),
+                    None => Err(warp::reject::not_found()),
+                }
+            }
+        })
+}
@@ -0,0 +1,60 @@
+use crate::Fortune;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+struct CacheEntry {
+    fetched_at: Instant,
+    fortunes: Vec<Fortune>,
+    // Backend ETag of the list, used to revalidate once the entry expires
+    etag: Option<String>,
+}
+
+// TTL cache for the backend's fortune list. A TTL of zero disables caching.
+pub struct FortuneCache {
+    ttl: Duration,
+    entry: RwLock<Option<CacheEntry>>,
+}
+
+impl FortuneCache {
+    pub fn new(ttl: Duration) -> Self {
+        FortuneCache {
+            ttl,
+            entry: RwLock::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    pub async fn get(&self) -> Option<Vec<Fortune>> {
+        let entry = self.entry.read().await;
+        entry
+            .as_ref()
+            .filter(|e| e.fetched_at.elapsed() < self.ttl)
+            .map(|e| e.fortunes.clone())
+    }
+
+    // The cached list and its ETag, kept past the TTL so it can be revalidated
+    pub async fn stale(&self) -> Option<(String, Vec<Fortune>)> {
+        let entry = self.entry.read().await;
+        entry
+            .as_ref()
+            .and_then(|e| Some((e.etag.clone()?, e.fortunes.clone())))
+    }
+
+    pub async fn set(&self, fortunes: Vec<Fortune>, etag: Option<String>) {
+        if !self.is_enabled() {
+            return;
+        }
+        *self.entry.write().await = Some(CacheEntry {
+            fetched_at: Instant::now(),
+            fortunes,
+            etag,
+        });
+    }
+
+    pub async fn invalidate(&self) {
+        *self.entry.write().await = None;
+    }
+}
@@ -0,0 +1,152 @@
+use serde::Deserialize;
+use std::fmt::Write;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Open Graph image size, which Slack and most social sites crop the least
+const WIDTH: u32 = 1200;
+const HEIGHT: u32 = 630;
+const PADDING: u32 = 90;
+const MAX_LINES: usize = 6;
+// Average glyph width of the sans-serif stack relative to the font size
+const GLYPH_WIDTH: f32 = 0.55;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+    Sepia,
+}
+
+struct Palette {
+    background: &'static str,
+    text: &'static str,
+    accent: &'static str,
+}
+
+impl Theme {
+    fn palette(self) -> Palette {
+        match self {
+            Theme::Light => Palette { background: "#f8f9fa", text: "#212529", accent: "#6c757d" },
+            Theme::Dark => Palette { background: "#1e1f24", text: "#f1f3f5", accent: "#adb5bd" },
+            Theme::Sepia => Palette { background: "#f4ecd8", text: "#5b4636", accent: "#8a6d4f" },
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Columns a text takes up: one per grapheme cluster, two for wide ones such
+// as CJK characters and emoji
+fn columns(text: &str) -> usize {
+    text.graphemes(true).map(|g| g.width().clamp(1, 2)).sum()
+}
+
+// Greedy word wrap to at most `width` columns per line; words longer than a
+// line are split between grapheme clusters, so no character is cut apart
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.to_string();
+        if columns(&word) > width {
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            let mut rest = String::new();
+            for grapheme in word.graphemes(true) {
+                if !rest.is_empty() && columns(&rest) + columns(grapheme) > width {
+                    lines.push(std::mem::take(&mut rest));
+                }
+                rest.push_str(grapheme);
+            }
+            word = rest;
+        }
+        if !line.is_empty() && columns(&line) + 1 + columns(&word) > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// Picks the largest font size that fits the message in MAX_LINES, then
+// truncates with an ellipsis if even the smallest size does not fit
+fn layout(message: &str) -> (u32, Vec<String>) {
+    let mut font_size = 64;
+    loop {
+        let per_line = ((WIDTH - 2 * PADDING) as f32 / (font_size as f32 * GLYPH_WIDTH)) as usize;
+        let mut lines = wrap(message, per_line.max(1));
+        if lines.len() <= MAX_LINES || font_size <= 32 {
+            if lines.len() > MAX_LINES {
+                lines.truncate(MAX_LINES);
+                lines[MAX_LINES - 1].push('…');
+            }
+            return (font_size, lines);
+        }
+        font_size -= 4;
+    }
+}
+
+// Renders the fortune as a standalone SVG card
+pub fn render(message: &str, author: Option<&str>, theme: Theme) -> String {
+    let palette = theme.palette();
+    let (font_size, lines) = layout(message);
+    let line_height = font_size * 13 / 10;
+    let author_height = if author.is_some() { line_height } else { 0 };
+    let block = line_height * lines.len() as u32 + author_height;
+    let top = HEIGHT.saturating_sub(block) / 2 + font_size;
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = WIDTH,
+        h = HEIGHT
+    );
+    let _ = write!(svg, r#"<rect width="100%" height="100%" fill="{}"/>"#, palette.background);
+    let _ = write!(
+        svg,
+        r#"<rect x="30" y="30" width="{}" height="{}" rx="24" fill="none" stroke="{}" stroke-width="4"/>"#,
+        WIDTH - 60,
+        HEIGHT - 60,
+        palette.accent
+    );
+    let _ = write!(
+        svg,
+        r#"<text x="{}" font-family="Georgia, 'Times New Roman', serif" font-size="{}" fill="{}" text-anchor="middle">"#,
+        WIDTH / 2,
+        font_size,
+        palette.text
+    );
+    for (i, line) in lines.iter().enumerate() {
+        let _ = write!(svg, r#"<tspan x="{}" y="{}">{}</tspan>"#, WIDTH / 2, top + line_height * i as u32, escape(line));
+    }
+    svg.push_str("</text>");
+    if let Some(author) = author {
+        let _ = write!(
+            svg,
+            r#"<text x="{}" y="{}" font-family="Helvetica, Arial, sans-serif" font-size="{}" fill="{}" text-anchor="middle">&#8212; {}</text>"#,
+            WIDTH / 2,
+            top + line_height * lines.len() as u32,
+            font_size * 2 / 3,
+            palette.accent,
+            escape(author)
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
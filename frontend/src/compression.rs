@@ -0,0 +1,16 @@
+use std::convert::Infallible;
+use warp::filters::BoxedFilter;
+use warp::{Filter, Reply};
+
+// Paths whose responses are never compressed; buffering would stall the SSE stream
+const SKIP: &[&str] = &["/api/stream"];
+
+// Compresses responses with brotli or gzip when the client asks for it; see
+// fortune_common::compression
+pub fn wrap<F, R>(routes: F, enabled: bool) -> BoxedFilter<(warp::reply::Response,)>
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply + Send + 'static,
+{
+    fortune_common::compression::wrap(routes, enabled, SKIP)
+}
@@ -0,0 +1,605 @@
+use crate::access_log::{self, AccessLog};
+use crate::client_ip::TrustedProxies;
+use crate::discovery;
+use crate::security_headers;
+use crate::server;
+use crate::templates;
+use crate::theme::Theme;
+use crate::tts;
+use fortune_common::{env_file, log};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use warp::http::HeaderValue;
+
+// All settings are read from the environment once at startup. Field names map
+// to upper-cased env vars, e.g. `frontend_port` is read from FRONTEND_PORT.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default = "default_bind_addr")]
+    pub bind_addr: IpAddr,
+    #[serde(default = "default_frontend_port")]
+    pub frontend_port: u16,
+    #[serde(default = "default_frontend_tls_port")]
+    pub frontend_tls_port: u16,
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    #[serde(default)]
+    pub tls_only: bool,
+    #[serde(default = "default_backend_dns")]
+    pub backend_dns: String,
+    #[serde(default = "default_backend_port")]
+    pub backend_port: u16,
+    // Backends to take turns over instead of BACKEND_DNS (see discovery.rs)
+    pub backends: Option<String>,
+    // SRV record naming the backends, e.g. _http._tcp.backend.default.svc.cluster.local
+    pub backend_srv: Option<String>,
+    #[serde(default = "default_backend_eject_secs")]
+    pub backend_eject_secs: u64,
+    #[serde(default = "default_backend_timeout_ms")]
+    pub backend_timeout_ms: u64,
+    #[serde(default = "default_backend_retry_attempts")]
+    pub backend_retry_attempts: u32,
+    #[serde(default = "default_backend_retry_base_ms")]
+    pub backend_retry_base_ms: u64,
+    #[serde(default = "default_backend_retry_max_ms")]
+    pub backend_retry_max_ms: u64,
+    #[serde(default = "default_breaker_failure_threshold")]
+    pub breaker_failure_threshold: u32,
+    #[serde(default = "default_breaker_open_secs")]
+    pub breaker_open_secs: u64,
+    #[serde(default = "default_fortune_cache_ttl_secs")]
+    pub fortune_cache_ttl_secs: u64,
+    #[serde(default = "default_stream_interval_secs")]
+    pub stream_interval_secs: u64,
+    #[serde(default = "default_compression_enabled")]
+    pub compression_enabled: bool,
+    // Connection tuning for the plain HTTP listener (see server.rs)
+    #[serde(default = "default_http2")]
+    pub http2: bool,
+    #[serde(default = "default_http_keep_alive")]
+    pub http_keep_alive: bool,
+    #[serde(default)]
+    pub keep_alive_timeout_secs: u64,
+    #[serde(default)]
+    pub http2_max_concurrent_streams: u32,
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    // Serve plain HTTP on this Unix domain socket instead of the TCP port
+    pub unix_socket: Option<PathBuf>,
+    // Octal permissions for the socket, e.g. `660`
+    pub unix_socket_mode: Option<String>,
+    #[serde(default = "default_static_dir")]
+    pub static_dir: PathBuf,
+    // Templates that replace the built-in ones of the same name, e.g.
+    // `fortune.html` or `admin/layout.html`, and themes/<name>.css stylesheets
+    pub template_dir: Option<PathBuf>,
+    // `default`, `dark`, `sepia`, or a stylesheet in TEMPLATE_DIR/themes
+    #[serde(default = "default_theme")]
+    pub theme: String,
+    // Lines below it are not logged (see fortune_common::log)
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    // Refuse to start until the backend's /healthz answers
+    #[serde(default)]
+    pub strict_startup: bool,
+    #[serde(default = "default_startup_deadline_secs")]
+    pub startup_deadline_secs: u64,
+    // Keeps the last fortune list fetched from the backend across restarts
+    pub last_good_file: Option<PathBuf>,
+    #[serde(default = "default_feed_size")]
+    pub feed_size: usize,
+    // Absolute URL of the site used for links in the feed, e.g.
+    // https://fortunes.example.com; defaults to http://<Host header>
+    pub public_url: Option<String>,
+    // Signs CSRF tokens; shared by all replicas so a form rendered by one can
+    // be posted to another. A random secret is used when unset.
+    pub csrf_secret: Option<String>,
+    // The admin pages are off unless both are set; the hash is an Argon2 PHC
+    // string such as `$argon2id$v=19$...`
+    pub admin_user: Option<String>,
+    pub admin_password_hash: Option<String>,
+    // Signs admin session cookies; a random secret is used when unset
+    pub session_secret: Option<String>,
+    #[serde(default = "default_session_ttl_secs")]
+    pub session_ttl_secs: u64,
+    // Sent as X-API-Key on the admin pages' calls to the backend
+    pub backend_api_key: Option<String>,
+    // Shared with the backend, which then only accepts writes signed with it
+    pub request_signing_secret: Option<String>,
+    // Largest fortune file accepted for import, and how many of its fortunes
+    // go to the backend per batch request
+    #[serde(default = "default_max_import_bytes")]
+    pub max_import_bytes: u64,
+    #[serde(default = "default_import_batch_size")]
+    pub import_batch_size: usize,
+    // Forward /api/backend/<path> to the backend's /<path>, with request
+    // bodies of up to MAX_PASSTHROUGH_BYTES
+    #[serde(default = "default_backend_passthrough")]
+    pub backend_passthrough: bool,
+    #[serde(default = "default_max_passthrough_bytes")]
+    pub max_passthrough_bytes: u64,
+    // Text to speech for /api/fortune-audio: `command` runs TTS_COMMAND, `api`
+    // calls an OpenAI-compatible speech API; off when unset
+    pub tts_provider: Option<tts::ProviderKind>,
+    #[serde(default = "default_tts_command")]
+    pub tts_command: String,
+    #[serde(default = "default_tts_api_url")]
+    pub tts_api_url: String,
+    pub tts_api_key: Option<String>,
+    #[serde(default = "default_tts_model")]
+    pub tts_model: String,
+    #[serde(default = "default_tts_voice")]
+    pub tts_voice: String,
+    #[serde(default = "default_tts_timeout_ms")]
+    pub tts_timeout_ms: u64,
+    // Security headers on every response; an empty value leaves one out.
+    // STRICT_TRANSPORT_SECURITY is only sent when TLS is on
+    #[serde(default = "default_content_security_policy")]
+    pub content_security_policy: String,
+    #[serde(default = "default_content_type_options")]
+    pub content_type_options: String,
+    #[serde(default = "default_referrer_policy")]
+    pub referrer_policy: String,
+    #[serde(default = "default_strict_transport_security")]
+    pub strict_transport_security: String,
+    // Extra headers as a JSON object, e.g. `{"X-Frame-Options":"DENY"}`
+    pub response_headers: Option<String>,
+    // Comma-separated CIDRs of reverse proxies whose X-Forwarded-For and
+    // Forwarded headers name the client, e.g. `10.0.0.0/8,192.168.1.7`
+    pub trusted_proxies: Option<String>,
+    // Opt-in access log: `common`, `combined` or `json`
+    pub access_log: Option<access_log::Format>,
+    // Written to stdout when unset; rotated once it reaches ACCESS_LOG_MAX_BYTES
+    pub access_log_file: Option<PathBuf>,
+    #[serde(default = "default_access_log_max_bytes")]
+    pub access_log_max_bytes: u64,
+    #[serde(default = "default_access_log_max_files")]
+    pub access_log_max_files: usize,
+}
+
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    pub addr: SocketAddr,
+    pub only: bool,
+}
+
+fn default_bind_addr() -> IpAddr {
+    IpAddr::from([0, 0, 0, 0])
+}
+
+fn default_frontend_port() -> u16 {
+    8080
+}
+
+fn default_frontend_tls_port() -> u16 {
+    8443
+}
+
+fn default_backend_dns() -> String {
+    "localhost".to_string()
+}
+
+fn default_backend_port() -> u16 {
+    9000
+}
+
+fn default_backend_eject_secs() -> u64 {
+    10
+}
+
+fn default_backend_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_backend_retry_attempts() -> u32 {
+    3
+}
+
+fn default_backend_retry_base_ms() -> u64 {
+    100
+}
+
+fn default_backend_retry_max_ms() -> u64 {
+    2000
+}
+
+fn default_breaker_failure_threshold() -> u32 {
+    5
+}
+
+fn default_breaker_open_secs() -> u64 {
+    30
+}
+
+fn default_fortune_cache_ttl_secs() -> u64 {
+    10
+}
+
+fn default_stream_interval_secs() -> u64 {
+    10
+}
+
+fn default_compression_enabled() -> bool {
+    true
+}
+
+fn default_http2() -> bool {
+    true
+}
+
+fn default_http_keep_alive() -> bool {
+    true
+}
+
+fn default_tcp_nodelay() -> bool {
+    true
+}
+
+fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8).ok().filter(|mode| *mode <= 0o777)
+}
+
+fn default_static_dir() -> PathBuf {
+    PathBuf::from("./static")
+}
+
+fn default_theme() -> String {
+    "default".to_string()
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_startup_deadline_secs() -> u64 {
+    30
+}
+
+fn default_feed_size() -> usize {
+    20
+}
+
+fn default_session_ttl_secs() -> u64 {
+    8 * 60 * 60
+}
+
+fn default_max_import_bytes() -> u64 {
+    4 * 1024 * 1024
+}
+
+fn default_import_batch_size() -> usize {
+    100
+}
+
+fn default_backend_passthrough() -> bool {
+    true
+}
+
+fn default_max_passthrough_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_tts_command() -> String {
+    "espeak-ng --stdin --stdout | lame --quiet - -".to_string()
+}
+
+fn default_tts_api_url() -> String {
+    "https://api.openai.com/v1/audio/speech".to_string()
+}
+
+fn default_tts_model() -> String {
+    "tts-1".to_string()
+}
+
+fn default_tts_voice() -> String {
+    "alloy".to_string()
+}
+
+fn default_tts_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_content_security_policy() -> String {
+    security_headers::DEFAULT_CSP.to_string()
+}
+
+fn default_content_type_options() -> String {
+    "nosniff".to_string()
+}
+
+fn default_referrer_policy() -> String {
+    "strict-origin-when-cross-origin".to_string()
+}
+
+fn default_strict_transport_security() -> String {
+    "max-age=31536000; includeSubDomains".to_string()
+}
+
+fn default_access_log_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_access_log_max_files() -> usize {
+    5
+}
+
+const REDACTED: &str = "<redacted>";
+
+impl Config {
+    pub fn from_env() -> Result<Config, String> {
+        let config: Config = envy::from_env().map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Settings from command-line `overrides`, then the environment, then
+    // the `KEY=VALUE` lines of `file`
+    pub fn load(file: Option<&Path>, overrides: &[(&str, String)]) -> Result<Config, String> {
+        let mut vars: HashMap<String, String> = HashMap::new();
+        if let Some(file) = file {
+            vars.extend(env_file::read(file)?);
+        }
+        vars.extend(std::env::vars());
+        vars.extend(overrides.iter().map(|(key, value)| (key.to_string(), value.clone())));
+        let config: Config = envy::from_iter(vars).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // A copy safe to print, with secrets and the admin password hash replaced
+    pub fn redacted(&self) -> Config {
+        let hide = |value: &Option<String>| value.as_ref().map(|_| REDACTED.to_string());
+        Config {
+            csrf_secret: hide(&self.csrf_secret),
+            session_secret: hide(&self.session_secret),
+            backend_api_key: hide(&self.backend_api_key),
+            admin_password_hash: hide(&self.admin_password_hash),
+            request_signing_secret: hide(&self.request_signing_secret),
+            tts_api_key: hide(&self.tts_api_key),
+            ..self.clone()
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for (key, port) in [
+            ("FRONTEND_PORT", self.frontend_port),
+            ("FRONTEND_TLS_PORT", self.frontend_tls_port),
+            ("BACKEND_PORT", self.backend_port),
+        ] {
+            if port == 0 {
+                return Err(format!("{} must be between 1 and 65535", key));
+            }
+        }
+
+        if self.backend_dns.is_empty() {
+            return Err("BACKEND_DNS must not be empty".to_string());
+        }
+
+        if self.backends.is_some() && self.backend_srv.is_some() {
+            return Err("BACKENDS and BACKEND_SRV cannot both be set".to_string());
+        }
+
+        if let Some(list) = &self.backends {
+            discovery::parse_list(list, self.backend_port).map_err(|e| format!("invalid BACKENDS: {}", e))?;
+        }
+
+        if self.backend_srv.as_ref().is_some_and(|name| name.trim().is_empty()) {
+            return Err("BACKEND_SRV must not be empty".to_string());
+        }
+
+        if self.backend_timeout_ms == 0 {
+            return Err("BACKEND_TIMEOUT_MS must be greater than 0".to_string());
+        }
+
+        if self.backend_retry_attempts == 0 {
+            return Err("BACKEND_RETRY_ATTEMPTS must be at least 1".to_string());
+        }
+
+        if self.backend_retry_base_ms > self.backend_retry_max_ms {
+            return Err("BACKEND_RETRY_BASE_MS must not exceed BACKEND_RETRY_MAX_MS".to_string());
+        }
+
+        if self.breaker_failure_threshold == 0 {
+            return Err("BREAKER_FAILURE_THRESHOLD must be at least 1".to_string());
+        }
+
+        if self.stream_interval_secs == 0 {
+            return Err("STREAM_INTERVAL_SECS must be at least 1".to_string());
+        }
+
+        if self.strict_startup && self.startup_deadline_secs == 0 {
+            return Err("STARTUP_DEADLINE_SECS must be at least 1".to_string());
+        }
+
+        if self.feed_size == 0 {
+            return Err("FEED_SIZE must be at least 1".to_string());
+        }
+
+        if let Some(url) = &self.public_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!("PUBLIC_URL '{}' must start with http:// or https://", url));
+            }
+        }
+
+        if self.csrf_secret.as_ref().is_some_and(|secret| secret.len() < 16) {
+            return Err("CSRF_SECRET must be at least 16 characters".to_string());
+        }
+
+        match (&self.admin_user, &self.admin_password_hash) {
+            (Some(user), Some(hash)) => {
+                if user.trim().is_empty() {
+                    return Err("ADMIN_USER must not be empty".to_string());
+                }
+                if let Err(e) = argon2::PasswordHash::new(hash) {
+                    return Err(format!("ADMIN_PASSWORD_HASH is not an Argon2 hash: {}", e));
+                }
+            }
+            (None, None) => {}
+            _ => return Err("ADMIN_USER and ADMIN_PASSWORD_HASH must be set together".to_string()),
+        }
+
+        if self.session_secret.as_ref().is_some_and(|secret| secret.len() < 16) {
+            return Err("SESSION_SECRET must be at least 16 characters".to_string());
+        }
+
+        if self.session_ttl_secs == 0 {
+            return Err("SESSION_TTL_SECS must be at least 1".to_string());
+        }
+
+        if self.max_import_bytes == 0 {
+            return Err("MAX_IMPORT_BYTES must be at least 1".to_string());
+        }
+
+        if self.import_batch_size == 0 {
+            return Err("IMPORT_BATCH_SIZE must be at least 1".to_string());
+        }
+
+        if self.request_signing_secret.as_ref().is_some_and(|secret| secret.len() < 16) {
+            return Err("REQUEST_SIGNING_SECRET must be at least 16 characters".to_string());
+        }
+
+        if self.max_passthrough_bytes == 0 {
+            return Err("MAX_PASSTHROUGH_BYTES must be at least 1".to_string());
+        }
+
+        if self.tts_timeout_ms == 0 {
+            return Err("TTS_TIMEOUT_MS must be at least 1".to_string());
+        }
+
+        if self.tts_provider == Some(tts::ProviderKind::Api) && self.tts_api_key.is_none() {
+            return Err("TTS_PROVIDER=api requires TTS_API_KEY".to_string());
+        }
+
+        for (key, value) in [
+            ("CONTENT_SECURITY_POLICY", &self.content_security_policy),
+            ("CONTENT_TYPE_OPTIONS", &self.content_type_options),
+            ("REFERRER_POLICY", &self.referrer_policy),
+            ("STRICT_TRANSPORT_SECURITY", &self.strict_transport_security),
+        ] {
+            if HeaderValue::from_str(value).is_err() {
+                return Err(format!("{} is not a valid header value", key));
+            }
+        }
+
+        if let Some(headers) = &self.response_headers {
+            security_headers::parse_custom(headers)?;
+        }
+
+        if let Some(mode) = &self.unix_socket_mode {
+            if self.unix_socket.is_none() {
+                return Err("UNIX_SOCKET_MODE requires UNIX_SOCKET".to_string());
+            }
+            if parse_mode(mode).is_none() {
+                return Err(format!("UNIX_SOCKET_MODE '{}' must be octal permissions such as 660", mode));
+            }
+        }
+
+        if self.access_log_file.is_some() && self.access_log.is_none() {
+            return Err("ACCESS_LOG_FILE requires ACCESS_LOG to choose a format".to_string());
+        }
+
+        if let Some(proxies) = &self.trusted_proxies {
+            TrustedProxies::parse(proxies).map_err(|e| format!("TRUSTED_PROXIES: {}", e))?;
+        }
+
+        if let Some(dir) = &self.template_dir {
+            if !dir.is_dir() {
+                return Err(format!("TEMPLATE_DIR '{}' is not a directory", dir.display()));
+            }
+            templates::registry(Some(dir), &self.theme, "").map_err(|e| format!("TEMPLATE_DIR: {}", e))?;
+        }
+
+        Theme::load(&self.theme, self.template_dir.as_deref())?;
+
+        self.log_level.parse::<log::Level>()?;
+
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => {
+                for path in [cert, key] {
+                    if !Path::new(path).is_file() {
+                        return Err(format!("TLS file '{}' does not exist", path));
+                    }
+                }
+            }
+            (None, None) => {}
+            _ => return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string()),
+        }
+
+        Ok(())
+    }
+
+    pub fn listen(&self) -> server::Listen {
+        match &self.unix_socket {
+            Some(path) => server::Listen::Unix {
+                path: path.clone(),
+                mode: self.unix_socket_mode.as_deref().and_then(parse_mode),
+            },
+            None => server::Listen::Tcp(self.listen_addr()),
+        }
+    }
+
+    // 0 leaves idle connections unprobed and HTTP/2 streams unlimited
+    pub fn server_tuning(&self) -> server::Tuning {
+        server::Tuning {
+            http2: self.http2,
+            keep_alive: self.http_keep_alive,
+            keep_alive_timeout: match self.keep_alive_timeout_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            max_concurrent_streams: match self.http2_max_concurrent_streams {
+                0 => None,
+                streams => Some(streams),
+            },
+            tcp_nodelay: self.tcp_nodelay,
+        }
+    }
+
+    pub fn listen_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_addr, self.frontend_port)
+    }
+
+    // HTTPS is served on its own port so it can run alongside plain HTTP unless TLS_ONLY is set.
+    pub fn tls(&self) -> Option<TlsConfig> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => Some(TlsConfig {
+                cert_path: cert.clone(),
+                key_path: key.clone(),
+                addr: SocketAddr::new(self.bind_addr, self.frontend_tls_port),
+                only: self.tls_only,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn backend_timeout(&self) -> Duration {
+        Duration::from_millis(self.backend_timeout_ms)
+    }
+
+    pub fn startup_deadline(&self) -> Duration {
+        Duration::from_secs(self.startup_deadline_secs)
+    }
+
+    pub fn trusted_proxies(&self) -> TrustedProxies {
+        self.trusted_proxies
+            .as_deref()
+            .and_then(|proxies| TrustedProxies::parse(proxies).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn access_log(&self) -> Option<AccessLog> {
+        self.access_log.map(|format| {
+            AccessLog::new(
+                format,
+                self.access_log_file.clone(),
+                self.access_log_max_bytes,
+                self.access_log_max_files,
+                self.trusted_proxies(),
+            )
+        })
+    }
+}
@@ -0,0 +1,89 @@
+use crate::signing::{now_secs, Signer};
+use warp::Filter;
+
+// Double-submit CSRF tokens. A page with a form gets the visitor's token both
+// in a cookie and in its hidden field; a POST must send the token back in the
+// form (or the X-CSRF-Token header) matching the cookie. Tokens are signed,
+// so a cookie planted by another site or subdomain is not accepted either.
+
+pub const COOKIE: &str = "csrf";
+pub const HEADER: &str = "x-csrf-token";
+// Replaced in HTML pages with the visitor's token
+pub const PLACEHOLDER: &str = "{{csrf_token}}";
+// Tokens older than this are replaced on the next page load and refused
+const MAX_AGE_SECS: u64 = 24 * 60 * 60;
+
+pub struct Csrf {
+    signer: Signer,
+}
+
+// The token a page is rendered with; `fresh` when the visitor had no valid
+// one and the cookie has to be set
+pub struct Token {
+    pub value: String,
+    pub fresh: bool,
+}
+
+// The tokens a POST carried outside its body
+#[derive(Debug, Default)]
+pub struct Submitted {
+    cookie: Option<String>,
+    header: Option<String>,
+}
+
+impl Csrf {
+    pub fn new(secret: Option<&str>) -> Self {
+        Csrf {
+            signer: Signer::new(secret, "CSRF_SECRET"),
+        }
+    }
+
+    // `<issued at>.<nonce>.<signature>`
+    fn issue(&self) -> String {
+        self.signer.sign(&format!("{}.{}", now_secs(), hex::encode(rand::random::<[u8; 16]>())))
+    }
+
+    fn is_valid(&self, token: &str) -> bool {
+        self.signer
+            .verify(token)
+            .and_then(|payload| payload.split_once('.'))
+            .and_then(|(issued, _)| issued.parse::<u64>().ok())
+            .is_some_and(|issued| now_secs().saturating_sub(issued) < MAX_AGE_SECS)
+    }
+
+    // Keeps the visitor's token while it is valid so cached pages still match it
+    pub fn token_for(&self, cookie: Option<&str>) -> Token {
+        match cookie {
+            Some(token) if self.is_valid(token) => Token {
+                value: token.to_string(),
+                fresh: false,
+            },
+            _ => Token {
+                value: self.issue(),
+                fresh: true,
+            },
+        }
+    }
+
+    // True when the cookie holds a valid token and the form field or header
+    // repeats it
+    pub fn verify(&self, submitted: &Submitted, field: Option<&str>) -> bool {
+        let Some(cookie) = submitted.cookie.as_deref() else {
+            return false;
+        };
+        let echoed = field.or(submitted.header.as_deref());
+        echoed == Some(cookie) && self.is_valid(cookie)
+    }
+}
+
+pub fn cookie(token: &Token) -> String {
+    format!("{}={}; Path=/; Max-Age={}; SameSite=Strict", COOKIE, token.value, MAX_AGE_SECS)
+}
+
+pub fn submitted() -> impl Filter<Extract = (Submitted,), Error = std::convert::Infallible> + Clone {
+    warp::cookie::optional::<String>(COOKIE)
+        .and(warp::header::optional::<String>(HEADER))
+        .map(|cookie, header| Submitted { cookie, header })
+        .or(warp::any().map(Submitted::default))
+        .unify()
+}
@@ -0,0 +1,111 @@
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+
+// Visual themes for /api/random?decorated=...: an emoji border around the
+// message and a `fortune-theme-<name>` class the pages style. `day` gives
+// everyone the same theme for the UTC day, and a holiday's own theme on and
+// around it; `request` picks a fresh everyday theme for every fortune.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decorated {
+    #[default]
+    #[serde(alias = "false")]
+    Off,
+    #[serde(alias = "true")]
+    Day,
+    Request,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub name: &'static str,
+    emoji: &'static str,
+}
+
+pub const DAY_SECS: u64 = 24 * 60 * 60;
+
+// Rotated through day by day
+const EVERYDAY: &[Theme] = &[
+    Theme { name: "cookie", emoji: "🥠" },
+    Theme { name: "stars", emoji: "✨" },
+    Theme { name: "leaves", emoji: "🍃" },
+    Theme { name: "waves", emoji: "🌊" },
+    Theme { name: "sunny", emoji: "☀️" },
+    Theme { name: "moon", emoji: "🌙" },
+    Theme { name: "blossom", emoji: "🌸" },
+];
+
+// (first month, first day, last month, last day, theme); a range may wrap the year
+const HOLIDAYS: &[(u32, u32, u32, u32, Theme)] = &[
+    (12, 31, 1, 1, Theme { name: "new-year", emoji: "🎆" }),
+    (2, 13, 2, 14, Theme { name: "valentine", emoji: "💝" }),
+    (10, 25, 10, 31, Theme { name: "halloween", emoji: "🎃" }),
+    (12, 20, 12, 26, Theme { name: "winter-holidays", emoji: "🎄" }),
+];
+
+// Year, month and day of the days since 1970-01-01 (Howard Hinnant's
+// civil_from_days)
+pub fn civil_date(days: u64) -> (i64, u32, u32) {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn month_day(days: u64) -> (u32, u32) {
+    let (_, month, day) = civil_date(days);
+    (month, day)
+}
+
+fn holiday(days: u64) -> Option<Theme> {
+    let date = month_day(days);
+    HOLIDAYS
+        .iter()
+        .find(|(from_month, from_day, to_month, to_day, _)| {
+            let (from, to) = ((*from_month, *from_day), (*to_month, *to_day));
+            if from <= to {
+                from <= date && date <= to
+            } else {
+                date >= from || date <= to
+            }
+        })
+        .map(|(_, _, _, _, theme)| *theme)
+}
+
+// The theme of the UTC day `days` after the epoch
+pub fn of_the_day(days: u64) -> Theme {
+    holiday(days).unwrap_or(EVERYDAY[(days % EVERYDAY.len() as u64) as usize])
+}
+
+impl Decorated {
+    pub fn theme(self, days: u64) -> Option<Theme> {
+        match self {
+            Decorated::Off => None,
+            Decorated::Day => Some(of_the_day(days)),
+            Decorated::Request => EVERYDAY.choose(&mut rand::thread_rng()).copied(),
+        }
+    }
+}
+
+impl Theme {
+    // `message` is already rendered: plain text, or sanitized HTML when `html`
+    pub fn wrap(self, message: &str, html: bool) -> String {
+        if html {
+            format!(
+                "<div class=\"fortune-theme fortune-theme-{name}\" data-theme=\"{name}\"><span aria-hidden=\"true\">{emoji}</span> {message} <span aria-hidden=\"true\">{emoji}</span></div>",
+                name = self.name,
+                emoji = self.emoji,
+                message = message,
+            )
+        } else {
+            format!("{emoji} {message} {emoji}", emoji = self.emoji, message = message)
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::config::Config;
+use crate::SharedState;
+use fortune_common::{error, info, warn};
+use hickory_resolver::TokioAsyncResolver;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+// Where requests to the backend go: BACKEND_DNS:BACKEND_PORT, the fixed
+// BACKENDS list, or the targets of the BACKEND_SRV record, looked up again
+// whenever its TTL runs out so backends can come and go without a restart.
+// Requests take turns over the instances, and an instance whose request
+// failed is left out for BACKEND_EJECT_SECS, unless all of them are.
+
+// Bounds on the wait between SRV lookups, whatever the TTL says
+const MIN_REFRESH: Duration = Duration::from_secs(1);
+const MAX_REFRESH: Duration = Duration::from_secs(300);
+// Wait before trying again after a failed lookup
+const RETRY_REFRESH: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    // As it goes in a URL, so IPv6 addresses keep their brackets
+    pub host: String,
+    pub port: u16,
+    // SRV priority, lower preferred; 0 for the other sources
+    pub priority: u16,
+}
+
+impl Instance {
+    fn authority(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+// BACKENDS: comma-separated `host:port` entries, e.g.
+// `backend-a:9000,backend-b:9000,[fd00::5]`; the port defaults to BACKEND_PORT
+pub fn parse_list(list: &str, default_port: u16) -> Result<Vec<Instance>, String> {
+    let instances: Vec<Instance> = list
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let url = Url::parse(&format!("http://{}", entry))
+                .ok()
+                .filter(|url| url.path() == "/" && url.query().is_none() && url.username().is_empty() && !entry.contains('/'))
+                .ok_or_else(|| format!("'{}' is not a host or host:port", entry))?;
+            Ok(Instance {
+                host: url.host_str().unwrap_or_default().to_string(),
+                port: url.port().unwrap_or(default_port),
+                priority: 0,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    if instances.is_empty() {
+        return Err("BACKENDS lists no backends".to_string());
+    }
+    Ok(instances)
+}
+
+pub struct Backends {
+    // BACKEND_DNS:BACKEND_PORT, also used while a BACKEND_SRV record has not
+    // been found yet
+    fallback: Instance,
+    srv: Option<(String, TokioAsyncResolver)>,
+    instances: RwLock<Vec<Instance>>,
+    next: AtomicUsize,
+    eject_for: Duration,
+    // Instances left out until the given time, by host:port
+    ejected: Mutex<HashMap<String, Instant>>,
+    ejections: AtomicU64,
+    lookup_failures: AtomicU64,
+}
+
+impl Backends {
+    pub fn from_config(config: &Config) -> Self {
+        let fallback = Instance {
+            host: config.backend_dns.clone(),
+            port: config.backend_port,
+            priority: 0,
+        };
+        let instances = match &config.backends {
+            Some(list) => parse_list(list, config.backend_port).unwrap_or_default(),
+            None => vec![fallback.clone()],
+        };
+        let srv = config.backend_srv.as_ref().map(|name| {
+            let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+                warn!("failed to read the system DNS configuration, using public resolvers: {}", e);
+                TokioAsyncResolver::tokio(Default::default(), Default::default())
+            });
+            (name.clone(), resolver)
+        });
+        Backends {
+            fallback,
+            srv,
+            instances: RwLock::new(instances),
+            next: AtomicUsize::new(0),
+            eject_for: Duration::from_secs(config.backend_eject_secs),
+            ejected: Mutex::new(HashMap::new()),
+            ejections: AtomicU64::new(0),
+            lookup_failures: AtomicU64::new(0),
+        }
+    }
+
+    // The URL of `path` on the next instance in turn
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.pick().authority(), path)
+    }
+
+    // Sends a retry to the next instance in turn, which skips the one that
+    // just failed if there is another
+    pub fn repoint(&self, url: &mut Url) {
+        let instance = self.pick();
+        if url.set_host(Some(&instance.host)).is_ok() {
+            let _ = url.set_port(Some(instance.port));
+        }
+    }
+
+    // Round robin over the instances of the best priority among those not
+    // left out; over all of them when every one is
+    fn pick(&self) -> Instance {
+        let instances = self.instances.read().unwrap();
+        if instances.is_empty() {
+            return self.fallback.clone();
+        }
+        let now = Instant::now();
+        let ejected = self.ejected.lock().unwrap();
+        let mut candidates: Vec<&Instance> = instances
+            .iter()
+            .filter(|instance| ejected.get(&instance.authority()).is_none_or(|until| *until <= now))
+            .collect();
+        if candidates.is_empty() {
+            candidates = instances.iter().collect();
+        }
+        let best = candidates.iter().map(|instance| instance.priority).min().unwrap_or_default();
+        candidates.retain(|instance| instance.priority == best);
+        candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()].clone()
+    }
+
+    fn known(&self, url: &Url) -> Option<String> {
+        let authority = format!("{}:{}", url.host_str()?, url.port_or_known_default()?);
+        let instances = self.instances.read().unwrap();
+        instances.iter().any(|instance| instance.authority() == authority).then_some(authority)
+    }
+
+    // Leaves out the instance `url` went to after a connection error or 5xx
+    pub fn record_failure(&self, url: Option<&Url>) {
+        let Some(authority) = url.and_then(|url| self.known(url)) else {
+            return;
+        };
+        if self.eject_for.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut ejected = self.ejected.lock().unwrap();
+        if ejected.get(&authority).is_none_or(|until| *until <= now) {
+            self.ejections.fetch_add(1, Ordering::Relaxed);
+            warn!("backend {} failed, leaving it out for {}s", authority, self.eject_for.as_secs());
+        }
+        ejected.insert(authority, now + self.eject_for);
+    }
+
+    pub fn record_success(&self, url: &Url) {
+        if let Some(authority) = self.known(url) {
+            self.ejected.lock().unwrap().remove(&authority);
+        }
+    }
+
+    // Looks up the BACKEND_SRV record and takes its targets, keeping the
+    // previous ones when the lookup fails. Returns when to look again, or
+    // None without BACKEND_SRV.
+    pub async fn refresh(&self) -> Option<Duration> {
+        let (name, resolver) = self.srv.as_ref()?;
+        let lookup = match resolver.srv_lookup(name.as_str()).await {
+            Ok(lookup) => lookup,
+            Err(e) => {
+                self.lookup_failures.fetch_add(1, Ordering::Relaxed);
+                error!("SRV lookup of {} failed: {}", name, e);
+                return Some(RETRY_REFRESH);
+            }
+        };
+        let mut found: Vec<Instance> = lookup
+            .iter()
+            .map(|srv| Instance {
+                host: srv.target().to_utf8().trim_end_matches('.').to_string(),
+                port: srv.port(),
+                priority: srv.priority(),
+            })
+            .collect();
+        found.sort_by(|a, b| a.priority.cmp(&b.priority).then_with(|| a.authority().cmp(&b.authority())));
+        let mut instances = self.instances.write().unwrap();
+        if *instances != found {
+            let listed: Vec<String> = found.iter().map(Instance::authority).collect();
+            info!("SRV {} now points at {}", name, listed.join(", "));
+            *instances = found;
+        }
+        let ttl = lookup.as_lookup().valid_until().saturating_duration_since(Instant::now());
+        Some(ttl.clamp(MIN_REFRESH, MAX_REFRESH))
+    }
+
+    pub fn metrics(&self) -> String {
+        let now = Instant::now();
+        let ejected = self.ejected.lock().unwrap().values().filter(|until| **until > now).count();
+        format!(
+            "frontend_backend_instances {}\n\
+             frontend_backend_instances_ejected {}\n\
+             frontend_backend_ejections_total {}\n\
+             frontend_backend_srv_lookup_failures_total {}\n",
+            self.instances.read().unwrap().len(),
+            ejected,
+            self.ejections.load(Ordering::Relaxed),
+            self.lookup_failures.load(Ordering::Relaxed),
+        )
+    }
+}
+
+// Keeps the BACKEND_SRV targets current; does nothing without BACKEND_SRV
+pub fn spawn_refresh(state: SharedState) {
+    tokio::spawn(async move {
+        while let Some(wait) = state.backends.refresh().await {
+            tokio::time::sleep(wait).await;
+        }
+    });
+}
@@ -0,0 +1,63 @@
+use crate::{markdown, Fortune};
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use unicode_segmentation::UnicodeSegmentation;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// RFC 822 date as RSS expects, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+fn rfc822(secs: u64) -> String {
+    httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+// The `limit` newest fortunes, newest first. Fortunes without a creation time
+// (from a backend that does not record one) come last, in list order.
+pub fn latest(mut fortunes: Vec<Fortune>, limit: usize) -> Vec<Fortune> {
+    fortunes.sort_by_key(|f| std::cmp::Reverse(f.created_at));
+    fortunes.truncate(limit);
+    fortunes
+}
+
+// Renders an RSS 2.0 channel; `base` is the absolute URL of the site root
+pub fn render(fortunes: &[Fortune], base: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let updated = fortunes.iter().filter_map(|f| f.created_at).max().unwrap_or(now);
+
+    let mut xml = String::new();
+    xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/elements/1.1/"><channel>"#);
+    xml.push_str("<title>Simple Fortune Cookie</title>");
+    let _ = write!(xml, "<link>{}/</link>", escape(base));
+    let _ = write!(
+        xml,
+        r#"<atom:link href="{}/feed.xml" rel="self" type="application/rss+xml"/>"#,
+        escape(base)
+    );
+    xml.push_str("<description>The latest fortunes in the jar</description>");
+    let _ = write!(xml, "<lastBuildDate>{}</lastBuildDate>", rfc822(updated));
+    for fortune in fortunes {
+        xml.push_str("<item>");
+        let message = markdown::to_plain(&fortune.message);
+        let title: String = message.graphemes(true).take(80).collect();
+        let _ = write!(xml, "<title>{}</title>", escape(&title));
+        let _ = write!(xml, "<description>{}</description>", escape(&message));
+        if let Some(author) = &fortune.author {
+            let _ = write!(xml, "<dc:creator>{}</dc:creator>", escape(author));
+        }
+        let _ = write!(xml, r#"<guid isPermaLink="false">fortune-{}</guid>"#, escape(&fortune.id));
+        if let Some(created_at) = fortune.created_at {
+            let _ = write!(xml, "<pubDate>{}</pubDate>", rfc822(created_at));
+        }
+        xml.push_str("</item>");
+    }
+    xml.push_str("</channel></rss>");
+    xml
+}
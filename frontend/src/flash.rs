@@ -0,0 +1,58 @@
+use warp::http::{header, StatusCode};
+use warp::Reply;
+
+// Cookie holding a one-off message for the next page load; script.js shows
+// it and deletes the cookie
+pub const COOKIE: &str = "flash";
+
+// Redirects a plain form submission back to a page with `message` to show there
+pub fn redirect(location: &str, message: &str) -> warp::reply::Response {
+    let cookie = format!("{}={}; Path=/; Max-Age=60; SameSite=Lax", COOKIE, encode(message));
+    let mut res = warp::reply::with_status(warp::reply::reply(), StatusCode::SEE_OTHER).into_response();
+    let headers = res.headers_mut();
+    headers.insert(header::LOCATION, location.parse().expect("redirect location is a valid header"));
+    headers.insert(header::SET_COOKIE, cookie.parse().expect("encoded cookie is a valid header"));
+    res
+}
+
+// Percent-encodes everything but unreserved characters, which keeps the value
+// a valid cookie and readable with decodeURIComponent
+fn encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Set alongside a page that showed the flash message, so it is shown once
+pub fn clear_cookie() -> &'static str {
+    "flash=; Path=/; Max-Age=0; SameSite=Lax"
+}
+
+// Reverses `encode`; invalid escapes are kept as they are
+pub fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
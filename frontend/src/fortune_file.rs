@@ -0,0 +1,28 @@
+use crate::Fortune;
+
+// Fortune files as uploaded by admins: a JSON array of fortunes, or the
+// classic `fortune` format with entries separated by lines holding only `%`.
+// Ids in a JSON file are kept; entries without one get an id from the backend.
+
+pub fn parse(contents: &str) -> Result<Vec<Fortune>, String> {
+    let fortunes: Vec<Fortune> = if contents.trim_start().starts_with('[') {
+        serde_json::from_str(contents).map_err(|e| format!("invalid JSON: {}", e))?
+    } else {
+        contents
+            .replace("\r\n", "\n")
+            .split("\n%")
+            .map(|entry| entry.trim().trim_start_matches('%').trim())
+            .filter(|entry| !entry.is_empty())
+            .map(|message| Fortune {
+                id: String::new(),
+                message: message.to_string(),
+                author: None,
+                created_at: None,
+            })
+            .collect()
+    };
+    if fortunes.is_empty() {
+        return Err("the file holds no fortunes".to_string());
+    }
+    Ok(fortunes)
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::sync::OnceLock;
+use warp::http::header::{HeaderMap, HeaderValue, ACCEPT_LANGUAGE, CONTENT_LANGUAGE, VARY};
+use warp::{Filter, Reply};
+
+// Translations of the messages the public endpoints answer with, picked from
+// the caller's Accept-Language header. Each catalog is a flat JSON object in
+// locales/ compiled into the binary; a message missing from one falls back
+// to English, as does a header naming no supported language. Adding a
+// language is a new JSON file and a line in BUNDLED.
+
+const BUNDLED: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.json")),
+    ("de", include_str!("../locales/de.json")),
+    ("es", include_str!("../locales/es.json")),
+    ("fr", include_str!("../locales/fr.json")),
+];
+
+const DEFAULT: &str = "en";
+
+fn catalogs() -> &'static HashMap<&'static str, HashMap<String, String>> {
+    static CATALOGS: OnceLock<HashMap<&'static str, HashMap<String, String>>> = OnceLock::new();
+    CATALOGS.get_or_init(|| {
+        BUNDLED
+            .iter()
+            .map(|(language, json)| {
+                let catalog = serde_json::from_str(json)
+                    .unwrap_or_else(|e| panic!("locales/{}.json is not a JSON object of strings: {}", language, e));
+                (*language, catalog)
+            })
+            .collect()
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale(&'static str);
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale(DEFAULT)
+    }
+}
+
+impl Locale {
+    // The message for `key`, in English if this catalog lacks it, or the key
+    // itself if no catalog has it
+    pub fn text(self, key: &str) -> String {
+        let catalogs = catalogs();
+        [self.0, DEFAULT]
+            .iter()
+            .find_map(|language| catalogs.get(language)?.get(key))
+            .cloned()
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    // `text` with each `{name}` placeholder replaced by its value
+    pub fn format(self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        args.iter().fold(self.text(key), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), &value.to_string())
+        })
+    }
+
+    // Labels a reply with its language and tells caches it depends on Accept-Language
+    pub fn attach(self, reply: impl Reply) -> warp::reply::Response {
+        let mut res = reply.into_response();
+        let headers = res.headers_mut();
+        headers.insert(CONTENT_LANGUAGE, HeaderValue::from_static(self.0));
+        headers.append(VARY, HeaderValue::from_static("accept-language"));
+        res
+    }
+}
+
+// The supported language the caller prefers most, by the q-values of an
+// Accept-Language header such as `de-AT, de;q=0.9, en;q=0.5`. Regional tags
+// match their base language.
+pub fn negotiate(header: &str) -> Locale {
+    let mut ranges: Vec<(f32, String)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim().to_ascii_lowercase();
+            let q = match parts.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(q) => q.trim().parse().ok()?,
+                None => 1.0,
+            };
+            (!tag.is_empty() && q > 0.0).then_some((q, tag))
+        })
+        .collect();
+    // Stable, so equally preferred languages keep the header's order
+    ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let catalogs = catalogs();
+    ranges
+        .iter()
+        .find_map(|(_, tag)| {
+            let language = tag.split('-').next().unwrap_or(tag);
+            match language {
+                "*" => Some(DEFAULT),
+                _ => catalogs.get_key_value(language).map(|(language, _)| *language),
+            }
+        })
+        .map(Locale)
+        .unwrap_or_default()
+}
+
+// The caller's locale; English without a readable Accept-Language header
+pub fn filter() -> impl Filter<Extract = (Locale,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| {
+        headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .map(negotiate)
+            .unwrap_or_default()
+    })
+}
@@ -0,0 +1,190 @@
+use crate::i18n::Locale;
+use crate::request_id::{self, RequestId};
+use crate::resilience::{self, BackendError};
+use crate::{csrf, fortune_file, Fortune, AppState, SharedState};
+use fortune_common::{debug, error};
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use warp::http::StatusCode;
+use warp::multipart::{FormData, Part};
+use warp::{Buf, Reply};
+
+// Fortune file imports. The entries of an uploaded file go to the backend's
+// POST /fortunes/batch IMPORT_BATCH_SIZE at a time, so a large file never
+// exceeds the backend's MAX_BATCH_BYTES and a failure part-way through keeps
+// the batches already stored.
+
+// The fields of an upload form
+#[derive(Default)]
+pub struct Upload {
+    pub file: Vec<u8>,
+    pub force: bool,
+    pub csrf_token: Option<String>,
+}
+
+async fn part_bytes(part: Part) -> Result<Vec<u8>, warp::Error> {
+    part.stream()
+        .try_fold(Vec::new(), |mut bytes, chunk| async move {
+            bytes.extend_from_slice(chunk.chunk());
+            Ok(bytes)
+        })
+        .await
+}
+
+pub async fn read_upload(mut form: FormData) -> Result<Upload, warp::Error> {
+    let mut upload = Upload::default();
+    while let Some(part) = form.try_next().await? {
+        let name = part.name().to_string();
+        let bytes = part_bytes(part).await?;
+        match name.as_str() {
+            "file" => upload.file = bytes,
+            "force" => upload.force = bytes == b"true",
+            "csrf_token" => upload.csrf_token = String::from_utf8(bytes).ok(),
+            _ => {}
+        }
+    }
+    Ok(upload)
+}
+
+// The fortunes of an uploaded file, or why there are none
+pub fn parse(upload: &Upload) -> Result<Vec<Fortune>, String> {
+    let contents = std::str::from_utf8(&upload.file).map_err(|_| "the file is not UTF-8 text".to_string())?;
+    fortune_file::parse(contents)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    // 1-based position in the file
+    entry: usize,
+    reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    total: usize,
+    created: usize,
+    // Created but held for moderation
+    pending: usize,
+    failed: Vec<Failure>,
+    batches: usize,
+    // Entries never sent because a batch was refused
+    not_sent: usize,
+    // Why the import stopped early
+    stopped: Option<String>,
+}
+
+impl Summary {
+    pub fn message(&self) -> String {
+        let mut message = format!("Imported {} of {} fortunes.", self.created + self.pending, self.total);
+        if self.pending > 0 {
+            message.push_str(&format!(" {} await moderation.", self.pending));
+        }
+        if let Some(first) = self.failed.first() {
+            message.push_str(&format!(" {} failed, first: {}.", self.failed.len(), first.reason));
+        }
+        if let Some(reason) = &self.stopped {
+            message.push_str(&format!(" Stopped with {} not sent: {}.", self.not_sent, reason));
+        }
+        message
+    }
+
+    // Nothing reached the backend because it refused or could not be reached
+    fn backend_failed(&self) -> bool {
+        self.stopped.is_some() && self.not_sent == self.total
+    }
+
+    fn tally(&mut self, offset: usize, results: &[Value]) {
+        for (i, result) in results.iter().enumerate() {
+            match result["result"].as_str() {
+                Some("created") if result["fortune"]["status"] == "pending" => self.pending += 1,
+                Some("created") => self.created += 1,
+                _ => self.failed.push(Failure {
+                    entry: offset + i + 1,
+                    reason: result["reason"].as_str().unwrap_or("unknown").to_string(),
+                }),
+            }
+        }
+    }
+}
+
+// Sends `fortunes` batch by batch, stopping at the first batch the backend
+// refuses. `admin` calls carry BACKEND_API_KEY and so skip moderation.
+pub async fn send(state: &AppState, request_id: &RequestId, fortunes: &[Fortune], force: bool, admin: bool) -> Summary {
+    let mut summary = Summary {
+        total: fortunes.len(),
+        ..Summary::default()
+    };
+    let path = if force { "/fortunes/batch?force=true" } else { "/fortunes/batch" };
+    for (n, batch) in fortunes.chunks(state.config.import_batch_size).enumerate() {
+        let offset = n * state.config.import_batch_size;
+        let mut request = state.http
+            .post(state.backends.url(path))
+            .header(request_id::HEADER, request_id.as_str())
+            .json(batch);
+        if let Some(key) = state.config.backend_api_key.as_ref().filter(|_| admin) {
+            request = request.header("x-api-key", key);
+        }
+        let stopped = match resilience::send_once(state.signed(request), &state.breaker, &state.backends).await {
+            Ok(response) if response.status().is_success() => match response.json::<Vec<Value>>().await {
+                Ok(results) => {
+                    summary.tally(offset, &results);
+                    None
+                }
+                Err(e) => Some(format!("invalid response: {}", e)),
+            },
+            Ok(response) if response.status() == reqwest::StatusCode::PAYLOAD_TOO_LARGE => {
+                Some("a batch is larger than the backend accepts; lower IMPORT_BATCH_SIZE".to_string())
+            }
+            Ok(response) if response.status() == reqwest::StatusCode::FORBIDDEN => Some("the backend is read-only".to_string()),
+            Ok(response) => Some(format!("the backend answered {}", response.status())),
+            Err(BackendError::CircuitOpen) => Some("backend temporarily unavailable".to_string()),
+            Err(e) => Some(format!("request failed: {}", e)),
+        };
+        if let Some(reason) = stopped {
+            error!("[{}] import stopped after {} of {} fortunes: {}", request_id, offset, fortunes.len(), reason);
+            summary.not_sent = fortunes.len() - offset;
+            summary.stopped = Some(reason);
+            break;
+        }
+        summary.batches += 1;
+        debug!("[{}] import: {} of {} fortunes sent", request_id, offset + batch.len(), fortunes.len());
+    }
+    if summary.created + summary.pending > 0 {
+        state.cache.invalidate().await;
+    }
+    summary
+}
+
+fn summary_page(state: &AppState, status: StatusCode, data: Value) -> warp::reply::Response {
+    match state.templates.render("import", &data) {
+        Ok(html) => warp::reply::with_status(warp::reply::html(html), status).into_response(),
+        Err(e) => {
+            error!("Template rendering failed: {}", e);
+            warp::reply::with_status(format!("Template error: {}", e), StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    }
+}
+
+// POST /api/import: like /api/add, without the admin key, so imported
+// fortunes are moderated when the backend moderates submissions
+pub async fn handler(request_id: RequestId, form: FormData, submitted: csrf::Submitted, state: SharedState) -> Result<warp::reply::Response, Infallible> {
+    let upload = match read_upload(form).await {
+        Ok(upload) => upload,
+        Err(e) => {
+            let data = json!({"error": format!("the upload failed: {}", e)});
+            return Ok(request_id.attach(summary_page(&state, StatusCode::BAD_REQUEST, data)));
+        }
+    };
+    if !state.csrf.verify(&submitted, upload.csrf_token.as_deref()) {
+        return Ok(request_id.attach(crate::csrf_rejected(Locale::default())));
+    }
+    let fortunes = match parse(&upload) {
+        Ok(fortunes) => fortunes,
+        Err(e) => return Ok(request_id.attach(summary_page(&state, StatusCode::BAD_REQUEST, json!({"error": e})))),
+    };
+    let summary = send(&state, &request_id, &fortunes, false, false).await;
+    let status = if summary.backend_failed() { StatusCode::BAD_GATEWAY } else { StatusCode::OK };
+    Ok(request_id.attach(summary_page(&state, status, json!({"summary": summary}))))
+}
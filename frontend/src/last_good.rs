@@ -0,0 +1,83 @@
+use crate::Fortune;
+use fortune_common::{error, info};
+use rand::seq::SliceRandom;
+use std::path::{Path, PathBuf};
+use tokio::sync::RwLock;
+
+// The last fortune list the backend returned, kept after the TTL cache has
+// expired so pages can still be served while the backend is unreachable.
+// With a file configured the list also survives a frontend restart.
+pub struct LastKnownGood {
+    fortunes: RwLock<Vec<Fortune>>,
+    file: Option<PathBuf>,
+}
+
+impl LastKnownGood {
+    pub fn load(file: Option<PathBuf>) -> Self {
+        let fortunes = file.as_deref().map(read).unwrap_or_default();
+        LastKnownGood {
+            fortunes: RwLock::new(fortunes),
+            file,
+        }
+    }
+
+    // Remembers a list fetched from the backend; an empty list never replaces
+    // a useful one
+    pub async fn remember(&self, fortunes: &[Fortune]) {
+        if fortunes.is_empty() {
+            return;
+        }
+        {
+            let mut current = self.fortunes.write().await;
+            let unchanged = current.len() == fortunes.len()
+                && current.iter().zip(fortunes).all(|(a, b)| a.id == b.id && a.message == b.message);
+            *current = fortunes.to_vec();
+            if unchanged {
+                return;
+            }
+        }
+        if let Some(file) = &self.file {
+            if let Err(e) = write(file, fortunes).await {
+                error!("failed to write last-known-good list {}: {}", file.display(), e);
+            }
+        }
+    }
+
+    pub async fn all(&self) -> Option<Vec<Fortune>> {
+        let fortunes = self.fortunes.read().await;
+        (!fortunes.is_empty()).then(|| fortunes.clone())
+    }
+
+    pub async fn pick(&self) -> Option<Fortune> {
+        self.fortunes.read().await.choose(&mut rand::thread_rng()).cloned()
+    }
+}
+
+fn read(file: &Path) -> Vec<Fortune> {
+    let json = match std::fs::read(file) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            error!("failed to read last-known-good list {}: {}", file.display(), e);
+            return Vec::new();
+        }
+    };
+    match serde_json::from_slice::<Vec<Fortune>>(&json) {
+        Ok(fortunes) => {
+            info!("loaded {} last-known-good fortunes from {}", fortunes.len(), file.display());
+            fortunes
+        }
+        Err(e) => {
+            error!("failed to parse last-known-good list {}: {}", file.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+async fn write(file: &Path, fortunes: &[Fortune]) -> std::io::Result<()> {
+    let json = serde_json::to_vec(fortunes)?;
+    // Write to a temp file and rename so a crash never leaves a truncated list
+    let tmp = file.with_extension("tmp");
+    tokio::fs::write(&tmp, json).await?;
+    tokio::fs::rename(&tmp, file).await
+}
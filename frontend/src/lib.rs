@@ -0,0 +1,1084 @@
+mod admin;
+mod assets;
+mod cache;
+mod card;
+mod csrf;
+mod decoration;
+pub mod discovery;
+pub mod compression;
+pub mod config;
+mod feed;
+mod flash;
+mod fortune_file;
+mod i18n;
+mod import;
+mod last_good;
+mod markdown;
+mod passthrough;
+mod qr;
+pub mod request_id;
+mod resilience;
+mod security_headers;
+mod session;
+mod sitemap;
+mod signing;
+pub mod startup;
+pub mod stream;
+mod templates;
+mod theme;
+mod tts;
+
+pub use fortune_common::{access_log, client_ip, server};
+
+use fortune_common::{error, warn};
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use warp::{Filter, Reply, Rejection};
+use serde::{Deserialize, Serialize};
+use handlebars::Handlebars;
+use assets::Assets;
+use cache::FortuneCache;
+use csrf::Csrf;
+use i18n::Locale;
+use rand::seq::SliceRandom;
+use request_id::RequestId;
+use last_good::LastKnownGood;
+use markdown::Render;
+use decoration::{Decorated, Theme};
+use session::Sessions;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Reported by /healthz; GIT_COMMIT is passed in by the Docker build
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const COMMIT: &str = match option_env!("GIT_COMMIT") {
+    Some(commit) => commit,
+    None => "unknown",
+};
+use discovery::Backends;
+use resilience::{BackendError, CircuitBreaker, RetryPolicy};
+use config::Config;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Fortune {
+    // Left empty on new fortunes; the backend allocates one
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    id: String,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+    // Unix seconds; older backends don't send it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewFortune {
+    message: String,
+    #[serde(default)]
+    author: Option<String>,
+    // The hidden form field; JSON callers may send X-CSRF-Token instead
+    #[serde(default)]
+    csrf_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CardParams {
+    id: String,
+    #[serde(default)]
+    theme: card::Theme,
+}
+
+#[derive(Debug, Deserialize)]
+struct AudioParams {
+    id: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct QrParams {
+    #[serde(default)]
+    format: qr::Format,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RenderParams {
+    #[serde(default)]
+    render: Render,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RandomParams {
+    #[serde(default)]
+    render: Render,
+    #[serde(default)]
+    decorated: Decorated,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FeedParams {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct HealthParams {
+    #[serde(default)]
+    verbose: bool,
+}
+
+#[derive(Serialize)]
+struct Health {
+    // `ok`, or `degraded` when the backend does not answer
+    status: &'static str,
+    version: &'static str,
+    commit: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    components: Option<Components>,
+}
+
+#[derive(Serialize)]
+struct Components {
+    // `up` when the backend's own /healthz answers, otherwise `down`
+    backend: &'static str,
+    breaker: &'static str,
+}
+
+// Marks a reply built from the last-known-good list instead of the backend
+fn served_from_cache(reply: impl Reply) -> warp::reply::Response {
+    warp::reply::with_header(reply, "x-served-from", "cache").into_response()
+}
+
+pub struct AppState {
+    config: Config,
+    http: reqwest::Client,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
+    backends: Backends,
+    // Last fortune successfully fetched from the backend, served while the breaker is open
+    last_fortune: RwLock<Option<String>>,
+    cache: FortuneCache,
+    // Served with X-Served-From: cache while the backend is unreachable
+    last_good: LastKnownGood,
+    // Fortunes created on the backend, relayed to SSE subscribers
+    created: broadcast::Sender<Fortune>,
+    assets: Arc<Assets>,
+    csrf: Arc<Csrf>,
+    // None when no admin account is configured
+    sessions: Option<Arc<Sessions>>,
+    templates: Handlebars<'static>,
+    // None leaves /api/fortune-audio out
+    tts: Option<tts::Provider>,
+}
+
+pub type SharedState = Arc<AppState>;
+
+impl AppState {
+    // Signs a write to the backend when REQUEST_SIGNING_SECRET is set
+    fn signed(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.config.request_signing_secret {
+            Some(secret) => signing::sign_request(request, secret),
+            None => request,
+        }
+    }
+}
+
+pub fn create_state(config: Config) -> SharedState {
+    let retry = RetryPolicy {
+        max_attempts: config.backend_retry_attempts,
+        base_delay: Duration::from_millis(config.backend_retry_base_ms),
+        max_delay: Duration::from_millis(config.backend_retry_max_ms),
+    };
+    let breaker = CircuitBreaker::new(
+        config.breaker_failure_threshold,
+        Duration::from_secs(config.breaker_open_secs),
+    );
+    let http = reqwest::Client::builder()
+        .timeout(config.backend_timeout())
+        .build()
+        .expect("failed to build HTTP client");
+    let backends = Backends::from_config(&config);
+    let cache = FortuneCache::new(Duration::from_secs(config.fortune_cache_ttl_secs));
+    let theme = theme::Theme::load(&config.theme, config.template_dir.as_deref()).expect("THEME is checked by Config::validate");
+    let assets = Arc::new(Assets::load(&config.static_dir, &theme));
+    let templates = templates::registry(config.template_dir.as_deref(), &theme.name, &assets.url(theme::FILE))
+        .expect("TEMPLATE_DIR is checked by Config::validate");
+    let last_good = LastKnownGood::load(config.last_good_file.clone());
+    let csrf = Arc::new(Csrf::new(config.csrf_secret.as_deref()));
+    let sessions = Sessions::from_config(&config).map(Arc::new);
+    let tts = tts::Provider::from_config(&config);
+
+    Arc::new(AppState {
+        config,
+        http,
+        retry,
+        breaker,
+        backends,
+        last_fortune: RwLock::new(None),
+        cache,
+        last_good,
+        created: broadcast::channel(64).0,
+        assets,
+        csrf,
+        sessions,
+        templates,
+        tts,
+    })
+}
+
+fn with_state(state: SharedState) -> impl Filter<Extract = (SharedState,), Error = Infallible> + Clone {
+    warp::any().map(move || state.clone())
+}
+
+// Stays 200 while the frontend runs, as it can serve cached fortunes without
+// the backend; ?verbose=true checks the backend too
+async fn healthz_handler(params: HealthParams, request_id: RequestId, state: SharedState) -> Result<impl Reply, Infallible> {
+    let components = if params.verbose {
+        let up = matches!(
+            backend_get(&state, "/healthz", &request_id).send().await,
+            Ok(response) if response.status().is_success()
+        );
+        Some(Components {
+            backend: if up { "up" } else { "down" },
+            breaker: state.breaker.state().as_str(),
+        })
+    } else {
+        None
+    };
+    let degraded = components.as_ref().is_some_and(|c| c.backend == "down");
+    Ok(request_id.attach(warp::reply::json(&Health {
+        status: if degraded { "degraded" } else { "ok" },
+        version: VERSION,
+        commit: COMMIT,
+        components,
+    })))
+}
+
+async fn metrics_handler(request_id: RequestId, state: SharedState) -> Result<impl Reply, Infallible> {
+    Ok(request_id.attach(warp::reply::with_header(
+        state.breaker.metrics() + &state.backends.metrics(),
+        "content-type",
+        "text/plain; version=0.0.4",
+    )))
+}
+
+// GET request to the backend tagged with the caller's request id
+fn backend_get(state: &AppState, path: &str, request_id: &RequestId) -> reqwest::RequestBuilder {
+    state.http
+        .get(state.backends.url(path))
+        .header(request_id::HEADER, request_id.as_str())
+}
+
+// Fetches the full fortune list from the backend and refreshes the cache.
+// An expired cache entry is revalidated with its ETag, so an unchanged list
+// is not downloaded again.
+async fn fetch_fortunes(state: &AppState, request_id: &RequestId) -> Result<Vec<Fortune>, BackendError> {
+    let mut request = backend_get(state, "/fortunes", request_id);
+    let stale = state.cache.stale().await;
+    if let Some((etag, _)) = &stale {
+        request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+
+    let response = resilience::get_with_retry(request, request_id, &state.retry, &state.breaker, &state.backends).await?;
+    if let (reqwest::StatusCode::NOT_MODIFIED, Some((etag, fortunes))) = (response.status(), stale) {
+        state.cache.set(fortunes.clone(), Some(etag)).await;
+        state.last_good.remember(&fortunes).await;
+        return Ok(fortunes);
+    }
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let fortunes = response.json::<Vec<Fortune>>().await.map_err(BackendError::Request)?;
+    state.cache.set(fortunes.clone(), etag).await;
+    state.last_good.remember(&fortunes).await;
+    Ok(fortunes)
+}
+
+// Picks a random fortune locally from the cached list, warming the cache if needed.
+// Returns None when caching is disabled or the list is unavailable or empty.
+async fn pick_cached_fortune(state: &AppState, request_id: &RequestId) -> Option<Fortune> {
+    if !state.cache.is_enabled() {
+        return None;
+    }
+    let fortunes = match state.cache.get().await {
+        Some(fortunes) => fortunes,
+        None => fetch_fortunes(state, request_id).await.ok()?,
+    };
+    fortunes.choose(&mut rand::thread_rng()).cloned()
+}
+
+async fn random_handler(
+    request_id: RequestId,
+    locale: Locale,
+    params: RandomParams,
+    state: SharedState,
+) -> Result<impl Reply, Infallible> {
+    let theme = params.decorated.theme(signing::now_secs() / decoration::DAY_SECS);
+    let response = random_response(&state, &request_id, locale, params.render, theme).await;
+    Ok(request_id.attach(locale.attach(response)))
+}
+
+// A fortune message as /api/random sends it: plain text, or sanitized HTML
+// with ?render=html, wrapped in the theme with ?decorated=
+fn message_reply(message: &str, render: Render, theme: Option<Theme>, status: warp::http::StatusCode) -> warp::reply::Response {
+    let mut message = render.apply(message);
+    if let Some(theme) = theme {
+        message = theme.wrap(&message, render == Render::Html);
+    }
+    let reply = match render {
+        Render::Text => warp::reply::with_status(message, status).into_response(),
+        Render::Html => warp::reply::with_status(warp::reply::html(message), status).into_response(),
+    };
+    match theme {
+        Some(theme) => warp::reply::with_header(reply, "x-fortune-theme", theme.name).into_response(),
+        None => reply,
+    }
+}
+
+async fn random_response(
+    state: &AppState,
+    request_id: &RequestId,
+    locale: Locale,
+    render: Render,
+    theme: Option<Theme>,
+) -> warp::reply::Response {
+    if let Some(fortune) = pick_cached_fortune(state, request_id).await {
+        *state.last_fortune.write().await = Some(fortune.message.clone());
+        return message_reply(&fortune.message, render, theme, warp::http::StatusCode::OK);
+    }
+
+    let request = backend_get(state, "/fortunes/random", request_id);
+
+    match resilience::get_with_retry(request, request_id, &state.retry, &state.breaker, &state.backends).await {
+        Ok(response) => {
+            match response.json::<Fortune>().await {
+                Ok(fortune) => {
+                    *state.last_fortune.write().await = Some(fortune.message.clone());
+                    message_reply(&fortune.message, render, theme, warp::http::StatusCode::OK)
+                }
+                Err(e) => {
+                    error!("[{}] Failed to parse JSON: {}", request_id, e);
+                    warp::reply::with_status(
+                        locale.format("parse_failed", &[("error", &e)]),
+                        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    ).into_response()
+                }
+            }
+        }
+        Err(e) => match state.last_good.pick().await {
+            Some(fortune) => {
+                warn!("[{}] Backend unavailable ({}); serving the last-known-good list", request_id, e);
+                served_from_cache(message_reply(&fortune.message, render, theme, warp::http::StatusCode::OK))
+            }
+            None => backend_failure(state, request_id, e, locale, render, theme).await,
+        },
+    }
+}
+
+// Reply to /api/random when the backend failed and no list was ever fetched
+async fn backend_failure(
+    state: &AppState,
+    request_id: &RequestId,
+    e: BackendError,
+    locale: Locale,
+    render: Render,
+    theme: Option<Theme>,
+) -> warp::reply::Response {
+    match e {
+        BackendError::CircuitOpen => {
+            let message = state.last_fortune.read().await.clone()
+                .unwrap_or_else(|| locale.text("fallback_fortune"));
+            message_reply(&message, render, theme, warp::http::StatusCode::SERVICE_UNAVAILABLE)
+        }
+        e => {
+            error!("[{}] Request failed: {}", request_id, e);
+            warp::reply::with_status(
+                locale.format("request_failed", &[("error", &e)]),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ).into_response()
+        }
+    }
+}
+
+async fn all_handler(
+    request_id: RequestId,
+    locale: Locale,
+    params: RenderParams,
+    state: SharedState,
+) -> Result<impl Reply, Infallible> {
+    let response = all_response(&state, &request_id, locale, params.render).await;
+    Ok(request_id.attach(locale.attach(response)))
+}
+
+async fn all_response(state: &AppState, request_id: &RequestId, locale: Locale, render: Render) -> warp::reply::Response {
+    let mut from_cache = false;
+    let fortunes = match state.cache.get().await {
+        Some(fortunes) => fortunes,
+        None => match fetch_fortunes(state, request_id).await {
+            Ok(fortunes) => fortunes,
+            Err(BackendError::Request(e)) if e.is_decode() => {
+                error!("[{}] Failed to parse JSON: {}", request_id, e);
+                return warp::reply::with_status(
+                    warp::reply::html(locale.format("parse_failed", &[("error", &e)])),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                ).into_response();
+            }
+            Err(e) => match state.last_good.all().await {
+                Some(fortunes) => {
+                    warn!("[{}] Backend unavailable ({}); serving the last-known-good list", request_id, e);
+                    from_cache = true;
+                    fortunes
+                }
+                None => {
+                    error!("[{}] Request failed: {}", request_id, e);
+                    let (message, status) = match e {
+                        BackendError::CircuitOpen => (
+                            locale.text("backend_unavailable"),
+                            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+                        ),
+                        e => (
+                            locale.format("request_failed", &[("error", &e)]),
+                            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                        ),
+                    };
+                    return warp::reply::with_status(warp::reply::html(message), status).into_response();
+                }
+            },
+        },
+    };
+
+    // Create Handlebars template engine
+    let handlebars = Handlebars::new();
+    // Rendered messages are sanitized HTML and go in unescaped
+    let template = match render {
+        Render::Text => r#"{{#each this}}
+    <p>{{id}}: {{message}}{{#if author}} &mdash; {{author}}{{/if}}</p>
+{{/each}}"#,
+        Render::Html => r#"{{#each this}}
+    <p>{{id}}: {{{message}}}{{#if author}} &mdash; {{author}}{{/if}}</p>
+{{/each}}"#,
+    };
+    let fortunes: Vec<Fortune> = fortunes
+        .into_iter()
+        .map(|fortune| Fortune {
+            message: render.apply(&fortune.message),
+            ..fortune
+        })
+        .collect();
+
+    match handlebars.render_template(template, &fortunes) {
+        Ok(rendered) if from_cache => served_from_cache(warp::reply::html(rendered)),
+        Ok(rendered) => warp::reply::with_status(
+            warp::reply::html(rendered),
+            warp::http::StatusCode::OK,
+        ).into_response(),
+        Err(e) => {
+            error!("[{}] Template rendering failed: {}", request_id, e);
+            warp::reply::with_status(
+                warp::reply::html(format!("Template error: {}", e)),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ).into_response()
+        }
+    }
+}
+
+async fn card_handler(
+    request_id: RequestId,
+    locale: Locale,
+    params: CardParams,
+    state: SharedState,
+) -> Result<impl Reply, Infallible> {
+    let response = card_response(&state, &request_id, locale, params).await;
+    Ok(request_id.attach(locale.attach(response)))
+}
+
+// The fortune with `id` from the backend, or the reply explaining why not
+async fn fetch_fortune(state: &AppState, request_id: &RequestId, locale: Locale, id: &str) -> Result<Fortune, warp::reply::Response> {
+    // The id becomes part of the backend path, so only plain ids are accepted
+    let valid = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(warp::reply::with_status(locale.text("invalid_fortune_id"), warp::http::StatusCode::BAD_REQUEST).into_response());
+    }
+
+    let request = backend_get(state, &format!("/fortunes/{}", id), request_id);
+    match resilience::get_with_retry(request, request_id, &state.retry, &state.breaker, &state.backends).await {
+        Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => {
+            Err(warp::reply::with_status(locale.text("fortune_not_found"), warp::http::StatusCode::NOT_FOUND).into_response())
+        }
+        Ok(response) => response.json::<Fortune>().await.map_err(|e| {
+            error!("[{}] Failed to parse JSON: {}", request_id, e);
+            warp::reply::with_status(
+                locale.format("parse_failed", &[("error", &e)]),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ).into_response()
+        }),
+        Err(BackendError::CircuitOpen) => Err(warp::reply::with_status(
+            locale.text("backend_unavailable"),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ).into_response()),
+        Err(e) => {
+            error!("[{}] Request failed: {}", request_id, e);
+            Err(warp::reply::with_status(
+                locale.format("request_failed", &[("error", &e)]),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ).into_response())
+        }
+    }
+}
+
+async fn card_response(state: &AppState, request_id: &RequestId, locale: Locale, params: CardParams) -> warp::reply::Response {
+    let fortune = match fetch_fortune(state, request_id, locale, &params.id).await {
+        Ok(fortune) => fortune,
+        Err(reply) => return reply,
+    };
+    let svg = card::render(&markdown::to_plain(&fortune.message), fortune.author.as_deref(), params.theme);
+    let reply = warp::reply::with_header(svg, "content-type", "image/svg+xml");
+    warp::reply::with_header(reply, "cache-control", "public, max-age=300").into_response()
+}
+
+async fn audio_handler(
+    request_id: RequestId,
+    locale: Locale,
+    params: AudioParams,
+    state: SharedState,
+) -> Result<impl Reply, Infallible> {
+    let response = audio_response(&state, &request_id, locale, params).await;
+    Ok(request_id.attach(locale.attach(response)))
+}
+
+async fn audio_response(state: &AppState, request_id: &RequestId, locale: Locale, params: AudioParams) -> warp::reply::Response {
+    let Some(tts) = &state.tts else {
+        return warp::reply::with_status("Not Found", warp::http::StatusCode::NOT_FOUND).into_response();
+    };
+    let fortune = match fetch_fortune(state, request_id, locale, &params.id).await {
+        Ok(fortune) => fortune,
+        Err(reply) => return reply,
+    };
+    let text = match &fortune.author {
+        Some(author) => format!("{} {}", markdown::to_plain(&fortune.message), author),
+        None => markdown::to_plain(&fortune.message),
+    };
+    match tts.synthesize(&text).await {
+        Ok(audio) => warp::http::Response::builder()
+            .header("content-type", "audio/mpeg")
+            .header("cache-control", "public, max-age=300")
+            .body(audio)
+            .unwrap_or_else(|_| warp::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+        Err(e) => {
+            error!("[{}] Speech synthesis failed: {}", request_id, e);
+            warp::reply::with_status(
+                locale.format("speech_failed", &[("error", &e)]),
+                warp::http::StatusCode::BAD_GATEWAY,
+            ).into_response()
+        }
+    }
+}
+
+// Where the site is reachable from outside, without a trailing slash:
+// PUBLIC_URL, else the request's Host header
+fn public_base(state: &AppState, host: Option<String>) -> String {
+    match (&state.config.public_url, host) {
+        (Some(url), _) => url.trim_end_matches('/').to_string(),
+        (None, Some(host)) => format!("http://{}", host),
+        (None, None) => format!("http://localhost:{}", state.config.frontend_port),
+    }
+}
+
+async fn qr_handler(
+    id: String,
+    request_id: RequestId,
+    locale: Locale,
+    params: QrParams,
+    host: Option<String>,
+    state: SharedState,
+) -> Result<impl Reply, Infallible> {
+    let response = qr_response(&state, &request_id, locale, &id, params, host).await;
+    Ok(request_id.attach(locale.attach(response)))
+}
+
+async fn qr_response(
+    state: &AppState,
+    request_id: &RequestId,
+    locale: Locale,
+    id: &str,
+    params: QrParams,
+    host: Option<String>,
+) -> warp::reply::Response {
+    // Only fortunes that exist get a code, so a typo is not printed on a slip
+    let fortune = match fetch_fortune(state, request_id, locale, id).await {
+        Ok(fortune) => fortune,
+        Err(reply) => return reply,
+    };
+    let url = format!("{}/fortune/{}", public_base(state, host), fortune.id);
+    match qr::render(&url, params.format) {
+        Ok(image) => warp::http::Response::builder()
+            .header("content-type", params.format.content_type())
+            .header("cache-control", "public, max-age=300")
+            .body(warp::hyper::Body::from(image))
+            .unwrap_or_else(|_| warp::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+        Err(e) => {
+            error!("[{}] QR code failed: {}", request_id, e);
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn permalink_handler(
+    id: String,
+    request_id: RequestId,
+    locale: Locale,
+    host: Option<String>,
+    state: SharedState,
+) -> Result<impl Reply, Infallible> {
+    let response = permalink_response(&state, &request_id, locale, &id, host).await;
+    Ok(request_id.attach(locale.attach(response)))
+}
+
+// The fortune's own page, with Open Graph and Twitter tags so a shared link
+// previews the fortune and its card
+async fn permalink_response(
+    state: &AppState,
+    request_id: &RequestId,
+    locale: Locale,
+    id: &str,
+    host: Option<String>,
+) -> warp::reply::Response {
+    let fortune = match fetch_fortune(state, request_id, locale, id).await {
+        Ok(fortune) => fortune,
+        Err(reply) => return reply,
+    };
+    let base = public_base(state, host);
+    let plain = markdown::to_plain(&fortune.message);
+    let title: String = plain.graphemes(true).take(70).collect();
+    let description = match &fortune.author {
+        Some(author) => format!("{} — {}", plain, author),
+        None => plain,
+    };
+    let data = serde_json::json!({
+        "id": fortune.id,
+        "title": title,
+        "description": description,
+        "message": markdown::to_html(&fortune.message),
+        "author": fortune.author,
+        "url": format!("{}/fortune/{}", base, fortune.id),
+        "image": format!("{}/api/fortune-card?id={}", base, fortune.id),
+    });
+    match state.templates.render("fortune", &data) {
+        Ok(html) => warp::reply::with_header(warp::reply::html(html), "cache-control", "public, max-age=300").into_response(),
+        Err(e) => {
+            error!("[{}] Template rendering failed: {}", request_id, e);
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn sitemap_handler(request_id: RequestId, host: Option<String>, state: SharedState) -> Result<impl Reply, Infallible> {
+    let response = sitemap_response(&state, &request_id, host).await;
+    Ok(request_id.attach(response))
+}
+
+async fn sitemap_response(state: &AppState, request_id: &RequestId, host: Option<String>) -> warp::reply::Response {
+    let fortunes = match state.cache.get().await {
+        Some(fortunes) => fortunes,
+        None => match fetch_fortunes(state, request_id).await {
+            Ok(fortunes) => fortunes,
+            Err(BackendError::CircuitOpen) => return warp::reply::with_status(
+                "Backend temporarily unavailable, please try again shortly.",
+                warp::http::StatusCode::SERVICE_UNAVAILABLE,
+            ).into_response(),
+            Err(e) => {
+                error!("[{}] Request failed: {}", request_id, e);
+                return warp::reply::with_status(
+                    format!("Request failed: {}", e),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                ).into_response();
+            }
+        },
+    };
+    let xml = sitemap::render(&fortunes, &public_base(state, host));
+    warp::reply::with_header(xml, "content-type", "application/xml; charset=utf-8").into_response()
+}
+
+async fn feed_handler(
+    request_id: RequestId,
+    params: FeedParams,
+    host: Option<String>,
+    state: SharedState,
+) -> Result<impl Reply, Infallible> {
+    let response = feed_response(&state, &request_id, params, host).await;
+    Ok(request_id.attach(response))
+}
+
+async fn feed_response(
+    state: &AppState,
+    request_id: &RequestId,
+    params: FeedParams,
+    host: Option<String>,
+) -> warp::reply::Response {
+    let fortunes = match state.cache.get().await {
+        Some(fortunes) => fortunes,
+        None => match fetch_fortunes(state, request_id).await {
+            Ok(fortunes) => fortunes,
+            Err(BackendError::CircuitOpen) => return warp::reply::with_status(
+                "Backend temporarily unavailable, please try again shortly.",
+                warp::http::StatusCode::SERVICE_UNAVAILABLE,
+            ).into_response(),
+            Err(e) => {
+                error!("[{}] Request failed: {}", request_id, e);
+                return warp::reply::with_status(
+                    format!("Request failed: {}", e),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                ).into_response();
+            }
+        },
+    };
+
+    // `?limit=` may shorten the feed but not grow it past FEED_SIZE
+    let limit = params.limit.unwrap_or(state.config.feed_size).clamp(1, state.config.feed_size);
+    let xml = feed::render(&feed::latest(fortunes, limit), &public_base(state, host));
+    warp::reply::with_header(xml, "content-type", "application/rss+xml; charset=utf-8").into_response()
+}
+
+async fn add_handler(
+    request_id: RequestId,
+    locale: Locale,
+    client: Option<IpAddr>,
+    new_fortune: NewFortune,
+    submitted: csrf::Submitted,
+    state: SharedState,
+) -> Result<impl Reply, Infallible> {
+    if !state.csrf.verify(&submitted, new_fortune.csrf_token.as_deref()) {
+        return Ok(request_id.attach(locale.attach(csrf_rejected(locale))));
+    }
+    let response = add_response(&state, &request_id, locale, client, new_fortune).await;
+    Ok(request_id.attach(locale.attach(response)))
+}
+
+fn csrf_rejected(locale: Locale) -> warp::reply::Response {
+    warp::reply::with_status(
+        locale.text("csrf_rejected"),
+        warp::http::StatusCode::FORBIDDEN,
+    ).into_response()
+}
+
+async fn add_response(
+    state: &AppState,
+    request_id: &RequestId,
+    locale: Locale,
+    client: Option<IpAddr>,
+    new_fortune: NewFortune,
+) -> warp::reply::Response {
+    let (message, status) = add_fortune(state, request_id, locale, client, new_fortune).await;
+    warp::reply::with_status(message, status).into_response()
+}
+
+// POST /submit: the add form without JavaScript. The outcome is shown on the
+// page it redirects back to.
+async fn submit_handler(
+    request_id: RequestId,
+    locale: Locale,
+    client: Option<IpAddr>,
+    new_fortune: NewFortune,
+    submitted: csrf::Submitted,
+    state: SharedState,
+) -> Result<impl Reply, Infallible> {
+    if !state.csrf.verify(&submitted, new_fortune.csrf_token.as_deref()) {
+        return Ok(request_id.attach(locale.attach(csrf_rejected(locale))));
+    }
+    let (message, _) = add_fortune(&state, &request_id, locale, client, new_fortune).await;
+    Ok(request_id.attach(flash::redirect("/", &message)))
+}
+
+// Sends a new fortune to the backend; returns the message for the user. The
+// client's address goes along in X-Forwarded-For, so the backend's audit log
+// names the submitter when it trusts this frontend as a proxy.
+async fn add_fortune(
+    state: &AppState,
+    request_id: &RequestId,
+    locale: Locale,
+    client: Option<IpAddr>,
+    new_fortune: NewFortune,
+) -> (String, warp::http::StatusCode) {
+    let url = state.backends.url("/fortunes");
+
+    let fortune_data = Fortune {
+        id: String::new(),
+        message: new_fortune.message,
+        author: new_fortune.author.map(|a| a.trim().to_string()).filter(|a| !a.is_empty()),
+        created_at: None,
+    };
+
+    let mut request = state.http
+        .post(&url)
+        .header(request_id::HEADER, request_id.as_str())
+        .json(&fortune_data);
+    if let Some(client) = client {
+        request = request.header("x-forwarded-for", client.to_string());
+    }
+    match resilience::send_once(state.signed(request), &state.breaker, &state.backends).await {
+        Ok(response) if response.status() == reqwest::StatusCode::CONFLICT => (
+            locale.text("already_in_jar"),
+            warp::http::StatusCode::CONFLICT,
+        ),
+        Ok(response) if response.status() == reqwest::StatusCode::UNPROCESSABLE_ENTITY => {
+            let reason = response
+                .json::<String>()
+                .await
+                .unwrap_or_else(|_| locale.text("fortune_rejected"));
+            (reason, warp::http::StatusCode::UNPROCESSABLE_ENTITY)
+        }
+        // The backend runs with READ_ONLY
+        Ok(response) if response.status() == reqwest::StatusCode::FORBIDDEN => (
+            locale.text("adding_disabled"),
+            warp::http::StatusCode::FORBIDDEN,
+        ),
+        Ok(response) if response.status() == reqwest::StatusCode::ACCEPTED => (
+            locale.text("pending_approval"),
+            warp::http::StatusCode::ACCEPTED,
+        ),
+        Ok(_) => {
+            state.cache.invalidate().await;
+            (locale.text("cookie_added"), warp::http::StatusCode::OK)
+        }
+        Err(BackendError::CircuitOpen) => (
+            locale.text("backend_unavailable"),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ),
+        Err(e) => {
+            let client = client.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+            error!("[{}] Request from {} failed: {}", request_id, client, e);
+            (locale.format("request_failed", &[("error", &e)]), warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    if err.find::<session::LoginRequired>().is_some() {
+        let mut res = warp::reply::with_status(warp::reply::reply(), warp::http::StatusCode::SEE_OTHER).into_response();
+        res.headers_mut().insert(
+            warp::http::header::LOCATION,
+            warp::http::HeaderValue::from_static("/admin/login"),
+        );
+        Ok(res)
+    } else if err.is_not_found() {
+        Ok(warp::reply::with_status(
+            "Not Found",
+            warp::http::StatusCode::NOT_FOUND,
+        ).into_response())
+    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
+        Ok(warp::reply::with_status(
+            "Invalid query",
+            warp::http::StatusCode::BAD_REQUEST,
+        ).into_response())
+    } else if err.find::<warp::filters::body::BodyDeserializeError>().is_some() {
+        Ok(warp::reply::with_status(
+            "Invalid JSON",
+            warp::http::StatusCode::BAD_REQUEST,
+        ).into_response())
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        Ok(warp::reply::with_status(
+            "Unsupported Media Type",
+            warp::http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ).into_response())
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        Ok(warp::reply::with_status(
+            "Payload Too Large",
+            warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+        ).into_response())
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        Ok(warp::reply::with_status(
+            "Method Not Allowed",
+            warp::http::StatusCode::METHOD_NOT_ALLOWED,
+        ).into_response())
+    } else {
+        Ok(warp::reply::with_status(
+            "Internal Server Error",
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ).into_response())
+    }
+}
+
+pub fn routes(state: SharedState) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    // Health check endpoint
+    let healthz = warp::path("healthz")
+        .and(warp::get())
+        .and(warp::query::<HealthParams>())
+        .and(request_id::filter())
+        .and(with_state(state.clone()))
+        .and_then(healthz_handler);
+
+    // Circuit breaker and retry metrics
+    let metrics = warp::path("metrics")
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(with_state(state.clone()))
+        .and_then(metrics_handler);
+
+    // API endpoints
+    let api_random = warp::path!("api" / "random")
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(i18n::filter())
+        .and(warp::query::<RandomParams>())
+        .and(with_state(state.clone()))
+        .and_then(random_handler);
+
+    let api_all = warp::path!("api" / "all")
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(i18n::filter())
+        .and(warp::query::<RenderParams>())
+        .and(with_state(state.clone()))
+        .and_then(all_handler);
+
+    let api_stream = warp::path!("api" / "stream")
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(with_state(state.clone()))
+        .and_then(stream::stream_handler);
+
+    let api_card = warp::path!("api" / "fortune-card")
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(i18n::filter())
+        .and(warp::query::<CardParams>())
+        .and(with_state(state.clone()))
+        .and_then(card_handler);
+
+    // The fortune read aloud as MP3; a plain 404 without TTS_PROVIDER
+    let tts_enabled = state.tts.is_some();
+    let api_audio = warp::path!("api" / "fortune-audio")
+        .and(warp::any().and_then(move || async move {
+            if tts_enabled {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        }))
+        .untuple_one()
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(i18n::filter())
+        .and(warp::query::<AudioParams>())
+        .and(with_state(state.clone()))
+        .and_then(audio_handler);
+
+    let api_qr = warp::path!("api" / "qr" / String)
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(i18n::filter())
+        .and(warp::query::<QrParams>())
+        .and(warp::header::optional::<String>("host"))
+        .and(with_state(state.clone()))
+        .and_then(qr_handler);
+
+    let permalink = warp::path!("fortune" / String)
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(i18n::filter())
+        .and(warp::header::optional::<String>("host"))
+        .and(with_state(state.clone()))
+        .and_then(permalink_handler);
+
+    let sitemap = warp::path!("sitemap.xml")
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(warp::header::optional::<String>("host"))
+        .and(with_state(state.clone()))
+        .and_then(sitemap_handler);
+
+    let feed = warp::path!("feed.xml")
+        .and(warp::get())
+        .and(request_id::filter())
+        .and(warp::query::<FeedParams>())
+        .and(warp::header::optional::<String>("host"))
+        .and(with_state(state.clone()))
+        .and_then(feed_handler);
+
+    // JSON from script.js, or a form-encoded body
+    let api_add = warp::path!("api" / "add")
+        .and(warp::post())
+        .and(request_id::filter())
+        .and(i18n::filter())
+        .and(client_ip::filter(state.config.trusted_proxies()))
+        .and(warp::body::json().or(warp::body::form()).unify())
+        .and(csrf::submitted())
+        .and(with_state(state.clone()))
+        .and_then(add_handler);
+
+    // The add form posted without JavaScript; redirects back to the page
+    let submit = warp::path!("submit")
+        .and(warp::post())
+        .and(request_id::filter())
+        .and(i18n::filter())
+        .and(client_ip::filter(state.config.trusted_proxies()))
+        .and(warp::body::form())
+        .and(csrf::submitted())
+        .and(with_state(state.clone()))
+        .and_then(submit_handler);
+
+    // A fortune file uploaded as multipart/form-data, answered with a summary page
+    let import = warp::path!("api" / "import")
+        .and(warp::post())
+        .and(request_id::filter())
+        .and(warp::multipart::form().max_length(state.config.max_import_bytes))
+        .and(csrf::submitted())
+        .and(with_state(state.clone()))
+        .and_then(import::handler);
+
+    // Any other backend route, forwarded as is; a plain 404 when turned off
+    let passthrough_enabled = state.config.backend_passthrough;
+    let passthrough = warp::path!("api" / "backend" / ..)
+        .and(warp::any().and_then(move || async move {
+            if passthrough_enabled {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        }))
+        .untuple_one()
+        .and(warp::method())
+        .and(warp::path::tail())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::stream())
+        .and(request_id::filter())
+        .and(i18n::filter())
+        .and(client_ip::filter(state.config.trusted_proxies()))
+        .and(csrf::submitted())
+        .and(with_state(state.clone()))
+        .and_then(passthrough::handler)
+        .boxed();
+
+    // Admin pages behind the login
+    let admin = admin::routes(state.clone());
+
+    // Static file serving, with cache validators and content-hashed URLs
+    let static_files = request_id::filter()
+        .and(assets::filter(state.assets.clone(), state.csrf.clone()))
+        .map(|request_id: RequestId, file| request_id.attach(file));
+
+    // Combine all routes
+    let routes = healthz
+        .or(metrics)
+        .or(api_random)
+        .or(api_all)
+        .or(api_add)
+        .or(submit)
+        .or(import)
+        .or(api_card)
+        .or(api_audio)
+        .or(api_qr)
+        .or(permalink)
+        .or(sitemap)
+        .or(api_stream)
+        .or(feed)
+        .or(passthrough)
+        .or(admin)
+        .or(static_files)
+        .recover(handle_rejection);
+    let routes = security_headers::wrap(routes, security_headers::from_config(&state.config));
+    access_log::wrap(routes, state.config.access_log())
+}
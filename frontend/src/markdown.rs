@@ -0,0 +1,86 @@
+use pulldown_cmark::{CowStr, Event, Parser, Tag, TagEnd};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+// Fortune messages may use a little Markdown: *emphasis*, **strong**, `code`
+// and [links](https://example.com). The backend stores the source as written;
+// `to_html` renders it for replies that ask for `render=html` and `to_plain`
+// strips it everywhere else. Other Markdown such as headings, lists or raw
+// HTML is reduced to its text.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Render {
+    #[default]
+    Text,
+    Html,
+}
+
+impl Render {
+    pub fn apply(self, message: &str) -> String {
+        match self {
+            Render::Text => to_plain(message),
+            Render::Html => to_html(message),
+        }
+    }
+}
+
+// The inline events of the message, with block structure flattened to spaces
+// and raw HTML turned into text
+fn inline_events(message: &str) -> impl Iterator<Item = Event<'_>> {
+    Parser::new(message).filter_map(|event| match event {
+        Event::Start(Tag::Emphasis | Tag::Strong | Tag::Link { .. }) => Some(event),
+        Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Link) => Some(event),
+        Event::Text(_) | Event::Code(_) | Event::HardBreak => Some(event),
+        Event::Html(html) | Event::InlineHtml(html) => Some(Event::Text(html)),
+        Event::SoftBreak
+        | Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::CodeBlock | TagEnd::BlockQuote(_)) => {
+            Some(Event::Text(CowStr::Borrowed(" ")))
+        }
+        _ => None,
+    })
+}
+
+fn sanitizer() -> &'static ammonia::Builder<'static> {
+    static SANITIZER: OnceLock<ammonia::Builder<'static>> = OnceLock::new();
+    SANITIZER.get_or_init(|| {
+        let mut builder = ammonia::Builder::empty();
+        builder
+            .tags(HashSet::from(["a", "br", "code", "em", "strong"]))
+            .tag_attributes([("a", HashSet::from(["href"]))].into())
+            .url_schemes(HashSet::from(["http", "https", "mailto"]))
+            .link_rel(Some("nofollow noopener noreferrer"));
+        builder
+    })
+}
+
+// Sanitized HTML: only the allowed inline tags survive, links keep http,
+// https and mailto targets and get rel="nofollow noopener noreferrer"
+pub fn to_html(message: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, inline_events(message));
+    sanitizer().clean(html.trim()).to_string()
+}
+
+// The text a reader would see, with each link's target after its text
+pub fn to_plain(message: &str) -> String {
+    let mut text = String::new();
+    let mut links = Vec::new();
+    for event in inline_events(message) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::HardBreak => text.push('\n'),
+            Event::Start(Tag::Link { dest_url, .. }) => links.push((dest_url, text.len())),
+            Event::End(TagEnd::Link) => {
+                if let Some((url, start)) = links.pop() {
+                    if text[start..] != *url {
+                        text.push_str(&format!(" ({})", url));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
@@ -0,0 +1,177 @@
+use crate::i18n::Locale;
+use crate::request_id::{self, RequestId};
+use crate::{csrf, csrf_rejected, SharedState};
+use fortune_common::error;
+use futures_util::{pin_mut, Stream, TryStreamExt};
+use std::convert::Infallible;
+use std::net::IpAddr;
+use warp::http::header::{HeaderMap, HeaderName, CONTENT_LENGTH};
+use warp::http::{Method, Response, StatusCode};
+use warp::hyper::Body;
+use warp::path::Tail;
+use warp::{Buf, Reply};
+
+// /api/backend/<path> forwards any request to the backend's /<path> with its
+// method, query, headers and body, and streams the backend's answer back, so
+// a new backend route is reachable from the browser without a handler here.
+// Writes need the CSRF token like /api/add. They are not signed with
+// REQUEST_SIGNING_SECRET: anyone can get a CSRF token by loading the page, so
+// signing them would let any browser make the writes the secret is there to
+// keep to the frontend. The frontend's cookies and BACKEND_API_KEY never go
+// along either; callers bring their own X-API-Key.
+
+// Headers about one connection rather than the message (RFC 9110, 7.6.1)
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// Request headers the frontend sets itself, or keeps to itself
+const REPLACED: &[&str] = &["host", "content-length", "cookie", "forwarded", "x-forwarded-for", request_id::HEADER];
+
+fn forwarded(name: &HeaderName, also_dropped: &[&str]) -> bool {
+    !HOP_BY_HOP.contains(&name.as_str()) && !also_dropped.contains(&name.as_str())
+}
+
+// The whole body, or None once it grows past `limit` bytes. The backend wants
+// a Content-Length, so the body is read before it is sent on.
+async fn read_body<S, B>(body: S, limit: u64) -> Result<Option<Vec<u8>>, warp::Error>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    pin_mut!(body);
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.try_next().await? {
+        bytes.extend_from_slice(chunk.chunk());
+        if bytes.len() as u64 > limit {
+            return Ok(None);
+        }
+    }
+    Ok(Some(bytes))
+}
+
+fn too_large(locale: Locale) -> warp::reply::Response {
+    warp::reply::with_status(locale.text("request_too_large"), StatusCode::PAYLOAD_TOO_LARGE).into_response()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn handler<S, B>(
+    method: Method,
+    tail: Tail,
+    query: String,
+    headers: HeaderMap,
+    body: S,
+    request_id: RequestId,
+    locale: Locale,
+    client: Option<IpAddr>,
+    submitted: csrf::Submitted,
+    state: SharedState,
+) -> Result<impl Reply, Infallible>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let response = forward(method, tail, query, headers, body, &request_id, locale, client, submitted, &state).await;
+    Ok(request_id.attach(response))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn forward<S, B>(
+    method: Method,
+    tail: Tail,
+    query: String,
+    headers: HeaderMap,
+    body: S,
+    request_id: &RequestId,
+    locale: Locale,
+    client: Option<IpAddr>,
+    submitted: csrf::Submitted,
+    state: &SharedState,
+) -> warp::reply::Response
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let safe = matches!(method, Method::GET | Method::HEAD | Method::OPTIONS);
+    if !safe && !state.csrf.verify(&submitted, None) {
+        return locale.attach(csrf_rejected(locale));
+    }
+    let limit = state.config.max_passthrough_bytes;
+    let declared = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > limit) {
+        return locale.attach(too_large(locale));
+    }
+    let body = match read_body(body, limit).await {
+        Ok(Some(body)) => body,
+        Ok(None) => return locale.attach(too_large(locale)),
+        Err(e) => {
+            return warp::reply::with_status(
+                locale.format("request_failed", &[("error", &e)]),
+                StatusCode::BAD_REQUEST,
+            ).into_response();
+        }
+    };
+
+    let mut path = format!("/{}", tail.as_str());
+    if !query.is_empty() {
+        path = format!("{}?{}", path, query);
+    }
+    let mut outgoing = HeaderMap::new();
+    for (name, value) in headers.iter().filter(|(name, _)| forwarded(name, REPLACED)) {
+        outgoing.append(name.clone(), value.clone());
+    }
+    let mut request = state.http
+        .request(method, state.backends.url(&path))
+        .headers(outgoing)
+        .header(request_id::HEADER, request_id.as_str());
+    if let Some(client) = client {
+        request = request.header("x-forwarded-for", client.to_string());
+    }
+    if !body.is_empty() {
+        request = request.body(body);
+    }
+
+    // Not retried: the request may not be safe to repeat. Answers from the
+    // backend, errors included, are passed on; only 5xx count against the breaker.
+    if !state.breaker.allow_request() {
+        return warp::reply::with_status(locale.text("backend_unavailable"), StatusCode::SERVICE_UNAVAILABLE).into_response();
+    }
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            state.breaker.record_failure();
+            state.backends.record_failure(e.url());
+            error!("[{}] passthrough to {} failed: {}", request_id, path, e);
+            return warp::reply::with_status(
+                locale.format("request_failed", &[("error", &e)]),
+                StatusCode::BAD_GATEWAY,
+            ).into_response();
+        }
+    };
+    if response.status().is_server_error() {
+        state.breaker.record_failure();
+        state.backends.record_failure(Some(response.url()));
+    } else {
+        state.breaker.record_success();
+        state.backends.record_success(response.url());
+    }
+
+    let mut reply = Response::builder().status(response.status());
+    for (name, value) in response.headers().iter().filter(|(name, _)| forwarded(name, &[])) {
+        reply = reply.header(name, value);
+    }
+    reply
+        .body(Body::wrap_stream(response.bytes_stream()))
+        .unwrap_or_else(|_| StatusCode::BAD_GATEWAY.into_response())
+}
@@ -0,0 +1,46 @@
+use image::{ImageFormat, Luma};
+use qrcode::render::svg;
+use qrcode::{EcLevel, QrCode};
+use serde::Deserialize;
+use std::io::Cursor;
+
+// QR codes for /api/qr/{id}, pointing at a fortune's permalink so a printed
+// cookie slip can lead back to the site. Medium error correction survives a
+// crumpled slip while keeping the code small enough to scan from a few cm.
+
+// Size of one module in pixels for PNG; SVG scales freely
+const MODULE_PX: u32 = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Png,
+    Svg,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Png => "image/png",
+            Format::Svg => "image/svg+xml",
+        }
+    }
+}
+
+pub fn render(url: &str, format: Format) -> Result<Vec<u8>, String> {
+    let code = QrCode::with_error_correction_level(url, EcLevel::M).map_err(|e| e.to_string())?;
+    match format {
+        Format::Png => {
+            let image = code.render::<Luma<u8>>().module_dimensions(MODULE_PX, MODULE_PX).build();
+            let mut png = Cursor::new(Vec::new());
+            image.write_to(&mut png, ImageFormat::Png).map_err(|e| e.to_string())?;
+            Ok(png.into_inner())
+        }
+        Format::Svg => Ok(code
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .build()
+            .into_bytes()),
+    }
+}
@@ -0,0 +1,50 @@
+use std::convert::Infallible;
+use std::fmt;
+use warp::{Filter, Reply};
+
+pub const HEADER: &str = fortune_common::REQUEST_ID_HEADER;
+
+const MAX_LEN: usize = 128;
+
+// Correlates a frontend request with the backend calls it makes
+#[derive(Debug, Clone)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        RequestId(format!("{:032x}", rand::random::<u128>()))
+    }
+
+    // Accepts a client-supplied id only if it is safe to log and echo back
+    fn parse(value: &str) -> Option<Self> {
+        let valid = !value.is_empty()
+            && value.len() <= MAX_LEN
+            && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        valid.then(|| RequestId(value.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn attach(&self, reply: impl Reply) -> warp::reply::Response {
+        warp::reply::with_header(reply, HEADER, self.as_str()).into_response()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// Uses the incoming X-Request-Id when valid, otherwise generates a new one
+pub fn filter() -> impl Filter<Extract = (RequestId,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: warp::http::HeaderMap| {
+        headers
+            .get(HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(RequestId::parse)
+            .unwrap_or_else(RequestId::generate)
+    })
+}
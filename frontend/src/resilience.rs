@@ -0,0 +1,270 @@
+use crate::discovery::Backends;
+use crate::request_id::RequestId;
+use fortune_common::{debug, info, warn};
+use rand::Rng;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl BreakerState {
+    fn as_gauge(self) -> u8 {
+        match self {
+            BreakerState::Closed => 0,
+            BreakerState::Open => 1,
+            BreakerState::HalfOpen => 2,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BackendError {
+    CircuitOpen,
+    Request(reqwest::Error),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::CircuitOpen => write!(f, "circuit breaker is open"),
+            BackendError::Request(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // Full jitter: sleep a random amount between zero and the capped exponential delay
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self.base_delay.saturating_mul(1u32 << attempt.min(16));
+        let cap = exp.min(self.max_delay);
+        let millis = cap.as_millis() as u64;
+        if millis == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(rand::thread_rng().gen_range(0..=millis))
+    }
+}
+
+struct BreakerInner {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    // When the half-open trial request went out; set while it is in flight
+    probe_started: Option<Instant>,
+}
+
+pub struct CircuitBreaker {
+    inner: Mutex<BreakerInner>,
+    failure_threshold: u32,
+    open_timeout: Duration,
+    trips: AtomicU64,
+    short_circuits: AtomicU64,
+    retries: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_timeout: Duration) -> Self {
+        CircuitBreaker {
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                probe_started: None,
+            }),
+            failure_threshold: failure_threshold.max(1),
+            open_timeout,
+            trips: AtomicU64::new(0),
+            short_circuits: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.inner.lock().unwrap().state
+    }
+
+    // Returns false while the breaker is open; after the open timeout a single
+    // trial request is let through in the half-open state, and everything else
+    // is refused until it reports back. The check and the claim happen under
+    // one lock, so concurrent callers cannot both become the probe. A probe
+    // that never reports (its request was dropped) is replaced after another
+    // open timeout.
+    pub fn allow_request(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let allowed = match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::HalfOpen => {
+                let stale = inner.probe_started.is_none_or(|t| t.elapsed() >= self.open_timeout);
+                if stale {
+                    inner.probe_started = Some(Instant::now());
+                }
+                stale
+            }
+            BreakerState::Open => {
+                let elapsed = inner.opened_at.map(|t| t.elapsed()).unwrap_or_default();
+                if elapsed >= self.open_timeout {
+                    inner.state = BreakerState::HalfOpen;
+                    inner.probe_started = Some(Instant::now());
+                    debug!("circuit breaker half-open, probing backend");
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        if !allowed {
+            self.short_circuits.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state != BreakerState::Closed {
+            info!("circuit breaker closed");
+        }
+        inner.state = BreakerState::Closed;
+        inner.consecutive_failures = 0;
+        inner.opened_at = None;
+        inner.probe_started = None;
+    }
+
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        let should_trip = inner.state == BreakerState::HalfOpen
+            || (inner.state == BreakerState::Closed
+                && inner.consecutive_failures >= self.failure_threshold);
+        if should_trip {
+            inner.state = BreakerState::Open;
+            inner.opened_at = Some(Instant::now());
+            inner.probe_started = None;
+            self.trips.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "circuit breaker opened after {} consecutive failures",
+                inner.consecutive_failures
+            );
+        }
+    }
+
+    pub fn metrics(&self) -> String {
+        format!(
+            "frontend_breaker_state {}\n\
+             frontend_breaker_trips_total {}\n\
+             frontend_breaker_short_circuits_total {}\n\
+             frontend_backend_retries_total {}\n",
+            self.state().as_gauge(),
+            self.trips.load(Ordering::Relaxed),
+            self.short_circuits.load(Ordering::Relaxed),
+            self.retries.load(Ordering::Relaxed),
+        )
+    }
+}
+
+// Only 5xx responses are treated as backend failures; 4xx are passed through.
+fn server_error_for_status(response: reqwest::Response) -> reqwest::Result<reqwest::Response> {
+    if response.status().is_server_error() {
+        response.error_for_status()
+    } else {
+        Ok(response)
+    }
+}
+
+// Tells `backends` how the instance a request went to fared
+fn record(backends: &Backends, result: &reqwest::Result<reqwest::Response>) {
+    match result {
+        Ok(response) => backends.record_success(response.url()),
+        Err(e) => backends.record_failure(e.url()),
+    }
+}
+
+// GET with retries for idempotent requests. Connection errors and 5xx
+// responses count as failures; the breaker sees one result per call. Retries
+// go to the next backend instance.
+pub async fn get_with_retry(
+    request: reqwest::RequestBuilder,
+    request_id: &RequestId,
+    policy: &RetryPolicy,
+    breaker: &CircuitBreaker,
+    backends: &Backends,
+) -> Result<reqwest::Response, BackendError> {
+    if !breaker.allow_request() {
+        return Err(BackendError::CircuitOpen);
+    }
+
+    let attempts = policy.max_attempts.max(1);
+    let mut last_err = None;
+    for attempt in 0..attempts {
+        if attempt > 0 {
+            breaker.retries.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(policy.backoff(attempt - 1)).await;
+        }
+
+        let (client, attempt_request) = request.try_clone().expect("GET requests have no streaming body").build_split();
+        let mut attempt_request = attempt_request.map_err(BackendError::Request)?;
+        if attempt > 0 {
+            backends.repoint(attempt_request.url_mut());
+        }
+        let result = client.execute(attempt_request).await.and_then(server_error_for_status);
+        record(backends, &result);
+        match result {
+            Ok(response) => {
+                breaker.record_success();
+                return Ok(response);
+            }
+            Err(e) => {
+                warn!("[{}] Attempt {}: GET failed: {}", request_id, attempt + 1, e);
+                last_err = Some(e);
+            }
+        }
+    }
+
+    breaker.record_failure();
+    Err(BackendError::Request(last_err.expect("at least one attempt")))
+}
+
+// Non-idempotent requests are not retried but still go through the breaker.
+pub async fn send_once(
+    request: reqwest::RequestBuilder,
+    breaker: &CircuitBreaker,
+    backends: &Backends,
+) -> Result<reqwest::Response, BackendError> {
+    if !breaker.allow_request() {
+        return Err(BackendError::CircuitOpen);
+    }
+
+    let result = request.send().await.and_then(server_error_for_status);
+    record(backends, &result);
+    match result {
+        Ok(response) => {
+            breaker.record_success();
+            Ok(response)
+        }
+        Err(e) => {
+            breaker.record_failure();
+            Err(BackendError::Request(e))
+        }
+    }
+}
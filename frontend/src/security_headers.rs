@@ -0,0 +1,76 @@
+use crate::config::Config;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use warp::http::header::{HeaderName, HeaderValue};
+use warp::http::HeaderMap;
+use warp::{Filter, Reply};
+
+// Headers added to every response: the security headers below, each turned
+// off by setting it to an empty value, and any custom RESPONSE_HEADERS. A
+// header the handler already set is left alone, so a route can send its own.
+
+// Scripts and styles come from the site and the Bootstrap CDN; the page's
+// theme styles are inline, its handlers are bound in script.js
+pub const DEFAULT_CSP: &str = "default-src 'self'; script-src 'self' https://cdn.jsdelivr.net; style-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; img-src 'self' data:; object-src 'none'; base-uri 'self'; form-action 'self'; frame-ancestors 'none'";
+
+// RESPONSE_HEADERS: a JSON object of header names to values, e.g.
+// `{"X-Frame-Options":"DENY","Permissions-Policy":"camera=()"}`
+pub fn parse_custom(json: &str) -> Result<Vec<(HeaderName, HeaderValue)>, String> {
+    let headers: BTreeMap<String, String> =
+        serde_json::from_str(json).map_err(|e| format!("RESPONSE_HEADERS is not a JSON object of strings: {}", e))?;
+    headers
+        .into_iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("'{}' is not a valid header name", name))?;
+            let value = HeaderValue::from_str(&value).map_err(|_| format!("the value of {} is not a valid header value", name))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+// The headers to add, checked by Config::validate
+pub fn from_config(config: &Config) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let mut security = vec![
+        ("content-security-policy", &config.content_security_policy),
+        ("x-content-type-options", &config.content_type_options),
+        ("referrer-policy", &config.referrer_policy),
+    ];
+    // Browsers only heed HSTS over HTTPS, and it would lock them out of a
+    // site that has no TLS to fall back to
+    if config.tls().is_some() {
+        security.push(("strict-transport-security", &config.strict_transport_security));
+    }
+    for (name, value) in security {
+        match HeaderValue::from_str(value) {
+            Ok(value) if !value.is_empty() => {
+                headers.insert(name, value);
+            }
+            _ => {}
+        }
+    }
+    if let Some(custom) = &config.response_headers {
+        for (name, value) in parse_custom(custom).unwrap_or_default() {
+            headers.insert(name, value);
+        }
+    }
+    headers
+}
+
+pub fn wrap<F, R>(routes: F, headers: HeaderMap) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let headers = Arc::new(headers);
+    routes.map(move |reply: R| {
+        let mut res = reply.into_response();
+        for (name, value) in headers.iter() {
+            if !res.headers().contains_key(name) {
+                res.headers_mut().insert(name, value.clone());
+            }
+        }
+        res
+    })
+}
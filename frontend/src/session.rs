@@ -0,0 +1,99 @@
+use crate::config::Config;
+use crate::signing::{now_secs, Signer};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use std::sync::Arc;
+use warp::{Filter, Rejection};
+
+// Signed session cookies for the admin pages. There is a single admin
+// account, ADMIN_USER with the Argon2 ADMIN_PASSWORD_HASH; a successful login
+// sets a cookie `<expires at>.<hex user>.<signature>` that is valid until it
+// expires or SESSION_SECRET changes.
+
+pub const COOKIE: &str = "session";
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user: String,
+}
+
+// An admin page was requested without a valid session; answered with a
+// redirect to the login page
+#[derive(Debug)]
+pub struct LoginRequired;
+
+impl warp::reject::Reject for LoginRequired {}
+
+pub struct Sessions {
+    signer: Signer,
+    user: String,
+    password_hash: String,
+    ttl_secs: u64,
+}
+
+impl Sessions {
+    // None when ADMIN_USER is not set, which turns the admin pages off
+    pub fn from_config(config: &Config) -> Option<Sessions> {
+        let (user, password_hash) = config.admin_user.clone().zip(config.admin_password_hash.clone())?;
+        Some(Sessions {
+            signer: Signer::new(config.session_secret.as_deref(), "SESSION_SECRET"),
+            user,
+            password_hash,
+            ttl_secs: config.session_ttl_secs,
+        })
+    }
+
+    // The session cookie for a correct user and password
+    pub async fn login(&self, user: &str, password: &str) -> Option<String> {
+        // Argon2 is slow on purpose, so it runs off the async workers. The
+        // password is checked even for a wrong user to not reveal which it was.
+        let hash = self.password_hash.clone();
+        let password = password.to_string();
+        let password_ok = tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        })
+        .await
+        .unwrap_or(false);
+        (password_ok && user == self.user).then(|| self.cookie(user))
+    }
+
+    fn cookie(&self, user: &str) -> String {
+        let value = self.signer.sign(&format!("{}.{}", now_secs() + self.ttl_secs, hex::encode(user)));
+        format!("{}={}; Path=/admin; Max-Age={}; HttpOnly; SameSite=Strict", COOKIE, value, self.ttl_secs)
+    }
+
+    pub fn verify(&self, cookie: &str) -> Option<Session> {
+        let (expires, user) = self.signer.verify(cookie)?.split_once('.')?;
+        if expires.parse::<u64>().ok()? <= now_secs() {
+            return None;
+        }
+        let user = String::from_utf8(hex::decode(user).ok()?).ok()?;
+        // Sessions of a user that is no longer configured are void
+        (user == self.user).then_some(Session { user })
+    }
+}
+
+pub fn logout_cookie() -> String {
+    format!("{}=; Path=/admin; Max-Age=0; HttpOnly; SameSite=Strict", COOKIE)
+}
+
+// Admin login is configured and the visitor may have a session
+pub fn enabled(sessions: Option<Arc<Sessions>>) -> impl Filter<Extract = (Arc<Sessions>, Option<Session>), Error = Rejection> + Clone {
+    warp::cookie::optional::<String>(COOKIE)
+        .and_then(move |cookie: Option<String>| {
+            let sessions = sessions.clone();
+            async move {
+                let sessions = sessions.ok_or_else(warp::reject::not_found)?;
+                let session = cookie.as_deref().and_then(|cookie| sessions.verify(cookie));
+                Ok::<_, Rejection>((sessions, session))
+            }
+        })
+        .untuple_one()
+}
+
+// The signed-in admin; 404 when admin login is off, LoginRequired without a
+// valid session
+pub fn required(sessions: Option<Arc<Sessions>>) -> impl Filter<Extract = (Session,), Error = Rejection> + Clone {
+    enabled(sessions).and_then(|_, session: Option<Session>| async move {
+        session.ok_or_else(|| warp::reject::custom(LoginRequired))
+    })
+}
@@ -0,0 +1,91 @@
+use fortune_common::info;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Signs cookie values with HMAC-SHA256 so the frontend can trust them when
+// they come back: `<payload>.<hex signature>`
+pub struct Signer {
+    key: Vec<u8>,
+}
+
+impl Signer {
+    // Without a secret a random key is used, so signed values stop working
+    // when the process restarts and are not shared between replicas. `name`
+    // is the env var that sets the secret.
+    pub fn new(secret: Option<&str>, name: &str) -> Self {
+        let key = match secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                info!("{} not set; using a random secret, which does not survive restarts or span replicas", name);
+                rand::random::<[u8; 32]>().to_vec()
+            }
+        };
+        Signer { key }
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, payload: &str) -> String {
+        format!("{}.{}", payload, hex::encode(self.mac(payload).finalize().into_bytes()))
+    }
+
+    // The payload of a value this signer produced; None if it was tampered with
+    pub fn verify<'a>(&self, signed: &'a str) -> Option<&'a str> {
+        let (payload, signature) = signed.rsplit_once('.')?;
+        let signature = hex::decode(signature).ok()?;
+        self.mac(payload).verify_slice(&signature).ok()?;
+        Some(payload)
+    }
+}
+
+// Signs a request to the backend with REQUEST_SIGNING_SECRET, as the backend's
+// signing.rs expects: an HMAC-SHA256 of
+// `<method>\n<path>?<query>\n<timestamp>\n<hex SHA-256 of the body>`, sent in
+// X-Signature with X-Signature-Timestamp and X-Content-SHA256. A request whose
+// body is a stream, or that cannot be built, is returned unsigned.
+pub fn sign_request(request: reqwest::RequestBuilder, secret: &str) -> reqwest::RequestBuilder {
+    let Some(unsigned) = request.try_clone() else {
+        return request;
+    };
+    let (client, built) = request.build_split();
+    let Ok(mut built) = built else {
+        // Sending it fails with the same error
+        return unsigned;
+    };
+    let body = built.body().and_then(|body| body.as_bytes()).unwrap_or_default();
+    let content_hash = hex::encode(Sha256::digest(body));
+    let url = built.url();
+    let path_and_query = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let timestamp = now_secs();
+    let canonical = format!("{}\n{}\n{}\n{}", built.method(), path_and_query, timestamp, content_hash);
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(canonical.as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+
+    let headers = built.headers_mut();
+    for (name, value) in [
+        ("x-signature-timestamp", timestamp.to_string()),
+        ("x-content-sha256", content_hash),
+        ("x-signature", signature),
+    ] {
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+    reqwest::RequestBuilder::from_parts(client, built)
+}
+
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,37 @@
+use crate::decoration::{civil_date, DAY_SECS};
+use crate::Fortune;
+use std::fmt::Write;
+
+// A sitemap may list at most 50,000 URLs; the site root takes one of them
+const MAX_FORTUNES: usize = 49_999;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// W3C date as <lastmod> expects, e.g. `2024-03-09`
+fn w3c_date(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs / DAY_SECS);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// The site root and every fortune's permalink page; `base` is the absolute
+// URL of the site root
+pub fn render(fortunes: &[Fortune], base: &str) -> String {
+    let mut xml = String::new();
+    xml.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+    let _ = write!(xml, "<url><loc>{}/</loc></url>", escape(base));
+    for fortune in fortunes.iter().take(MAX_FORTUNES) {
+        let _ = write!(xml, "<url><loc>{}/fortune/{}</loc>", escape(base), escape(&fortune.id));
+        if let Some(created_at) = fortune.created_at {
+            let _ = write!(xml, "<lastmod>{}</lastmod>", w3c_date(created_at));
+        }
+        xml.push_str("</url>");
+    }
+    xml.push_str("</urlset>");
+    xml
+}
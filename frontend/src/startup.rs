@@ -0,0 +1,30 @@
+use crate::config::Config;
+use crate::discovery::Backends;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Polls the backend's /healthz until it answers 200 or `deadline` passes. With
+// several backends each poll asks the next one, and any of them will do.
+pub async fn wait_for_backend(config: &Config, deadline: Duration) -> Result<(), String> {
+    let http = reqwest::Client::builder()
+        .timeout(config.backend_timeout())
+        .build()
+        .map_err(|e| e.to_string())?;
+    let backends = Backends::from_config(config);
+    let give_up = Instant::now() + deadline;
+    loop {
+        backends.refresh().await;
+        let url = backends.url("/healthz");
+        let last = match http.get(&url).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => format!("{} answered {}", url, response.status()),
+            Err(e) => e.to_string(),
+        };
+        if Instant::now() + POLL_INTERVAL > give_up {
+            return Err(format!("backend not healthy after {}s: {}", deadline.as_secs(), last));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
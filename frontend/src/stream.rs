@@ -0,0 +1,87 @@
+use crate::request_id::RequestId;
+use crate::{backend_get, markdown, pick_cached_fortune, resilience, Fortune, SharedState};
+use fortune_common::{error, info, warn};
+use futures_util::{Stream, StreamExt};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+use warp::sse::Event;
+use warp::Reply;
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BackendEvent {
+    Create { fortune: Fortune },
+    Update { fortune: Fortune },
+    #[serde(other)]
+    Other,
+}
+
+// Relays fortune events from the backend WebSocket to SSE subscribers,
+// reconnecting whenever the backend goes away.
+pub fn spawn_relay(state: SharedState) {
+    tokio::spawn(async move {
+        let url = state.backends.url("/fortunes/ws").replacen("http://", "ws://", 1);
+        loop {
+            match tokio_tungstenite::connect_async(url.as_str()).await {
+                Ok((mut socket, _)) => {
+                    info!("connected to backend event stream");
+                    while let Some(Ok(msg)) = socket.next().await {
+                        let Message::Text(text) = msg else { continue };
+                        match serde_json::from_str::<BackendEvent>(&text) {
+                            Ok(BackendEvent::Create { fortune } | BackendEvent::Update { fortune }) => {
+                                state.cache.invalidate().await;
+                                let _ = state.created.send(fortune);
+                            }
+                            Ok(BackendEvent::Other) => {}
+                            Err(e) => warn!("invalid backend event: {}", e),
+                        }
+                    }
+                    warn!("backend event stream closed");
+                }
+                Err(e) => error!("backend event stream connect failed: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    });
+}
+
+// The ticker shows messages as plain text
+async fn random_message(state: &SharedState, request_id: &RequestId) -> Option<String> {
+    if let Some(fortune) = pick_cached_fortune(state, request_id).await {
+        return Some(markdown::to_plain(&fortune.message));
+    }
+    let request = backend_get(state, "/fortunes/random", request_id);
+    let response = resilience::get_with_retry(request, request_id, &state.retry, &state.breaker, &state.backends).await.ok()?;
+    response.json::<Fortune>().await.ok().map(|f| markdown::to_plain(&f.message))
+}
+
+// Backend calls made for the ticker reuse the request id of the stream itself
+fn events(state: SharedState, request_id: RequestId) -> impl Stream<Item = Result<Event, Infallible>> {
+    let ticker = tokio::time::interval(Duration::from_secs(state.config.stream_interval_secs));
+    let created = state.created.subscribe();
+
+    futures_util::stream::unfold((state, request_id, ticker, created), |(state, request_id, mut ticker, mut created)| async move {
+        let event = tokio::select! {
+            _ = ticker.tick() => match random_message(&state, &request_id).await {
+                Some(message) => Event::default().event("fortune").data(message),
+                None => Event::default().comment("no fortune available"),
+            },
+            fortune = created.recv() => match fortune {
+                Ok(fortune) => Event::default()
+                    .event("created")
+                    .json_data(&fortune)
+                    .unwrap_or_else(|_| Event::default().comment("invalid event")),
+                Err(broadcast::error::RecvError::Lagged(_)) => Event::default().comment("lagged"),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            },
+        };
+        Some((Ok(event), (state, request_id, ticker, created)))
+    })
+}
+
+pub async fn stream_handler(request_id: RequestId, state: SharedState) -> Result<impl Reply, Infallible> {
+    let stream = events(state, request_id.clone());
+    Ok(request_id.attach(warp::sse::reply(warp::sse::keep_alive().stream(stream))))
+}
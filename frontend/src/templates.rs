@@ -0,0 +1,75 @@
+use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext};
+use std::path::{Path, PathBuf};
+
+// Server-rendered pages, built into the binary
+const TEMPLATES: &[(&str, &str)] = &[
+    ("import", include_str!("../templates/import.html")),
+    ("fortune", include_str!("../templates/fortune.html")),
+    ("admin/layout", include_str!("../templates/admin/layout.html")),
+    ("admin/login", include_str!("../templates/admin/login.html")),
+    ("admin/index", include_str!("../templates/admin/index.html")),
+    ("admin/fortunes", include_str!("../templates/admin/fortunes.html")),
+    ("admin/edit", include_str!("../templates/admin/edit.html")),
+    ("admin/moderation", include_str!("../templates/admin/moderation.html")),
+    ("admin/import", include_str!("../templates/admin/import.html")),
+];
+
+// The built-in templates, then every `.html` file under `dir`, named by its
+// path without the extension: `admin/layout.html` replaces `admin/layout`,
+// and a new file such as `partials/footer.html` can be included from the
+// others with `{{> partials/footer}}`. Every page gets `{{theme}}`, the
+// active theme's name, and `{{theme_css}}`, the versioned URL of its
+// stylesheet.
+pub fn registry(dir: Option<&Path>, theme: &str, theme_css: &str) -> Result<Handlebars<'static>, String> {
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(false);
+    for (name, template) in TEMPLATES {
+        handlebars
+            .register_template_string(name, *template)
+            .expect("templates are valid");
+    }
+    if let Some(dir) = dir {
+        for (name, path) in find(dir).map_err(|e| format!("{}: {}", dir.display(), e))? {
+            let template = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            handlebars
+                .register_template_string(&name, template)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+    }
+    handlebars.register_helper("theme", Box::new(text(theme)));
+    handlebars.register_helper("theme_css", Box::new(text(theme_css)));
+    Ok(handlebars)
+}
+
+// A helper that writes `value`
+fn text(value: &str) -> impl Fn(&Helper, &Handlebars, &Context, &mut RenderContext, &mut dyn Output) -> HelperResult + Send + Sync {
+    let value = value.to_string();
+    move |_: &Helper, _: &Handlebars, _: &Context, _: &mut RenderContext, out: &mut dyn Output| {
+        out.write(&value)?;
+        Ok(())
+    }
+}
+
+// The `.html` files under `dir` and its subdirectories, with their template names
+fn find(dir: &Path) -> std::io::Result<Vec<(String, PathBuf)>> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "html") {
+                let Ok(relative) = path.with_extension("").strip_prefix(dir).map(Path::to_path_buf) else {
+                    continue;
+                };
+                let name: Vec<String> = relative
+                    .components()
+                    .map(|part| part.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                found.push((name.join("/"), path));
+            }
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,48 @@
+use std::path::Path;
+
+// Replaced in static HTML pages with the active theme's name
+pub const PLACEHOLDER: &str = "{{theme}}";
+
+// Served at /theme.css, linked from every page
+pub const FILE: &str = "theme.css";
+
+// Stylesheets built into the binary
+const BUILT_IN: &[(&str, &str)] = &[
+    ("default", include_str!("../themes/default.css")),
+    ("dark", include_str!("../themes/dark.css")),
+    ("sepia", include_str!("../themes/sepia.css")),
+];
+
+pub struct Theme {
+    pub name: String,
+    pub css: String,
+}
+
+impl Theme {
+    // `themes/<name>.css` in TEMPLATE_DIR when there is one, otherwise the
+    // built-in stylesheet of that name
+    pub fn load(name: &str, template_dir: Option<&Path>) -> Result<Theme, String> {
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+            return Err(format!("THEME '{}' may only contain letters, digits, '-' and '_'", name));
+        }
+        if let Some(dir) = template_dir {
+            let path = dir.join("themes").join(format!("{}.css", name));
+            if path.is_file() {
+                let css = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                return Ok(Theme { name: name.to_string(), css });
+            }
+        }
+        match BUILT_IN.iter().find(|(built_in, _)| *built_in == name) {
+            Some((_, css)) => Ok(Theme {
+                name: name.to_string(),
+                css: css.to_string(),
+            }),
+            None => Err(format!(
+                "THEME '{}' is not one of {} and TEMPLATE_DIR has no themes/{}.css",
+                name,
+                BUILT_IN.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", "),
+                name
+            )),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::config::Config;
+use serde::Deserialize;
+use serde_json::json;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use warp::hyper::Body;
+
+// Text to speech for /api/fortune-audio. Either a local command, run through
+// `sh -c` with the text on stdin and MP3 expected on stdout (the default pipes
+// espeak-ng into lame), or an OpenAI-compatible speech API such as
+// https://api.openai.com/v1/audio/speech, whose MP3 answer is streamed through.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    Command,
+    Api,
+}
+
+pub enum Provider {
+    Command {
+        command: String,
+        timeout: Duration,
+    },
+    Api {
+        http: reqwest::Client,
+        url: String,
+        api_key: String,
+        model: String,
+        voice: String,
+    },
+}
+
+impl Provider {
+    // None when TTS_PROVIDER is unset, which leaves /api/fortune-audio out
+    pub fn from_config(config: &Config) -> Option<Provider> {
+        let timeout = Duration::from_millis(config.tts_timeout_ms);
+        match config.tts_provider? {
+            ProviderKind::Command => Some(Provider::Command {
+                command: config.tts_command.clone(),
+                timeout,
+            }),
+            ProviderKind::Api => Some(Provider::Api {
+                http: reqwest::Client::builder()
+                    .connect_timeout(timeout)
+                    .build()
+                    .expect("failed to build TTS HTTP client"),
+                url: config.tts_api_url.clone(),
+                // Checked by Config::validate
+                api_key: config.tts_api_key.clone().unwrap_or_default(),
+                model: config.tts_model.clone(),
+                voice: config.tts_voice.clone(),
+            }),
+        }
+    }
+
+    // MP3 of `text`. A command's output is collected first so a failing one
+    // can still be reported; the API's answer is streamed as it arrives.
+    pub async fn synthesize(&self, text: &str) -> Result<Body, String> {
+        match self {
+            Provider::Command { command, timeout } => {
+                let output = tokio::time::timeout(*timeout, run(command, text))
+                    .await
+                    .map_err(|_| format!("{} timed out", command))??;
+                Ok(Body::from(output))
+            }
+            Provider::Api { http, url, api_key, model, voice } => {
+                let response = http
+                    .post(url)
+                    .bearer_auth(api_key)
+                    .json(&json!({ "model": model, "voice": voice, "input": text, "response_format": "mp3" }))
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if !response.status().is_success() {
+                    return Err(format!("speech API answered {}", response.status()));
+                }
+                Ok(Body::wrap_stream(response.bytes_stream()))
+            }
+        }
+    }
+}
+
+async fn run(command: &str, text: &str) -> Result<Vec<u8>, String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("could not start {}: {}", command, e))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let text = text.to_string();
+    // Written alongside the read, so a long text cannot fill both pipes
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(text.as_bytes()).await;
+    });
+    let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+    let _ = writer.await;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{} exited with {}: {}", command, output.status, stderr.trim()));
+    }
+    if output.stdout.is_empty() {
+        return Err(format!("{} produced no audio", command));
+    }
+    Ok(output.stdout)
+}
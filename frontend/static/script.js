@@ -1,9 +1,11 @@
+// Both answer with sanitized HTML, so their Markdown formatting shows; the
+// random fortune comes in the theme of the day
 function getRandom() {
-    get("/api/random");
+    get("/api/random?render=html&decorated=day");
 }
 
 function getAll() {
-    get("/api/all");
+    get("/api/all?render=html");
 }
 
 function get(endpoint) {
@@ -24,14 +26,17 @@ function addCookie(e) {
 
         const params = {
             message: document.querySelector('#message').value,
+            author: document.querySelector('#author').value,
+            csrf_token: document.querySelector('input[name="csrf_token"]').value,
         }
 
         var xhttp = new XMLHttpRequest();
         xhttp.onload = function() {
-            if (this.status == 200) {
+            if (this.status == 200 || this.status == 202) {
                 document.getElementById("output").innerHTML =
                     this.responseText;
                 document.querySelector('#message').value = ""
+                document.querySelector('#author').value = ""
             } else {
                 document.getElementById("output").innerHTML =
                     `Error: ${this.status}, ${this.responseText}`
@@ -45,3 +50,39 @@ function addCookie(e) {
     }
     return false;
 }
+
+function startTicker() {
+    if (!window.EventSource) {
+        return;
+    }
+    var source = new EventSource("/api/stream");
+    source.addEventListener("fortune", function(e) {
+        document.getElementById("ticker").textContent = e.data;
+    });
+    source.addEventListener("created", function(e) {
+        var fortune = JSON.parse(e.data);
+        document.getElementById("ticker").textContent = "New: " + fortune.message;
+    });
+}
+
+// Shows the message left by a form submission without JavaScript (POST /submit)
+function showFlash() {
+    var match = document.cookie.match(/(?:^|;\s*)flash=([^;]*)/);
+    if (!match) {
+        return;
+    }
+    document.getElementById("output").textContent = decodeURIComponent(match[1]);
+    document.cookie = "flash=; Path=/; Max-Age=0";
+}
+
+// Handlers are bound here rather than in onclick attributes, which the
+// Content-Security-Policy would block as inline script
+function bindControls() {
+    document.getElementById("get-random").addEventListener("click", getRandom);
+    document.getElementById("get-all").addEventListener("click", getAll);
+    document.getElementById("add-form").addEventListener("submit", addCookie);
+}
+
+window.addEventListener("load", bindControls);
+window.addEventListener("load", startTicker);
+window.addEventListener("load", showFlash);
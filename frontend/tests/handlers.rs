@@ -0,0 +1,1610 @@
+use fortune_frontend::config::Config;
+use fortune_frontend::{compression, create_state, routes, startup};
+use serde_json::{json, Value};
+use std::time::Duration;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+use wiremock::matchers::{body_partial_json, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn test_config(backend: &MockServer, extra: &[(&str, &str)]) -> Config {
+    let mut vars = vec![
+        ("BACKEND_DNS".to_string(), backend.address().ip().to_string()),
+        ("BACKEND_PORT".to_string(), backend.address().port().to_string()),
+        ("BACKEND_RETRY_BASE_MS".to_string(), "1".to_string()),
+        ("BACKEND_RETRY_MAX_MS".to_string(), "5".to_string()),
+    ];
+    vars.extend(extra.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+    envy::from_iter(vars).unwrap()
+}
+
+// Loads the page, as a browser would before posting the form, and returns
+// the CSRF token from its cookie
+async fn csrf_token<F>(api: &F) -> String
+where
+    F: Filter + 'static,
+    F::Extract: Reply + Send,
+{
+    let res = warp::test::request().path("/").reply(api).await;
+    let cookie = res.headers()["set-cookie"].to_str().unwrap();
+    let token = cookie.strip_prefix("csrf=").unwrap().split(';').next().unwrap().to_string();
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.contains(&format!("value=\"{}\"", token)), "the form carries the token");
+    token
+}
+
+fn fortunes_body() -> serde_json::Value {
+    json!([
+        {"id": "1", "message": "A mocked fortune."},
+        {"id": "2", "message": "Another <b>mocked</b> fortune."}
+    ])
+}
+
+#[tokio::test]
+async fn random_is_served_from_the_fortune_list() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"id": "1", "message": "Only one."}])))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    for _ in 0..3 {
+        let res = warp::test::request().path("/api/random").reply(&api).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body(), "Only one.");
+    }
+}
+
+#[tokio::test]
+async fn all_renders_escaped_html() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fortunes_body()))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().path("/api/all").reply(&api).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(body.contains("<p>1: A mocked fortune.</p>"));
+    assert!(body.contains("&lt;b&gt;mocked&lt;/b&gt;"));
+}
+
+#[tokio::test]
+async fn markdown_is_rendered_to_sanitized_html_on_request() {
+    let message = "Be *bold*, read [the docs](https://example.com/docs) <script>alert(1)</script> [x](javascript:alert(1))";
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"id": "1", "message": message}])))
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1", "message": message})))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().path("/api/random?render=html").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.contains("Be <em>bold</em>"));
+    assert!(html.contains(r#"<a href="https://example.com/docs" rel="nofollow noopener noreferrer">the docs</a>"#));
+    assert!(html.contains("&lt;script&gt;"));
+    assert!(!html.contains("<script"));
+    assert!(!html.contains("javascript:"));
+
+    let res = warp::test::request().path("/api/all?render=html").reply(&api).await;
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(body.contains("<p>1: Be <em>bold</em>"));
+    assert!(!body.contains("<script"));
+
+    // Everywhere else the Markdown is reduced to plain text
+    let plain = "Be bold, read the docs (https://example.com/docs) <script>alert(1)</script> x (javascript:alert(1))";
+    let res = warp::test::request().path("/api/random").reply(&api).await;
+    assert_eq!(res.body(), plain);
+    let res = warp::test::request().path("/api/all").reply(&api).await;
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(body.contains("<p>1: Be bold, read the docs (https://example.com/docs) &lt;script&gt;"));
+    let res = warp::test::request().path("/api/fortune-card?id=1").reply(&api).await;
+    let svg = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(svg.contains("Be bold,"));
+    assert!(!svg.contains('*'));
+
+    let res = warp::test::request().path("/api/random?render=pdf").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn random_fortunes_can_be_decorated_with_a_theme() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"id": "1", "message": "Be *bold*."}])))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().path("/api/random?render=html&decorated=day").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let theme = res.headers()["x-fortune-theme"].to_str().unwrap().to_string();
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.starts_with(&format!(r#"<div class="fortune-theme fortune-theme-{}""#, theme)), "{}", html);
+    assert!(html.contains("Be <em>bold</em>."));
+    // The theme of the day is the same for every request that day
+    let res = warp::test::request().path("/api/random?decorated=true").reply(&api).await;
+    assert_eq!(res.headers()["x-fortune-theme"], theme.as_str());
+    let text = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(text.contains(" Be bold. "), "{}", text);
+    assert!(!text.starts_with("Be"));
+
+    let res = warp::test::request().path("/api/random?decorated=request").reply(&api).await;
+    assert!(res.headers().contains_key("x-fortune-theme"));
+
+    let res = warp::test::request().path("/api/random?decorated=false").reply(&api).await;
+    assert!(!res.headers().contains_key("x-fortune-theme"));
+    assert_eq!(res.body(), "Be bold.");
+    let res = warp::test::request().path("/api/random?decorated=sparkly").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn add_posts_to_backend_and_invalidates_cache() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fortunes_body()))
+        .expect(2)
+        .mount(&backend)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "3", "message": "new"})))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+    let token = csrf_token(&api).await;
+
+    warp::test::request().path("/api/all").reply(&api).await;
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/add")
+        .header("cookie", format!("csrf={}", token))
+        .header("x-csrf-token", &token)
+        .json(&json!({"message": "new"}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.body(), "Cookie added!");
+
+    // The cache was invalidated, so this fetches the list again
+    warp::test::request().path("/api/all").reply(&api).await;
+}
+
+#[tokio::test]
+async fn add_forwards_the_author_and_list_shows_it() {
+    let backend = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .and(body_partial_json(json!({"message": "Know thyself.", "author": "Socrates"})))
+        .and(header("x-forwarded-for", "198.51.100.9"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "5", "message": "Know thyself."})))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!([{"id": "5", "message": "Know thyself.", "author": "Socrates"}])),
+        )
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[("TRUSTED_PROXIES", "10.0.0.0/8")])));
+    let token = csrf_token(&api).await;
+
+    // The client address behind the ingress goes to the backend
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/add")
+        .remote_addr("10.0.0.2:41000".parse().unwrap())
+        .header("x-forwarded-for", "198.51.100.9")
+        .header("cookie", format!("csrf={}", token))
+        .header("x-csrf-token", &token)
+        .json(&json!({"message": "Know thyself.", "author": " Socrates "}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = warp::test::request().path("/api/all").reply(&api).await;
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(body.contains("<p>5: Know thyself. &mdash; Socrates</p>"));
+}
+
+#[tokio::test]
+async fn messages_follow_accept_language() {
+    let backend = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "3", "message": "new"})))
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/missing"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+    let token = csrf_token(&api).await;
+
+    for (accept_language, language, expected) in [
+        ("fr-CA, de;q=0.9", "fr", "Biscuit ajouté !"),
+        ("ja, de;q=0.5, en;q=0.2", "de", "Glückskeks hinzugefügt!"),
+        ("ja", "en", "Cookie added!"),
+    ] {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/add")
+            .header("accept-language", accept_language)
+            .header("cookie", format!("csrf={}", token))
+            .header("x-csrf-token", &token)
+            .json(&json!({"message": "new"}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body(), expected, "for {}", accept_language);
+        assert_eq!(res.headers()["content-language"], language);
+        assert_eq!(res.headers()["vary"], "accept-language");
+    }
+
+    let res = warp::test::request()
+        .path("/api/fortune-card?id=missing")
+        .header("accept-language", "es-MX")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(res.body(), "Galleta no encontrada");
+
+    // Every bundled catalog translates every English message
+    let catalog = |name: &str| -> serde_json::Map<String, serde_json::Value> {
+        serde_json::from_str(&std::fs::read_to_string(format!("locales/{}", name)).unwrap()).unwrap()
+    };
+    let english: Vec<String> = catalog("en.json").keys().cloned().collect();
+    for entry in std::fs::read_dir("locales").unwrap() {
+        let name = entry.unwrap().file_name().into_string().unwrap();
+        let keys: Vec<String> = catalog(&name).keys().cloned().collect();
+        assert_eq!(keys, english, "keys of locales/{}", name);
+    }
+}
+
+#[tokio::test]
+async fn form_submissions_are_accepted_and_redirect_with_a_flash_message() {
+    let backend = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .and(body_partial_json(json!({"message": "Know thyself.", "author": "Socrates"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "5", "message": "Know thyself."})))
+        .expect(2)
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+    let token = csrf_token(&api).await;
+    let form = format!("message=Know+thyself.&author=+Socrates+&csrf_token={}", token);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/add")
+        .header("cookie", format!("csrf={}", token))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(&form)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.body(), "Cookie added!");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/submit")
+        .header("cookie", format!("csrf={}", token))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(&form)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(res.headers()["location"], "/");
+    let cookie = res.headers()["set-cookie"].to_str().unwrap();
+    assert!(cookie.starts_with("flash=Cookie%20added%21;"), "{}", cookie);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/add")
+        .header("content-type", "text/plain")
+        .body("Know thyself.")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+async fn add_requires_a_matching_signed_csrf_token() {
+    let backend = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "3", "message": "new"})))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let secret = [("CSRF_SECRET", "a-secret-shared-by-replicas")];
+    let api = routes(create_state(test_config(&backend, &secret)));
+    let token = csrf_token(&api).await;
+    let add = |cookie: &str, header: &str| {
+        warp::test::request()
+            .method("POST")
+            .path("/api/add")
+            .header("cookie", format!("csrf={}", cookie))
+            .header("x-csrf-token", header)
+            .json(&json!({"message": "new"}))
+    };
+
+    let res = warp::test::request().method("POST").path("/api/add").json(&json!({"message": "new"})).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = add(&token, "something-else").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    // Matching but not signed with the secret
+    let forged = "4102444800.00.00";
+    let res = add(forged, forged).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = warp::test::request()
+        .method("POST")
+        .path("/submit")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body("message=new")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // A visitor with a valid token keeps it, and a replica with the same
+    // secret accepts it
+    let res = warp::test::request().path("/").header("cookie", format!("csrf={}", token)).reply(&api).await;
+    assert!(!res.headers().contains_key("set-cookie"));
+    let replica = routes(create_state(test_config(&backend, &secret)));
+    let res = add(&token, &token).reply(&replica).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+fn admin_config(backend: &MockServer) -> Config {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+    let salt = SaltString::encode_b64(b"a test salt").unwrap();
+    let hash = argon2::Argon2::default().hash_password(b"hunter22", &salt).unwrap().to_string();
+    test_config(
+        backend,
+        &[("ADMIN_USER", "admin"), ("ADMIN_PASSWORD_HASH", &hash), ("BACKEND_API_KEY", "backend-key")],
+    )
+}
+
+// The value of the `name` cookie a response sets
+fn set_cookie(res: &warp::http::Response<warp::hyper::body::Bytes>, name: &str) -> Option<String> {
+    res.headers()
+        .get_all("set-cookie")
+        .iter()
+        .filter_map(|v| v.to_str().ok()?.strip_prefix(&format!("{}=", name))?.split(';').next().map(str::to_string))
+        .next()
+}
+
+#[tokio::test]
+async fn admin_pages_require_a_login() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/admin/stats"))
+        .and(header("x-api-key", "backend-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "fortunes": 42,
+            "moderation": {"pending": 3, "approved": 39, "rejected": 0},
+            "redis": "disabled",
+            "uptime_secs": 7,
+            "read_only": false
+        })))
+        .mount(&backend)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path("/fortunes/7"))
+        .and(header("x-api-key", "backend-key"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(admin_config(&backend)));
+
+    let res = warp::test::request().path("/admin").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(res.headers()["location"], "/admin/login");
+
+    let res = warp::test::request().path("/admin/login").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let token = set_cookie(&res, "csrf").unwrap();
+    let login = |password: &str| {
+        warp::test::request()
+            .method("POST")
+            .path("/admin/login")
+            .header("cookie", format!("csrf={}", token))
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(format!("user=admin&password={}&csrf_token={}", password, token))
+    };
+
+    let res = login("wrong").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert!(set_cookie(&res, "session").is_none());
+
+    let res = login("hunter22").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    let session = set_cookie(&res, "session").unwrap();
+    let cookies = format!("csrf={}; session={}", token, session);
+
+    let res = warp::test::request().path("/admin").header("cookie", &cookies).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.contains("<td>42</td>"));
+    assert!(html.contains("<td>3</td>"));
+
+    // A tampered session is no session
+    let forged = format!("csrf={}; session={}0", token, session);
+    let res = warp::test::request().path("/admin").header("cookie", forged).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+
+    let delete = |csrf_token: &str| {
+        warp::test::request()
+            .method("POST")
+            .path("/admin/fortunes/7/delete")
+            .header("cookie", &cookies)
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(format!("csrf_token={}", csrf_token))
+    };
+    let res = delete("").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = delete(&token).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(res.headers()["location"], "/admin/fortunes");
+    assert!(set_cookie(&res, "flash").unwrap().contains("deleted"));
+}
+
+// Signs in as the admin of `admin_config` and returns the CSRF token and the
+// cookies to send with admin requests
+async fn admin_login<F>(api: &F) -> (String, String)
+where
+    F: Filter + 'static,
+    F::Extract: Reply + Send,
+{
+    let res = warp::test::request().path("/admin/login").reply(api).await;
+    let token = set_cookie(&res, "csrf").unwrap();
+    let res = warp::test::request()
+        .method("POST")
+        .path("/admin/login")
+        .header("cookie", format!("csrf={}", token))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(format!("user=admin&password=hunter22&csrf_token={}", token))
+        .reply(api)
+        .await;
+    let session = set_cookie(&res, "session").unwrap();
+    let cookies = format!("csrf={}; session={}", token, session);
+    (token, cookies)
+}
+
+#[tokio::test]
+async fn admin_can_list_edit_moderate_and_import_fortunes() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .and(header("x-api-key", "backend-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fortunes_body()))
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1", "message": "A mocked fortune.", "lang": "en"})))
+        .mount(&backend)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .and(header("x-api-key", "backend-key"))
+        .and(body_partial_json(json!({"id": "1", "message": "An edited fortune.", "lang": "en", "author": "Ann"})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1", "message": "An edited fortune."})))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/admin/moderation"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"id": "9", "message": "Awaiting review."}])))
+        .mount(&backend)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes/batch"))
+        .and(header("x-api-key", "backend-key"))
+        .and(body_partial_json(json!([{"message": "First."}, {"message": "Second,\non two lines."}])))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {"result": "created", "fortune": {"id": "3", "message": "First."}},
+            {"result": "failed", "id": "", "reason": "message duplicates fortune 2"}
+        ])))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(admin_config(&backend)));
+    let (token, cookies) = admin_login(&api).await;
+
+    let res = warp::test::request().path("/admin/fortunes").header("cookie", &cookies).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.contains("href=\"/admin/fortunes/1/edit\""));
+    assert!(html.contains("action=\"/admin/fortunes/2/delete\""));
+    assert!(html.contains("Another &lt;b&gt;mocked&lt;/b&gt; fortune."));
+
+    let res = warp::test::request().path("/admin/fortunes/1/edit").header("cookie", &cookies).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(String::from_utf8(res.body().to_vec()).unwrap().contains(">A mocked fortune.</textarea>"));
+    let res = warp::test::request()
+        .method("POST")
+        .path("/admin/fortunes/1/edit")
+        .header("cookie", &cookies)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(format!("message=An+edited+fortune.&author=Ann&csrf_token={}", token))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(res.headers()["location"], "/admin/fortunes");
+    assert!(set_cookie(&res, "flash").unwrap().contains("saved"));
+
+    let res = warp::test::request().path("/admin/moderation").header("cookie", &cookies).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.contains("Awaiting review."));
+    assert!(html.contains("action=\"/admin/fortunes/9/approve\""));
+
+    let upload = |csrf_token: &str| {
+        let body = format!(
+            "--B\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\n{}\r\n\
+             --B\r\nContent-Disposition: form-data; name=\"file\"; filename=\"fortunes\"\r\n\r\n\
+             First.\n%\nSecond,\non two lines.\n%\n\r\n--B--\r\n",
+            csrf_token
+        );
+        warp::test::request()
+            .method("POST")
+            .path("/admin/import")
+            .header("cookie", &cookies)
+            .header("content-type", "multipart/form-data; boundary=B")
+            .body(body)
+    };
+    let res = upload("wrong").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = upload(&token).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    let flash = set_cookie(&res, "flash").unwrap();
+    assert!(flash.contains("Imported%201%20of%202"), "{}", flash);
+}
+
+#[tokio::test]
+async fn admin_pages_are_off_without_an_admin_user() {
+    let backend = MockServer::start().await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    for path in ["/admin", "/admin/login"] {
+        let res = warp::test::request().path(path).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn import_sends_a_file_in_batches_and_summarizes_the_outcome() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+        .mount(&backend)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes/batch"))
+        .and(body_partial_json(json!([{"id": "a", "message": "One."}, {"message": "Two."}])))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {"result": "created", "fortune": {"id": "a", "message": "One.", "status": "pending"}},
+            {"result": "failed", "id": "", "reason": "message duplicates fortune 7"}
+        ])))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes/batch"))
+        .and(body_partial_json(json!([{"message": "Three."}])))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!("the backend is read-only")))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[("IMPORT_BATCH_SIZE", "2")])));
+    let token = csrf_token(&api).await;
+    let upload = |file: &str, csrf_token: &str| {
+        let body = format!(
+            "--B\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\n{}\r\n\
+             --B\r\nContent-Disposition: form-data; name=\"file\"; filename=\"fortunes.json\"\r\n\r\n{}\r\n--B--\r\n",
+            csrf_token, file
+        );
+        warp::test::request()
+            .method("POST")
+            .path("/api/import")
+            .header("cookie", format!("csrf={}", token))
+            .header("content-type", "multipart/form-data; boundary=B")
+            .body(body)
+    };
+    let file = r#"[{"id": "a", "message": "One."}, {"message": "Two."}, {"message": "Three."}]"#;
+
+    let res = upload(file, "").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = upload("[not json", &token).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8(res.body().to_vec()).unwrap().contains("invalid JSON"));
+
+    let res = upload(file, &token).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.contains("<tr><th>Fortunes in the file</th><td>3</td></tr>"));
+    assert!(html.contains("<tr><th>Awaiting moderation</th><td>1</td></tr>"));
+    assert!(html.contains("<tr><td>2</td><td>message duplicates fortune 7</td></tr>"));
+    assert!(html.contains("with 1 fortunes not sent: the backend is read-only"));
+}
+
+#[tokio::test]
+async fn breaker_opens_after_repeated_backend_failures() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&backend)
+        .await;
+    let config = test_config(&backend, &[("BREAKER_FAILURE_THRESHOLD", "1"), ("FORTUNE_CACHE_TTL_SECS", "0")]);
+    let api = routes(create_state(config));
+
+    let res = warp::test::request().path("/api/random").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let res = warp::test::request().path("/api/random").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let res = warp::test::request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(metrics.contains("frontend_breaker_state 1"));
+}
+
+#[tokio::test]
+async fn half_open_breaker_lets_one_probe_through() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({"id": "1", "message": "Slow but fine."}))
+                .set_delay(Duration::from_millis(300)),
+        )
+        .mount(&backend)
+        .await;
+    let config = test_config(
+        &backend,
+        &[
+            ("BREAKER_FAILURE_THRESHOLD", "1"),
+            ("BREAKER_OPEN_SECS", "1"),
+            ("BACKEND_RETRY_ATTEMPTS", "1"),
+            ("FORTUNE_CACHE_TTL_SECS", "0"),
+        ],
+    );
+    let api = routes(create_state(config));
+
+    let res = warp::test::request().path("/api/random").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    // While the probe is out, everyone else is still turned away
+    let (first, second) = tokio::join!(
+        warp::test::request().path("/api/random").reply(&api),
+        warp::test::request().path("/api/random").reply(&api),
+    );
+    let mut statuses = [first.status(), second.status()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE]);
+
+    let res = warp::test::request().path("/api/random").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn healthz_is_ok() {
+    let backend = MockServer::start().await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().path("/healthz").reply(&api).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let health: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["version"], env!("CARGO_PKG_VERSION"));
+    assert!(health.get("components").is_none());
+}
+
+#[tokio::test]
+async fn verbose_health_checks_the_backend() {
+    let backend = MockServer::start().await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().path("/healthz?verbose=true").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let health: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(health["status"], "degraded");
+    assert_eq!(health["components"], json!({"backend": "down", "breaker": "closed"}));
+
+    Mock::given(method("GET"))
+        .and(path("/healthz"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"status": "ok"})))
+        .mount(&backend)
+        .await;
+    let res = warp::test::request().path("/healthz?verbose=true").reply(&api).await;
+    let health: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["components"]["backend"], "up");
+}
+
+#[tokio::test]
+async fn request_id_is_forwarded_to_backend_and_returned() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .and(header("x-request-id", "trace-42"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fortunes_body()))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request()
+        .path("/api/all")
+        .header("x-request-id", "trace-42")
+        .reply(&api)
+        .await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-request-id"], "trace-42");
+}
+
+#[tokio::test]
+async fn request_id_is_generated_when_missing_or_invalid() {
+    let backend = MockServer::start().await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request()
+        .path("/healthz")
+        .header("x-request-id", "not valid")
+        .reply(&api)
+        .await;
+
+    let id = res.headers()["x-request-id"].to_str().unwrap();
+    assert_eq!(id.len(), 32);
+    assert_ne!(id, "not valid");
+}
+
+#[tokio::test]
+async fn access_log_uses_the_generated_request_id() {
+    let backend = MockServer::start().await;
+    let path = std::env::temp_dir().join(format!("fortune-frontend-access-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = test_config(
+        &backend,
+        &[("ACCESS_LOG", "common"), ("ACCESS_LOG_FILE", path.to_str().unwrap()), ("TRUSTED_PROXIES", "10.0.0.0/8")],
+    );
+    let api = routes(create_state(config));
+
+    let res = warp::test::request()
+        .path("/healthz")
+        .remote_addr("10.0.0.2:41000".parse().unwrap())
+        .header("x-forwarded-for", "198.51.100.9")
+        .reply(&api)
+        .await;
+
+    let id = res.headers()["x-request-id"].to_str().unwrap();
+    let line = std::fs::read_to_string(&path).unwrap();
+    assert!(line.starts_with("198.51.100.9 - - ["), "{}", line);
+    assert!(line.contains(&format!("] \"GET /healthz\" 200 {} \"", res.body().len())), "{}", line);
+    assert!(line.contains(&format!("\"{}\" ", id)), "{}", line);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn add_reports_duplicates_as_conflict() {
+    let backend = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(409).set_body_json(json!({"id": "1", "message": "A mocked fortune."})))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+    let token = csrf_token(&api).await;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/add")
+        .header("cookie", format!("csrf={}", token))
+        .header("x-csrf-token", &token)
+        .json(&json!({"message": "a mocked  fortune."}))
+        .reply(&api)
+        .await;
+
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn add_reports_submissions_held_for_moderation() {
+    let backend = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(202).set_body_json(json!({"id": "7", "message": "new", "status": "pending"})))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+    let token = csrf_token(&api).await;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/add")
+        .header("cookie", format!("csrf={}", token))
+        .header("x-csrf-token", &token)
+        .json(&json!({"message": "new"}))
+        .reply(&api)
+        .await;
+
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn add_relays_content_filter_rejections() {
+    let backend = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(json!("message contains blocked word \"darn\"")))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+    let token = csrf_token(&api).await;
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/add")
+        .header("cookie", format!("csrf={}", token))
+        .header("x-csrf-token", &token)
+        .json(&json!({"message": "darn"}))
+        .reply(&api)
+        .await;
+
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(res.body(), "message contains blocked word \"darn\"");
+}
+
+#[tokio::test]
+async fn rejections_get_matching_statuses() {
+    let backend = MockServer::start().await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().method("POST").path("/api/random").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(res.body(), "Method Not Allowed");
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/add")
+        .header("content-type", "application/json")
+        .body("{not json")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.body(), "Invalid JSON");
+}
+
+#[tokio::test]
+async fn expired_cache_is_revalidated_with_etag() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .and(header("if-none-match", "\"v1\""))
+        .respond_with(ResponseTemplate::new(304).insert_header("etag", "\"v1\""))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("etag", "\"v1\"")
+                .set_body_json(fortunes_body()),
+        )
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let config = test_config(&backend, &[("FORTUNE_CACHE_TTL_SECS", "1")]);
+    let api = routes(create_state(config));
+
+    warp::test::request().path("/api/all").reply(&api).await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let res = warp::test::request().path("/api/all").reply(&api).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(body.contains("<p>1: A mocked fortune.</p>"));
+}
+
+#[tokio::test]
+async fn responses_are_compressed_when_accepted() {
+    let backend = MockServer::start().await;
+    let api = compression::wrap(routes(create_state(test_config(&backend, &[]))), true);
+
+    let res = warp::test::request()
+        .path("/healthz")
+        .header("accept-encoding", "br")
+        .reply(&api)
+        .await;
+
+    assert_eq!(res.headers()["content-encoding"], "br");
+}
+
+#[tokio::test]
+async fn static_files_are_cached_by_content_hash() {
+    let backend = MockServer::start().await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().path("/").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["cache-control"], "no-cache");
+    assert!(res.headers().contains_key("last-modified"));
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    let start = html.find("script.js?v=").expect("script reference is versioned");
+    let script = &html[start..start + html[start..].find('"').unwrap()];
+
+    let res = warp::test::request().path(&format!("/{}", script)).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["cache-control"], "public, max-age=31536000, immutable");
+    assert!(res.headers()["content-type"].to_str().unwrap().contains("javascript"));
+    let etag = res.headers()["etag"].clone();
+
+    let res = warp::test::request()
+        .path("/script.js")
+        .header("if-none-match", etag)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert!(res.body().is_empty());
+
+    // A missing directory falls back to the copies built into the binary
+    let api = routes(create_state(test_config(&backend, &[("STATIC_DIR", "./no-such-dir")])));
+    let res = warp::test::request().path("/script.js").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn fortune_card_renders_an_svg() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            json!({"id": "7", "message": "Fish & <chips> bring luck.", "author": "Ann"}),
+        ))
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/8"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!("not found")))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().path("/api/fortune-card?id=7&theme=dark").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/svg+xml");
+    let svg = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains("Fish &amp; &lt;chips&gt; bring luck."));
+    assert!(svg.contains("&#8212; Ann"));
+    assert!(svg.contains("#1e1f24"));
+
+    let res = warp::test::request().path("/api/fortune-card?id=8").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = warp::test::request().path("/api/fortune-card?id=7&theme=neon").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = warp::test::request().path("/api/fortune-card?id=..%2Fadmin").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn fortune_audio_speaks_the_plain_message_and_author() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            json!({"id": "7", "message": "**Bold** moves pay off.", "author": "Ann"}),
+        ))
+        .mount(&backend)
+        .await;
+    let off = routes(create_state(test_config(&backend, &[])));
+    let res = warp::test::request().path("/api/fortune-audio?id=7").reply(&off).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // `cat` echoes the text it was asked to speak
+    let api = routes(create_state(test_config(
+        &backend,
+        &[("TTS_PROVIDER", "command"), ("TTS_COMMAND", "cat")],
+    )));
+    let res = warp::test::request().path("/api/fortune-audio?id=7").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "audio/mpeg");
+    assert_eq!(res.body().as_ref(), b"Bold moves pay off. Ann");
+
+    let failing = routes(create_state(test_config(
+        &backend,
+        &[("TTS_PROVIDER", "command"), ("TTS_COMMAND", "echo broken >&2; exit 3")],
+    )));
+    let res = warp::test::request().path("/api/fortune-audio?id=7").reply(&failing).await;
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    assert!(String::from_utf8_lossy(res.body()).contains("broken"));
+}
+
+#[tokio::test]
+async fn qr_codes_are_served_for_existing_fortunes() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "7", "message": "Scan me."})))
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/8"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!("not found")))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[("PUBLIC_URL", "https://fortunes.example")])));
+
+    let res = warp::test::request().path("/api/qr/7").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/png");
+    assert!(res.body().starts_with(b"\x89PNG\r\n\x1a\n"));
+
+    let res = warp::test::request().path("/api/qr/7?format=svg").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "image/svg+xml");
+    assert!(String::from_utf8_lossy(res.body()).contains("<svg"));
+
+    let res = warp::test::request().path("/api/qr/8").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = warp::test::request().path("/api/qr/7?format=gif").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn cards_and_feeds_never_split_a_character() {
+    // Decomposed accents, emoji ZWJ sequences and CJK without spaces all need
+    // breaking inside a "word"
+    let message = format!("{} {} {}", "e\u{301}".repeat(60), "👨‍👩‍👧‍👦".repeat(30), "千里之行始于足下".repeat(5));
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "7", "message": message})))
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {"id": "7", "message": "e\u{301}".repeat(100), "created_at": 1_700_000_000}
+        ])))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().path("/api/fortune-card?id=7").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let svg = String::from_utf8(res.body().to_vec()).unwrap();
+    let lines: Vec<&str> = svg
+        .split("<tspan ")
+        .skip(1)
+        .map(|tspan| &tspan[tspan.find('>').unwrap() + 1..tspan.find("</tspan>").unwrap()])
+        .collect();
+    assert!(lines.len() > 3);
+    for line in &lines {
+        assert!(!line.starts_with(['\u{301}', '\u{200d}']), "line starts inside a character: {:?}", line);
+    }
+    assert_eq!(lines.concat(), message.replace(' ', ""));
+
+    let res = warp::test::request().path("/feed.xml").reply(&api).await;
+    let xml = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(xml.contains(&format!("<title>{}</title>", "e\u{301}".repeat(80))));
+}
+
+#[tokio::test]
+async fn feed_lists_the_newest_fortunes_first() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {"id": "old", "message": "Old news.", "created_at": 1_600_000_000},
+            {"id": "undated", "message": "No date."},
+            {"id": "new", "message": "Fish & <chips>.", "author": "Ann", "created_at": 1_700_000_000},
+            {"id": "mid", "message": "Middling.", "created_at": 1_650_000_000}
+        ])))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[("FEED_SIZE", "3")])));
+
+    let res = warp::test::request()
+        .path("/feed.xml?limit=10")
+        .header("host", "fortunes.test")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "application/rss+xml; charset=utf-8");
+    let xml = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(xml.starts_with("<?xml"));
+    assert!(xml.contains("<link>http://fortunes.test/</link>"));
+    assert!(xml.contains("<description>Fish &amp; &lt;chips&gt;.</description>"));
+    assert!(xml.contains("<dc:creator>Ann</dc:creator>"));
+    assert!(xml.contains("<pubDate>Tue, 14 Nov 2023 22:13:20 GMT</pubDate>"));
+    let new = xml.find("fortune-new").unwrap();
+    let mid = xml.find("fortune-mid").unwrap();
+    let old = xml.find("fortune-old").unwrap();
+    assert!(new < mid && mid < old);
+    // FEED_SIZE caps the requested limit
+    assert!(!xml.contains("fortune-undated"));
+
+    let res = warp::test::request().path("/feed.xml?limit=1").reply(&api).await;
+    let xml = String::from_utf8(res.body().to_vec()).unwrap();
+    assert_eq!(xml.matches("<item>").count(), 1);
+}
+
+#[tokio::test]
+async fn permalink_pages_carry_link_preview_tags() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            json!({"id": "7", "message": "Fish & **chips** \"bring\" luck.", "author": "Ann"}),
+        ))
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/8"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!("not found")))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[("PUBLIC_URL", "https://fortunes.example/")])));
+
+    let res = warp::test::request().path("/fortune/7").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.contains(r#"<meta property="og:url" content="https://fortunes.example/fortune/7" />"#));
+    assert!(html.contains(r#"<meta property="og:image" content="https://fortunes.example/api/fortune-card?id&#x3D;7" />"#));
+    assert!(html.contains(r#"<meta property="og:title" content="Fish &amp; chips &quot;bring&quot; luck." />"#));
+    assert!(html.contains(r#"<meta name="twitter:card" content="summary_large_image" />"#));
+    assert!(html.contains("<strong>chips</strong>"));
+    assert!(html.contains("Ann"));
+
+    let res = warp::test::request().path("/fortune/8").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn sitemap_lists_every_fortune_page() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+            {"id": "new", "message": "New.", "created_at": 1_700_000_000},
+            {"id": "undated", "message": "No date."}
+        ])))
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request()
+        .path("/sitemap.xml")
+        .header("host", "fortunes.test")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "application/xml; charset=utf-8");
+    let xml = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(xml.contains("<url><loc>http://fortunes.test/</loc></url>"));
+    assert!(xml.contains("<url><loc>http://fortunes.test/fortune/new</loc><lastmod>2023-11-14</lastmod></url>"));
+    assert!(xml.contains("<url><loc>http://fortunes.test/fortune/undated</loc></url>"));
+}
+
+#[tokio::test]
+async fn last_known_good_list_is_served_while_the_backend_is_down() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!([{"id": "1", "message": "Still here."}])))
+        .up_to_n_times(1)
+        .mount(&backend)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&backend)
+        .await;
+    let file = std::env::temp_dir().join(format!("last-good-{}.json", std::process::id()));
+    let file_var = file.to_string_lossy().to_string();
+    let config = test_config(&backend, &[("FORTUNE_CACHE_TTL_SECS", "1"), ("LAST_GOOD_FILE", &file_var)]);
+    let api = routes(create_state(config.clone()));
+
+    let res = warp::test::request().path("/api/random").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("x-served-from").is_none());
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let res = warp::test::request().path("/api/random").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-served-from"], "cache");
+    assert_eq!(res.body(), "Still here.");
+    let res = warp::test::request().path("/api/all").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-served-from"], "cache");
+
+    // A restarted frontend picks the list up from the file
+    let api = routes(create_state(config));
+    let res = warp::test::request().path("/api/random").reply(&api).await;
+    assert_eq!(res.headers()["x-served-from"], "cache");
+    assert_eq!(res.body(), "Still here.");
+    std::fs::remove_file(file).unwrap();
+}
+
+#[tokio::test]
+async fn strict_startup_waits_for_backend_health() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/healthz"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"status": "ok"})))
+        .mount(&backend)
+        .await;
+    let config = test_config(&backend, &[]);
+    assert!(startup::wait_for_backend(&config, Duration::from_secs(1)).await.is_ok());
+
+    let down = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&down)
+        .await;
+    let config = test_config(&down, &[]);
+    let err = startup::wait_for_backend(&config, Duration::from_secs(1)).await.unwrap_err();
+    assert!(err.contains("503"), "{}", err);
+}
+
+#[test]
+fn config_files_fill_in_what_flags_and_the_environment_leave_out() {
+    let path = std::env::temp_dir().join(format!("fortune-frontend-{}.env", std::process::id()));
+    std::fs::write(&path, "SESSION_SECRET=\"a-session-secret-of-some-length\"\nFRONTEND_PORT=8090\n").unwrap();
+
+    let config = Config::load(Some(&path), &[("FRONTEND_PORT", "8181".to_string())]).unwrap();
+    assert_eq!(config.frontend_port, 8181);
+    assert_eq!(config.session_secret.as_deref(), Some("a-session-secret-of-some-length"));
+    assert_eq!(config.redacted().session_secret.as_deref(), Some("<redacted>"));
+
+    assert!(Config::load(Some(&path.with_extension("missing")), &[]).is_err());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn backend_routes_pass_through_under_api_backend() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/rotation"))
+        .and(wiremock::matchers::query_param("tags", "zen:3"))
+        .and(header("x-api-key", "caller-key"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("x-backend-only", "yes")
+                .set_body_json(json!({"id": "7", "message": "Breathe."})),
+        )
+        .mount(&backend)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .and(body_partial_json(json!({"message": "Passed through."})))
+        .respond_with(ResponseTemplate::new(202).set_body_json(json!({"id": "8", "message": "Passed through."})))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[("MAX_PASSTHROUGH_BYTES", "64")])));
+
+    let res = warp::test::request()
+        .path("/api/backend/fortunes/rotation?tags=zen:3")
+        .header("x-api-key", "caller-key")
+        .header("cookie", "admin_session=secret")
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-backend-only"], "yes");
+    let body: Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["message"], "Breathe.");
+    let received = backend.received_requests().await.unwrap();
+    assert!(received[0].headers.get("cookie").is_none(), "the frontend's cookies stay here");
+    assert!(received[0].headers.get("x-request-id").is_some());
+
+    // Unknown backend paths answer with the backend's own 404
+    let res = warp::test::request().path("/api/backend/nothing/here").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // Writes need the CSRF token, like /api/add
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/backend/fortunes")
+        .json(&json!({"message": "Passed through."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let token = csrf_token(&api).await;
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/backend/fortunes")
+        .header("cookie", format!("csrf={}", token))
+        .header("x-csrf-token", &token)
+        .json(&json!({"message": "Passed through."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/backend/fortunes")
+        .header("cookie", format!("csrf={}", token))
+        .header("x-csrf-token", &token)
+        .json(&json!({"message": "x".repeat(100)}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let api = routes(create_state(test_config(&backend, &[("BACKEND_PASSTHROUGH", "false")])));
+    let res = warp::test::request().path("/api/backend/fortunes/rotation?tags=zen:3").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn writes_to_the_backend_are_signed_with_the_shared_secret() {
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+
+    let backend = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fortunes"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "9", "message": "Signed."})))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let secret = "a-shared-secret-of-some-length";
+    let api = routes(create_state(test_config(&backend, &[("REQUEST_SIGNING_SECRET", secret)])));
+
+    let token = csrf_token(&api).await;
+    let res = warp::test::request()
+        .method("POST")
+        .path("/api/add")
+        .header("cookie", format!("csrf={}", token))
+        .header("x-csrf-token", &token)
+        .json(&json!({"message": "Signed."}))
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let received = backend.received_requests().await.unwrap();
+    let request = &received[0];
+    let header = |name: &str| request.headers.get(name).unwrap().to_str().unwrap().to_string();
+    let content_hash = hex::encode(Sha256::digest(&request.body));
+    assert_eq!(header("x-content-sha256"), content_hash);
+    let canonical = format!("POST\n/fortunes\n{}\n{}", header("x-signature-timestamp"), content_hash);
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(canonical.as_bytes());
+    assert_eq!(header("x-signature"), hex::encode(mac.finalize().into_bytes()));
+}
+
+#[tokio::test]
+async fn passthrough_writes_are_not_signed() {
+    // A backend with REQUEST_SIGNING_SECRET set: signed writes go through,
+    // the rest get 401
+    let backend = MockServer::start().await;
+    Mock::given(method("DELETE"))
+        .and(path("/fortunes/7"))
+        .and(wiremock::matchers::header_exists("x-signature"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(0)
+        .mount(&backend)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path("/fortunes/7"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!("missing or invalid request signature")))
+        .expect(1)
+        .mount(&backend)
+        .await;
+    let api = routes(create_state(test_config(&backend, &[("REQUEST_SIGNING_SECRET", "a-shared-secret-of-some-length")])));
+
+    let token = csrf_token(&api).await;
+    let res = warp::test::request()
+        .method("DELETE")
+        .path("/api/backend/fortunes/7")
+        .header("cookie", format!("csrf={}", token))
+        .header("x-csrf-token", &token)
+        .reply(&api)
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let received = backend.received_requests().await.unwrap();
+    assert!(received[0].headers.get("x-signature").is_none());
+}
+
+#[tokio::test]
+async fn security_headers_are_sent_and_configurable() {
+    let backend = MockServer::start().await;
+    let api = routes(create_state(test_config(&backend, &[])));
+
+    let res = warp::test::request().path("/").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let csp = res.headers()["content-security-policy"].to_str().unwrap();
+    assert!(csp.contains("script-src 'self' https://cdn.jsdelivr.net"), "{}", csp);
+    assert!(csp.contains("frame-ancestors 'none'"), "{}", csp);
+    assert_eq!(res.headers()["x-content-type-options"], "nosniff");
+    assert_eq!(res.headers()["referrer-policy"], "strict-origin-when-cross-origin");
+    assert!(!res.headers().contains_key("strict-transport-security"), "no HSTS without TLS");
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(!html.contains("onclick=") && !html.contains("onsubmit="), "no inline handlers for the CSP to block");
+
+    let res = warp::test::request().path("/no-such-page").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(res.headers()["x-content-type-options"], "nosniff");
+
+    let api = routes(create_state(test_config(
+        &backend,
+        &[
+            ("TLS_CERT_PATH", "cert.pem"),
+            ("TLS_KEY_PATH", "key.pem"),
+            ("CONTENT_SECURITY_POLICY", ""),
+            ("REFERRER_POLICY", "no-referrer"),
+            ("RESPONSE_HEADERS", r#"{"X-Frame-Options":"DENY","Cache-Control":"no-store"}"#),
+        ],
+    )));
+    let res = warp::test::request().path("/").reply(&api).await;
+    assert!(!res.headers().contains_key("content-security-policy"));
+    assert_eq!(res.headers()["referrer-policy"], "no-referrer");
+    assert_eq!(res.headers()["strict-transport-security"], "max-age=31536000; includeSubDomains");
+    assert_eq!(res.headers()["x-frame-options"], "DENY");
+    assert_eq!(res.headers()["cache-control"], "no-cache", "the page keeps its own header");
+
+    for (key, bad) in [("RESPONSE_HEADERS", r#"{"bad header":"x"}"#), ("RESPONSE_HEADERS", "[]"), ("REFERRER_POLICY", "a\nb")] {
+        assert!(Config::load(None, &[(key, bad.to_string())]).is_err(), "{}={}", key, bad);
+    }
+}
+
+#[tokio::test]
+async fn the_tuned_listener_follows_the_http2_setting() {
+    use fortune_frontend::server;
+    use std::net::SocketAddr;
+
+    let backend = MockServer::start().await;
+    let serve = |extra: &[(&str, &str)]| {
+        let config = test_config(&backend, extra);
+        let tuning = config.server_tuning();
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        tokio::spawn(server::run(compression::wrap(routes(create_state(config)), false), server::Listen::Tcp(addr), tuning));
+        format!("http://{}/healthz", addr)
+    };
+    let http2 = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+
+    let url = serve(&[]);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let res = http2.get(&url).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.version(), reqwest::Version::HTTP_2);
+
+    let url = serve(&[("HTTP2", "false")]);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(http2.get(&url).send().await.is_err(), "HTTP/2 is turned off");
+    let res = reqwest::get(&url).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn requests_take_turns_over_the_backends_and_skip_a_failing_one() {
+    let fortune = || ResponseTemplate::new(200).set_body_json(json!({"id": "1", "message": "Only one."}));
+    let (a, b, failing) = (MockServer::start().await, MockServer::start().await, MockServer::start().await);
+    for backend in [&a, &b] {
+        Mock::given(method("GET")).and(path("/fortunes/random")).respond_with(fortune()).mount(backend).await;
+    }
+    Mock::given(method("GET")).respond_with(ResponseTemplate::new(500)).mount(&failing).await;
+
+    let list = format!("{},{}", a.address(), b.address());
+    let api = routes(create_state(test_config(&a, &[("BACKENDS", &list), ("FORTUNE_CACHE_TTL_SECS", "0")])));
+    for _ in 0..4 {
+        let res = warp::test::request().path("/api/random").reply(&api).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    assert_eq!(a.received_requests().await.unwrap().len(), 2);
+    assert_eq!(b.received_requests().await.unwrap().len(), 2);
+
+    // The failed request is retried on the other backend, and the failing
+    // one is left out from then on
+    let list = format!("{},{}", a.address(), failing.address());
+    let api = routes(create_state(test_config(&a, &[("BACKENDS", &list), ("FORTUNE_CACHE_TTL_SECS", "0")])));
+    for _ in 0..6 {
+        let res = warp::test::request().path("/api/random").reply(&api).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body(), "Only one.");
+    }
+    assert_eq!(failing.received_requests().await.unwrap().len(), 1);
+    let res = warp::test::request().path("/metrics").reply(&api).await;
+    let metrics = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(metrics.contains("frontend_backend_instances 2\n"), "{}", metrics);
+    assert!(metrics.contains("frontend_backend_instances_ejected 1\n"), "{}", metrics);
+    assert!(metrics.contains("frontend_backend_ejections_total 1\n"), "{}", metrics);
+    assert!(metrics.contains("frontend_breaker_state 0\n"), "{}", metrics);
+
+    for (key, bad) in [("BACKENDS", " , "), ("BACKENDS", "backend:http"), ("BACKENDS", "backend/fortunes"), ("BACKEND_SRV", " ")] {
+        assert!(Config::load(None, &[(key, bad.to_string())]).is_err(), "{}={}", key, bad);
+    }
+    let both = [("BACKENDS", "backend".to_string()), ("BACKEND_SRV", "_http._tcp.backend".to_string())];
+    assert!(Config::load(None, &both).is_err());
+    assert!(Config::load(None, &[("BACKENDS", "backend-a:9001, [fd00::5], backend-b".to_string())]).is_ok());
+}
+
+#[tokio::test]
+async fn template_dir_overrides_pages_and_supplies_the_theme() {
+    let backend = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/fortunes/7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "7", "message": "Branded luck."})))
+        .mount(&backend)
+        .await;
+    let dir = std::env::temp_dir().join(format!("fortune-frontend-templates-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("partials")).unwrap();
+    std::fs::create_dir_all(dir.join("themes")).unwrap();
+    std::fs::write(dir.join("partials/footer.html"), "<footer>Brand Inc.</footer>").unwrap();
+    std::fs::write(
+        dir.join("fortune.html"),
+        r#"<html data-theme="{{theme}}"><link href="{{theme_css}}" rel="stylesheet"><p>{{{message}}}</p>{{> partials/footer}}</html>"#,
+    )
+    .unwrap();
+    std::fs::write(dir.join("themes/brand.css"), "body { color: rebeccapurple; }").unwrap();
+    let template_dir = dir.to_str().unwrap().to_string();
+    let config = test_config(&backend, &[("TEMPLATE_DIR", template_dir.as_str()), ("THEME", "brand")]);
+    let api = routes(create_state(config));
+
+    let res = warp::test::request().path("/fortune/7").reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.contains(r#"<html data-theme="brand">"#));
+    assert!(html.contains("<p>Branded luck.</p><footer>Brand Inc.</footer>"));
+    let css_url = html.split(r#"<link href=""#).nth(1).unwrap().split('"').next().unwrap().to_string();
+    assert!(css_url.starts_with("/theme.css?v="));
+
+    let res = warp::test::request().path(&css_url).reply(&api).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["content-type"], "text/css");
+    assert_eq!(res.headers()["cache-control"], "public, max-age=31536000, immutable");
+    assert_eq!(res.body().as_ref(), b"body { color: rebeccapurple; }");
+
+    // The built-in home page names the theme and links its stylesheet too
+    let res = warp::test::request().path("/").reply(&api).await;
+    let html = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(html.contains(r#"<html lang="en" data-theme="brand">"#));
+    assert!(html.contains(&format!(r#"<link href="{}" rel="stylesheet">"#, &css_url[1..])));
+
+    std::fs::write(dir.join("broken.html"), "{{#if}}").unwrap();
+    let err = Config::load(None, &[("TEMPLATE_DIR", template_dir.clone()), ("THEME", "brand".to_string())]).unwrap_err();
+    assert!(err.starts_with("TEMPLATE_DIR: "), "{}", err);
+    let err = Config::load(None, &[("THEME", "neon".to_string())]).unwrap_err();
+    assert!(err.contains("THEME 'neon' is not one of default, dark, sepia"), "{}", err);
+    std::fs::remove_dir_all(&dir).unwrap();
+}